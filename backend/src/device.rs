@@ -0,0 +1,178 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+// A device that can go away under the renderer, after a driver update or a
+// GPU hang, and be built again from what the CPU still has.
+pub trait RecoverableDevice {
+    type Error: fmt::Display;
+
+    // Why the device went away, None when the API does not say.
+    fn removed_reason(&self) -> Option<String>;
+
+    // Drops the device and everything created on it.
+    fn release(&mut self);
+
+    fn recreate(&mut self) -> Result<(), Self::Error>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceState {
+    Ready,
+    // Released and waiting to be recreated, after `attempts` failed tries.
+    Lost { attempts: u32 },
+    // Recreating failed too often, nothing more is tried.
+    Failed,
+}
+
+// Drives a RecoverableDevice from loss back to Ready. Failed recreations
+// are retried with a doubling delay, so a device that cannot come back
+// right away, as during a driver update, is not hammered every frame.
+#[derive(Clone, Debug)]
+pub struct DeviceRecovery {
+    state: DeviceState,
+    max_attempts: u32,
+    retry_delay: Duration,
+    next_attempt: Option<Instant>,
+}
+
+impl DeviceRecovery {
+    pub fn new(max_attempts: u32, retry_delay: Duration) -> Self {
+        DeviceRecovery {
+            state: DeviceState::Ready,
+            max_attempts,
+            retry_delay,
+            next_attempt: None,
+        }
+    }
+
+    pub fn state(&self) -> DeviceState {
+        self.state
+    }
+
+    // Called when the device went away. Releases it and returns why it was
+    // removed; the first recreation is tried on the next `recover`.
+    pub fn lose<D: RecoverableDevice>(&mut self, device: &mut D) -> Option<String> {
+        if self.state != DeviceState::Ready {
+            return None;
+        }
+        let reason = device.removed_reason();
+        device.release();
+        self.state = DeviceState::Lost { attempts: 0 };
+        self.next_attempt = None;
+        reason
+    }
+
+    // Tries to recreate a lost device when the retry delay has passed, does
+    // nothing in the other states. Returns the error of a failed try.
+    pub fn recover<D: RecoverableDevice>(&mut self, device: &mut D, now: Instant) -> Result<DeviceState, D::Error> {
+        let attempts = match self.state {
+            DeviceState::Lost { attempts } => attempts,
+            state => return Ok(state),
+        };
+        if self.next_attempt.is_some_and(|next_attempt| now < next_attempt) {
+            return Ok(self.state);
+        }
+
+        match device.recreate() {
+            Ok(()) => {
+                self.state = DeviceState::Ready;
+                self.next_attempt = None;
+                Ok(self.state)
+            }
+            Err(error) => {
+                // Whatever the failed try did create goes too.
+                device.release();
+                let attempts = attempts + 1;
+                if attempts >= self.max_attempts {
+                    self.state = DeviceState::Failed;
+                } else {
+                    self.state = DeviceState::Lost { attempts };
+                    self.next_attempt = Some(now + self.retry_delay * 2u32.pow(attempts - 1));
+                }
+                Err(error)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::HeadlessDevice;
+
+    const DELAY: Duration = Duration::from_millis(100);
+
+    #[test]
+    fn lost_device_is_released_and_recreated() {
+        let mut device = HeadlessDevice::new();
+        let mut recovery = DeviceRecovery::new(3, DELAY);
+        let now = Instant::now();
+        assert_eq!(recovery.recover(&mut device, now), Ok(DeviceState::Ready));
+        assert_eq!(device.recreations, 0);
+
+        device.remove("hung");
+        assert_eq!(recovery.lose(&mut device), Some("hung".to_string()));
+        assert_eq!(recovery.state(), DeviceState::Lost { attempts: 0 });
+        assert_eq!(device.releases, 1);
+        assert!(!device.alive);
+
+        assert_eq!(recovery.recover(&mut device, now), Ok(DeviceState::Ready));
+        assert!(device.alive);
+        assert_eq!(device.recreations, 1);
+    }
+
+    #[test]
+    fn losing_twice_releases_once() {
+        let mut device = HeadlessDevice::new();
+        let mut recovery = DeviceRecovery::new(3, DELAY);
+        device.remove("hung");
+        recovery.lose(&mut device);
+        assert_eq!(recovery.lose(&mut device), None);
+        assert_eq!(device.releases, 1);
+    }
+
+    #[test]
+    fn failed_recreations_back_off() {
+        let mut device = HeadlessDevice::new();
+        device.failing_recreations = 2;
+        let mut recovery = DeviceRecovery::new(5, DELAY);
+        let start = Instant::now();
+        recovery.lose(&mut device);
+
+        assert!(recovery.recover(&mut device, start).is_err());
+        assert_eq!(recovery.state(), DeviceState::Lost { attempts: 1 });
+        // Nothing is tried before the delay passed.
+        assert_eq!(recovery.recover(&mut device, start + DELAY / 2), Ok(DeviceState::Lost { attempts: 1 }));
+        assert_eq!(device.recreations, 1);
+
+        assert!(recovery.recover(&mut device, start + DELAY).is_err());
+        assert_eq!(recovery.state(), DeviceState::Lost { attempts: 2 });
+        // The delay doubled.
+        assert_eq!(recovery.recover(&mut device, start + DELAY * 2), Ok(DeviceState::Lost { attempts: 2 }));
+        assert_eq!(device.recreations, 2);
+
+        assert_eq!(recovery.recover(&mut device, start + DELAY * 3), Ok(DeviceState::Ready));
+        assert_eq!(device.recreations, 3);
+        // Once on loss and once after every failed recreation.
+        assert_eq!(device.releases, 3);
+        assert!(device.alive);
+    }
+
+    #[test]
+    fn recovery_gives_up_after_max_attempts() {
+        let mut device = HeadlessDevice::new();
+        device.failing_recreations = 10;
+        let mut recovery = DeviceRecovery::new(3, DELAY);
+        let start = Instant::now();
+        recovery.lose(&mut device);
+        for attempt in 0..3 {
+            let error = recovery.recover(&mut device, start + DELAY * 10 * attempt);
+            assert_eq!(error, Err("no device available".to_string()));
+        }
+        assert_eq!(recovery.state(), DeviceState::Failed);
+
+        assert_eq!(recovery.recover(&mut device, start + DELAY * 100), Ok(DeviceState::Failed));
+        assert_eq!(device.recreations, 3);
+        assert!(!device.alive);
+    }
+}
@@ -0,0 +1,44 @@
+// Same layout as D3D12_DRAW_INDEXED_ARGUMENTS so that a buffer of these can
+// be consumed directly by ExecuteIndirect.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DrawIndexedArguments {
+    pub index_count_per_instance: u32,
+    pub instance_count: u32,
+    pub start_index_location: u32,
+    pub base_vertex_location: i32,
+    pub start_instance_location: u32,
+}
+
+// How the visible draws of a frame reach the command stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DrawSubmission {
+    // Culled and compacted on the GPU, submitted with a single indirect call.
+    GpuIndirect,
+    // Culled on the CPU, one draw call per visible chunk.
+    Cpu,
+}
+
+pub trait DrawEncoder {
+    fn draw_indexed(&mut self, args: &DrawIndexedArguments);
+
+    // Draws up to max_draw_count argument records previously written to the
+    // encoder's argument buffer; the actual count is read from the GPU.
+    fn draw_indexed_indirect(&mut self, max_draw_count: u32);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::{offset_of, size_of};
+
+    use super::*;
+
+    #[test]
+    fn arguments_match_d3d12_draw_indexed_arguments() {
+        assert_eq!(size_of::<DrawIndexedArguments>(), 20);
+        assert_eq!(offset_of!(DrawIndexedArguments, instance_count), 4);
+        assert_eq!(offset_of!(DrawIndexedArguments, start_index_location), 8);
+        assert_eq!(offset_of!(DrawIndexedArguments, base_vertex_location), 12);
+        assert_eq!(offset_of!(DrawIndexedArguments, start_instance_location), 16);
+    }
+}
@@ -0,0 +1,90 @@
+use crate::device::RecoverableDevice;
+use crate::draw::{DrawEncoder, DrawIndexedArguments};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordedCommand {
+    DrawIndexed(DrawIndexedArguments),
+    DrawIndexedIndirect { max_draw_count: u32 },
+}
+
+// Records submitted commands instead of executing them.
+#[derive(Debug, Default)]
+pub struct HeadlessEncoder {
+    pub commands: Vec<RecordedCommand>,
+}
+
+impl HeadlessEncoder {
+    pub fn new() -> Self {
+        HeadlessEncoder::default()
+    }
+}
+
+impl DrawEncoder for HeadlessEncoder {
+    fn draw_indexed(&mut self, args: &DrawIndexedArguments) {
+        self.commands.push(RecordedCommand::DrawIndexed(*args));
+    }
+
+    fn draw_indexed_indirect(&mut self, max_draw_count: u32) {
+        self.commands
+            .push(RecordedCommand::DrawIndexedIndirect { max_draw_count });
+    }
+}
+
+// Stands in for a GPU device: can be removed on demand and made to fail a
+// number of recreations, and counts what recovery did to it.
+#[derive(Debug)]
+pub struct HeadlessDevice {
+    pub alive: bool,
+    // Recreations that fail before one succeeds.
+    pub failing_recreations: u32,
+    pub releases: u32,
+    pub recreations: u32,
+    removed_reason: Option<String>,
+}
+
+impl HeadlessDevice {
+    pub fn new() -> Self {
+        HeadlessDevice {
+            alive: true,
+            failing_recreations: 0,
+            releases: 0,
+            recreations: 0,
+            removed_reason: None,
+        }
+    }
+
+    pub fn remove(&mut self, reason: &str) {
+        self.alive = false;
+        self.removed_reason = Some(reason.to_string());
+    }
+}
+
+impl Default for HeadlessDevice {
+    fn default() -> Self {
+        HeadlessDevice::new()
+    }
+}
+
+impl RecoverableDevice for HeadlessDevice {
+    type Error = String;
+
+    fn removed_reason(&self) -> Option<String> {
+        self.removed_reason.clone()
+    }
+
+    fn release(&mut self) {
+        self.alive = false;
+        self.releases += 1;
+    }
+
+    fn recreate(&mut self) -> Result<(), String> {
+        self.recreations += 1;
+        if self.failing_recreations > 0 {
+            self.failing_recreations -= 1;
+            return Err("no device available".to_string());
+        }
+        self.alive = true;
+        self.removed_reason = None;
+        Ok(())
+    }
+}
@@ -0,0 +1,12 @@
+// API independent rendering interfaces. The D3D12 implementation lives in
+// voxel_engine, the headless one here so logic can be exercised without a GPU.
+
+mod device;
+mod draw;
+mod headless;
+mod present;
+
+pub use device::{DeviceRecovery, DeviceState, RecoverableDevice};
+pub use draw::{DrawEncoder, DrawIndexedArguments, DrawSubmission};
+pub use headless::{HeadlessDevice, HeadlessEncoder, RecordedCommand};
+pub use present::{PresentMode, PresentParameters};
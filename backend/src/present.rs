@@ -0,0 +1,127 @@
+use std::fmt;
+use std::str::FromStr;
+
+// How finished frames reach the screen. Can change from one present to the
+// next, the swap chain is created to allow all of them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PresentMode {
+    // Waits for the vertical blank, never tears.
+    Vsync,
+    // Shows the frame right away. Tears where the display allows it, which
+    // lets a variable refresh display follow the frame rate.
+    Immediate,
+    // Does not wait, a newer frame replaces the queued one at the vertical
+    // blank and nothing tears.
+    Mailbox,
+}
+
+impl PresentMode {
+    pub const ALL: [PresentMode; 3] = [PresentMode::Vsync, PresentMode::Immediate, PresentMode::Mailbox];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PresentMode::Vsync => "vsync",
+            PresentMode::Immediate => "immediate",
+            PresentMode::Mailbox => "mailbox",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            PresentMode::Vsync => PresentMode::Immediate,
+            PresentMode::Immediate => PresentMode::Mailbox,
+            PresentMode::Mailbox => PresentMode::Vsync,
+        }
+    }
+
+    // What a present in this mode asks of the swap chain. Without tearing
+    // support Immediate is presented like Mailbox.
+    pub fn parameters(self, tearing_supported: bool) -> PresentParameters {
+        match self {
+            PresentMode::Vsync => PresentParameters {
+                sync_interval: 1,
+                allow_tearing: false,
+            },
+            PresentMode::Immediate => PresentParameters {
+                sync_interval: 0,
+                allow_tearing: tearing_supported,
+            },
+            PresentMode::Mailbox => PresentParameters {
+                sync_interval: 0,
+                allow_tearing: false,
+            },
+        }
+    }
+}
+
+impl fmt::Display for PresentMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for PresentMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        PresentMode::ALL
+            .into_iter()
+            .find(|mode| mode.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("unknown present mode \"{}\", expected vsync, immediate or mailbox", s))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PresentParameters {
+    // Vertical blanks to wait for, 0 presents right away.
+    pub sync_interval: u32,
+    pub allow_tearing: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parameters(sync_interval: u32, allow_tearing: bool) -> PresentParameters {
+        PresentParameters {
+            sync_interval,
+            allow_tearing,
+        }
+    }
+
+    #[test]
+    fn modes_select_interval_and_tearing() {
+        for tearing_supported in [false, true] {
+            assert_eq!(PresentMode::Vsync.parameters(tearing_supported), parameters(1, false));
+            assert_eq!(PresentMode::Mailbox.parameters(tearing_supported), parameters(0, false));
+        }
+        assert_eq!(PresentMode::Immediate.parameters(true), parameters(0, true));
+    }
+
+    #[test]
+    fn immediate_falls_back_to_mailbox_without_tearing() {
+        assert_eq!(PresentMode::Immediate.parameters(false), PresentMode::Mailbox.parameters(false));
+    }
+
+    #[test]
+    fn next_cycles_through_every_mode() {
+        let mut mode = PresentMode::Vsync;
+        for expected in PresentMode::ALL.iter().cycle().skip(1).take(PresentMode::ALL.len()) {
+            mode = mode.next();
+            assert_eq!(mode, *expected);
+        }
+        assert_eq!(mode, PresentMode::Vsync);
+    }
+
+    #[test]
+    fn names_parse_back() {
+        for mode in PresentMode::ALL {
+            assert_eq!(mode.to_string().parse(), Ok(mode));
+        }
+        assert_eq!("Immediate".parse(), Ok(PresentMode::Immediate));
+        assert_eq!(
+            "fifo".parse::<PresentMode>(),
+            Err("unknown present mode \"fifo\", expected vsync, immediate or mailbox".to_string())
+        );
+    }
+}
@@ -29,7 +29,7 @@ fn compile_shader(entry : &ShaderEntry, out_file : &str) -> std::io::Result<Outp
 
 fn main() -> std::io::Result<()>
 {
-    let shaders : [ShaderEntry; 2] = [
+    let shaders : [ShaderEntry; 17] = [
         ShaderEntry {
             shader_file : String::from("shaders\\shaders.hlsl"),
             out_file    : String::from("vs.bin"),
@@ -42,8 +42,101 @@ fn main() -> std::io::Result<()>
             entry_point : String::from("PSMain"),
             profile     : String::from("ps_6_0"),
         },
+        ShaderEntry {
+            shader_file : String::from("shaders\\cull.hlsl"),
+            out_file    : String::from("cs_cull.bin"),
+            entry_point : String::from("CSMain"),
+            profile     : String::from("cs_6_0"),
+        },
+        ShaderEntry {
+            shader_file : String::from("shaders\\hzb.hlsl"),
+            out_file    : String::from("cs_hzb.bin"),
+            entry_point : String::from("CSDownsample"),
+            profile     : String::from("cs_6_0"),
+        },
+        ShaderEntry {
+            shader_file : String::from("shaders\\hzb.hlsl"),
+            out_file    : String::from("cs_hzb_ms.bin"),
+            entry_point : String::from("CSCopyMultisampled"),
+            profile     : String::from("cs_6_0"),
+        },
+        ShaderEntry {
+            shader_file : String::from("shaders\\raymarch.hlsl"),
+            out_file    : String::from("cs_raymarch.bin"),
+            entry_point : String::from("CSMain"),
+            profile     : String::from("cs_6_0"),
+        },
+        ShaderEntry {
+            shader_file : String::from("shaders\\sky.hlsl"),
+            out_file    : String::from("sky_vs.bin"),
+            entry_point : String::from("VSSky"),
+            profile     : String::from("vs_6_0"),
+        },
+        ShaderEntry {
+            shader_file : String::from("shaders\\sky.hlsl"),
+            out_file    : String::from("sky_ps.bin"),
+            entry_point : String::from("PSSky"),
+            profile     : String::from("ps_6_0"),
+        },
+        ShaderEntry {
+            shader_file : String::from("shaders\\post.hlsl"),
+            out_file    : String::from("post_vs.bin"),
+            entry_point : String::from("VSPost"),
+            profile     : String::from("vs_6_0"),
+        },
+        ShaderEntry {
+            shader_file : String::from("shaders\\post.hlsl"),
+            out_file    : String::from("post_bloom_extract_ps.bin"),
+            entry_point : String::from("PSBloomExtract"),
+            profile     : String::from("ps_6_0"),
+        },
+        ShaderEntry {
+            shader_file : String::from("shaders\\post.hlsl"),
+            out_file    : String::from("post_blur_ps.bin"),
+            entry_point : String::from("PSBlur"),
+            profile     : String::from("ps_6_0"),
+        },
+        ShaderEntry {
+            shader_file : String::from("shaders\\post.hlsl"),
+            out_file    : String::from("post_tonemap_ps.bin"),
+            entry_point : String::from("PSTonemap"),
+            profile     : String::from("ps_6_0"),
+        },
+        ShaderEntry {
+            shader_file : String::from("shaders\\post.hlsl"),
+            out_file    : String::from("post_fxaa_ps.bin"),
+            entry_point : String::from("PSFxaa"),
+            profile     : String::from("ps_6_0"),
+        },
+        ShaderEntry {
+            shader_file : String::from("shaders\\debug.hlsl"),
+            out_file    : String::from("debug_vs.bin"),
+            entry_point : String::from("VSDebug"),
+            profile     : String::from("vs_6_0"),
+        },
+        ShaderEntry {
+            shader_file : String::from("shaders\\debug.hlsl"),
+            out_file    : String::from("debug_ps.bin"),
+            entry_point : String::from("PSDebug"),
+            profile     : String::from("ps_6_0"),
+        },
+        ShaderEntry {
+            shader_file : String::from("shaders\\ui.hlsl"),
+            out_file    : String::from("ui_vs.bin"),
+            entry_point : String::from("VSUi"),
+            profile     : String::from("vs_6_0"),
+        },
+        ShaderEntry {
+            shader_file : String::from("shaders\\ui.hlsl"),
+            out_file    : String::from("ui_ps.bin"),
+            entry_point : String::from("PSUi"),
+            profile     : String::from("ps_6_0"),
+        },
     ];
 
+    // Included by the shaders above rather than compiled on its own.
+    println!("cargo:rerun-if-changed=shaders\\lighting.hlsli");
+
     let out_dir = std::env::var("OUT_DIR").unwrap();
     for x in shaders {
         println!("cargo:rerun-if-changed={}", x.shader_file.as_str());
@@ -0,0 +1,120 @@
+struct ChunkRecord
+{
+    float3 bounds_min;
+    uint index_count;
+    float3 bounds_max;
+    uint first_index;
+    int base_vertex;
+    uint3 padding;
+};
+
+// Same layout as D3D12_DRAW_INDEXED_ARGUMENTS
+struct DrawIndexedArguments
+{
+    uint index_count_per_instance;
+    uint instance_count;
+    uint start_index_location;
+    int base_vertex_location;
+    uint start_instance_location;
+};
+
+cbuffer CullConstants : register(b0)
+{
+    float4 planes[6];
+    // View projection the depth pyramid was rendered with.
+    row_major float4x4 occlusion_view_projection;
+    uint chunk_count;
+    uint occlusion_enabled;
+    uint hzb_width;
+    uint hzb_height;
+    uint hzb_mip_count;
+};
+
+StructuredBuffer<ChunkRecord> chunks : register(t0);
+Texture2D<float> hzb : register(t1);
+RWStructuredBuffer<DrawIndexedArguments> draw_arguments : register(u0);
+RWByteAddressBuffer draw_count : register(u1);
+
+bool IsVisible(float3 center, float3 extents)
+{
+    for (uint i = 0; i < 6; ++i)
+    {
+        float radius = dot(extents, abs(planes[i].xyz));
+        if (dot(planes[i].xyz, center) + planes[i].w < -radius)
+        {
+            return false;
+        }
+    }
+    return true;
+}
+
+bool IsOccluded(float3 bounds_min, float3 bounds_max)
+{
+    float2 uv_min = float2(1.0, 1.0);
+    float2 uv_max = float2(0.0, 0.0);
+    float min_depth = 1.0;
+    for (uint i = 0; i < 8; ++i)
+    {
+        float3 corner = float3(
+            (i & 1) != 0 ? bounds_max.x : bounds_min.x,
+            (i & 2) != 0 ? bounds_max.y : bounds_min.y,
+            (i & 4) != 0 ? bounds_max.z : bounds_min.z);
+        float4 clip = mul(occlusion_view_projection, float4(corner, 1.0));
+        if (clip.w <= 0.0)
+        {
+            // Reaches behind the camera, the projection is unbounded.
+            return false;
+        }
+        float2 uv = clip.xy / clip.w * float2(0.5, -0.5) + 0.5;
+        uv_min = min(uv_min, uv);
+        uv_max = max(uv_max, uv);
+        min_depth = min(min_depth, clip.z / clip.w);
+    }
+    uv_min = saturate(uv_min);
+    uv_max = saturate(uv_max);
+
+    // Pick the level where the rectangle covers at most 2x2 texels.
+    float2 size = (uv_max - uv_min) * float2(hzb_width, hzb_height);
+    uint mip = min((uint)ceil(log2(max(max(size.x, size.y), 1.0))), hzb_mip_count - 1);
+    uint2 mip_size = max(uint2(hzb_width, hzb_height) >> mip, uint2(1, 1));
+    uint2 texel_min = min((uint2)(uv_min * mip_size), mip_size - 1);
+    uint2 texel_max = min((uint2)(uv_max * mip_size), mip_size - 1);
+
+    float farthest = hzb.Load(int3(texel_min.x, texel_min.y, mip));
+    farthest = max(farthest, hzb.Load(int3(texel_max.x, texel_min.y, mip)));
+    farthest = max(farthest, hzb.Load(int3(texel_min.x, texel_max.y, mip)));
+    farthest = max(farthest, hzb.Load(int3(texel_max.x, texel_max.y, mip)));
+    return min_depth > farthest;
+}
+
+[numthreads(64, 1, 1)]
+void CSMain(uint3 id : SV_DispatchThreadID)
+{
+    if (id.x >= chunk_count)
+    {
+        return;
+    }
+
+    ChunkRecord chunk = chunks[id.x];
+    float3 center = (chunk.bounds_min + chunk.bounds_max) * 0.5;
+    float3 extents = (chunk.bounds_max - chunk.bounds_min) * 0.5;
+    if (!IsVisible(center, extents))
+    {
+        return;
+    }
+    if (occlusion_enabled != 0 && IsOccluded(chunk.bounds_min, chunk.bounds_max))
+    {
+        return;
+    }
+
+    uint slot;
+    draw_count.InterlockedAdd(0, 1, slot);
+
+    DrawIndexedArguments args;
+    args.index_count_per_instance = chunk.index_count;
+    args.instance_count = 1;
+    args.start_index_location = chunk.first_index;
+    args.base_vertex_location = chunk.base_vertex;
+    args.start_instance_location = 0;
+    draw_arguments[slot] = args;
+}
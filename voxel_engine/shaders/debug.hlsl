@@ -0,0 +1,29 @@
+#include "lighting.hlsli"
+
+// The main root constants, with an identity matrix for the text, which is
+// in clip space already.
+cbuffer SceneConstants : register(b0)
+{
+    row_major float4x4 view_projection;
+};
+
+struct DebugInput
+{
+    float4 position : SV_POSITION;
+    float4 color : COLOR;
+};
+
+DebugInput VSDebug(float3 position : POSITION, float4 color : COLOR)
+{
+    DebugInput result;
+
+    result.position = mul(view_projection, float4(position, 1.0));
+    result.color = float4(SrgbToLinear(color.rgb), color.a);
+
+    return result;
+}
+
+float4 PSDebug(DebugInput input) : SV_TARGET
+{
+    return input.color;
+}
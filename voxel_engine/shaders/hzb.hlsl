@@ -0,0 +1,68 @@
+cbuffer DownsampleConstants : register(b0)
+{
+    uint2 source_size;
+    uint2 destination_size;
+};
+
+Texture2D<float> source : register(t0);
+Texture2DMS<float> multisampled_source : register(t0);
+RWTexture2D<float> destination : register(u0);
+
+// Level 0 is a copy of the depth buffer, every further level keeps the
+// farthest depth of the 2x2 (or 3x2, 2x3, 3x3 on odd sizes) texels it covers.
+[numthreads(8, 8, 1)]
+void CSDownsample(uint3 id : SV_DispatchThreadID)
+{
+    if (id.x >= destination_size.x || id.y >= destination_size.y)
+    {
+        return;
+    }
+
+    if (source_size.x == destination_size.x && source_size.y == destination_size.y)
+    {
+        destination[id.xy] = source.Load(int3(id.xy, 0));
+        return;
+    }
+
+    uint2 first = min(id.xy * 2, source_size - 1);
+    uint2 last = id.xy * 2 + 1;
+    if (id.x == destination_size.x - 1 && (source_size.x & 1) != 0)
+    {
+        last.x += 1;
+    }
+    if (id.y == destination_size.y - 1 && (source_size.y & 1) != 0)
+    {
+        last.y += 1;
+    }
+    last = min(last, source_size - 1);
+
+    float farthest = 0.0;
+    for (uint y = first.y; y <= last.y; ++y)
+    {
+        for (uint x = first.x; x <= last.x; ++x)
+        {
+            farthest = max(farthest, source.Load(int3(x, y, 0)));
+        }
+    }
+    destination[id.xy] = farthest;
+}
+
+// Level 0 from a multisampled depth buffer: the farthest of the samples, so
+// the pyramid never hides more than every sample of a pixel would.
+[numthreads(8, 8, 1)]
+void CSCopyMultisampled(uint3 id : SV_DispatchThreadID)
+{
+    if (id.x >= destination_size.x || id.y >= destination_size.y)
+    {
+        return;
+    }
+
+    uint width, height, sample_count;
+    multisampled_source.GetDimensions(width, height, sample_count);
+    float farthest = 0.0;
+    for (uint i = 0; i < sample_count; ++i)
+    {
+        farthest = max(farthest, multisampled_source.Load(int2(id.xy), i));
+    }
+    destination[id.xy] = farthest;
+}
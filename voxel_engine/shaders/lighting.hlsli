@@ -0,0 +1,30 @@
+// Time of day lighting shared by the main and the sky pass, laid out like
+// sky::LightingConstants.
+cbuffer LightingConstants : register(b2)
+{
+    // right and up are scaled to the edges of the screen.
+    float3 camera_forward;
+    float fog_start;
+    float3 camera_right;
+    float fog_end;
+    float3 camera_up;
+    float padding0;
+    // Towards the sun.
+    float3 sun_direction;
+    float padding1;
+    float3 sun_color;
+    float padding2;
+    float3 ambient;
+    float padding3;
+    float3 zenith;
+    float padding4;
+    float3 horizon;
+    float padding5;
+};
+
+// Block and sky colors are picked as sRGB, lighting happens in linear space.
+// The power curve is close enough for colors that are never shown unlit.
+float3 SrgbToLinear(float3 color)
+{
+    return pow(max(color, 0.0), 2.2);
+}
@@ -0,0 +1,146 @@
+// Full screen passes of the post processing chain, see post.rs for what
+// the parameters of each one mean.
+cbuffer PostConstants : register(b0)
+{
+    float2 texel_size;
+    float2 padding;
+    float4 parameters;
+};
+
+Texture2D<float4> input0 : register(t0);
+Texture2D<float4> input1 : register(t1);
+SamplerState linear_clamp : register(s0);
+
+struct PostInput
+{
+    float4 position : SV_POSITION;
+    float2 uv : TEXCOORD0;
+};
+
+PostInput VSPost(uint id : SV_VertexID)
+{
+    PostInput result;
+
+    float2 uv = float2((id << 1) & 2, id & 2);
+    result.position = float4(uv * float2(2.0, -2.0) + float2(-1.0, 1.0), 0.0, 1.0);
+    result.uv = uv;
+
+    return result;
+}
+
+float Luminance(float3 color)
+{
+    return dot(color, float3(0.2126, 0.7152, 0.0722));
+}
+
+// Half resolution: the four bilinear taps average a 4x4 block of the scene,
+// which keeps single bright pixels from flickering in the bloom.
+float4 PSBloomExtract(PostInput input) : SV_TARGET
+{
+    float threshold = parameters.x;
+    float knee = parameters.y;
+    float exposure = parameters.z;
+
+    float3 color = 0.0;
+    color += input0.SampleLevel(linear_clamp, input.uv + texel_size * float2(-1.0, -1.0), 0).rgb;
+    color += input0.SampleLevel(linear_clamp, input.uv + texel_size * float2(1.0, -1.0), 0).rgb;
+    color += input0.SampleLevel(linear_clamp, input.uv + texel_size * float2(-1.0, 1.0), 0).rgb;
+    color += input0.SampleLevel(linear_clamp, input.uv + texel_size * float2(1.0, 1.0), 0).rgb;
+    color *= 0.25 * exposure;
+
+    // Quadratic knee below the threshold, linear above it.
+    float brightness = max(color.r, max(color.g, color.b));
+    float soft = clamp(brightness - threshold + knee, 0.0, 2.0 * knee);
+    soft = soft * soft / (4.0 * knee + 1e-4);
+    float contribution = max(soft, brightness - threshold) / max(brightness, 1e-4);
+    return float4(color * contribution, 1.0);
+}
+
+// Nine tap gaussian in five bilinear taps along parameters.xy.
+float4 PSBlur(PostInput input) : SV_TARGET
+{
+    float2 step = parameters.xy * texel_size;
+    float3 color = input0.SampleLevel(linear_clamp, input.uv, 0).rgb * 0.2270270270;
+    color += input0.SampleLevel(linear_clamp, input.uv + step * 1.3846153846, 0).rgb * 0.3162162162;
+    color += input0.SampleLevel(linear_clamp, input.uv - step * 1.3846153846, 0).rgb * 0.3162162162;
+    color += input0.SampleLevel(linear_clamp, input.uv + step * 3.2307692308, 0).rgb * 0.0702702703;
+    color += input0.SampleLevel(linear_clamp, input.uv - step * 3.2307692308, 0).rgb * 0.0702702703;
+    return float4(color, 1.0);
+}
+
+float3 Tonemap(float3 color, float tonemapper)
+{
+    if (tonemapper < 0.5)
+    {
+        return saturate((color * (2.51 * color + 0.03)) / (color * (2.43 * color + 0.59) + 0.14));
+    }
+    return color / (1.0 + color);
+}
+
+// Writes linear color, the sRGB view of the target encodes it.
+float4 PSTonemap(PostInput input) : SV_TARGET
+{
+    float exposure = parameters.x;
+    float tonemapper = parameters.y;
+    float bloom_intensity = parameters.z;
+    float gamma = parameters.w;
+
+    float3 color = input0.SampleLevel(linear_clamp, input.uv, 0).rgb * exposure;
+    // Unbound without bloom, which reads as zero.
+    color += input1.SampleLevel(linear_clamp, input.uv, 0).rgb * bloom_intensity;
+    color = Tonemap(color, tonemapper);
+    color = pow(color, 2.2 / gamma);
+    return float4(color, 1.0);
+}
+
+static const float FXAA_SPAN_MAX = 8.0;
+static const float FXAA_REDUCE_MUL = 1.0 / 8.0;
+static const float FXAA_REDUCE_MIN = 1.0 / 128.0;
+
+// Luma of the sRGB encoded color, edges are found where they are seen.
+float FxaaLuma(float3 color)
+{
+    return sqrt(Luminance(color));
+}
+
+// FXAA in its original console form: blurs along the edge direction found
+// from the four diagonal neighbours, and falls back to the narrower blur
+// when the wide one picks up colors from across the edge.
+float4 PSFxaa(PostInput input) : SV_TARGET
+{
+    float2 uv = input.uv;
+    float3 rgb_nw = input0.SampleLevel(linear_clamp, uv + float2(-1.0, -1.0) * texel_size, 0).rgb;
+    float3 rgb_ne = input0.SampleLevel(linear_clamp, uv + float2(1.0, -1.0) * texel_size, 0).rgb;
+    float3 rgb_sw = input0.SampleLevel(linear_clamp, uv + float2(-1.0, 1.0) * texel_size, 0).rgb;
+    float3 rgb_se = input0.SampleLevel(linear_clamp, uv + float2(1.0, 1.0) * texel_size, 0).rgb;
+    float3 rgb_m = input0.SampleLevel(linear_clamp, uv, 0).rgb;
+
+    float luma_nw = FxaaLuma(rgb_nw);
+    float luma_ne = FxaaLuma(rgb_ne);
+    float luma_sw = FxaaLuma(rgb_sw);
+    float luma_se = FxaaLuma(rgb_se);
+    float luma_m = FxaaLuma(rgb_m);
+    float luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    float luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    float2 direction;
+    direction.x = -((luma_nw + luma_ne) - (luma_sw + luma_se));
+    direction.y = (luma_nw + luma_sw) - (luma_ne + luma_se);
+    float reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * FXAA_REDUCE_MUL, FXAA_REDUCE_MIN);
+    float scale = 1.0 / (min(abs(direction.x), abs(direction.y)) + reduce);
+    direction = clamp(direction * scale, -FXAA_SPAN_MAX, FXAA_SPAN_MAX) * texel_size;
+
+    float3 rgb_a = 0.5 * (
+        input0.SampleLevel(linear_clamp, uv + direction * (1.0 / 3.0 - 0.5), 0).rgb +
+        input0.SampleLevel(linear_clamp, uv + direction * (2.0 / 3.0 - 0.5), 0).rgb);
+    float3 rgb_b = rgb_a * 0.5 + 0.25 * (
+        input0.SampleLevel(linear_clamp, uv - direction * 0.5, 0).rgb +
+        input0.SampleLevel(linear_clamp, uv + direction * 0.5, 0).rgb);
+
+    float luma_b = FxaaLuma(rgb_b);
+    if (luma_b < luma_min || luma_b > luma_max)
+    {
+        return float4(rgb_a, 1.0);
+    }
+    return float4(rgb_b, 1.0);
+}
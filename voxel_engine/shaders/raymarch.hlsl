@@ -0,0 +1,194 @@
+cbuffer RayMarchConstants : register(b0)
+{
+    float3 camera_position;
+    uint width;
+    // right and up are scaled to the edges of the screen.
+    float3 camera_forward;
+    uint height;
+    float3 camera_right;
+    float max_distance;
+    float3 camera_up;
+    uint root;
+    int3 octree_origin;
+    uint octree_depth;
+    float4 sky_color;
+    uint palette_count;
+};
+
+// Encoded SparseVoxelOctree nodes: the block id of a uniform cube, or the
+// index of the first of eight children with the top bit set.
+StructuredBuffer<uint> nodes : register(t0);
+StructuredBuffer<float4> palette : register(t1);
+RWTexture2D<float4> output : register(u0);
+
+static const uint BRANCH_BIT = 0x80000000;
+
+struct Leaf
+{
+    uint block;
+    int3 min;
+    int size;
+};
+
+// Descends to the leaf holding a cell, in coordinates local to the octree.
+Leaf FindLeaf(int3 cell)
+{
+    Leaf leaf;
+    leaf.min = int3(0, 0, 0);
+    leaf.size = 1 << octree_depth;
+    uint node = root;
+    while ((node & BRANCH_BIT) != 0)
+    {
+        leaf.size /= 2;
+        uint child = 0;
+        for (uint axis = 0; axis < 3; ++axis)
+        {
+            if ((cell[axis] & leaf.size) != 0)
+            {
+                child |= 1u << axis;
+                leaf.min[axis] += leaf.size;
+            }
+        }
+        node = nodes[(node & ~BRANCH_BIT) + child];
+    }
+    leaf.block = node;
+    return leaf;
+}
+
+// Same as mesher::FACES.
+float FaceShade(int3 normal)
+{
+    if (normal.x != 0)
+    {
+        return 0.8;
+    }
+    if (normal.y > 0)
+    {
+        return 1.0;
+    }
+    if (normal.y < 0)
+    {
+        return 0.5;
+    }
+    if (normal.z != 0)
+    {
+        return 0.7;
+    }
+    return 1.0;
+}
+
+// Port of SparseVoxelOctree::raycast, returns false on a miss.
+bool Trace(float3 o, float3 d, out uint block, out int3 normal)
+{
+    block = 0;
+    normal = int3(0, 0, 0);
+    int size = 1 << octree_depth;
+    float3 low = float3(octree_origin);
+
+    float enter = 0.0;
+    float exit = max_distance;
+    int enter_axis = -1;
+    for (uint axis = 0; axis < 3; ++axis)
+    {
+        if (d[axis] == 0.0)
+        {
+            if (o[axis] < low[axis] || o[axis] >= low[axis] + size)
+            {
+                return false;
+            }
+            continue;
+        }
+        float t0 = (low[axis] - o[axis]) / d[axis];
+        float t1 = (low[axis] + size - o[axis]) / d[axis];
+        float nearest = min(t0, t1);
+        if (nearest > enter)
+        {
+            enter = nearest;
+            enter_axis = axis;
+        }
+        exit = min(exit, max(t0, t1));
+    }
+    if (enter > exit)
+    {
+        return false;
+    }
+
+    int3 cell = clamp(int3(floor(o + d * enter)) - octree_origin, 0, size - 1);
+    if (enter_axis >= 0)
+    {
+        cell[enter_axis] = d[enter_axis] > 0.0 ? 0 : size - 1;
+        normal[enter_axis] = d[enter_axis] > 0.0 ? -1 : 1;
+    }
+
+    // Every step leaves a leaf, so the loop is bounded by the leaves along
+    // the ray. The cap only guards against precision stalls.
+    for (uint i = 0; i < 4096; ++i)
+    {
+        Leaf leaf = FindLeaf(cell);
+        if (leaf.block != 0)
+        {
+            block = leaf.block;
+            return true;
+        }
+
+        float leave = asfloat(0x7f800000);
+        uint leave_axis = 0;
+        for (uint axis = 0; axis < 3; ++axis)
+        {
+            if (d[axis] == 0.0)
+            {
+                continue;
+            }
+            int face = d[axis] > 0.0 ? leaf.min[axis] + leaf.size : leaf.min[axis];
+            float t = (float(face + octree_origin[axis]) - o[axis]) / d[axis];
+            if (t < leave)
+            {
+                leave = t;
+                leave_axis = axis;
+            }
+        }
+        if (leave > max_distance)
+        {
+            return false;
+        }
+
+        int3 next = clamp(
+            int3(floor(o + d * leave)) - octree_origin,
+            leaf.min,
+            leaf.min + leaf.size - 1);
+        next[leave_axis] = d[leave_axis] > 0.0 ? leaf.min[leave_axis] + leaf.size : leaf.min[leave_axis] - 1;
+        cell = next;
+        normal = int3(0, 0, 0);
+        normal[leave_axis] = d[leave_axis] > 0.0 ? -1 : 1;
+        if (any(cell < 0) || any(cell >= size))
+        {
+            return false;
+        }
+    }
+    return false;
+}
+
+// One ray per pixel, see raymarch::trace_pixel for the CPU reference.
+[numthreads(8, 8, 1)]
+void CSMain(uint3 id : SV_DispatchThreadID)
+{
+    if (id.x >= width || id.y >= height)
+    {
+        return;
+    }
+
+    float2 ndc = (float2(id.xy) + 0.5) / float2(width, height) * 2.0 - 1.0;
+    ndc.y = -ndc.y;
+    float3 direction = normalize(camera_forward + camera_right * ndc.x + camera_up * ndc.y);
+
+    uint block;
+    int3 normal;
+    if (!Trace(camera_position, direction, block, normal))
+    {
+        output[id.xy] = sky_color;
+        return;
+    }
+
+    float4 color = block < palette_count ? palette[block] : float4(1.0, 0.0, 1.0, 1.0);
+    output[id.xy] = float4(color.rgb * FaceShade(normal), color.a);
+}
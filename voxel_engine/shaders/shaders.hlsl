@@ -1,21 +1,125 @@
+#include "lighting.hlsli"
+
+cbuffer SceneConstants : register(b0)
+{
+    row_major float4x4 view_projection;
+    // Seconds, animates the water.
+    float time;
+};
+
+// Water surface waves, in blocks. The surface only ever sinks, so it never
+// pokes through the block above or separates from the banks.
+static const float WAVE_AMPLITUDE = 0.06;
+static const float WAVE_SPEED = 1.7;
+
+static const uint CASCADE_COUNT = 4;
+
+cbuffer ShadowConstants : register(b1)
+{
+    row_major float4x4 light_view_projections[CASCADE_COUNT];
+    float4 cascade_splits;
+    float4 cascade_texel_sizes;
+    float3 camera_position;
+    float shadow_map_texel;
+};
+
+Texture2DArray<float> shadow_map : register(t0);
+SamplerComparisonState shadow_sampler : register(s0);
+
 struct PSInput
 {
     float4 position : SV_POSITION;
     float4 color : COLOR;
+    float3 world_position : TEXCOORD0;
+    float view_depth : TEXCOORD1;
 };
 
-PSInput VSMain(float4 position : POSITION, float4 color : COLOR)
+// Two crossing waves, the same function of the world position for every
+// vertex so that neighbouring faces stay joined.
+float WaveOffset(float3 position)
+{
+    float wave = sin(time * WAVE_SPEED + position.x * 0.9 + position.z * 0.4)
+        + sin(time * WAVE_SPEED * 1.3 - position.z * 1.1 + position.x * 0.3);
+    return (wave * 0.25 - 0.5) * WAVE_AMPLITUDE * 2.0;
+}
+
+// Also the vertex shader of the shadow pass, with a light view projection
+// in the root constants.
+PSInput VSMain(float4 position : POSITION, float4 color : COLOR, float wave : WAVE)
 {
     PSInput result;
 
-    result.position = position;
-    result.color = color;
+    position.y += wave * WaveOffset(position.xyz);
+    result.position = mul(view_projection, float4(position.xyz, 1.0));
+    result.color = float4(SrgbToLinear(color.rgb), color.a);
+    result.world_position = position.xyz;
+    result.view_depth = result.position.w;
 
     return result;
 }
 
-float4 PSMain(PSInput input) : SV_TARGET
+// 3x3 PCF, 1 in full light and 0 in full shadow.
+float SampleShadow(uint cascade, float3 world_position)
+{
+    float4 light = mul(light_view_projections[cascade], float4(world_position, 1.0));
+    float2 uv = light.xy * float2(0.5, -0.5) + 0.5;
+    float depth = light.z;
+    if (depth > 1.0)
+    {
+        return 1.0;
+    }
+
+    float visibility = 0.0;
+    for (int y = -1; y <= 1; ++y)
+    {
+        for (int x = -1; x <= 1; ++x)
+        {
+            float2 offset = float2(x, y) * shadow_map_texel;
+            visibility += shadow_map.SampleCmpLevelZero(
+                shadow_sampler,
+                float3(uv + offset, cascade),
+                depth);
+        }
+    }
+    return visibility / 9.0;
+}
+
+float ShadowVisibility(PSInput input)
 {
-    return input.color;
+    uint cascade = 0;
+    while (cascade < CASCADE_COUNT && input.view_depth > cascade_splits[cascade])
+    {
+        ++cascade;
+    }
+    if (cascade == CASCADE_COUNT)
+    {
+        return 1.0;
+    }
+
+    // Block faces are flat, the screen space derivatives give their normal.
+    float3 normal = normalize(cross(ddx(input.world_position), ddy(input.world_position)));
+    if (dot(normal, camera_position - input.world_position) < 0.0)
+    {
+        normal = -normal;
+    }
+    if (dot(normal, sun_direction) <= 0.0)
+    {
+        // Facing away from the sun.
+        return 0.0;
+    }
+
+    // Pushing the lookup off the surface by about a texel keeps the face
+    // from shadowing itself.
+    float3 offset = normal * cascade_texel_sizes[cascade] * 1.5;
+    return SampleShadow(cascade, input.world_position + offset);
 }
 
+float4 PSMain(PSInput input) : SV_TARGET
+{
+    float3 light = ambient + sun_color * ShadowVisibility(input);
+    float3 color = input.color.rgb * light;
+
+    // Fades into the horizon color of the sky before the streaming edge.
+    float fog = saturate((distance(input.world_position, camera_position) - fog_start) / (fog_end - fog_start));
+    return float4(lerp(color, SrgbToLinear(horizon), fog), input.color.a);
+}
@@ -0,0 +1,36 @@
+#include "lighting.hlsli"
+
+struct SkyInput
+{
+    float4 position : SV_POSITION;
+    float2 ndc : TEXCOORD0;
+};
+
+// One triangle covering the screen, on the far plane so that it only fills
+// what the chunks left empty.
+SkyInput VSSky(uint id : SV_VertexID)
+{
+    SkyInput result;
+
+    float2 ndc = float2((id << 1) & 2, id & 2) * 2.0 - 1.0;
+    result.position = float4(ndc, 1.0, 1.0);
+    result.ndc = ndc;
+
+    return result;
+}
+
+float4 PSSky(SkyInput input) : SV_TARGET
+{
+    float3 direction = normalize(camera_forward + camera_right * input.ndc.x + camera_up * input.ndc.y);
+
+    // Below the horizon the sky stays at the fog color.
+    float3 color = SrgbToLinear(lerp(horizon, zenith, sqrt(saturate(direction.y))));
+
+    float sun = saturate(dot(direction, sun_direction));
+    float disc = smoothstep(0.9995, 0.9998, sun);
+    float glow = pow(sun, 64.0) * 0.5;
+    // Far brighter than anything lit, so the disc blooms.
+    color += sun_color * (disc * 16.0 + glow);
+
+    return float4(color, 1.0);
+}
@@ -0,0 +1,38 @@
+#include "lighting.hlsli"
+
+// The window size in points, the unit egui lays out in.
+cbuffer UiConstants : register(b0)
+{
+    float2 screen_size;
+};
+
+Texture2D ui_texture : register(t0);
+SamplerState ui_sampler : register(s0);
+
+struct UiInput
+{
+    float4 position : SV_POSITION;
+    float2 uv : TEXCOORD;
+    float4 color : COLOR;
+};
+
+UiInput VSUi(float2 position : POSITION, float2 uv : TEXCOORD, float4 color : COLOR)
+{
+    UiInput result;
+
+    result.position = float4(
+        position.x / screen_size.x * 2.0 - 1.0,
+        1.0 - position.y / screen_size.y * 2.0,
+        0.0,
+        1.0);
+    result.uv = uv;
+    // Premultiplied sRGB, the texture is sampled as linear already.
+    result.color = float4(SrgbToLinear(color.rgb), color.a);
+
+    return result;
+}
+
+float4 PSUi(UiInput input) : SV_TARGET
+{
+    return input.color * ui_texture.Sample(ui_sampler, input.uv);
+}
@@ -0,0 +1,356 @@
+use std::fmt;
+use std::str::FromStr;
+
+use backend::DrawSubmission;
+
+// Which adapter the device is created on, picked from the command line with
+// `--adapter <index|name|vram|warp>` or `--warp`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum AdapterPreference {
+    // The hardware adapter with the most dedicated video memory.
+    #[default]
+    HighestVram,
+    // Position in DXGI's enumeration order.
+    Index(u32),
+    // The first adapter whose description contains this, ignoring case.
+    Name(String),
+    // The WARP software rasterizer.
+    Warp,
+}
+
+impl FromStr for AdapterPreference {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Err("empty adapter selection".to_string());
+        }
+        Ok(match value.to_ascii_lowercase().as_str() {
+            "warp" => AdapterPreference::Warp,
+            "vram" | "highest-vram" => AdapterPreference::HighestVram,
+            _ => match value.parse() {
+                Ok(index) => AdapterPreference::Index(index),
+                Err(_) => AdapterPreference::Name(value.to_string()),
+            },
+        })
+    }
+}
+
+// The preference the command line asks for, None when it does not mention
+// one. Arguments that are not about the adapter are left alone.
+pub fn adapter_preference_from_args(args: &[String]) -> Result<Option<AdapterPreference>, String> {
+    let mut preference = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--warp" | "/warp" => preference = Some(AdapterPreference::Warp),
+            "--adapter" => match args.next() {
+                Some(value) => preference = Some(value.parse()?),
+                None => return Err("--adapter needs an index, a name, vram or warp".to_string()),
+            },
+            _ => (),
+        }
+    }
+    Ok(preference)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AdapterInfo {
+    // Position in DXGI's enumeration order.
+    pub index: u32,
+    pub name: String,
+    pub dedicated_video_memory: u64,
+    pub software: bool,
+    // Whether a device with the minimum feature level can be created on it.
+    pub supports_d3d12: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AdapterChoice {
+    // Index into the enumerated adapters.
+    Adapter(usize),
+    Warp,
+}
+
+// Applies `preference` to the enumerated adapters. Asking for the highest
+// VRAM falls back to WARP when there is no hardware adapter, a specific
+// index or name that does not fit any adapter is an error.
+pub fn choose_adapter(adapters: &[AdapterInfo], preference: &AdapterPreference) -> Result<AdapterChoice, String> {
+    match preference {
+        AdapterPreference::Warp => Ok(AdapterChoice::Warp),
+        AdapterPreference::HighestVram => {
+            let mut best: Option<usize> = None;
+            for (i, adapter) in adapters.iter().enumerate() {
+                if !adapter.supports_d3d12 || adapter.software {
+                    continue;
+                }
+                if best.is_none_or(|best| adapter.dedicated_video_memory > adapters[best].dedicated_video_memory) {
+                    best = Some(i);
+                }
+            }
+            Ok(best.map_or(AdapterChoice::Warp, AdapterChoice::Adapter))
+        }
+        AdapterPreference::Index(index) => adapters
+            .iter()
+            .position(|adapter| adapter.supports_d3d12 && adapter.index == *index)
+            .map(AdapterChoice::Adapter)
+            .ok_or_else(|| format!("no adapter {} that supports Direct3D 12", index)),
+        AdapterPreference::Name(name) => {
+            let needle = name.to_lowercase();
+            adapters
+                .iter()
+                .position(|adapter| adapter.supports_d3d12 && adapter.name.to_lowercase().contains(&needle))
+                .map(AdapterChoice::Adapter)
+                .ok_or_else(|| format!("no adapter named like \"{}\" that supports Direct3D 12", name))
+        }
+    }
+}
+
+// A D3D_FEATURE_LEVEL value, 0xc100 for 12_1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FeatureLevel(pub u32);
+
+impl fmt::Display for FeatureLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}_{}", self.0 >> 12, (self.0 >> 8) & 0xf)
+    }
+}
+
+// A D3D_SHADER_MODEL value, 0x60 for 6.0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ShaderModel(pub u32);
+
+impl fmt::Display for ShaderModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.0 >> 4, self.0 & 0xf)
+    }
+}
+
+// What the device was found to support. Tiers are the values D3D12
+// reports, 0 where the feature is missing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceCapabilities {
+    pub adapter: AdapterInfo,
+    pub feature_level: FeatureLevel,
+    pub shader_model: ShaderModel,
+    pub resource_binding_tier: u32,
+    pub mesh_shader_tier: u32,
+    pub raytracing_tier: u32,
+}
+
+impl DeviceCapabilities {
+    // Every shader is built for shader model 6.0, see build.rs.
+    pub const MIN_SHADER_MODEL: ShaderModel = ShaderModel(0x60);
+
+    pub fn check(&self) -> Result<(), String> {
+        if self.shader_model < Self::MIN_SHADER_MODEL {
+            return Err(format!(
+                "{} supports shader model {}, at least {} is needed",
+                self.adapter.name,
+                self.shader_model,
+                Self::MIN_SHADER_MODEL
+            ));
+        }
+        Ok(())
+    }
+
+    // WARP runs compute shaders on the CPU, where culling on the GPU and
+    // reading the count back only costs time.
+    pub fn preferred_draw_submission(&self) -> DrawSubmission {
+        if self.adapter.software {
+            DrawSubmission::Cpu
+        } else {
+            DrawSubmission::GpuIndirect
+        }
+    }
+}
+
+impl fmt::Display for DeviceCapabilities {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let tier = |tier: u32| match tier {
+            0 => "not supported".to_string(),
+            // Mesh shader and raytracing tiers count in tenths.
+            tier if tier >= 10 => format!("tier {}.{}", tier / 10, tier % 10),
+            tier => format!("tier {}", tier),
+        };
+        writeln!(
+            f,
+            "adapter {}: {}{}, {} MiB dedicated video memory",
+            self.adapter.index,
+            self.adapter.name,
+            if self.adapter.software { " (software)" } else { "" },
+            self.adapter.dedicated_video_memory / (1024 * 1024)
+        )?;
+        writeln!(f, "feature level {}", self.feature_level)?;
+        writeln!(f, "shader model {}", self.shader_model)?;
+        writeln!(f, "resource binding {}", tier(self.resource_binding_tier))?;
+        writeln!(f, "mesh shaders {}", tier(self.mesh_shader_tier))?;
+        write!(f, "raytracing {}", tier(self.raytracing_tier))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn adapter(index: u32, name: &str, dedicated_video_memory: u64) -> AdapterInfo {
+        AdapterInfo {
+            index,
+            name: name.to_string(),
+            dedicated_video_memory,
+            software: false,
+            supports_d3d12: true,
+        }
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn capabilities(software: bool, shader_model: u32) -> DeviceCapabilities {
+        DeviceCapabilities {
+            adapter: AdapterInfo {
+                software,
+                ..adapter(1, "Radeon", 8 << 30)
+            },
+            feature_level: FeatureLevel(0xc100),
+            shader_model: ShaderModel(shader_model),
+            resource_binding_tier: 3,
+            mesh_shader_tier: 10,
+            raytracing_tier: 11,
+        }
+    }
+
+    #[test]
+    fn preferences_parse() {
+        assert_eq!("2".parse(), Ok(AdapterPreference::Index(2)));
+        assert_eq!(" GeForce ".parse(), Ok(AdapterPreference::Name("GeForce".into())));
+        assert_eq!("VRAM".parse(), Ok(AdapterPreference::HighestVram));
+        assert_eq!("highest-vram".parse(), Ok(AdapterPreference::HighestVram));
+        assert_eq!("Warp".parse(), Ok(AdapterPreference::Warp));
+        assert_eq!("-1".parse(), Ok(AdapterPreference::Name("-1".into())));
+        assert_eq!(" ".parse::<AdapterPreference>(), Err("empty adapter selection".to_string()));
+    }
+
+    #[test]
+    fn preferences_come_from_args() {
+        assert_eq!(adapter_preference_from_args(&args(&["--fullscreen"])), Ok(None));
+        assert_eq!(adapter_preference_from_args(&args(&["--warp"])), Ok(Some(AdapterPreference::Warp)));
+        assert_eq!(adapter_preference_from_args(&args(&["/warp"])), Ok(Some(AdapterPreference::Warp)));
+        // The last one wins.
+        assert_eq!(
+            adapter_preference_from_args(&args(&["--warp", "--adapter", "1", "--vsync"])),
+            Ok(Some(AdapterPreference::Index(1)))
+        );
+        assert_eq!(
+            adapter_preference_from_args(&args(&["--adapter"])),
+            Err("--adapter needs an index, a name, vram or warp".to_string())
+        );
+        assert_eq!(
+            adapter_preference_from_args(&args(&["--adapter", ""])),
+            Err("empty adapter selection".to_string())
+        );
+    }
+
+    #[test]
+    fn highest_vram_skips_software_and_unsupported_adapters() {
+        let adapters = [
+            adapter(0, "Intel UHD", 128 << 20),
+            AdapterInfo {
+                supports_d3d12: false,
+                ..adapter(1, "Old GeForce", 16 << 30)
+            },
+            adapter(2, "Radeon", 8 << 30),
+            AdapterInfo {
+                software: true,
+                ..adapter(3, "Microsoft Basic Render Driver", 32 << 30)
+            },
+        ];
+        assert_eq!(
+            choose_adapter(&adapters, &AdapterPreference::HighestVram),
+            Ok(AdapterChoice::Adapter(2))
+        );
+        assert_eq!(choose_adapter(&adapters, &AdapterPreference::Warp), Ok(AdapterChoice::Warp));
+    }
+
+    #[test]
+    fn highest_vram_falls_back_to_warp() {
+        let adapters = [AdapterInfo {
+            software: true,
+            ..adapter(0, "Microsoft Basic Render Driver", 0)
+        }];
+        assert_eq!(
+            choose_adapter(&adapters, &AdapterPreference::HighestVram),
+            Ok(AdapterChoice::Warp)
+        );
+        assert_eq!(choose_adapter(&[], &AdapterPreference::HighestVram), Ok(AdapterChoice::Warp));
+    }
+
+    #[test]
+    fn specific_adapters_must_exist() {
+        let adapters = [
+            adapter(0, "Intel UHD", 128 << 20),
+            AdapterInfo {
+                supports_d3d12: false,
+                ..adapter(1, "Old GeForce", 16 << 30)
+            },
+            adapter(2, "AMD Radeon", 8 << 30),
+        ];
+        assert_eq!(choose_adapter(&adapters, &AdapterPreference::Index(2)), Ok(AdapterChoice::Adapter(2)));
+        assert_eq!(
+            choose_adapter(&adapters, &AdapterPreference::Name("radeon".into())),
+            Ok(AdapterChoice::Adapter(2))
+        );
+        assert_eq!(
+            choose_adapter(&adapters, &AdapterPreference::Index(1)),
+            Err("no adapter 1 that supports Direct3D 12".to_string())
+        );
+        assert_eq!(
+            choose_adapter(&adapters, &AdapterPreference::Index(3)),
+            Err("no adapter 3 that supports Direct3D 12".to_string())
+        );
+        assert_eq!(
+            choose_adapter(&adapters, &AdapterPreference::Name("GeForce".into())),
+            Err("no adapter named like \"GeForce\" that supports Direct3D 12".to_string())
+        );
+    }
+
+    #[test]
+    fn versions_display() {
+        assert_eq!(FeatureLevel(0xb000).to_string(), "11_0");
+        assert_eq!(FeatureLevel(0xc100).to_string(), "12_1");
+        assert_eq!(ShaderModel(0x60).to_string(), "6.0");
+        assert_eq!(ShaderModel(0x65).to_string(), "6.5");
+    }
+
+    #[test]
+    fn capabilities_display() {
+        assert_eq!(
+            capabilities(false, 0x66).to_string(),
+            "adapter 1: Radeon, 8192 MiB dedicated video memory\n\
+             feature level 12_1\n\
+             shader model 6.6\n\
+             resource binding tier 3\n\
+             mesh shaders tier 1.0\n\
+             raytracing tier 1.1"
+        );
+        let warp = DeviceCapabilities {
+            mesh_shader_tier: 0,
+            ..capabilities(true, 0x60)
+        };
+        assert!(warp.to_string().starts_with("adapter 1: Radeon (software), "));
+        assert!(warp.to_string().contains("\nmesh shaders not supported\n"));
+    }
+
+    #[test]
+    fn capabilities_are_checked() {
+        assert_eq!(capabilities(false, 0x60).check(), Ok(()));
+        assert_eq!(
+            capabilities(false, 0x51).check(),
+            Err("Radeon supports shader model 5.1, at least 6.0 is needed".to_string())
+        );
+        assert_eq!(capabilities(false, 0x60).preferred_draw_submission(), DrawSubmission::GpuIndirect);
+        assert_eq!(capabilities(true, 0x60).preferred_draw_submission(), DrawSubmission::Cpu);
+    }
+}
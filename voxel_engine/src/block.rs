@@ -0,0 +1,121 @@
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(pub u16);
+
+impl BlockId {
+    pub const AIR: BlockId = BlockId(0);
+
+    pub fn is_air(self) -> bool {
+        self == BlockId::AIR
+    }
+}
+
+pub const STONE: BlockId = BlockId(1);
+pub const DIRT: BlockId = BlockId(2);
+pub const GRASS: BlockId = BlockId(3);
+pub const SAND: BlockId = BlockId(4);
+pub const WATER: BlockId = BlockId(5);
+pub const GLASS: BlockId = BlockId(6);
+
+// Drawn for ids the registry does not know.
+pub const MISSING_COLOR: [f32; 4] = [1.0, 0.0, 1.0, 1.0];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockDefinition {
+    pub name: String,
+    // Alpha below 1 puts the block's faces in the translucent layer.
+    pub color: [f32; 4],
+    // Surface waves in the vertex shader.
+    pub liquid: bool,
+}
+
+impl BlockDefinition {
+    pub fn is_translucent(&self) -> bool {
+        self.color[3] < 1.0
+    }
+}
+
+// Block ids index into the registry, air is always id 0.
+pub struct BlockRegistry {
+    definitions: Vec<BlockDefinition>,
+}
+
+impl BlockRegistry {
+    pub fn new() -> Self {
+        BlockRegistry {
+            definitions: vec![BlockDefinition {
+                name: "air".into(),
+                color: [0.0, 0.0, 0.0, 0.0],
+                liquid: false,
+            }],
+        }
+    }
+
+    pub fn register(&mut self, name: &str, color: [f32; 4]) -> BlockId {
+        self.push(BlockDefinition {
+            name: name.into(),
+            color,
+            liquid: false,
+        })
+    }
+
+    pub fn register_liquid(&mut self, name: &str, color: [f32; 4]) -> BlockId {
+        self.push(BlockDefinition {
+            name: name.into(),
+            color,
+            liquid: true,
+        })
+    }
+
+    fn push(&mut self, definition: BlockDefinition) -> BlockId {
+        let id = BlockId(self.definitions.len() as u16);
+        self.definitions.push(definition);
+        id
+    }
+
+    pub fn get(&self, id: BlockId) -> Option<&BlockDefinition> {
+        self.definitions.get(id.0 as usize)
+    }
+
+    // Every block but air.
+    pub fn blocks(&self) -> impl Iterator<Item = (BlockId, &BlockDefinition)> {
+        self.definitions
+            .iter()
+            .enumerate()
+            .skip(1)
+            .map(|(index, definition)| (BlockId(index as u16), definition))
+    }
+
+    pub fn color(&self, id: BlockId) -> [f32; 4] {
+        self.get(id).map_or(MISSING_COLOR, |definition| definition.color)
+    }
+
+    // Unknown ids are drawn opaque in MISSING_COLOR.
+    pub fn is_translucent(&self, id: BlockId) -> bool {
+        self.get(id).is_some_and(BlockDefinition::is_translucent)
+    }
+
+    pub fn is_liquid(&self, id: BlockId) -> bool {
+        self.get(id).is_some_and(|definition| definition.liquid)
+    }
+
+    // Color of every block, indexed by id.
+    pub fn colors(&self) -> Vec<[f32; 4]> {
+        self.definitions
+            .iter()
+            .map(|definition| definition.color)
+            .collect()
+    }
+}
+
+impl Default for BlockRegistry {
+    fn default() -> Self {
+        let mut registry = BlockRegistry::new();
+        registry.register("stone", [0.5, 0.5, 0.5, 1.0]);
+        registry.register("dirt", [0.45, 0.3, 0.15, 1.0]);
+        registry.register("grass", [0.3, 0.6, 0.2, 1.0]);
+        registry.register("sand", [0.85, 0.8, 0.55, 1.0]);
+        registry.register_liquid("water", [0.15, 0.35, 0.75, 0.6]);
+        registry.register("glass", [0.8, 0.9, 0.95, 0.3]);
+        registry
+    }
+}
@@ -0,0 +1,65 @@
+use crate::math::{Mat4, Vec3};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera {
+    pub position: Vec3,
+    // Radians, yaw 0 looks down +z, positive pitch looks up.
+    pub yaw: f32,
+    pub pitch: f32,
+    pub fov_y: f32,
+    pub near: f32,
+    pub far: f32,
+}
+
+impl Camera {
+    pub fn forward(&self) -> Vec3 {
+        Vec3::new(
+            self.pitch.cos() * self.yaw.sin(),
+            self.pitch.sin(),
+            self.pitch.cos() * self.yaw.cos(),
+        )
+    }
+
+    // Forward, right and up, the latter two scaled so that
+    // forward + right * x + up * y points through (x, y) in normalized
+    // device coordinates.
+    pub fn screen_axes(&self, aspect_ratio: f32) -> (Vec3, Vec3, Vec3) {
+        let forward = self.forward();
+        let right = Vec3::new(0.0, 1.0, 0.0).cross(forward).normalize();
+        let up = forward.cross(right);
+        let half_height = (self.fov_y * 0.5).tan();
+        (forward, right * (half_height * aspect_ratio), up * half_height)
+    }
+
+    // Direction of the ray through a point on screen, uv in [0, 1] with y
+    // pointing down.
+    pub fn ray_direction(&self, uv: [f32; 2], aspect_ratio: f32) -> Vec3 {
+        let (forward, right, up) = self.screen_axes(aspect_ratio);
+        (forward + right * (uv[0] * 2.0 - 1.0) + up * (1.0 - uv[1] * 2.0)).normalize()
+    }
+
+    pub fn view(&self) -> Mat4 {
+        Mat4::look_to_lh(self.position, self.forward(), Vec3::new(0.0, 1.0, 0.0))
+    }
+
+    pub fn projection(&self, aspect_ratio: f32) -> Mat4 {
+        Mat4::perspective_lh(self.fov_y, aspect_ratio, self.near, self.far)
+    }
+
+    pub fn view_projection(&self, aspect_ratio: f32) -> Mat4 {
+        self.projection(aspect_ratio) * self.view()
+    }
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Camera {
+            position: Vec3::new(0.0, 72.0, -48.0),
+            yaw: 0.0,
+            pitch: -0.35,
+            fov_y: 60.0_f32.to_radians(),
+            near: 0.1,
+            far: 1000.0,
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use std::ffi::c_void;
+
+use windows::{
+    core::*, Win32::Graphics::Dxgi::*, Win32::System::LibraryLoader::*,
+};
+
+// eRENDERDOC_API_Version_1_1_2, the layout RenderDocApi follows.
+const RENDERDOC_API_VERSION: i32 = 10102;
+
+// The start of RENDERDOC_API_1_1_2, a table of cdecl function pointers.
+#[repr(C)]
+pub struct RenderDocApi {
+    // GetAPIVersion through SetActiveWindow.
+    unused: [usize; 19],
+    start_frame_capture: unsafe extern "C" fn(device: *mut c_void, window: *mut c_void),
+    is_frame_capturing: unsafe extern "C" fn() -> u32,
+    end_frame_capture: unsafe extern "C" fn(device: *mut c_void, window: *mut c_void) -> u32,
+}
+
+type GetApi = unsafe extern "C" fn(version: i32, api: *mut *mut c_void) -> i32;
+
+// The frame debugger the process runs under, if any. RenderDoc is found by
+// its injected module, PIX and the Visual Studio graphics debugger through
+// the DXGI graphics analysis interface they install.
+pub enum CaptureTool {
+    RenderDoc(&'static RenderDocApi),
+    GraphicsAnalysis(IDXGraphicsAnalysis),
+}
+
+impl CaptureTool {
+    pub fn attached() -> Option<Self> {
+        render_doc()
+            .map(CaptureTool::RenderDoc)
+            .or_else(|| unsafe { DXGIGetDebugInterface1(0) }.ok().map(CaptureTool::GraphicsAnalysis))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CaptureTool::RenderDoc(_) => "RenderDoc",
+            CaptureTool::GraphicsAnalysis(_) => "PIX",
+        }
+    }
+
+    // Captures everything submitted until `end`, which follows the present.
+    // Null device and window let RenderDoc pick the only ones there are.
+    pub fn begin(&self) {
+        match self {
+            CaptureTool::RenderDoc(api) => unsafe {
+                if (api.is_frame_capturing)() == 0 {
+                    (api.start_frame_capture)(std::ptr::null_mut(), std::ptr::null_mut());
+                }
+            },
+            CaptureTool::GraphicsAnalysis(analysis) => unsafe { analysis.BeginCapture() },
+        }
+    }
+
+    // Whether the capture was written.
+    pub fn end(&self) -> bool {
+        match self {
+            CaptureTool::RenderDoc(api) => unsafe {
+                (api.end_frame_capture)(std::ptr::null_mut(), std::ptr::null_mut()) != 0
+            },
+            CaptureTool::GraphicsAnalysis(analysis) => {
+                unsafe { analysis.EndCapture() };
+                true
+            }
+        }
+    }
+}
+
+fn render_doc() -> Option<&'static RenderDocApi> {
+    let module = unsafe { GetModuleHandleA(s!("renderdoc.dll")) }.ok()?;
+    let get_api = unsafe { GetProcAddress(module, s!("RENDERDOC_GetAPI")) }?;
+    let get_api: GetApi = unsafe { std::mem::transmute(get_api) };
+    let mut api = std::ptr::null_mut();
+    if unsafe { get_api(RENDERDOC_API_VERSION, &mut api) } != 1 {
+        return None;
+    }
+    // The table lives as long as the module, which is never unloaded.
+    unsafe { (api as *const RenderDocApi).as_ref() }
+}
@@ -0,0 +1,136 @@
+use crate::block::BlockId;
+use crate::math::{Aabb, Vec3};
+
+pub const CHUNK_SIZE: usize = 32;
+pub const CHUNK_VOLUME: usize = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
+
+// Position of a chunk in the chunk grid, in chunks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ChunkPos {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+}
+
+impl ChunkPos {
+    pub const fn new(x: i32, y: i32, z: i32) -> Self {
+        ChunkPos { x, y, z }
+    }
+
+    // Chunk containing a block given in world block coordinates.
+    pub fn from_block(block: [i32; 3]) -> Self {
+        let size = CHUNK_SIZE as i32;
+        ChunkPos::new(
+            block[0].div_euclid(size),
+            block[1].div_euclid(size),
+            block[2].div_euclid(size),
+        )
+    }
+
+    pub fn from_world(position: Vec3) -> Self {
+        ChunkPos::from_block([
+            position.x.floor() as i32,
+            position.y.floor() as i32,
+            position.z.floor() as i32,
+        ])
+    }
+
+    // World block coordinates of the chunk's minimum corner.
+    pub fn origin(&self) -> [i32; 3] {
+        let size = CHUNK_SIZE as i32;
+        [self.x * size, self.y * size, self.z * size]
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        let [x, y, z] = self.origin();
+        let min = Vec3::new(x as f32, y as f32, z as f32);
+        Aabb::new(min, min + Vec3::new(1.0, 1.0, 1.0) * CHUNK_SIZE as f32)
+    }
+
+    pub fn offset(&self, dx: i32, dy: i32, dz: i32) -> ChunkPos {
+        ChunkPos::new(self.x + dx, self.y + dy, self.z + dz)
+    }
+}
+
+// Local coordinates of a block inside its chunk.
+pub fn local_coordinates(block: [i32; 3]) -> [usize; 3] {
+    let size = CHUNK_SIZE as i32;
+    [
+        block[0].rem_euclid(size) as usize,
+        block[1].rem_euclid(size) as usize,
+        block[2].rem_euclid(size) as usize,
+    ]
+}
+
+// Chunks whose mesh depends on a block: its own chunk, plus the neighbour
+// across every chunk face the block lies on, since their border faces are
+// culled against it.
+pub fn chunks_touching(block: [i32; 3]) -> Vec<ChunkPos> {
+    let pos = ChunkPos::from_block(block);
+    let local = local_coordinates(block);
+    let mut chunks = vec![pos];
+    for axis in 0..3 {
+        let mut offset = [0; 3];
+        if local[axis] == 0 {
+            offset[axis] = -1;
+        } else if local[axis] == CHUNK_SIZE - 1 {
+            offset[axis] = 1;
+        } else {
+            continue;
+        }
+        chunks.push(pos.offset(offset[0], offset[1], offset[2]));
+    }
+    chunks
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chunk {
+    blocks: Box<[BlockId]>,
+}
+
+impl Chunk {
+    pub fn new() -> Self {
+        Chunk::filled(BlockId::AIR)
+    }
+
+    pub fn filled(block: BlockId) -> Self {
+        Chunk {
+            blocks: vec![block; CHUNK_VOLUME].into_boxed_slice(),
+        }
+    }
+
+    // Takes blocks in the order of Chunk::index.
+    pub fn from_blocks(blocks: Vec<BlockId>) -> Self {
+        assert_eq!(blocks.len(), CHUNK_VOLUME);
+        Chunk {
+            blocks: blocks.into_boxed_slice(),
+        }
+    }
+
+    // x fastest, then z, then y, so horizontal slices are contiguous.
+    pub fn index(x: usize, y: usize, z: usize) -> usize {
+        x + z * CHUNK_SIZE + y * CHUNK_SIZE * CHUNK_SIZE
+    }
+
+    pub fn get(&self, x: usize, y: usize, z: usize) -> BlockId {
+        self.blocks[Chunk::index(x, y, z)]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, z: usize, block: BlockId) {
+        self.blocks[Chunk::index(x, y, z)] = block;
+    }
+
+    pub fn blocks(&self) -> &[BlockId] {
+        &self.blocks
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.iter().all(|block| block.is_air())
+    }
+}
+
+impl Default for Chunk {
+    fn default() -> Self {
+        Chunk::new()
+    }
+}
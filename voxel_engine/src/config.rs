@@ -0,0 +1,652 @@
+use std::fs;
+use std::io;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use backend::PresentMode;
+use log::LevelFilter;
+
+use crate::adapter::{adapter_preference_from_args, AdapterPreference};
+use crate::error::{Context, EngineError, EngineResult};
+use crate::msaa::MsaaSettings;
+use crate::streaming::{StreamingSettings, VIEW_DISTANCES};
+
+// Read when the command line names no other file with `--config <path>`.
+// Running without it is fine, everything has a default.
+pub const DEFAULT_CONFIG_PATH: &str = "engine.toml";
+
+// The largest texture side D3D12 allows, which bounds the back buffers.
+const MAX_RESOLUTION: u32 = 16384;
+
+// Back buffers of the swap chain. The flip model needs at least two, more
+// than four only adds latency.
+pub const FRAMES_IN_FLIGHT: RangeInclusive<u32> = 2..=4;
+
+// Presented frames that may queue up before the CPU waits, DXGI allows 16.
+pub const FRAME_LATENCIES: RangeInclusive<u32> = 1..=16;
+
+// What the engine starts with: the defaults, replaced by what the config
+// file sets, replaced by what the command line sets.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EngineConfig {
+    // Of the window's client area, in pixels.
+    pub width: u32,
+    pub height: u32,
+    pub title: String,
+    // Switched at runtime with F5.
+    pub present_mode: PresentMode,
+    pub frames_in_flight: u32,
+    pub max_frame_latency: u32,
+    pub msaa: MsaaSettings,
+    // Horizontal radius of resident chunks around the camera.
+    pub view_distance: i32,
+    pub adapter: AdapterPreference,
+    // RUST_LOG replaces it when set.
+    pub log_level: LevelFilter,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        EngineConfig {
+            width: 1280,
+            height: 720,
+            title: "D3D12 Hello Triangle".to_string(),
+            present_mode: PresentMode::Vsync,
+            frames_in_flight: 2,
+            max_frame_latency: 1,
+            msaa: MsaaSettings::default(),
+            view_distance: StreamingSettings::default().view_distance,
+            adapter: AdapterPreference::default(),
+            log_level: LevelFilter::Info,
+        }
+    }
+}
+
+impl EngineConfig {
+    // The config file merged with the command line, validated. A missing
+    // DEFAULT_CONFIG_PATH leaves the defaults, a missing file the command
+    // line names is an error. `--save-config` writes the result back to
+    // the file.
+    pub fn load(args: &[String]) -> EngineResult<Self> {
+        let named = config_path(args).map_err(EngineError::Config)?;
+        let path = named.unwrap_or(DEFAULT_CONFIG_PATH);
+        let mut config = match fs::read_to_string(path) {
+            Ok(text) => EngineConfig::from_toml(&text)
+                .map_err(|error| EngineError::Config(format!("{}: {}", path, error)))?,
+            Err(error) if named.is_none() && error.kind() == io::ErrorKind::NotFound => EngineConfig::default(),
+            Err(error) => return Err(error).context(&format!("reading {}", path)),
+        };
+        config.apply_args(args).map_err(EngineError::Config)?;
+        config.validate().map_err(EngineError::Config)?;
+
+        if args.iter().any(|arg| arg == "--save-config") {
+            fs::write(path, config.to_toml()).context(&format!("writing {}", path))?;
+        }
+        Ok(config)
+    }
+
+    // The settings of a config file on top of the defaults, see to_toml for
+    // the layout. Settings the engine does not know are an error rather
+    // than silently ignored, as they are most likely misspelled.
+    pub fn from_toml(text: &str) -> Result<Self, String> {
+        let mut config = EngineConfig::default();
+        for entry in parse_toml(text)? {
+            config
+                .set(&entry.key, &entry.value)
+                .map_err(|error| format!("line {}: {}", entry.line, error))?;
+        }
+        Ok(config)
+    }
+
+    fn set(&mut self, key: &str, value: &Value) -> Result<(), String> {
+        match key {
+            "window.width" => self.width = value.number(key)?,
+            "window.height" => self.height = value.number(key)?,
+            "window.title" => self.title = value.string(key)?.to_string(),
+            "renderer.present_mode" => self.present_mode = value.string(key)?.parse()?,
+            // From before the present modes, like --vsync and --no-vsync.
+            "renderer.vsync" => {
+                self.present_mode = if value.boolean(key)? {
+                    PresentMode::Vsync
+                } else {
+                    PresentMode::Immediate
+                }
+            }
+            "renderer.frames_in_flight" => self.frames_in_flight = value.number(key)?,
+            "renderer.max_frame_latency" => self.max_frame_latency = value.number(key)?,
+            "renderer.msaa" => self.msaa.sample_count = value.number(key)?,
+            "renderer.adapter" => {
+                self.adapter = match value {
+                    Value::Integer(_) => AdapterPreference::Index(value.number(key)?),
+                    _ => value.string(key)?.parse()?,
+                }
+            }
+            "world.view_distance" => self.view_distance = value.number(key)?,
+            "log.level" => self.log_level = parse_log_level(value.string(key)?)?,
+            _ => return Err(format!("unknown setting {}", key)),
+        }
+        Ok(())
+    }
+
+    // A config file with every setting, which from_toml reads back into the
+    // same config.
+    pub fn to_toml(&self) -> String {
+        let adapter = match &self.adapter {
+            AdapterPreference::HighestVram => quote("vram"),
+            AdapterPreference::Index(index) => index.to_string(),
+            AdapterPreference::Name(name) => quote(name),
+            AdapterPreference::Warp => quote("warp"),
+        };
+        format!(
+            "# Engine settings. Command line arguments override them, see config.rs.
+
+[window]
+width = {}
+height = {}
+title = {}
+
+[renderer]
+# vsync, immediate, which tears where the display allows it, or mailbox.
+present_mode = {}
+# Back buffers of the swap chain, {} to {}.
+frames_in_flight = {}
+# Presented frames that may queue up, {} to {}. Fewer mean less input lag.
+max_frame_latency = {}
+# Samples per pixel of the main pass: 1, 2, 4 or 8.
+msaa = {}
+# \"vram\" for the adapter with the most video memory, \"warp\", an index or
+# part of the adapter's name.
+adapter = {}
+
+[world]
+# In chunks, {} to {}.
+view_distance = {}
+
+[log]
+# off, error, warn, info, debug or trace.
+level = {}
+",
+            self.width,
+            self.height,
+            quote(&self.title),
+            quote(self.present_mode.name()),
+            FRAMES_IN_FLIGHT.start(),
+            FRAMES_IN_FLIGHT.end(),
+            self.frames_in_flight,
+            FRAME_LATENCIES.start(),
+            FRAME_LATENCIES.end(),
+            self.max_frame_latency,
+            self.msaa.sample_count,
+            adapter,
+            VIEW_DISTANCES.start(),
+            VIEW_DISTANCES.end(),
+            self.view_distance,
+            quote(&self.log_level.as_str().to_ascii_lowercase()),
+        )
+    }
+
+    // The command line on top of the config: `--resolution <width>x<height>`,
+    // `--width <pixels>`, `--height <pixels>`, `--title <text>`,
+    // `--present-mode <mode>`, `--vsync`, `--no-vsync` for immediate,
+    // `--frames-in-flight <count>`, `--max-frame-latency <frames>`,
+    // `--msaa <samples>`, `--view-distance <chunks>`, `--log-level <level>`
+    // and the adapter ones of adapter_preference_from_args. Arguments that
+    // are not about the config are left alone.
+    pub fn apply_args(&mut self, args: &[String]) -> Result<(), String> {
+        if let Some(adapter) = adapter_preference_from_args(args)? {
+            self.adapter = adapter;
+        }
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = |what: &str| {
+                args.next()
+                    .ok_or_else(|| format!("{} needs {}", arg, what))
+            };
+            match arg.as_str() {
+                "--resolution" => {
+                    let size = value("a size like 1920x1080")?;
+                    let (width, height) = size
+                        .split_once('x')
+                        .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+                        .ok_or_else(|| format!("\"{}\" is not a size like 1920x1080", size))?;
+                    self.width = width;
+                    self.height = height;
+                }
+                "--width" => self.width = number_arg(arg, value("a width")?)?,
+                "--height" => self.height = number_arg(arg, value("a height")?)?,
+                "--title" => self.title = value("a title")?.clone(),
+                "--present-mode" => self.present_mode = value("a present mode")?.parse()?,
+                "--vsync" => self.present_mode = PresentMode::Vsync,
+                "--no-vsync" => self.present_mode = PresentMode::Immediate,
+                "--frames-in-flight" => self.frames_in_flight = number_arg(arg, value("a count")?)?,
+                "--max-frame-latency" => self.max_frame_latency = number_arg(arg, value("a frame count")?)?,
+                "--msaa" => self.msaa.sample_count = number_arg(arg, value("a sample count")?)?,
+                "--view-distance" => self.view_distance = number_arg(arg, value("a distance in chunks")?)?,
+                "--log-level" => self.log_level = parse_log_level(value("a level")?)?,
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, size) in [("width", self.width), ("height", self.height)] {
+            if !(1..=MAX_RESOLUTION).contains(&size) {
+                return Err(format!(
+                    "window {} {} is out of range, expected 1 to {}",
+                    name, size, MAX_RESOLUTION
+                ));
+            }
+        }
+        if !FRAMES_IN_FLIGHT.contains(&self.frames_in_flight) {
+            return Err(format!(
+                "{} frames in flight is out of range, expected {} to {}",
+                self.frames_in_flight,
+                FRAMES_IN_FLIGHT.start(),
+                FRAMES_IN_FLIGHT.end()
+            ));
+        }
+        if !FRAME_LATENCIES.contains(&self.max_frame_latency) {
+            return Err(format!(
+                "maximum frame latency {} is out of range, expected {} to {}",
+                self.max_frame_latency,
+                FRAME_LATENCIES.start(),
+                FRAME_LATENCIES.end()
+            ));
+        }
+        self.msaa.validate()?;
+        if !VIEW_DISTANCES.contains(&self.view_distance) {
+            return Err(format!(
+                "view distance {} is out of range, expected {} to {} chunks",
+                self.view_distance,
+                VIEW_DISTANCES.start(),
+                VIEW_DISTANCES.end()
+            ));
+        }
+        Ok(())
+    }
+}
+
+// The file `--config` names, None when it names none.
+fn config_path(args: &[String]) -> Result<Option<&str>, String> {
+    let mut path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            match args.next() {
+                Some(value) => path = Some(value.as_str()),
+                None => return Err("--config needs a path".to_string()),
+            }
+        }
+    }
+    Ok(path)
+}
+
+fn number_arg<T: FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("\"{}\" is not a valid number for {}", value, arg))
+}
+
+fn parse_log_level(value: &str) -> Result<LevelFilter, String> {
+    value.parse().map_err(|_| {
+        format!(
+            "unknown log level \"{}\", expected off, error, warn, info, debug or trace",
+            value
+        )
+    })
+}
+
+// The values of the TOML subset config files are written in.
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Boolean(bool),
+    Integer(i64),
+    String(String),
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Boolean(_) => "a boolean",
+            Value::Integer(_) => "an integer",
+            Value::String(_) => "a string",
+        }
+    }
+
+    fn boolean(&self, key: &str) -> Result<bool, String> {
+        match self {
+            Value::Boolean(value) => Ok(*value),
+            _ => Err(format!("{} should be true or false, not {}", key, self.type_name())),
+        }
+    }
+
+    // An integer that fits T.
+    fn number<T: TryFrom<i64>>(&self, key: &str) -> Result<T, String> {
+        match self {
+            Value::Integer(value) => {
+                T::try_from(*value).map_err(|_| format!("{} {} is out of range", key, value))
+            }
+            _ => Err(format!("{} should be an integer, not {}", key, self.type_name())),
+        }
+    }
+
+    fn string(&self, key: &str) -> Result<&str, String> {
+        match self {
+            Value::String(value) => Ok(value),
+            _ => Err(format!("{} should be a string, not {}", key, self.type_name())),
+        }
+    }
+}
+
+// A `key = value` line, the key prefixed with its table: "window.width".
+#[derive(Clone, Debug, PartialEq)]
+struct Entry {
+    key: String,
+    value: Value,
+    line: usize,
+}
+
+// Reads the subset of TOML the config needs: `[table]` headers and
+// `key = value` lines with booleans, integers and basic strings, and `#`
+// comments. Anything else is an error naming the line.
+fn parse_toml(text: &str) -> Result<Vec<Entry>, String> {
+    let mut table = String::new();
+    let mut entries: Vec<Entry> = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let number = index + 1;
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[') {
+            let name = header
+                .strip_suffix(']')
+                .ok_or_else(|| format!("line {}: expected ] after the table name", number))?
+                .trim();
+            if !is_bare_key(name) {
+                return Err(format!("line {}: invalid table name \"{}\"", number, name));
+            }
+            table = name.to_string();
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected key = value", number))?;
+        let key = key.trim();
+        if !is_bare_key(key) {
+            return Err(format!("line {}: invalid key \"{}\"", number, key));
+        }
+        let value = parse_value(value.trim()).map_err(|error| format!("line {}: {}", number, error))?;
+        let key = if table.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", table, key)
+        };
+        if entries.iter().any(|entry| entry.key == key) {
+            return Err(format!("line {}: {} is set twice", number, key));
+        }
+        entries.push(Entry {
+            key,
+            value,
+            line: number,
+        });
+    }
+    Ok(entries)
+}
+
+fn is_bare_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+// The line up to a `#` outside of a string.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => (),
+        }
+    }
+    line
+}
+
+fn parse_value(value: &str) -> Result<Value, String> {
+    match value {
+        "true" => return Ok(Value::Boolean(true)),
+        "false" => return Ok(Value::Boolean(false)),
+        _ => (),
+    }
+    if let Some(rest) = value.strip_prefix('"') {
+        return parse_string(rest).map(Value::String);
+    }
+    // Underscores may group digits, like 1_000.
+    value
+        .replace('_', "")
+        .parse()
+        .map(Value::Integer)
+        .map_err(|_| format!("\"{}\" is not a boolean, integer or string", value))
+}
+
+// A basic string after its opening quote, nothing may follow the closing one.
+fn parse_string(rest: &str) -> Result<String, String> {
+    let mut string = String::new();
+    let mut chars = rest.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                return match chars.as_str().trim() {
+                    "" => Ok(string),
+                    trailing => Err(format!("unexpected \"{}\" after the string", trailing)),
+                }
+            }
+            '\\' => string.push(match chars.next() {
+                Some('"') => '"',
+                Some('\\') => '\\',
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some(other) => return Err(format!("unsupported escape \\{} in a string", other)),
+                None => return Err("unterminated string".to_string()),
+            }),
+            _ => string.push(c),
+        }
+    }
+    Err("unterminated string".to_string())
+}
+
+// `value` as a basic string, the inverse of parse_string.
+fn quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn custom() -> EngineConfig {
+        EngineConfig {
+            width: 1920,
+            height: 1080,
+            title: "say \"hi\"\\\tnow # not a comment".to_string(),
+            present_mode: PresentMode::Mailbox,
+            frames_in_flight: 3,
+            max_frame_latency: 2,
+            msaa: MsaaSettings { sample_count: 4 },
+            view_distance: 12,
+            adapter: AdapterPreference::Name("Radeon".to_string()),
+            log_level: LevelFilter::Debug,
+        }
+    }
+
+    #[test]
+    fn toml_round_trips() {
+        for config in [EngineConfig::default(), custom()] {
+            assert_eq!(EngineConfig::from_toml(&config.to_toml()), Ok(config));
+        }
+        for adapter in [AdapterPreference::HighestVram, AdapterPreference::Index(1), AdapterPreference::Warp] {
+            let config = EngineConfig {
+                adapter,
+                ..EngineConfig::default()
+            };
+            assert_eq!(EngineConfig::from_toml(&config.to_toml()), Ok(config));
+        }
+    }
+
+    #[test]
+    fn shipped_file_holds_the_defaults() {
+        assert_eq!(include_str!("../engine.toml"), EngineConfig::default().to_toml());
+    }
+
+    #[test]
+    fn file_settings_replace_defaults() {
+        let config = EngineConfig::from_toml(
+            "[window]\nwidth = 2_560 # wide\n\n[renderer]\nvsync = false\nadapter = 0\n",
+        )
+        .unwrap();
+        assert_eq!(config.width, 2560);
+        assert_eq!(config.height, 720);
+        assert_eq!(config.present_mode, PresentMode::Immediate);
+        assert_eq!(config.adapter, AdapterPreference::Index(0));
+    }
+
+    #[test]
+    fn malformed_files_name_the_line() {
+        let cases = [
+            ("[window]\nwidht = 10\n", "line 2: unknown setting window.widht"),
+            ("[window\n", "line 1: expected ] after the table name"),
+            ("[window]\nwidth\n", "line 2: expected key = value"),
+            ("[window]\nwidth = \"wide\"\n", "line 2: window.width should be an integer, not a string"),
+            ("[window]\nwidth = -1\n", "line 2: window.width -1 is out of range"),
+            ("[window]\nwidth = 1\nwidth = 2\n", "line 3: window.width is set twice"),
+            ("[window]\ntitle = \"open\n", "line 2: unterminated string"),
+            ("[renderer]\npresent_mode = \"fifo\"\n", "line 2: unknown present mode \"fifo\", expected vsync, immediate or mailbox"),
+            ("[log]\nlevel = \"loud\"\n", "line 2: unknown log level \"loud\", expected off, error, warn, info, debug or trace"),
+        ];
+        for (text, error) in cases {
+            assert_eq!(EngineConfig::from_toml(text), Err(error.to_string()), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn arguments_override_file_values() {
+        let mut config = custom();
+        config
+            .apply_args(&args(&[
+                "--resolution",
+                "800x600",
+                "--no-vsync",
+                "--msaa",
+                "2",
+                "--log-level",
+                "warn",
+                "--warp",
+                "--unrelated",
+            ]))
+            .unwrap();
+        assert_eq!((config.width, config.height), (800, 600));
+        assert_eq!(config.present_mode, PresentMode::Immediate);
+        assert_eq!(config.msaa.sample_count, 2);
+        assert_eq!(config.log_level, LevelFilter::Warn);
+        assert_eq!(config.adapter, AdapterPreference::Warp);
+        // Untouched by the arguments.
+        assert_eq!(config.title, custom().title);
+        assert_eq!(config.view_distance, 12);
+
+        // Later arguments win.
+        config.apply_args(&args(&["--width", "1024", "--present-mode", "mailbox", "--vsync"])).unwrap();
+        assert_eq!((config.width, config.height), (1024, 600));
+        assert_eq!(config.present_mode, PresentMode::Vsync);
+    }
+
+    #[test]
+    fn bad_arguments_are_rejected() {
+        let mut config = EngineConfig::default();
+        assert_eq!(
+            config.apply_args(&args(&["--resolution", "big"])),
+            Err("\"big\" is not a size like 1920x1080".to_string())
+        );
+        assert_eq!(
+            config.apply_args(&args(&["--view-distance", "far"])),
+            Err("\"far\" is not a valid number for --view-distance".to_string())
+        );
+        assert_eq!(config.apply_args(&args(&["--width"])), Err("--width needs a width".to_string()));
+        assert_eq!(config, EngineConfig::default());
+    }
+
+    #[test]
+    fn validation_rejects_out_of_range_values() {
+        assert_eq!(EngineConfig::default().validate(), Ok(()));
+        assert_eq!(custom().validate(), Ok(()));
+
+        let cases = [
+            (
+                EngineConfig { width: 0, ..EngineConfig::default() },
+                "window width 0 is out of range, expected 1 to 16384",
+            ),
+            (
+                EngineConfig { height: 20000, ..EngineConfig::default() },
+                "window height 20000 is out of range, expected 1 to 16384",
+            ),
+            (
+                EngineConfig { frames_in_flight: 1, ..EngineConfig::default() },
+                "1 frames in flight is out of range, expected 2 to 4",
+            ),
+            (
+                EngineConfig { max_frame_latency: 17, ..EngineConfig::default() },
+                "maximum frame latency 17 is out of range, expected 1 to 16",
+            ),
+            (
+                EngineConfig { msaa: MsaaSettings { sample_count: 3 }, ..EngineConfig::default() },
+                "unsupported MSAA sample count 3, expected one of [1, 2, 4, 8]",
+            ),
+            (
+                EngineConfig { view_distance: 1, ..EngineConfig::default() },
+                "view distance 1 is out of range, expected 2 to 32 chunks",
+            ),
+        ];
+        for (config, error) in cases {
+            assert_eq!(config.validate(), Err(error.to_string()));
+        }
+    }
+
+    #[test]
+    fn load_merges_file_and_arguments() {
+        let path = std::env::temp_dir().join(format!("engine-config-test-{}.toml", std::process::id()));
+        fs::write(&path, "[window]\nwidth = 640\nheight = 480\n[world]\nview_distance = 4\n").unwrap();
+        let path_arg = path.to_str().unwrap();
+
+        let config = EngineConfig::load(&args(&["--config", path_arg, "--height", "400"])).unwrap();
+        assert_eq!((config.width, config.height, config.view_distance), (640, 400, 4));
+
+        let error = EngineConfig::load(&args(&["--config", path_arg, "--view-distance", "99"])).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid configuration: view distance 99 is out of range, expected 2 to 32 chunks"
+        );
+
+        fs::remove_file(&path).unwrap();
+        assert!(EngineConfig::load(&args(&["--config", path_arg])).is_err());
+    }
+}
@@ -0,0 +1,167 @@
+use log::{log, warn};
+use windows::{core::*, Win32::Graphics::Direct3D12::*};
+
+use crate::debug_layer::{Breadcrumbs, DebugSettings, DredReport, MessageSeverity, PageFault};
+
+// Turns on what `settings` asks for. Has to run before the device is
+// created; a missing debug layer (the Graphics Tools optional feature) is
+// only a warning.
+pub fn enable_debug_layer(settings: &DebugSettings) {
+    if settings.debug_layer {
+        let mut debug: Option<ID3D12Debug1> = None;
+        match unsafe { D3D12GetDebugInterface(&mut debug) }.map(|()| debug) {
+            Ok(Some(debug)) => unsafe {
+                debug.EnableDebugLayer();
+                debug.SetEnableGPUBasedValidation(settings.gpu_based_validation);
+            },
+            Ok(None) | Err(_) => warn!("the D3D12 debug layer is not installed"),
+        }
+    }
+
+    if settings.dred {
+        let mut dred: Option<ID3D12DeviceRemovedExtendedDataSettings> = None;
+        match unsafe { D3D12GetDebugInterface(&mut dred) }.map(|()| dred) {
+            Ok(Some(dred)) => unsafe {
+                dred.SetAutoBreadcrumbsEnablement(D3D12_DRED_ENABLEMENT_FORCED_ON);
+                dred.SetPageFaultEnablement(D3D12_DRED_ENABLEMENT_FORCED_ON);
+            },
+            Ok(None) | Err(_) => warn!("device removed extended data is not available"),
+        }
+    }
+}
+
+// The debug layer's message queue. Messages are stored until `drain` logs
+// them under the "d3d12" target.
+pub struct InfoQueue {
+    queue: ID3D12InfoQueue,
+}
+
+impl InfoQueue {
+    // None when the debug layer is off or did not load.
+    pub fn new(device: &ID3D12Device, settings: &DebugSettings) -> Result<Option<Self>> {
+        if !settings.debug_layer {
+            return Ok(None);
+        }
+        let queue: ID3D12InfoQueue = match device.cast() {
+            Ok(queue) => queue,
+            Err(_) => return Ok(None),
+        };
+
+        let mut severities: Vec<D3D12_MESSAGE_SEVERITY> = settings
+            .denied_severities()
+            .into_iter()
+            .map(|severity| D3D12_MESSAGE_SEVERITY(severity.to_d3d12()))
+            .collect();
+        let mut ids: Vec<D3D12_MESSAGE_ID> = settings.muted_ids.iter().map(|&id| D3D12_MESSAGE_ID(id)).collect();
+        let filter = D3D12_INFO_QUEUE_FILTER {
+            DenyList: D3D12_INFO_QUEUE_FILTER_DESC {
+                NumSeverities: severities.len() as u32,
+                pSeverityList: severities.as_mut_ptr(),
+                NumIDs: ids.len() as u32,
+                pIDList: ids.as_mut_ptr(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        unsafe { queue.PushStorageFilter(&filter) }?;
+
+        for severity in settings.break_severities() {
+            unsafe { queue.SetBreakOnSeverity(D3D12_MESSAGE_SEVERITY(severity.to_d3d12()), true) }?;
+        }
+        Ok(Some(InfoQueue { queue }))
+    }
+
+    // Logs and clears the stored messages.
+    pub fn drain(&self) {
+        let count = unsafe { self.queue.GetNumStoredMessages() };
+        for i in 0..count {
+            let mut size = 0;
+            if unsafe { self.queue.GetMessage(i, None, &mut size) }.is_err() {
+                continue;
+            }
+            // D3D12_MESSAGE is followed by its description in the same
+            // allocation, which has to be aligned for the struct.
+            let mut buffer = vec![0u64; size.div_ceil(std::mem::size_of::<u64>())];
+            let message = buffer.as_mut_ptr() as *mut D3D12_MESSAGE;
+            if unsafe { self.queue.GetMessage(i, Some(message), &mut size) }.is_err() {
+                continue;
+            }
+            let message = unsafe { &*message };
+            let description = unsafe {
+                std::slice::from_raw_parts(
+                    message.pDescription.as_ptr(),
+                    message.DescriptionByteLength.saturating_sub(1),
+                )
+            };
+            let level = MessageSeverity::from_d3d12(message.Severity.0)
+                .unwrap_or(MessageSeverity::Message)
+                .level();
+            log!(
+                target: "d3d12",
+                level,
+                "{} [{}]",
+                String::from_utf8_lossy(description),
+                message.ID.0
+            );
+        }
+        unsafe { self.queue.ClearStoredMessages() };
+    }
+}
+
+// What DRED recorded on a removed device, None when it was not enabled.
+pub fn dred_report(device: &ID3D12Device) -> Option<DredReport> {
+    let dred: ID3D12DeviceRemovedExtendedData = device.cast().ok()?;
+    let mut report = DredReport::default();
+
+    if let Ok(output) = unsafe { dred.GetAutoBreadcrumbsOutput() } {
+        let mut node = output.pHeadAutoBreadcrumbNode;
+        while let Some(current) = unsafe { node.as_ref() } {
+            let ops = if current.pCommandHistory.is_null() {
+                Vec::new()
+            } else {
+                unsafe { std::slice::from_raw_parts(current.pCommandHistory, current.BreadcrumbCount as usize) }
+                    .iter()
+                    .map(|op| op.0)
+                    .collect()
+            };
+            report.breadcrumbs.push(Breadcrumbs {
+                command_list: debug_name(current.pCommandListDebugNameW, current.pCommandListDebugNameA),
+                command_queue: debug_name(current.pCommandQueueDebugNameW, current.pCommandQueueDebugNameA),
+                ops,
+                completed: unsafe { current.pLastBreadcrumbValue.as_ref() }.copied().unwrap_or(0),
+            });
+            node = current.pNext;
+        }
+    }
+
+    if let Ok(output) = unsafe { dred.GetPageFaultAllocationOutput() } {
+        if output.PageFaultVA != 0 {
+            report.page_fault = Some(PageFault {
+                address: output.PageFaultVA,
+                existing: allocation_names(output.pHeadExistingAllocationNode),
+                recently_freed: allocation_names(output.pHeadRecentFreedAllocationNode),
+            });
+        }
+    }
+    Some(report)
+}
+
+fn allocation_names(mut node: *const D3D12_DRED_ALLOCATION_NODE) -> Vec<String> {
+    let mut names = Vec::new();
+    while let Some(current) = unsafe { node.as_ref() } {
+        names.push(debug_name(current.ObjectNameW, current.ObjectNameA));
+        node = current.pNext;
+    }
+    names
+}
+
+fn debug_name(wide: PCWSTR, narrow: PCSTR) -> String {
+    let name = if !wide.is_null() {
+        unsafe { wide.to_string() }.ok()
+    } else if !narrow.is_null() {
+        unsafe { narrow.to_string() }.ok()
+    } else {
+        None
+    };
+    name.unwrap_or_else(|| "unnamed".to_string())
+}
@@ -0,0 +1,412 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+use crate::math::{Aabb, Frustum, Mat4, Vec3};
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DebugVertex {
+    pub position: [f32; 3],
+    // sRGB with straight alpha, like Vertex::color.
+    pub color: [f32; 4],
+}
+
+// Whether a primitive is hidden by the scene in front of it or drawn over
+// everything.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Depth {
+    Tested,
+    Overlay,
+}
+
+// Text at a pixel position from the top left corner of the window, lines
+// split at '\n'.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DebugText {
+    pub position: [f32; 2],
+    pub text: String,
+    pub color: [f32; 4],
+}
+
+// Segments around a sphere's circles.
+const SPHERE_SEGMENTS: usize = 24;
+
+// Pixels per unit of the glyph grid, see glyph.
+const TEXT_SCALE: f32 = 2.0;
+const GLYPH_ADVANCE: f32 = 6.0;
+const LINE_ADVANCE: f32 = 9.0;
+
+// One frame of debug drawing: world space line lists and screen text.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DebugPrimitives {
+    pub tested: Vec<DebugVertex>,
+    pub overlay: Vec<DebugVertex>,
+    pub text: Vec<DebugText>,
+}
+
+impl DebugPrimitives {
+    pub fn is_empty(&self) -> bool {
+        self.tested.is_empty() && self.overlay.is_empty() && self.text.is_empty()
+    }
+
+    pub fn line(&mut self, a: Vec3, b: Vec3, color: [f32; 4], depth: Depth) {
+        let vertices = match depth {
+            Depth::Tested => &mut self.tested,
+            Depth::Overlay => &mut self.overlay,
+        };
+        for position in [a, b] {
+            vertices.push(DebugVertex {
+                position: [position.x, position.y, position.z],
+                color,
+            });
+        }
+    }
+
+    pub fn aabb(&mut self, bounds: &Aabb, color: [f32; 4], depth: Depth) {
+        self.box_edges(&bounds.corners(), color, depth);
+    }
+
+    // A circle in each of the axis planes.
+    pub fn sphere(&mut self, center: Vec3, radius: f32, color: [f32; 4], depth: Depth) {
+        let point = |axis: usize, angle: f32| {
+            let (sin, cos) = angle.sin_cos();
+            let offset = match axis {
+                0 => Vec3::new(0.0, cos, sin),
+                1 => Vec3::new(cos, 0.0, sin),
+                _ => Vec3::new(cos, sin, 0.0),
+            };
+            center + offset * radius
+        };
+        let step = std::f32::consts::TAU / SPHERE_SEGMENTS as f32;
+        for axis in 0..3 {
+            for i in 0..SPHERE_SEGMENTS {
+                let a = point(axis, i as f32 * step);
+                let b = point(axis, (i + 1) as f32 * step);
+                self.line(a, b, color, depth);
+            }
+        }
+    }
+
+    // The volume `view_projection` maps into clip space.
+    pub fn frustum(&mut self, view_projection: &Mat4, color: [f32; 4], depth: Depth) {
+        let corners = Frustum::from_view_projection(view_projection).corners();
+        self.box_edges(&corners, color, depth);
+    }
+
+    pub fn text(&mut self, position: [f32; 2], text: &str, color: [f32; 4]) {
+        self.text.push(DebugText {
+            position,
+            text: text.to_string(),
+            color,
+        });
+    }
+
+    // The twelve edges between corners in the order of Aabb::corners, where
+    // neighbours differ in one bit of their index.
+    fn box_edges(&mut self, corners: &[Vec3; 8], color: [f32; 4], depth: Depth) {
+        for i in 0..8 {
+            for bit in [1, 2, 4] {
+                if i & bit == 0 {
+                    self.line(corners[i], corners[i | bit], color, depth);
+                }
+            }
+        }
+    }
+
+    // The text as a line list in clip space, for a window of `width` by
+    // `height` pixels.
+    pub fn text_vertices(&self, width: f32, height: f32) -> Vec<DebugVertex> {
+        let mut vertices = Vec::new();
+        let to_clip = |x: f32, y: f32| [x / width * 2.0 - 1.0, 1.0 - y / height * 2.0, 0.0];
+        for text in &self.text {
+            for (row, line) in text.text.split('\n').enumerate() {
+                let top = text.position[1] + row as f32 * LINE_ADVANCE * TEXT_SCALE;
+                for (column, c) in line.chars().enumerate() {
+                    let left = text.position[0] + column as f32 * GLYPH_ADVANCE * TEXT_SCALE;
+                    for stroke in glyph(c).split(';').filter(|stroke| !stroke.is_empty()) {
+                        let points: Vec<[f32; 3]> = stroke
+                            .split(' ')
+                            .filter_map(|point| {
+                                let (x, y) = point.split_once(',')?;
+                                let (x, y): (f32, f32) = (x.parse().ok()?, y.parse().ok()?);
+                                // The grid's y points up from the baseline, 6
+                                // units under the top of the line.
+                                Some(to_clip(left + x * TEXT_SCALE, top + (6.0 - y) * TEXT_SCALE))
+                            })
+                            .collect();
+                        for pair in points.windows(2) {
+                            for &position in pair {
+                                vertices.push(DebugVertex {
+                                    position,
+                                    color: text.color,
+                                });
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        vertices
+    }
+}
+
+// Strokes of a glyph on a 4 by 6 grid with y up: polylines separated by ';'
+// of points separated by ' '. Lower case is drawn as upper case and
+// characters without a glyph as a box.
+fn glyph(c: char) -> &'static str {
+    match c.to_ascii_uppercase() {
+        ' ' => "",
+        '0' => "0,0 4,0 4,6 0,6 0,0 4,6",
+        '1' => "1,5 2,6 2,0;1,0 3,0",
+        '2' => "0,6 4,6 4,3 0,3 0,0 4,0",
+        '3' => "0,6 4,6 4,0 0,0;1,3 4,3",
+        '4' => "0,6 0,3 4,3;4,6 4,0",
+        '5' => "4,6 0,6 0,4 3,4 4,3 4,1 3,0 0,0",
+        '6' => "4,6 0,6 0,0 4,0 4,3 0,3",
+        '7' => "0,6 4,6 1,0",
+        '8' => "0,0 4,0 4,6 0,6 0,0;0,3 4,3",
+        '9' => "4,3 0,3 0,6 4,6 4,0 0,0",
+        'A' => "0,0 0,4 2,6 4,4 4,0;0,3 4,3",
+        'B' => "0,0 0,6 3,6 4,5 4,4 3,3 0,3;3,3 4,2 4,1 3,0 0,0",
+        'C' => "4,6 0,6 0,0 4,0",
+        'D' => "0,0 0,6 2,6 4,4 4,2 2,0 0,0",
+        'E' => "4,6 0,6 0,0 4,0;0,3 3,3",
+        'F' => "4,6 0,6 0,0;0,3 3,3",
+        'G' => "4,5 4,6 0,6 0,0 4,0 4,3 2,3",
+        'H' => "0,0 0,6;4,0 4,6;0,3 4,3",
+        'I' => "1,6 3,6;2,6 2,0;1,0 3,0",
+        'J' => "4,6 4,0 0,0 0,2",
+        'K' => "0,0 0,6;4,6 0,3 4,0",
+        'L' => "0,6 0,0 4,0",
+        'M' => "0,0 0,6 2,3 4,6 4,0",
+        'N' => "0,0 0,6 4,0 4,6",
+        'O' => "0,0 0,6 4,6 4,0 0,0",
+        'P' => "0,0 0,6 4,6 4,3 0,3",
+        'Q' => "0,0 0,6 4,6 4,1 3,0 0,0;2,2 4,0",
+        'R' => "0,0 0,6 4,6 4,3 0,3 4,0",
+        'S' => "4,6 0,6 0,3 4,3 4,0 0,0",
+        'T' => "0,6 4,6;2,6 2,0",
+        'U' => "0,6 0,0 4,0 4,6",
+        'V' => "0,6 2,0 4,6",
+        'W' => "0,6 1,0 2,3 3,0 4,6",
+        'X' => "0,0 4,6;0,6 4,0",
+        'Y' => "0,6 2,3 4,6;2,3 2,0",
+        'Z' => "0,6 4,6 0,0 4,0",
+        '.' => "2,0 2,1",
+        ',' => "2,1 1,-1",
+        ':' => "2,1 2,2;2,4 2,5",
+        '-' => "1,3 3,3",
+        '+' => "1,3 3,3;2,2 2,4",
+        '=' => "1,2 3,2;1,4 3,4",
+        '/' => "0,0 4,6",
+        '(' => "3,6 2,5 2,1 3,0",
+        ')' => "1,6 2,5 2,1 1,0",
+        '[' => "3,6 1,6 1,0 3,0",
+        ']' => "1,6 3,6 3,0 1,0",
+        '<' => "4,6 0,3 4,0",
+        '>' => "0,6 4,3 0,0",
+        '%' => "0,0 4,6;0,6 0,5;4,1 4,0",
+        '_' => "0,0 4,0",
+        '!' => "2,6 2,2;2,1 2,0",
+        '?' => "0,5 1,6 4,6 4,3 2,3 2,2;2,1 2,0",
+        '*' => "1,2 3,4;1,4 3,2;2,2 2,4",
+        '\'' => "2,6 2,5",
+        '|' => "2,0 2,6",
+        _ => "0,0 4,0 4,6 0,6 0,0",
+    }
+}
+
+thread_local! {
+    static PRIMITIVES: RefCell<DebugPrimitives> = RefCell::new(DebugPrimitives::default());
+}
+
+// Adds primitives to this frame's debug drawing, from anywhere on the
+// render thread: `debug_draw(|draw| draw.aabb(&bounds, RED, Depth::Tested));`
+pub fn debug_draw(f: impl FnOnce(&mut DebugPrimitives)) {
+    PRIMITIVES.with(|primitives| f(&mut primitives.borrow_mut()));
+}
+
+// The primitives added since the last call.
+pub fn take_debug_primitives() -> DebugPrimitives {
+    PRIMITIVES.with(|primitives| std::mem::take(&mut *primitives.borrow_mut()))
+}
+
+// Sub-allocates a buffer the GPU reads for frames in flight. Allocations
+// wrap around to the start, behind the frames the GPU has finished.
+#[derive(Debug)]
+pub struct UploadRing {
+    capacity: u64,
+    head: u64,
+    // Bytes of unfinished frames, padding skipped at the end included.
+    used: u64,
+    frame_bytes: u64,
+    frames: VecDeque<(u64, u64)>,
+}
+
+impl UploadRing {
+    pub fn new(capacity: u64) -> Self {
+        UploadRing {
+            capacity,
+            head: 0,
+            used: 0,
+            frame_bytes: 0,
+            frames: VecDeque::new(),
+        }
+    }
+
+    // The offset of `size` bytes aligned to `alignment`, a power of two.
+    // None when the frames in flight leave no room.
+    pub fn allocate(&mut self, size: u64, alignment: u64) -> Option<u64> {
+        let aligned = (self.head + alignment - 1) & !(alignment - 1);
+        let start = if aligned + size > self.capacity { 0 } else { aligned };
+        let end = start + size;
+        let consumed = if start == 0 && self.head != 0 {
+            self.capacity - self.head + size
+        } else {
+            end - self.head
+        };
+        if end > self.capacity || self.used + consumed > self.capacity {
+            return None;
+        }
+        self.head = end;
+        self.used += consumed;
+        self.frame_bytes += consumed;
+        Some(start)
+    }
+
+    // Everything allocated since the last call belongs to `frame`.
+    pub fn finish_frame(&mut self, frame: u64) {
+        self.frames.push_back((frame, std::mem::take(&mut self.frame_bytes)));
+    }
+
+    // Frees the allocations of `frame` and the frames before it, once the
+    // GPU has finished them.
+    pub fn retire(&mut self, frame: u64) {
+        while let Some(&(oldest, bytes)) = self.frames.front() {
+            if oldest > frame {
+                break;
+            }
+            self.used -= bytes;
+            self.frames.pop_front();
+        }
+        // Nothing in flight, the next allocation need not wrap.
+        if self.used == 0 {
+            self.head = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+
+    fn unit_box() -> Aabb {
+        Aabb::new(Vec3::default(), Vec3::new(1.0, 1.0, 1.0))
+    }
+
+    #[test]
+    fn primitives_emit_two_vertices_per_line() {
+        let mut draw = DebugPrimitives::default();
+        draw.line(Vec3::default(), Vec3::new(1.0, 2.0, 3.0), RED, Depth::Tested);
+        assert_eq!(draw.tested.len(), 2);
+        assert_eq!(draw.tested[1].position, [1.0, 2.0, 3.0]);
+
+        let mut draw = DebugPrimitives::default();
+        draw.aabb(&unit_box(), RED, Depth::Tested);
+        assert_eq!(draw.tested.len(), 12 * 2);
+
+        let mut draw = DebugPrimitives::default();
+        draw.sphere(Vec3::default(), 2.0, RED, Depth::Tested);
+        assert_eq!(draw.tested.len(), 3 * SPHERE_SEGMENTS * 2);
+        for vertex in &draw.tested {
+            let [x, y, z] = vertex.position;
+            assert!((Vec3::new(x, y, z).length() - 2.0).abs() < 1e-4);
+        }
+
+        let mut draw = DebugPrimitives::default();
+        let view_projection = Mat4::perspective_lh(std::f32::consts::FRAC_PI_2, 1.0, 1.0, 10.0);
+        draw.frustum(&view_projection, RED, Depth::Tested);
+        assert_eq!(draw.tested.len(), 12 * 2);
+    }
+
+    #[test]
+    fn box_edges_are_axis_aligned() {
+        let mut draw = DebugPrimitives::default();
+        draw.aabb(&unit_box(), RED, Depth::Overlay);
+        for pair in draw.overlay.chunks(2) {
+            let differing = (0..3).filter(|&axis| pair[0].position[axis] != pair[1].position[axis]).count();
+            assert_eq!(differing, 1, "{:?}", pair);
+        }
+    }
+
+    #[test]
+    fn depth_picks_the_list() {
+        let mut draw = DebugPrimitives::default();
+        assert!(draw.is_empty());
+        draw.aabb(&unit_box(), RED, Depth::Tested);
+        draw.line(Vec3::default(), Vec3::new(0.0, 1.0, 0.0), RED, Depth::Overlay);
+        assert_eq!((draw.tested.len(), draw.overlay.len()), (24, 2));
+        assert!(draw.text.is_empty());
+        assert!(!draw.is_empty());
+    }
+
+    #[test]
+    fn frames_start_empty() {
+        debug_draw(|draw| {
+            draw.aabb(&unit_box(), RED, Depth::Tested);
+            draw.text([0.0, 0.0], "fps", RED);
+        });
+        debug_draw(|draw| draw.line(Vec3::default(), Vec3::new(1.0, 0.0, 0.0), RED, Depth::Overlay));
+        let frame = take_debug_primitives();
+        assert_eq!((frame.tested.len(), frame.overlay.len(), frame.text.len()), (24, 2, 1));
+        assert!(take_debug_primitives().is_empty());
+    }
+
+    #[test]
+    fn text_turns_into_glyph_strokes() {
+        let mut draw = DebugPrimitives::default();
+        // Two segments and one, a space, and the box of an unknown glyph.
+        draw.text([0.0, 0.0], "1 ~", RED);
+        assert_eq!(draw.text_vertices(100.0, 100.0).len(), (3 + 4) * 2);
+
+        let mut draw = DebugPrimitives::default();
+        draw.text([0.0, 0.0], "l", RED);
+        let vertices = draw.text_vertices(64.0, 48.0);
+        // 'L' from the top left corner down and along the baseline.
+        let positions: Vec<[f32; 3]> = vertices.iter().map(|vertex| vertex.position).collect();
+        assert_eq!(
+            positions,
+            [[-1.0, 1.0, 0.0], [-1.0, 0.5, 0.0], [-1.0, 0.5, 0.0], [-0.75, 0.5, 0.0]]
+        );
+        assert!(vertices.iter().all(|vertex| vertex.color == RED));
+
+        let mut draw = DebugPrimitives::default();
+        draw.text([0.0, 0.0], "a\nA", RED);
+        let vertices = draw.text_vertices(100.0, 100.0);
+        let (first, second) = vertices.split_at(vertices.len() / 2);
+        for (a, b) in first.iter().zip(second) {
+            assert_eq!(a.position[0], b.position[0]);
+            assert!((a.position[1] - b.position[1] - LINE_ADVANCE * TEXT_SCALE * 2.0 / 100.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn upload_ring_wraps_behind_finished_frames() {
+        let mut ring = UploadRing::new(256);
+        assert_eq!(ring.allocate(100, 16), Some(0));
+        assert_eq!(ring.allocate(50, 64), Some(128));
+        ring.finish_frame(1);
+        // 78 bytes left at the end, so this wraps, but frame 1 still holds
+        // the start.
+        assert_eq!(ring.allocate(100, 16), None);
+        ring.retire(1);
+        assert_eq!(ring.allocate(100, 16), Some(0));
+        assert_eq!(ring.allocate(100, 16), Some(112));
+        ring.finish_frame(2);
+        assert_eq!(ring.allocate(100, 16), None);
+        ring.retire(2);
+        assert_eq!(ring.allocate(256, 16), Some(0));
+    }
+}
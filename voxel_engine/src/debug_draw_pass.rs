@@ -0,0 +1,256 @@
+use log::debug;
+use windows::{
+    core::*, Win32::Foundation::*, Win32::Graphics::Direct3D::*,
+    Win32::Graphics::Direct3D12::*, Win32::Graphics::Dxgi::Common::*,
+};
+
+use crate::debug_draw::{DebugPrimitives, DebugVertex, UploadRing};
+use crate::markers::set_name;
+use crate::math::Mat4;
+use crate::{convert_to_bytecode, create_buffer, BACK_BUFFER_VIEW_FORMAT, DEPTH_FORMAT, SCENE_FORMAT};
+
+// Room for the debug vertices of the frames in flight, about 150k lines.
+const RING_SIZE: u64 = 8 << 20;
+
+// Draws debug_draw.rs primitives as line lists with shaders/debug.hlsl:
+// the lines into the scene, tested against its depth or not, and the text
+// into the back buffer after post processing, where it is not tonemapped.
+pub struct DebugDrawPass {
+    tested_pso: ID3D12PipelineState,
+    overlay_pso: ID3D12PipelineState,
+    text_pso: ID3D12PipelineState,
+    buffer: ID3D12Resource,
+    ring: UploadRing,
+}
+
+impl DebugDrawPass {
+    pub fn new(device: &ID3D12Device, root_signature: &ID3D12RootSignature, sample_count: u32) -> Result<Self> {
+        let scene = |depth_test| LineTarget::Scene {
+            sample_count,
+            depth_test,
+        };
+        let tested_pso = create_debug_pipeline_state(device, root_signature, scene(true))?;
+        let overlay_pso = create_debug_pipeline_state(device, root_signature, scene(false))?;
+        let text_pso = create_debug_pipeline_state(device, root_signature, LineTarget::BackBuffer)?;
+        let buffer = create_buffer(
+            device,
+            D3D12_HEAP_TYPE_UPLOAD,
+            RING_SIZE,
+            D3D12_RESOURCE_FLAG_NONE,
+            D3D12_RESOURCE_STATE_GENERIC_READ,
+        )?;
+        set_name(&tested_pso, "debug draw tested pso");
+        set_name(&overlay_pso, "debug draw overlay pso");
+        set_name(&text_pso, "debug draw text pso");
+        set_name(&buffer, "debug draw vertices");
+        Ok(DebugDrawPass {
+            tested_pso,
+            overlay_pso,
+            text_pso,
+            buffer,
+            ring: UploadRing::new(RING_SIZE),
+        })
+    }
+
+    // Expects the main pass state: scene target, depth buffer, viewport and
+    // the camera's view projection in the root constants. Changes the
+    // pipeline state, topology and vertex buffer.
+    pub fn record_lines(
+        &mut self,
+        command_list: &ID3D12GraphicsCommandList,
+        primitives: &DebugPrimitives,
+    ) -> Result<()> {
+        for (pso, vertices) in [
+            (&self.tested_pso, &primitives.tested),
+            (&self.overlay_pso, &primitives.overlay),
+        ] {
+            draw(&mut self.ring, &self.buffer, command_list, pso, vertices)?;
+        }
+        Ok(())
+    }
+
+    // Draws the text over the back buffer at `back_buffer_rtv`, which is
+    // expected to be a render target. Sets the root signature, viewport and
+    // root constants it needs.
+    pub fn record_text(
+        &mut self,
+        command_list: &ID3D12GraphicsCommandList,
+        primitives: &DebugPrimitives,
+        root_signature: &ID3D12RootSignature,
+        back_buffer_rtv: D3D12_CPU_DESCRIPTOR_HANDLE,
+        viewport: &D3D12_VIEWPORT,
+        scissor_rect: &RECT,
+    ) -> Result<()> {
+        let vertices = primitives.text_vertices(viewport.Width, viewport.Height);
+        if vertices.is_empty() {
+            return Ok(());
+        }
+        unsafe {
+            command_list.SetGraphicsRootSignature(root_signature);
+            // The text is in clip space already.
+            command_list.SetGraphicsRoot32BitConstants(0, 16, Mat4::IDENTITY.rows.as_ptr() as *const _, 0);
+            command_list.RSSetViewports(&[*viewport]);
+            command_list.RSSetScissorRects(&[*scissor_rect]);
+            command_list.OMSetRenderTargets(1, Some(&back_buffer_rtv), false, None);
+        }
+        draw(&mut self.ring, &self.buffer, command_list, &self.text_pso, &vertices)
+    }
+
+    // The vertices uploaded since the last call were for `frame`.
+    pub fn finish_frame(&mut self, frame: u64) {
+        self.ring.finish_frame(frame);
+    }
+
+    // Reuses the room of `frame` and earlier ones, once the GPU is done
+    // with them.
+    pub fn retire(&mut self, frame: u64) {
+        self.ring.retire(frame);
+    }
+}
+
+// Copies `vertices` into the ring and draws them as a line list. They are
+// dropped for the frame when the ring is full.
+fn draw(
+    ring: &mut UploadRing,
+    buffer: &ID3D12Resource,
+    command_list: &ID3D12GraphicsCommandList,
+    pso: &ID3D12PipelineState,
+    vertices: &[DebugVertex],
+) -> Result<()> {
+    if vertices.is_empty() {
+        return Ok(());
+    }
+    let size = std::mem::size_of_val(vertices);
+    let offset = match ring.allocate(size as u64, std::mem::size_of::<f32>() as u64) {
+        Some(offset) => offset as usize,
+        None => {
+            debug!("debug draw ring full, dropped {} vertices", vertices.len());
+            return Ok(());
+        }
+    };
+    unsafe {
+        let mut mapped = std::ptr::null_mut();
+        buffer.Map(0, Some(&D3D12_RANGE { Begin: 0, End: 0 }), Some(&mut mapped))?;
+        std::ptr::copy_nonoverlapping(
+            vertices.as_ptr(),
+            (mapped as *mut u8).add(offset) as *mut DebugVertex,
+            vertices.len(),
+        );
+        buffer.Unmap(
+            0,
+            Some(&D3D12_RANGE {
+                Begin: offset,
+                End: offset + size,
+            }),
+        );
+
+        command_list.SetPipelineState(pso);
+        command_list.IASetPrimitiveTopology(D3D_PRIMITIVE_TOPOLOGY_LINELIST);
+        command_list.IASetVertexBuffers(
+            0,
+            Some(&[D3D12_VERTEX_BUFFER_VIEW {
+                BufferLocation: buffer.GetGPUVirtualAddress() + offset as u64,
+                StrideInBytes: std::mem::size_of::<DebugVertex>() as u32,
+                SizeInBytes: size as u32,
+            }]),
+        );
+        command_list.DrawInstanced(vertices.len() as u32, 1, 0, 0);
+    }
+    Ok(())
+}
+
+enum LineTarget {
+    Scene { sample_count: u32, depth_test: bool },
+    BackBuffer,
+}
+
+// Alpha blended line lists, never writing depth.
+fn create_debug_pipeline_state(
+    device: &ID3D12Device,
+    root_signature: &ID3D12RootSignature,
+    target: LineTarget,
+) -> Result<ID3D12PipelineState> {
+    let read = |path: &str| {
+        std::fs::read(path).map_err(|error| Error::new(E_FAIL, error.to_string().into()))
+    };
+    let vs_bin = read("resources/debug_vs.bin")?;
+    let ps_bin = read("resources/debug_ps.bin")?;
+
+    let input_element_descs: [D3D12_INPUT_ELEMENT_DESC; 2] = [
+        D3D12_INPUT_ELEMENT_DESC {
+            SemanticName: s!("POSITION"),
+            SemanticIndex: 0,
+            Format: DXGI_FORMAT_R32G32B32_FLOAT,
+            InputSlot: 0,
+            AlignedByteOffset: 0,
+            InputSlotClass: D3D12_INPUT_CLASSIFICATION_PER_VERTEX_DATA,
+            InstanceDataStepRate: 0,
+        },
+        D3D12_INPUT_ELEMENT_DESC {
+            SemanticName: s!("COLOR"),
+            SemanticIndex: 0,
+            Format: DXGI_FORMAT_R32G32B32A32_FLOAT,
+            InputSlot: 0,
+            AlignedByteOffset: 12,
+            InputSlotClass: D3D12_INPUT_CLASSIFICATION_PER_VERTEX_DATA,
+            InstanceDataStepRate: 0,
+        },
+    ];
+
+    let (rtv_format, dsv_format, sample_count, depth_test) = match target {
+        LineTarget::Scene {
+            sample_count,
+            depth_test,
+        } => (SCENE_FORMAT, DEPTH_FORMAT, sample_count, depth_test),
+        LineTarget::BackBuffer => (BACK_BUFFER_VIEW_FORMAT, DXGI_FORMAT_UNKNOWN, 1, false),
+    };
+
+    let mut desc = D3D12_GRAPHICS_PIPELINE_STATE_DESC {
+        InputLayout: D3D12_INPUT_LAYOUT_DESC {
+            pInputElementDescs: input_element_descs.as_ptr(),
+            NumElements: input_element_descs.len() as u32,
+        },
+        pRootSignature: unsafe { std::mem::transmute_copy(root_signature) },
+        VS: convert_to_bytecode(&vs_bin),
+        PS: convert_to_bytecode(&ps_bin),
+        RasterizerState: D3D12_RASTERIZER_DESC {
+            FillMode: D3D12_FILL_MODE_SOLID,
+            CullMode: D3D12_CULL_MODE_NONE,
+            DepthClipEnable: true.into(),
+            ..Default::default()
+        },
+        BlendState: D3D12_BLEND_DESC {
+            RenderTarget: [D3D12_RENDER_TARGET_BLEND_DESC {
+                BlendEnable: true.into(),
+                SrcBlend: D3D12_BLEND_SRC_ALPHA,
+                DestBlend: D3D12_BLEND_INV_SRC_ALPHA,
+                BlendOp: D3D12_BLEND_OP_ADD,
+                SrcBlendAlpha: D3D12_BLEND_ONE,
+                DestBlendAlpha: D3D12_BLEND_INV_SRC_ALPHA,
+                BlendOpAlpha: D3D12_BLEND_OP_ADD,
+                LogicOp: D3D12_LOGIC_OP_NOOP,
+                RenderTargetWriteMask: D3D12_COLOR_WRITE_ENABLE_ALL.0 as u8,
+                ..Default::default()
+            }; 8],
+            ..Default::default()
+        },
+        DepthStencilState: D3D12_DEPTH_STENCIL_DESC {
+            DepthEnable: depth_test.into(),
+            DepthWriteMask: D3D12_DEPTH_WRITE_MASK_ZERO,
+            DepthFunc: D3D12_COMPARISON_FUNC_LESS_EQUAL,
+            ..Default::default()
+        },
+        DSVFormat: dsv_format,
+        SampleMask: u32::MAX,
+        PrimitiveTopologyType: D3D12_PRIMITIVE_TOPOLOGY_TYPE_LINE,
+        NumRenderTargets: 1,
+        SampleDesc: DXGI_SAMPLE_DESC {
+            Count: sample_count,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    desc.RTVFormats[0] = rtv_format;
+
+    unsafe { device.CreateGraphicsPipelineState(&desc) }
+}
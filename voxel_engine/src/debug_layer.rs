@@ -0,0 +1,498 @@
+use std::fmt;
+use std::str::FromStr;
+
+use log::Level;
+
+// D3D12_MESSAGE_ID_CLEARRENDERTARGETVIEW_MISMATCHINGCLEARVALUE and the depth
+// one: the clear color follows the time of day, so it rarely matches the
+// optimized clear value the targets were created with.
+pub const MISMATCHING_CLEAR_VALUE_IDS: [i32; 2] = [820, 821];
+
+// D3D12_MESSAGE_SEVERITY, most severe first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MessageSeverity {
+    Corruption,
+    Error,
+    Warning,
+    Info,
+    Message,
+}
+
+impl MessageSeverity {
+    pub const ALL: [MessageSeverity; 5] = [
+        MessageSeverity::Corruption,
+        MessageSeverity::Error,
+        MessageSeverity::Warning,
+        MessageSeverity::Info,
+        MessageSeverity::Message,
+    ];
+
+    pub fn from_d3d12(severity: i32) -> Option<Self> {
+        Self::ALL.get(usize::try_from(severity).ok()?).copied()
+    }
+
+    pub fn to_d3d12(self) -> i32 {
+        self as i32
+    }
+
+    // The log level the message is recorded at.
+    pub fn level(self) -> Level {
+        match self {
+            MessageSeverity::Corruption | MessageSeverity::Error => Level::Error,
+            MessageSeverity::Warning => Level::Warn,
+            MessageSeverity::Info => Level::Info,
+            MessageSeverity::Message => Level::Debug,
+        }
+    }
+}
+
+impl FromStr for MessageSeverity {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "corruption" => Ok(MessageSeverity::Corruption),
+            "error" => Ok(MessageSeverity::Error),
+            "warning" => Ok(MessageSeverity::Warning),
+            "info" => Ok(MessageSeverity::Info),
+            "message" => Ok(MessageSeverity::Message),
+            _ => Err(format!(
+                "unknown message severity \"{}\", expected corruption, error, warning, info or message",
+                value
+            )),
+        }
+    }
+}
+
+// What the D3D12 debug layer checks and where its findings go. The layer
+// itself costs little, GPU-based validation patches every shader and makes
+// frames several times slower, so it is only on when asked for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DebugSettings {
+    pub debug_layer: bool,
+    pub gpu_based_validation: bool,
+    // Device removed extended data: auto breadcrumbs and page fault
+    // reporting, read back when the device is lost.
+    pub dred: bool,
+    // Messages at least this severe stop in the debugger.
+    pub break_on: Option<MessageSeverity>,
+    // Less severe messages are dropped by the info queue.
+    pub min_severity: MessageSeverity,
+    // D3D12_MESSAGE_ID values that are never logged.
+    pub muted_ids: Vec<i32>,
+}
+
+impl Default for DebugSettings {
+    fn default() -> Self {
+        DebugSettings {
+            debug_layer: cfg!(debug_assertions),
+            gpu_based_validation: false,
+            dred: false,
+            break_on: None,
+            min_severity: MessageSeverity::Info,
+            muted_ids: MISMATCHING_CLEAR_VALUE_IDS.to_vec(),
+        }
+    }
+}
+
+impl DebugSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.gpu_based_validation && !self.debug_layer {
+            return Err("GPU-based validation needs the debug layer".to_string());
+        }
+        if self.break_on.is_some() && !self.debug_layer {
+            return Err("breaking on messages needs the debug layer".to_string());
+        }
+        Ok(())
+    }
+
+    // The severities the storage filter denies.
+    pub fn denied_severities(&self) -> Vec<MessageSeverity> {
+        MessageSeverity::ALL
+            .into_iter()
+            .filter(|&severity| severity > self.min_severity)
+            .collect()
+    }
+
+    // The severities the debugger breaks on.
+    pub fn break_severities(&self) -> Vec<MessageSeverity> {
+        match self.break_on {
+            Some(break_on) => MessageSeverity::ALL
+                .into_iter()
+                .filter(|&severity| severity <= break_on)
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+// The debug settings the command line asks for, on top of the defaults:
+// `--debug-layer`, `--no-debug-layer`, `--gpu-validation` (which turns the
+// layer on too), `--dred`, `--break-on-error`, `--break-on <severity>`,
+// `--debug-messages <severity>` and `--mute-message <id>`. Arguments that
+// are not about debugging are left alone.
+pub fn debug_settings_from_args(args: &[String]) -> Result<DebugSettings, String> {
+    let mut settings = DebugSettings::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |what: &str| {
+            args.next()
+                .ok_or_else(|| format!("{} needs {}", arg, what))
+        };
+        match arg.as_str() {
+            "--debug-layer" => settings.debug_layer = true,
+            "--no-debug-layer" => settings.debug_layer = false,
+            "--gpu-validation" => {
+                settings.debug_layer = true;
+                settings.gpu_based_validation = true;
+            }
+            "--dred" => settings.dred = true,
+            "--break-on-error" => settings.break_on = Some(MessageSeverity::Error),
+            "--break-on" => settings.break_on = Some(value("a severity")?.parse()?),
+            "--debug-messages" => settings.min_severity = value("a severity")?.parse()?,
+            "--mute-message" => {
+                let id = value("a message id")?;
+                let id = id
+                    .parse()
+                    .map_err(|_| format!("\"{}\" is not a D3D12 message id", id))?;
+                settings.muted_ids.push(id);
+            }
+            _ => (),
+        }
+    }
+    Ok(settings)
+}
+
+// D3D12_AUTO_BREADCRUMB_OP names, by value.
+const BREADCRUMB_OPS: [&str; 45] = [
+    "SetMarker",
+    "BeginEvent",
+    "EndEvent",
+    "DrawInstanced",
+    "DrawIndexedInstanced",
+    "ExecuteIndirect",
+    "Dispatch",
+    "CopyBufferRegion",
+    "CopyTextureRegion",
+    "CopyResource",
+    "CopyTiles",
+    "ResolveSubresource",
+    "ClearRenderTargetView",
+    "ClearUnorderedAccessView",
+    "ClearDepthStencilView",
+    "ResourceBarrier",
+    "ExecuteBundle",
+    "Present",
+    "ResolveQueryData",
+    "BeginSubmission",
+    "EndSubmission",
+    "DecodeFrame",
+    "ProcessFrames",
+    "AtomicCopyBufferUint",
+    "AtomicCopyBufferUint64",
+    "ResolveSubresourceRegion",
+    "WriteBufferImmediate",
+    "DecodeFrame1",
+    "SetProtectedResourceSession",
+    "DecodeFrame2",
+    "ProcessFrames1",
+    "BuildRaytracingAccelerationStructure",
+    "EmitRaytracingAccelerationStructurePostbuildInfo",
+    "CopyRaytracingAccelerationStructure",
+    "DispatchRays",
+    "InitializeMetaCommand",
+    "ExecuteMetaCommand",
+    "EstimateMotion",
+    "ResolveMotionVectorHeap",
+    "SetPipelineState1",
+    "InitializeExtensionCommand",
+    "ExecuteExtensionCommand",
+    "DispatchMesh",
+    "EncodeFrame",
+    "ResolveEncoderOutputMetadata",
+];
+
+pub fn breadcrumb_op_name(op: i32) -> String {
+    match usize::try_from(op).ok().and_then(|op| BREADCRUMB_OPS.get(op)) {
+        Some(name) => name.to_string(),
+        None => format!("op {}", op),
+    }
+}
+
+// The auto breadcrumbs of one command list: every operation it recorded and
+// how many of them the GPU finished.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Breadcrumbs {
+    pub command_list: String,
+    pub command_queue: String,
+    pub ops: Vec<i32>,
+    pub completed: u32,
+}
+
+impl Breadcrumbs {
+    pub fn finished(&self) -> bool {
+        self.completed as usize >= self.ops.len()
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PageFault {
+    pub address: u64,
+    // Names of the allocations at the address, alive and recently freed.
+    pub existing: Vec<String>,
+    pub recently_freed: Vec<String>,
+}
+
+// What DRED recorded before the device was removed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DredReport {
+    pub breadcrumbs: Vec<Breadcrumbs>,
+    pub page_fault: Option<PageFault>,
+}
+
+// Operations shown before the one the GPU stopped at.
+const BREADCRUMB_CONTEXT: usize = 4;
+
+impl fmt::Display for DredReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let unfinished: Vec<&Breadcrumbs> = self.breadcrumbs.iter().filter(|list| !list.finished()).collect();
+        if unfinished.is_empty() {
+            write!(f, "every command list in the breadcrumbs finished")?;
+        }
+        for (i, list) in unfinished.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(
+                f,
+                "command list \"{}\" on queue \"{}\" stopped after {} of {} operations",
+                list.command_list,
+                list.command_queue,
+                list.completed,
+                list.ops.len()
+            )?;
+            let stopped = list.completed as usize;
+            for (j, &op) in list.ops.iter().enumerate().skip(stopped.saturating_sub(BREADCRUMB_CONTEXT)) {
+                if j > stopped {
+                    break;
+                }
+                let marker = if j == stopped { "->" } else { "  " };
+                write!(f, "\n  {} {}: {}", marker, j, breadcrumb_op_name(op))?;
+            }
+        }
+        if let Some(page_fault) = &self.page_fault {
+            write!(f, "\npage fault at {:#x}", page_fault.address)?;
+            for name in &page_fault.existing {
+                write!(f, "\n  allocated: {}", name)?;
+            }
+            for name in &page_fault.recently_freed {
+                write!(f, "\n  recently freed: {}", name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn settings(debug_layer: bool) -> DebugSettings {
+        DebugSettings {
+            debug_layer,
+            ..DebugSettings::default()
+        }
+    }
+
+    #[test]
+    fn severities_map_to_d3d12_and_log_levels() {
+        for (value, severity) in MessageSeverity::ALL.into_iter().enumerate() {
+            assert_eq!(MessageSeverity::from_d3d12(value as i32), Some(severity));
+            assert_eq!(severity.to_d3d12(), value as i32);
+        }
+        assert_eq!(MessageSeverity::from_d3d12(-1), None);
+        assert_eq!(MessageSeverity::from_d3d12(5), None);
+
+        let levels: Vec<Level> = MessageSeverity::ALL.into_iter().map(MessageSeverity::level).collect();
+        assert_eq!(levels, [Level::Error, Level::Error, Level::Warn, Level::Info, Level::Debug]);
+    }
+
+    #[test]
+    fn severities_parse() {
+        assert_eq!("corruption".parse(), Ok(MessageSeverity::Corruption));
+        assert_eq!(" Error ".parse(), Ok(MessageSeverity::Error));
+        assert_eq!("WARNING".parse(), Ok(MessageSeverity::Warning));
+        assert_eq!("info".parse(), Ok(MessageSeverity::Info));
+        assert_eq!("Message".parse(), Ok(MessageSeverity::Message));
+        assert_eq!(
+            "fatal".parse::<MessageSeverity>(),
+            Err("unknown message severity \"fatal\", expected corruption, error, warning, info or message".to_string())
+        );
+    }
+
+    #[test]
+    fn thresholds_pick_severities() {
+        use MessageSeverity::*;
+
+        let expected_denied: [&[MessageSeverity]; 5] = [
+            &[Error, Warning, Info, Message],
+            &[Warning, Info, Message],
+            &[Info, Message],
+            &[Message],
+            &[],
+        ];
+        let expected_breaks: [&[MessageSeverity]; 5] = [
+            &[Corruption],
+            &[Corruption, Error],
+            &[Corruption, Error, Warning],
+            &[Corruption, Error, Warning, Info],
+            &[Corruption, Error, Warning, Info, Message],
+        ];
+        for (i, severity) in MessageSeverity::ALL.into_iter().enumerate() {
+            let settings = DebugSettings {
+                min_severity: severity,
+                break_on: Some(severity),
+                ..settings(true)
+            };
+            assert_eq!(settings.denied_severities(), expected_denied[i]);
+            assert_eq!(settings.break_severities(), expected_breaks[i]);
+        }
+        assert_eq!(settings(true).break_severities(), []);
+    }
+
+    #[test]
+    fn settings_come_from_args() {
+        assert_eq!(
+            debug_settings_from_args(&args(&["--fullscreen", "--adapter", "warp"])),
+            Ok(DebugSettings::default())
+        );
+
+        let settings = debug_settings_from_args(&args(&["--no-debug-layer", "--gpu-validation"])).unwrap();
+        assert!(settings.debug_layer);
+        assert!(settings.gpu_based_validation);
+
+        let settings = debug_settings_from_args(&args(&[
+            "--dred",
+            "--break-on",
+            "warning",
+            "--debug-messages",
+            "error",
+            "--mute-message",
+            "42",
+        ]))
+        .unwrap();
+        assert!(settings.dred);
+        assert_eq!(settings.break_on, Some(MessageSeverity::Warning));
+        assert_eq!(settings.min_severity, MessageSeverity::Error);
+        assert_eq!(settings.muted_ids, [820, 821, 42]);
+
+        let settings = debug_settings_from_args(&args(&["--debug-layer", "--break-on-error"])).unwrap();
+        assert!(settings.debug_layer);
+        assert_eq!(settings.break_on, Some(MessageSeverity::Error));
+        assert!(!debug_settings_from_args(&args(&["--debug-layer", "--no-debug-layer"])).unwrap().debug_layer);
+    }
+
+    #[test]
+    fn bad_args_are_errors() {
+        assert_eq!(
+            debug_settings_from_args(&args(&["--break-on"])),
+            Err("--break-on needs a severity".to_string())
+        );
+        assert_eq!(
+            debug_settings_from_args(&args(&["--debug-messages"])),
+            Err("--debug-messages needs a severity".to_string())
+        );
+        assert_eq!(
+            debug_settings_from_args(&args(&["--mute-message"])),
+            Err("--mute-message needs a message id".to_string())
+        );
+        assert_eq!(
+            debug_settings_from_args(&args(&["--mute-message", "clear"])),
+            Err("\"clear\" is not a D3D12 message id".to_string())
+        );
+        assert!(debug_settings_from_args(&args(&["--break-on", "loud"])).is_err());
+    }
+
+    #[test]
+    fn settings_validate() {
+        assert_eq!(settings(false).validate(), Ok(()));
+        assert_eq!(settings(true).validate(), Ok(()));
+        assert_eq!(
+            DebugSettings {
+                gpu_based_validation: true,
+                ..settings(false)
+            }
+            .validate(),
+            Err("GPU-based validation needs the debug layer".to_string())
+        );
+        assert_eq!(
+            DebugSettings {
+                break_on: Some(MessageSeverity::Error),
+                ..settings(false)
+            }
+            .validate(),
+            Err("breaking on messages needs the debug layer".to_string())
+        );
+    }
+
+    #[test]
+    fn breadcrumb_ops_have_names() {
+        assert_eq!(breadcrumb_op_name(0), "SetMarker");
+        assert_eq!(breadcrumb_op_name(5), "ExecuteIndirect");
+        assert_eq!(breadcrumb_op_name(44), "ResolveEncoderOutputMetadata");
+        assert_eq!(breadcrumb_op_name(45), "op 45");
+        assert_eq!(breadcrumb_op_name(-1), "op -1");
+    }
+
+    #[test]
+    fn dred_report_shows_where_the_gpu_stopped() {
+        let finished = Breadcrumbs {
+            command_list: "upload".into(),
+            command_queue: "copy".into(),
+            ops: vec![9],
+            completed: 1,
+        };
+        assert!(finished.finished());
+        let stopped = Breadcrumbs {
+            command_list: "frame".into(),
+            command_queue: "direct".into(),
+            // Barrier, clear, four draws, resolve, end.
+            ops: vec![15, 12, 3, 3, 3, 3, 11, 2],
+            completed: 6,
+        };
+        assert!(!stopped.finished());
+
+        let report = DredReport {
+            breadcrumbs: vec![finished.clone()],
+            page_fault: None,
+        };
+        assert_eq!(report.to_string(), "every command list in the breadcrumbs finished");
+
+        let report = DredReport {
+            breadcrumbs: vec![finished, stopped],
+            page_fault: Some(PageFault {
+                address: 0x1f000,
+                existing: vec!["scene".into()],
+                recently_freed: vec!["chunk 3 vertices".into()],
+            }),
+        };
+        assert_eq!(
+            report.to_string().lines().collect::<Vec<_>>(),
+            [
+                "command list \"frame\" on queue \"direct\" stopped after 6 of 8 operations",
+                "     2: DrawInstanced",
+                "     3: DrawInstanced",
+                "     4: DrawInstanced",
+                "     5: DrawInstanced",
+                "  -> 6: ResolveSubresource",
+                "page fault at 0x1f000",
+                "  allocated: scene",
+                "  recently freed: chunk 3 vertices",
+            ]
+        );
+    }
+}
@@ -0,0 +1,582 @@
+use std::time::Instant;
+
+use backend::PresentMode;
+use egui::{
+    epaint::ClippedPrimitive, pos2, vec2, ComboBox, Context, DragValue, Event, Key, Modifiers, PointerButton,
+    Pos2, RawInput, Rect, Slider, TexturesDelta, Ui, Window,
+};
+use winit::event::{ElementState, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent};
+
+use crate::camera::Camera;
+use crate::post::{BloomSettings, PostSettings, Tonemapper};
+use crate::shadow::ShadowSettings;
+use crate::sky::FogSettings;
+use crate::streaming::VIEW_DISTANCES;
+
+// Points a mouse wheel notch scrolls by.
+const SCROLL_LINE: f32 = 50.0;
+
+// Largest texture egui may ask for, the D3D12 limit.
+const MAX_TEXTURE_SIDE: usize = 16384;
+
+// Frame statistics the stats panel shows.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct UiStats {
+    pub cpu_ms: f64,
+    pub gpu_ms: f64,
+    pub frame: u64,
+    pub resident_chunks: usize,
+    pub drawn_chunks: u32,
+    pub culled_chunks: u32,
+}
+
+// What the panels show and edit. The engine fills it in before the UI runs
+// and applies whatever changed afterwards.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UiState {
+    pub stats: UiStats,
+    pub camera: Camera,
+    // In chunks.
+    pub view_distance: i32,
+    pub fog: FogSettings,
+    // Fraction of the day, 0 and 1 at midnight.
+    pub time_of_day: f32,
+    pub time_scale: f32,
+    pub paused: bool,
+    pub shadows: ShadowSettings,
+    pub post: PostSettings,
+    pub ray_marched: bool,
+    // Whether the device can ray march at all.
+    pub ray_march_available: bool,
+    pub debug_view: bool,
+    pub present_mode: PresentMode,
+}
+
+// A frame of the UI for dev_ui_pass.rs: triangles in points, clipped to
+// rectangles in points, and the texture changes to make before drawing them.
+pub struct UiFrame {
+    pub primitives: Vec<ClippedPrimitive>,
+    pub textures: TexturesDelta,
+    pub pixels_per_point: f32,
+}
+
+// The developer UI, toggled with F1: egui panels over the frame for the
+// stats, the camera, the world and the renderer settings. Takes the winit
+// events of the window and produces a UiFrame per frame while visible.
+pub struct DevUi {
+    context: Context,
+    // Events since the last frame.
+    input: RawInput,
+    // In points, None while the cursor is outside the window.
+    pointer: Option<Pos2>,
+    modifiers: Modifiers,
+    focused: bool,
+    pixels_per_point: f32,
+    visible: bool,
+    start: Instant,
+}
+
+impl DevUi {
+    pub fn new(pixels_per_point: f32) -> Self {
+        DevUi {
+            context: Context::default(),
+            input: RawInput::default(),
+            pointer: None,
+            modifiers: Modifiers::default(),
+            focused: true,
+            pixels_per_point,
+            visible: false,
+            start: Instant::now(),
+        }
+    }
+
+    // Starts over with a new context after the renderer lost its textures,
+    // so the font atlas is sent again. Keeps whether the UI is shown.
+    pub fn reset(&mut self, pixels_per_point: f32) {
+        *self = DevUi {
+            visible: self.visible,
+            ..DevUi::new(pixels_per_point)
+        };
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+        // Nothing the UI held on to, like a pressed button, carries over.
+        self.input = RawInput::default();
+    }
+
+    // Queues `event` for the next frame. Returns whether the UI used it, in
+    // which case the engine should not act on it too.
+    pub fn handle_event(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                self.pixels_per_point = *scale_factor as f32;
+                return false;
+            }
+            WindowEvent::ModifiersChanged(state) => {
+                self.modifiers = Modifiers {
+                    alt: state.alt(),
+                    ctrl: state.ctrl(),
+                    shift: state.shift(),
+                    mac_cmd: false,
+                    command: state.ctrl(),
+                };
+                return false;
+            }
+            WindowEvent::Focused(focused) => {
+                self.focused = *focused;
+                return false;
+            }
+            _ => (),
+        }
+        if !self.visible {
+            return false;
+        }
+
+        let pointer_event = match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                let position = pos2(
+                    position.x as f32 / self.pixels_per_point,
+                    position.y as f32 / self.pixels_per_point,
+                );
+                self.pointer = Some(position);
+                self.input.events.push(Event::PointerMoved(position));
+                // The engine keeps tracking the cursor under the panels.
+                return false;
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.pointer = None;
+                self.input.events.push(Event::PointerGone);
+                return false;
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                let (button, pos) = match (pointer_button(*button), self.pointer) {
+                    (Some(button), Some(pos)) => (button, pos),
+                    _ => return false,
+                };
+                self.input.events.push(Event::PointerButton {
+                    pos,
+                    button,
+                    pressed: *state == ElementState::Pressed,
+                    modifiers: self.modifiers,
+                });
+                true
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let delta = match delta {
+                    MouseScrollDelta::LineDelta(x, y) => vec2(*x, *y) * SCROLL_LINE,
+                    MouseScrollDelta::PixelDelta(delta) => {
+                        vec2(delta.x as f32, delta.y as f32) / self.pixels_per_point
+                    }
+                };
+                self.input.events.push(Event::Scroll(delta));
+                true
+            }
+            WindowEvent::ReceivedCharacter(c) => {
+                if c.is_control() {
+                    return false;
+                }
+                self.input.events.push(Event::Text(c.to_string()));
+                false
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } => {
+                // The F keys stay with the engine, so F1 still hides the UI.
+                // Other keys are the UI's while it has keyboard focus, even
+                // those egui has no use for, or typing a digit would also
+                // select a block.
+                if is_engine_hotkey(*key) {
+                    return false;
+                }
+                if let Some(key) = egui_key(*key) {
+                    self.input.events.push(Event::Key {
+                        key,
+                        pressed: *state == ElementState::Pressed,
+                        repeat: false,
+                        modifiers: self.modifiers,
+                    });
+                }
+                false
+            }
+            _ => return false,
+        };
+
+        if pointer_event {
+            self.context.wants_pointer_input()
+        } else {
+            self.context.wants_keyboard_input()
+        }
+    }
+
+    // Runs the panels over `state` for a window of `size` pixels. None while
+    // the UI is hidden.
+    pub fn run(&mut self, size: [u32; 2], state: &mut UiState) -> Option<UiFrame> {
+        if !self.visible {
+            return None;
+        }
+        let pixels_per_point = self.pixels_per_point;
+        let mut input = std::mem::take(&mut self.input);
+        input.screen_rect = Some(Rect::from_min_size(
+            Pos2::ZERO,
+            vec2(size[0] as f32, size[1] as f32) / pixels_per_point,
+        ));
+        input.pixels_per_point = Some(pixels_per_point);
+        input.max_texture_side = Some(MAX_TEXTURE_SIDE);
+        input.time = Some(self.start.elapsed().as_secs_f64());
+        input.modifiers = self.modifiers;
+        input.focused = self.focused;
+
+        let output = self.context.run(input, |context| {
+            stats_panel(context, &state.stats);
+            Window::new("Camera")
+                .default_pos([8.0, 160.0])
+                .resizable(false)
+                .show(context, |ui| camera_panel(ui, &mut state.camera));
+            Window::new("World")
+                .default_pos([8.0, 320.0])
+                .resizable(false)
+                .show(context, |ui| world_panel(ui, state));
+            Window::new("Renderer")
+                .default_pos([260.0, 8.0])
+                .resizable(false)
+                .show(context, |ui| renderer_panel(ui, state));
+        });
+        Some(UiFrame {
+            primitives: self.context.tessellate(output.shapes),
+            textures: output.textures_delta,
+            pixels_per_point,
+        })
+    }
+}
+
+fn stats_panel(context: &Context, stats: &UiStats) {
+    Window::new("Stats")
+        .default_pos([8.0, 8.0])
+        .resizable(false)
+        .show(context, |ui| {
+            let fps = if stats.cpu_ms > 0.0 { 1000.0 / stats.cpu_ms } else { 0.0 };
+            ui.label(format!("cpu {:.2} ms ({:.0} fps)", stats.cpu_ms, fps));
+            ui.label(format!("gpu {:.2} ms", stats.gpu_ms));
+            ui.label(format!("frame {}", stats.frame));
+            ui.separator();
+            ui.label(format!("{} chunks resident", stats.resident_chunks));
+            ui.label(format!(
+                "{} drawn, {} culled",
+                stats.drawn_chunks, stats.culled_chunks
+            ));
+        });
+}
+
+fn camera_panel(ui: &mut Ui, camera: &mut Camera) {
+    ui.horizontal(|ui| {
+        ui.label("position");
+        ui.add(DragValue::new(&mut camera.position.x).speed(0.1).prefix("x "));
+        ui.add(DragValue::new(&mut camera.position.y).speed(0.1).prefix("y "));
+        ui.add(DragValue::new(&mut camera.position.z).speed(0.1).prefix("z "));
+    });
+    degrees_slider(ui, &mut camera.yaw, -180.0..=180.0, "yaw");
+    degrees_slider(ui, &mut camera.pitch, -89.0..=89.0, "pitch");
+    degrees_slider(ui, &mut camera.fov_y, 30.0..=110.0, "vertical fov");
+}
+
+// Edits an angle in radians as degrees.
+fn degrees_slider(ui: &mut Ui, radians: &mut f32, range: std::ops::RangeInclusive<f32>, text: &str) {
+    let mut degrees = radians.to_degrees();
+    if ui.add(Slider::new(&mut degrees, range).suffix("°").text(text)).changed() {
+        *radians = degrees.to_radians();
+    }
+}
+
+fn world_panel(ui: &mut Ui, state: &mut UiState) {
+    ui.add(Slider::new(&mut state.view_distance, VIEW_DISTANCES).text("view distance"));
+    ui.add(Slider::new(&mut state.fog.start, 0.0..=state.fog.end).text("fog start"));
+    ui.add(Slider::new(&mut state.fog.end, 1.0..=512.0).text("fog end"));
+    ui.separator();
+    ui.add(Slider::new(&mut state.time_of_day, 0.0..=1.0).text("time of day"));
+    ui.add(
+        Slider::new(&mut state.time_scale, 0.01..=1000.0)
+            .logarithmic(true)
+            .text("time scale"),
+    );
+    ui.checkbox(&mut state.paused, "paused");
+}
+
+fn renderer_panel(ui: &mut Ui, state: &mut UiState) {
+    ui.horizontal(|ui| {
+        ui.radio_value(&mut state.ray_marched, false, "rasterized");
+        ui.add_enabled_ui(state.ray_march_available, |ui| {
+            ui.radio_value(&mut state.ray_marched, true, "ray marched");
+        });
+    });
+    ui.checkbox(&mut state.debug_view, "debug view");
+    ui.horizontal(|ui| {
+        ui.label("present");
+        for mode in PresentMode::ALL {
+            ui.radio_value(&mut state.present_mode, mode, mode.name());
+        }
+    });
+    ui.separator();
+
+    ui.label("shadows");
+    ui.add(Slider::new(&mut state.shadows.max_distance, 16.0..=512.0).text("max distance"));
+    ui.add(Slider::new(&mut state.shadows.split_lambda, 0.0..=1.0).text("split lambda"));
+    ui.separator();
+
+    let post = &mut state.post;
+    ui.label("post processing");
+    ComboBox::from_label("tonemapper")
+        .selected_text(format!("{:?}", post.tonemapper))
+        .show_ui(ui, |ui| {
+            ui.selectable_value(&mut post.tonemapper, Tonemapper::Aces, "Aces");
+            ui.selectable_value(&mut post.tonemapper, Tonemapper::Reinhard, "Reinhard");
+        });
+    ui.add(
+        Slider::new(&mut post.exposure, 0.0625..=16.0)
+            .logarithmic(true)
+            .text("exposure"),
+    );
+    ui.add(Slider::new(&mut post.gamma, 1.8..=2.6).text("gamma"));
+    ui.checkbox(&mut post.fxaa, "fxaa");
+    let mut bloom = post.bloom.is_some();
+    if ui.checkbox(&mut bloom, "bloom").changed() {
+        post.bloom = bloom.then(BloomSettings::default);
+    }
+    if let Some(bloom) = &mut post.bloom {
+        ui.add(Slider::new(&mut bloom.threshold, 0.0..=4.0).text("threshold"));
+        ui.add(Slider::new(&mut bloom.knee, 0.0..=2.0).text("knee"));
+        ui.add(Slider::new(&mut bloom.intensity, 0.0..=2.0).text("intensity"));
+    }
+}
+
+fn pointer_button(button: MouseButton) -> Option<PointerButton> {
+    match button {
+        MouseButton::Left => Some(PointerButton::Primary),
+        MouseButton::Right => Some(PointerButton::Secondary),
+        MouseButton::Middle => Some(PointerButton::Middle),
+        MouseButton::Other(_) => None,
+    }
+}
+
+fn is_engine_hotkey(key: VirtualKeyCode) -> bool {
+    matches!(
+        key,
+        VirtualKeyCode::F1
+            | VirtualKeyCode::F2
+            | VirtualKeyCode::F3
+            | VirtualKeyCode::F4
+            | VirtualKeyCode::F5
+            | VirtualKeyCode::F6
+            | VirtualKeyCode::F7
+            | VirtualKeyCode::F8
+            | VirtualKeyCode::F9
+    )
+}
+
+// The keys text fields and focus navigation use.
+fn egui_key(key: VirtualKeyCode) -> Option<Key> {
+    Some(match key {
+        VirtualKeyCode::Left => Key::ArrowLeft,
+        VirtualKeyCode::Right => Key::ArrowRight,
+        VirtualKeyCode::Up => Key::ArrowUp,
+        VirtualKeyCode::Down => Key::ArrowDown,
+        VirtualKeyCode::Escape => Key::Escape,
+        VirtualKeyCode::Tab => Key::Tab,
+        VirtualKeyCode::Back => Key::Backspace,
+        VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => Key::Enter,
+        VirtualKeyCode::Space => Key::Space,
+        VirtualKeyCode::Insert => Key::Insert,
+        VirtualKeyCode::Delete => Key::Delete,
+        VirtualKeyCode::Home => Key::Home,
+        VirtualKeyCode::End => Key::End,
+        VirtualKeyCode::PageUp => Key::PageUp,
+        VirtualKeyCode::PageDown => Key::PageDown,
+        VirtualKeyCode::A => Key::A,
+        VirtualKeyCode::C => Key::C,
+        VirtualKeyCode::V => Key::V,
+        VirtualKeyCode::X => Key::X,
+        VirtualKeyCode::Z => Key::Z,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use egui::{Id, TextureId};
+    use winit::dpi::{PhysicalPosition, PhysicalSize};
+    use winit::event::{DeviceId, ModifiersState, TouchPhase};
+
+    use crate::chunk::CHUNK_SIZE;
+
+    fn device_id() -> DeviceId {
+        unsafe { DeviceId::dummy() }
+    }
+
+    #[allow(deprecated)]
+    fn cursor_moved(x: f64, y: f64) -> WindowEvent<'static> {
+        WindowEvent::CursorMoved {
+            device_id: device_id(),
+            position: PhysicalPosition::new(x, y),
+            modifiers: ModifiersState::empty(),
+        }
+    }
+
+    #[allow(deprecated)]
+    fn wheel(delta: MouseScrollDelta) -> WindowEvent<'static> {
+        WindowEvent::MouseWheel {
+            device_id: device_id(),
+            delta,
+            phase: TouchPhase::Moved,
+            modifiers: ModifiersState::empty(),
+        }
+    }
+
+    #[allow(deprecated)]
+    fn key(key: VirtualKeyCode) -> WindowEvent<'static> {
+        WindowEvent::KeyboardInput {
+            device_id: device_id(),
+            input: KeyboardInput {
+                scancode: 0,
+                state: ElementState::Pressed,
+                virtual_keycode: Some(key),
+                modifiers: ModifiersState::empty(),
+            },
+            is_synthetic: false,
+        }
+    }
+
+    fn state() -> UiState {
+        UiState {
+            stats: UiStats::default(),
+            camera: Camera::default(),
+            view_distance: 8,
+            fog: FogSettings::for_view_distance(8, CHUNK_SIZE),
+            time_of_day: 0.5,
+            time_scale: 1.0,
+            paused: false,
+            shadows: ShadowSettings::default(),
+            post: PostSettings::default(),
+            ray_marched: false,
+            ray_march_available: true,
+            debug_view: false,
+            present_mode: PresentMode::Vsync,
+        }
+    }
+
+    #[test]
+    fn hidden_ui_takes_no_events() {
+        let mut ui = DevUi::new(1.0);
+        assert!(!ui.handle_event(&cursor_moved(10.0, 10.0)));
+        assert!(!ui.handle_event(&wheel(MouseScrollDelta::LineDelta(0.0, 1.0))));
+        assert!(!ui.handle_event(&key(VirtualKeyCode::Key5)));
+        assert!(!ui.handle_event(&WindowEvent::ReceivedCharacter('5')));
+        assert!(ui.input.events.is_empty());
+        assert!(ui.run([640, 480], &mut state()).is_none());
+    }
+
+    #[test]
+    fn positions_are_in_points() {
+        let mut ui = DevUi::new(1.0);
+        ui.toggle();
+        let mut size = PhysicalSize::new(1280, 960);
+        assert!(!ui.handle_event(&WindowEvent::ScaleFactorChanged {
+            scale_factor: 2.0,
+            new_inner_size: &mut size,
+        }));
+        assert_eq!(ui.pixels_per_point, 2.0);
+
+        // The engine keeps the cursor as well.
+        assert!(!ui.handle_event(&cursor_moved(200.0, 100.0)));
+        assert_eq!(ui.pointer, Some(pos2(100.0, 50.0)));
+        assert_eq!(ui.input.events, [Event::PointerMoved(pos2(100.0, 50.0))]);
+    }
+
+    #[test]
+    fn wheel_lines_scroll_by_points() {
+        let mut ui = DevUi::new(2.0);
+        ui.toggle();
+        ui.handle_event(&wheel(MouseScrollDelta::LineDelta(0.0, -2.0)));
+        ui.handle_event(&wheel(MouseScrollDelta::PixelDelta(PhysicalPosition::new(10.0, 40.0))));
+        assert_eq!(
+            ui.input.events,
+            [
+                Event::Scroll(vec2(0.0, -2.0 * SCROLL_LINE)),
+                Event::Scroll(vec2(5.0, 20.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn toggling_drops_queued_input() {
+        let mut ui = DevUi::new(1.0);
+        ui.toggle();
+        ui.handle_event(&cursor_moved(10.0, 10.0));
+        ui.handle_event(&WindowEvent::ReceivedCharacter('a'));
+        assert_eq!(ui.input.events.len(), 2);
+        ui.toggle();
+        assert!(ui.input.events.is_empty());
+        ui.toggle();
+        assert!(ui.input.events.is_empty());
+    }
+
+    #[test]
+    fn focused_ui_keeps_keys_but_the_f_keys() {
+        let mut ui = DevUi::new(1.0);
+        ui.toggle();
+        assert!(!ui.handle_event(&key(VirtualKeyCode::Key5)));
+
+        ui.context.memory_mut(|memory| memory.request_focus(Id::new("field")));
+        assert!(ui.handle_event(&key(VirtualKeyCode::Key5)));
+        assert!(ui.handle_event(&key(VirtualKeyCode::W)));
+        assert!(ui.handle_event(&key(VirtualKeyCode::Back)));
+        assert!(!ui.handle_event(&key(VirtualKeyCode::F1)));
+        assert!(!ui.handle_event(&key(VirtualKeyCode::F9)));
+        // Only the keys egui knows are queued.
+        assert_eq!(
+            ui.input.events,
+            [Event::Key {
+                key: Key::Backspace,
+                pressed: true,
+                repeat: false,
+                modifiers: Modifiers::default(),
+            }]
+        );
+    }
+
+    #[test]
+    fn first_frame_sends_the_font_atlas() {
+        let mut ui = DevUi::new(1.0);
+        ui.toggle();
+        let frame = ui.run([640, 480], &mut state()).unwrap();
+        assert_eq!(frame.pixels_per_point, 1.0);
+        // The whole atlas, later frames only patch it.
+        assert!(frame
+            .textures
+            .set
+            .iter()
+            .any(|(id, delta)| *id == TextureId::default() && delta.pos.is_none()));
+
+        // Windows are only sized on their first frame, and drawn after.
+        let frame = ui.run([640, 480], &mut state()).unwrap();
+        assert!(!frame.primitives.is_empty());
+    }
+
+    #[test]
+    fn f_keys_stay_with_the_engine() {
+        assert_eq!(egui_key(VirtualKeyCode::F1), None);
+        assert_eq!(egui_key(VirtualKeyCode::F9), None);
+        assert_eq!(egui_key(VirtualKeyCode::Key5), None);
+        assert_eq!(egui_key(VirtualKeyCode::Return), Some(Key::Enter));
+        assert!(is_engine_hotkey(VirtualKeyCode::F5));
+        assert!(!is_engine_hotkey(VirtualKeyCode::F10));
+        assert!(!is_engine_hotkey(VirtualKeyCode::Escape));
+    }
+}
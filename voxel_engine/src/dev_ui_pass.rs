@@ -0,0 +1,547 @@
+use std::collections::HashMap;
+
+use egui::epaint::{ImageData, ImageDelta, Mesh, Primitive, TextureId, Vertex};
+use windows::{
+    core::*, Win32::Foundation::*, Win32::Graphics::Direct3D::*,
+    Win32::Graphics::Direct3D12::*, Win32::Graphics::Dxgi::Common::*,
+};
+
+use crate::dev_ui::UiFrame;
+use crate::markers::set_name;
+use crate::{
+    convert_to_bytecode, create_buffer, create_texture, serialize_root_signature, transition_barrier,
+    BACK_BUFFER_VIEW_FORMAT,
+};
+
+// Textures the UI can have at once: the font atlas and whatever images the
+// panels show.
+const MAX_TEXTURES: u32 = 16;
+
+// egui images are premultiplied sRGB, sampled as linear.
+const TEXTURE_FORMAT: DXGI_FORMAT = DXGI_FORMAT_R8G8B8A8_UNORM_SRGB;
+
+struct UiTexture {
+    resource: ID3D12Resource,
+    // Of its SRV in the descriptor heap.
+    slot: u32,
+}
+
+// A growable upload buffer the UI geometry is rewritten into every frame.
+#[derive(Default)]
+struct GeometryBuffer {
+    buffer: Option<ID3D12Resource>,
+    capacity: u64,
+}
+
+// Draws dev_ui.rs frames over the back buffer with shaders/ui.hlsl: the
+// textures egui asks for, then its meshes, each clipped by a scissor rect.
+pub struct DevUiPass {
+    root_signature: ID3D12RootSignature,
+    pso: ID3D12PipelineState,
+    srv_heap: ID3D12DescriptorHeap,
+    srv_descriptor_size: usize,
+    textures: HashMap<TextureId, UiTexture>,
+    free_slots: Vec<u32>,
+    vertices: GeometryBuffer,
+    indices: GeometryBuffer,
+    // Uploads and freed textures of the last frame, released once the GPU
+    // is done with it, which holds by the next one.
+    retired: Vec<ID3D12Resource>,
+}
+
+impl DevUiPass {
+    pub fn new(device: &ID3D12Device) -> Result<Self> {
+        let root_signature = create_ui_root_signature(device)?;
+        let pso = create_ui_pipeline_state(device, &root_signature)?;
+        let srv_heap: ID3D12DescriptorHeap = unsafe {
+            device.CreateDescriptorHeap(&D3D12_DESCRIPTOR_HEAP_DESC {
+                NumDescriptors: MAX_TEXTURES,
+                Type: D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV,
+                Flags: D3D12_DESCRIPTOR_HEAP_FLAG_SHADER_VISIBLE,
+                ..Default::default()
+            })
+        }?;
+        set_name(&root_signature, "ui root signature");
+        set_name(&pso, "ui pso");
+        set_name(&srv_heap, "ui srv heap");
+        Ok(DevUiPass {
+            root_signature,
+            pso,
+            srv_heap,
+            srv_descriptor_size: unsafe {
+                device.GetDescriptorHandleIncrementSize(D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV)
+            } as usize,
+            textures: HashMap::new(),
+            free_slots: (0..MAX_TEXTURES).rev().collect(),
+            vertices: GeometryBuffer::default(),
+            indices: GeometryBuffer::default(),
+            retired: Vec::new(),
+        })
+    }
+
+    // Applies the frame's texture changes and draws it over the back buffer
+    // at `back_buffer_rtv`, which is expected to be a render target. Sets the
+    // root signature, pipeline state and viewport it needs.
+    pub fn record(
+        &mut self,
+        device: &ID3D12Device,
+        command_list: &ID3D12GraphicsCommandList,
+        frame: &UiFrame,
+        back_buffer_rtv: D3D12_CPU_DESCRIPTOR_HANDLE,
+        viewport: &D3D12_VIEWPORT,
+    ) -> Result<()> {
+        self.retired.clear();
+        for (id, delta) in &frame.textures.set {
+            self.set_texture(device, command_list, *id, delta)?;
+        }
+
+        let meshes: Vec<(&egui::Rect, &Mesh)> = frame
+            .primitives
+            .iter()
+            .filter_map(|primitive| match &primitive.primitive {
+                Primitive::Mesh(mesh) if !mesh.indices.is_empty() => Some((&primitive.clip_rect, mesh)),
+                // Paint callbacks are for custom renderers, the panels
+                // have none.
+                _ => None,
+            })
+            .collect();
+        if !meshes.is_empty() {
+            self.draw(device, command_list, &meshes, frame.pixels_per_point, back_buffer_rtv, viewport)?;
+        }
+
+        // After the draws, which may still use them.
+        for id in &frame.textures.free {
+            if let Some(texture) = self.textures.remove(id) {
+                self.free_slots.push(texture.slot);
+                self.retired.push(texture.resource);
+            }
+        }
+        Ok(())
+    }
+
+    fn draw(
+        &mut self,
+        device: &ID3D12Device,
+        command_list: &ID3D12GraphicsCommandList,
+        meshes: &[(&egui::Rect, &Mesh)],
+        pixels_per_point: f32,
+        back_buffer_rtv: D3D12_CPU_DESCRIPTOR_HANDLE,
+        viewport: &D3D12_VIEWPORT,
+    ) -> Result<()> {
+        let vertices: Vec<Vertex> = meshes.iter().flat_map(|(_, mesh)| mesh.vertices.iter().copied()).collect();
+        let indices: Vec<u32> = meshes.iter().flat_map(|(_, mesh)| mesh.indices.iter().copied()).collect();
+        let vertex_buffer = self.vertices.write(device, &vertices, "ui vertices")?;
+        let index_buffer = self.indices.write(device, &indices, "ui indices")?;
+
+        let screen_size = [viewport.Width / pixels_per_point, viewport.Height / pixels_per_point];
+        unsafe {
+            command_list.SetGraphicsRootSignature(&self.root_signature);
+            command_list.SetPipelineState(&self.pso);
+            command_list.SetDescriptorHeaps(&[Some(self.srv_heap.clone())]);
+            command_list.SetGraphicsRoot32BitConstants(0, 2, screen_size.as_ptr() as *const _, 0);
+            command_list.RSSetViewports(&[*viewport]);
+            command_list.OMSetRenderTargets(1, Some(&back_buffer_rtv), false, None);
+            command_list.IASetPrimitiveTopology(D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST);
+            command_list.IASetVertexBuffers(
+                0,
+                Some(&[D3D12_VERTEX_BUFFER_VIEW {
+                    BufferLocation: vertex_buffer.GetGPUVirtualAddress(),
+                    StrideInBytes: std::mem::size_of::<Vertex>() as u32,
+                    SizeInBytes: std::mem::size_of_val(vertices.as_slice()) as u32,
+                }]),
+            );
+            command_list.IASetIndexBuffer(Some(&D3D12_INDEX_BUFFER_VIEW {
+                BufferLocation: index_buffer.GetGPUVirtualAddress(),
+                SizeInBytes: std::mem::size_of_val(indices.as_slice()) as u32,
+                Format: DXGI_FORMAT_R32_UINT,
+            }));
+        }
+
+        let heap_start = unsafe { self.srv_heap.GetGPUDescriptorHandleForHeapStart() };
+        let (mut first_vertex, mut first_index) = (0, 0);
+        for (clip_rect, mesh) in meshes {
+            let (vertex_count, index_count) = (mesh.vertices.len(), mesh.indices.len());
+            let scissor_rect = RECT {
+                left: (clip_rect.min.x * pixels_per_point).round().clamp(0.0, viewport.Width) as i32,
+                top: (clip_rect.min.y * pixels_per_point).round().clamp(0.0, viewport.Height) as i32,
+                right: (clip_rect.max.x * pixels_per_point).round().clamp(0.0, viewport.Width) as i32,
+                bottom: (clip_rect.max.y * pixels_per_point).round().clamp(0.0, viewport.Height) as i32,
+            };
+            let clipped = scissor_rect.right <= scissor_rect.left || scissor_rect.bottom <= scissor_rect.top;
+            match self.textures.get(&mesh.texture_id) {
+                Some(texture) if !clipped => unsafe {
+                    command_list.RSSetScissorRects(&[scissor_rect]);
+                    command_list.SetGraphicsRootDescriptorTable(
+                        1,
+                        D3D12_GPU_DESCRIPTOR_HANDLE {
+                            ptr: heap_start.ptr + (texture.slot as usize * self.srv_descriptor_size) as u64,
+                        },
+                    );
+                    command_list.DrawIndexedInstanced(
+                        index_count as u32,
+                        1,
+                        first_index as u32,
+                        first_vertex as i32,
+                        0,
+                    );
+                },
+                _ => (),
+            }
+            first_vertex += vertex_count;
+            first_index += index_count;
+        }
+        Ok(())
+    }
+
+    // Creates the texture `id` when the delta covers all of it, or copies the
+    // delta into the existing one.
+    fn set_texture(
+        &mut self,
+        device: &ID3D12Device,
+        command_list: &ID3D12GraphicsCommandList,
+        id: TextureId,
+        delta: &ImageDelta,
+    ) -> Result<()> {
+        let [width, height] = delta.image.size();
+        let pixels: Vec<egui::Color32> = match &delta.image {
+            ImageData::Color(image) => image.pixels.clone(),
+            ImageData::Font(image) => image.srgba_pixels(None).collect(),
+        };
+
+        let resource = match delta.pos {
+            // A patch, like the glyphs added to the font atlas.
+            Some(_) => {
+                let resource = match self.textures.get(&id) {
+                    Some(texture) => texture.resource.clone(),
+                    None => {
+                        return Err(Error::new(
+                            E_INVALIDARG,
+                            format!("update of the unknown ui texture {:?}", id).into(),
+                        ))
+                    }
+                };
+                unsafe {
+                    command_list.ResourceBarrier(&[transition_barrier(
+                        &resource,
+                        D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE,
+                        D3D12_RESOURCE_STATE_COPY_DEST,
+                    )])
+                };
+                resource
+            }
+            None => {
+                let resource = create_texture(
+                    device,
+                    &D3D12_RESOURCE_DESC {
+                        Dimension: D3D12_RESOURCE_DIMENSION_TEXTURE2D,
+                        Width: width as u64,
+                        Height: height as u32,
+                        DepthOrArraySize: 1,
+                        MipLevels: 1,
+                        Format: TEXTURE_FORMAT,
+                        SampleDesc: DXGI_SAMPLE_DESC {
+                            Count: 1,
+                            Quality: 0,
+                        },
+                        ..Default::default()
+                    },
+                    D3D12_RESOURCE_STATE_COPY_DEST,
+                    None,
+                )?;
+                set_name(&resource, &format!("ui texture {:?}", id));
+                let slot = match self.textures.remove(&id) {
+                    Some(previous) => {
+                        self.retired.push(previous.resource);
+                        previous.slot
+                    }
+                    None => self
+                        .free_slots
+                        .pop()
+                        .ok_or_else(|| Error::new(E_OUTOFMEMORY, "out of ui texture slots".into()))?,
+                };
+                unsafe {
+                    device.CreateShaderResourceView(
+                        &resource,
+                        Some(&D3D12_SHADER_RESOURCE_VIEW_DESC {
+                            Format: TEXTURE_FORMAT,
+                            ViewDimension: D3D12_SRV_DIMENSION_TEXTURE2D,
+                            Shader4ComponentMapping: D3D12_DEFAULT_SHADER_4_COMPONENT_MAPPING,
+                            Anonymous: D3D12_SHADER_RESOURCE_VIEW_DESC_0 {
+                                Texture2D: D3D12_TEX2D_SRV {
+                                    MostDetailedMip: 0,
+                                    MipLevels: 1,
+                                    PlaneSlice: 0,
+                                    ResourceMinLODClamp: 0.0,
+                                },
+                            },
+                        }),
+                        D3D12_CPU_DESCRIPTOR_HANDLE {
+                            ptr: self.srv_heap.GetCPUDescriptorHandleForHeapStart().ptr
+                                + slot as usize * self.srv_descriptor_size,
+                        },
+                    )
+                };
+                self.textures.insert(
+                    id,
+                    UiTexture {
+                        resource: resource.clone(),
+                        slot,
+                    },
+                );
+                resource
+            }
+        };
+
+        // Rows in the upload buffer are padded to the copy pitch alignment.
+        let row_size = width * std::mem::size_of::<egui::Color32>();
+        let alignment = D3D12_TEXTURE_DATA_PITCH_ALIGNMENT as usize;
+        let row_pitch = (row_size + alignment - 1) & !(alignment - 1);
+        let upload = create_buffer(
+            device,
+            D3D12_HEAP_TYPE_UPLOAD,
+            (row_pitch * height) as u64,
+            D3D12_RESOURCE_FLAG_NONE,
+            D3D12_RESOURCE_STATE_GENERIC_READ,
+        )?;
+        set_name(&upload, "ui texture upload");
+        unsafe {
+            let mut mapped = std::ptr::null_mut();
+            upload.Map(0, Some(&D3D12_RANGE { Begin: 0, End: 0 }), Some(&mut mapped))?;
+            for (row, row_pixels) in pixels.chunks(width).enumerate() {
+                std::ptr::copy_nonoverlapping(
+                    row_pixels.as_ptr() as *const u8,
+                    (mapped as *mut u8).add(row * row_pitch),
+                    row_size,
+                );
+            }
+            upload.Unmap(0, None);
+
+            let [x, y] = delta.pos.unwrap_or([0, 0]);
+            command_list.CopyTextureRegion(
+                &D3D12_TEXTURE_COPY_LOCATION {
+                    pResource: std::mem::transmute_copy(&resource),
+                    Type: D3D12_TEXTURE_COPY_TYPE_SUBRESOURCE_INDEX,
+                    Anonymous: D3D12_TEXTURE_COPY_LOCATION_0 { SubresourceIndex: 0 },
+                },
+                x as u32,
+                y as u32,
+                0,
+                &D3D12_TEXTURE_COPY_LOCATION {
+                    pResource: std::mem::transmute_copy(&upload),
+                    Type: D3D12_TEXTURE_COPY_TYPE_PLACED_FOOTPRINT,
+                    Anonymous: D3D12_TEXTURE_COPY_LOCATION_0 {
+                        PlacedFootprint: D3D12_PLACED_SUBRESOURCE_FOOTPRINT {
+                            Offset: 0,
+                            Footprint: D3D12_SUBRESOURCE_FOOTPRINT {
+                                Format: TEXTURE_FORMAT,
+                                Width: width as u32,
+                                Height: height as u32,
+                                Depth: 1,
+                                RowPitch: row_pitch as u32,
+                            },
+                        },
+                    },
+                },
+                None,
+            );
+            command_list.ResourceBarrier(&[transition_barrier(
+                &resource,
+                D3D12_RESOURCE_STATE_COPY_DEST,
+                D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE,
+            )]);
+        }
+        self.retired.push(upload);
+        Ok(())
+    }
+}
+
+impl GeometryBuffer {
+    // Copies `data` to the start of the buffer, first replacing it with one
+    // twice the size when it is too small. Only valid while the GPU is not
+    // reading the buffer, which holds between frames.
+    fn write<T>(&mut self, device: &ID3D12Device, data: &[T], name: &str) -> Result<ID3D12Resource> {
+        let size = std::mem::size_of_val(data) as u64;
+        let buffer = match &self.buffer {
+            Some(buffer) if size <= self.capacity => buffer.clone(),
+            _ => {
+                let capacity = size.next_power_of_two().max(self.capacity * 2);
+                let buffer = create_buffer(
+                    device,
+                    D3D12_HEAP_TYPE_UPLOAD,
+                    capacity,
+                    D3D12_RESOURCE_FLAG_NONE,
+                    D3D12_RESOURCE_STATE_GENERIC_READ,
+                )?;
+                set_name(&buffer, name);
+                self.buffer = Some(buffer.clone());
+                self.capacity = capacity;
+                buffer
+            }
+        };
+        unsafe {
+            let mut mapped = std::ptr::null_mut();
+            buffer.Map(0, Some(&D3D12_RANGE { Begin: 0, End: 0 }), Some(&mut mapped))?;
+            std::ptr::copy_nonoverlapping(data.as_ptr(), mapped as *mut T, data.len());
+            buffer.Unmap(
+                0,
+                Some(&D3D12_RANGE {
+                    Begin: 0,
+                    End: size as usize,
+                }),
+            );
+        }
+        Ok(buffer)
+    }
+}
+
+// The window size in points as root constants and a table with the texture
+// of the mesh, read through a bilinear clamping sampler.
+fn create_ui_root_signature(device: &ID3D12Device) -> Result<ID3D12RootSignature> {
+    let texture_range = D3D12_DESCRIPTOR_RANGE {
+        RangeType: D3D12_DESCRIPTOR_RANGE_TYPE_SRV,
+        NumDescriptors: 1,
+        BaseShaderRegister: 0,
+        RegisterSpace: 0,
+        OffsetInDescriptorsFromTableStart: 0,
+    };
+
+    let parameters = [
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_32BIT_CONSTANTS,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Constants: D3D12_ROOT_CONSTANTS {
+                    ShaderRegister: 0,
+                    RegisterSpace: 0,
+                    Num32BitValues: 2,
+                },
+            },
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_VERTEX,
+        },
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_DESCRIPTOR_TABLE,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                DescriptorTable: D3D12_ROOT_DESCRIPTOR_TABLE {
+                    NumDescriptorRanges: 1,
+                    pDescriptorRanges: &texture_range,
+                },
+            },
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+        },
+    ];
+
+    let sampler = D3D12_STATIC_SAMPLER_DESC {
+        Filter: D3D12_FILTER_MIN_MAG_MIP_LINEAR,
+        AddressU: D3D12_TEXTURE_ADDRESS_MODE_CLAMP,
+        AddressV: D3D12_TEXTURE_ADDRESS_MODE_CLAMP,
+        AddressW: D3D12_TEXTURE_ADDRESS_MODE_CLAMP,
+        MipLODBias: 0.0,
+        MaxAnisotropy: 1,
+        ComparisonFunc: D3D12_COMPARISON_FUNC_NEVER,
+        BorderColor: D3D12_STATIC_BORDER_COLOR_TRANSPARENT_BLACK,
+        MinLOD: 0.0,
+        MaxLOD: 0.0,
+        ShaderRegister: 0,
+        RegisterSpace: 0,
+        ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+    };
+
+    let desc = D3D12_ROOT_SIGNATURE_DESC {
+        NumParameters: parameters.len() as u32,
+        pParameters: parameters.as_ptr(),
+        NumStaticSamplers: 1,
+        pStaticSamplers: &sampler,
+        Flags: D3D12_ROOT_SIGNATURE_FLAG_ALLOW_INPUT_ASSEMBLER_INPUT_LAYOUT,
+    };
+
+    serialize_root_signature(device, &desc)
+}
+
+// egui's epaint::Vertex as triangle lists, blended premultiplied into the
+// back buffer without depth.
+fn create_ui_pipeline_state(
+    device: &ID3D12Device,
+    root_signature: &ID3D12RootSignature,
+) -> Result<ID3D12PipelineState> {
+    let read = |path: &str| {
+        std::fs::read(path).map_err(|error| Error::new(E_FAIL, error.to_string().into()))
+    };
+    let vs_bin = read("resources/ui_vs.bin")?;
+    let ps_bin = read("resources/ui_ps.bin")?;
+
+    let input_element_descs: [D3D12_INPUT_ELEMENT_DESC; 3] = [
+        D3D12_INPUT_ELEMENT_DESC {
+            SemanticName: s!("POSITION"),
+            SemanticIndex: 0,
+            Format: DXGI_FORMAT_R32G32_FLOAT,
+            InputSlot: 0,
+            AlignedByteOffset: 0,
+            InputSlotClass: D3D12_INPUT_CLASSIFICATION_PER_VERTEX_DATA,
+            InstanceDataStepRate: 0,
+        },
+        D3D12_INPUT_ELEMENT_DESC {
+            SemanticName: s!("TEXCOORD"),
+            SemanticIndex: 0,
+            Format: DXGI_FORMAT_R32G32_FLOAT,
+            InputSlot: 0,
+            AlignedByteOffset: 8,
+            InputSlotClass: D3D12_INPUT_CLASSIFICATION_PER_VERTEX_DATA,
+            InstanceDataStepRate: 0,
+        },
+        // Color32, sRGB bytes the vertex shader linearizes.
+        D3D12_INPUT_ELEMENT_DESC {
+            SemanticName: s!("COLOR"),
+            SemanticIndex: 0,
+            Format: DXGI_FORMAT_R8G8B8A8_UNORM,
+            InputSlot: 0,
+            AlignedByteOffset: 16,
+            InputSlotClass: D3D12_INPUT_CLASSIFICATION_PER_VERTEX_DATA,
+            InstanceDataStepRate: 0,
+        },
+    ];
+
+    let mut desc = D3D12_GRAPHICS_PIPELINE_STATE_DESC {
+        InputLayout: D3D12_INPUT_LAYOUT_DESC {
+            pInputElementDescs: input_element_descs.as_ptr(),
+            NumElements: input_element_descs.len() as u32,
+        },
+        pRootSignature: unsafe { std::mem::transmute_copy(root_signature) },
+        VS: convert_to_bytecode(&vs_bin),
+        PS: convert_to_bytecode(&ps_bin),
+        RasterizerState: D3D12_RASTERIZER_DESC {
+            FillMode: D3D12_FILL_MODE_SOLID,
+            CullMode: D3D12_CULL_MODE_NONE,
+            DepthClipEnable: true.into(),
+            ..Default::default()
+        },
+        BlendState: D3D12_BLEND_DESC {
+            RenderTarget: [D3D12_RENDER_TARGET_BLEND_DESC {
+                BlendEnable: true.into(),
+                SrcBlend: D3D12_BLEND_ONE,
+                DestBlend: D3D12_BLEND_INV_SRC_ALPHA,
+                BlendOp: D3D12_BLEND_OP_ADD,
+                SrcBlendAlpha: D3D12_BLEND_ONE,
+                DestBlendAlpha: D3D12_BLEND_INV_SRC_ALPHA,
+                BlendOpAlpha: D3D12_BLEND_OP_ADD,
+                LogicOp: D3D12_LOGIC_OP_NOOP,
+                RenderTargetWriteMask: D3D12_COLOR_WRITE_ENABLE_ALL.0 as u8,
+                ..Default::default()
+            }; 8],
+            ..Default::default()
+        },
+        DepthStencilState: D3D12_DEPTH_STENCIL_DESC {
+            DepthEnable: false.into(),
+            ..Default::default()
+        },
+        SampleMask: u32::MAX,
+        PrimitiveTopologyType: D3D12_PRIMITIVE_TOPOLOGY_TYPE_TRIANGLE,
+        NumRenderTargets: 1,
+        SampleDesc: DXGI_SAMPLE_DESC {
+            Count: 1,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    desc.RTVFormats[0] = BACK_BUFFER_VIEW_FORMAT;
+
+    unsafe { device.CreateGraphicsPipelineState(&desc) }
+}
@@ -0,0 +1,84 @@
+use std::fmt;
+use std::io;
+
+// Failures of the engine by the part that failed. Context wraps any of them
+// with what was being done, so messages read from the outermost step in:
+// "creating the swap chain: <what DXGI said>".
+#[derive(Debug)]
+pub enum EngineError {
+    // A Direct3D 12 or DXGI call failed.
+    Device(windows::core::Error),
+    // Compiled shader bytecode could not be read, see build.rs.
+    Shader { path: String, source: io::Error },
+    // A model or other content file is missing or malformed.
+    Asset { path: String, source: io::Error },
+    Io(io::Error),
+    // Settings or command line arguments that cannot be used.
+    Config(String),
+    Context { context: String, source: Box<EngineError> },
+}
+
+pub type EngineResult<T> = Result<T, EngineError>;
+
+impl EngineError {
+    // The device error at the bottom of the context chain, if that is
+    // where it started.
+    pub fn device_error(&self) -> Option<&windows::core::Error> {
+        match self {
+            EngineError::Device(error) => Some(error),
+            EngineError::Context { source, .. } => source.device_error(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EngineError::Device(error) => write!(f, "{}", error),
+            EngineError::Shader { path, source } => write!(f, "failed to read shader {}: {}", path, source),
+            EngineError::Asset { path, source } => write!(f, "failed to load {}: {}", path, source),
+            EngineError::Io(error) => write!(f, "{}", error),
+            EngineError::Config(message) => write!(f, "invalid configuration: {}", message),
+            EngineError::Context { context, source } => write!(f, "{}: {}", context, source),
+        }
+    }
+}
+
+impl std::error::Error for EngineError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EngineError::Device(error) => Some(error),
+            EngineError::Shader { source, .. } | EngineError::Asset { source, .. } => Some(source),
+            EngineError::Io(error) => Some(error),
+            EngineError::Config(_) => None,
+            EngineError::Context { source, .. } => Some(source.as_ref()),
+        }
+    }
+}
+
+impl From<windows::core::Error> for EngineError {
+    fn from(error: windows::core::Error) -> Self {
+        EngineError::Device(error)
+    }
+}
+
+impl From<io::Error> for EngineError {
+    fn from(error: io::Error) -> Self {
+        EngineError::Io(error)
+    }
+}
+
+// Adds what was being done to the error of a failed step.
+pub trait Context<T> {
+    fn context(self, context: &str) -> EngineResult<T>;
+}
+
+impl<T, E: Into<EngineError>> Context<T> for Result<T, E> {
+    fn context(self, context: &str) -> EngineResult<T> {
+        self.map_err(|error| EngineError::Context {
+            context: context.to_string(),
+            source: Box::new(error.into()),
+        })
+    }
+}
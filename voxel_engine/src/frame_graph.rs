@@ -0,0 +1,382 @@
+use std::collections::HashMap;
+
+use log::debug;
+use windows::{
+    core::*, Win32::Foundation::*, Win32::Graphics::Direct3D12::*,
+    Win32::Graphics::Dxgi::Common::*,
+};
+
+use crate::post::{PostPass, PostTarget};
+use crate::render_graph::{
+    Barrier, CompiledGraph, PassId, RenderGraph, ResourceId, ResourceState,
+};
+use crate::markers::set_name;
+use crate::{created, transition_barrier, BACK_BUFFER_VIEW_FORMAT, SCENE_FORMAT};
+
+// Tonemapped color, read back as linear by FXAA.
+const LDR_FORMAT: DXGI_FORMAT = DXGI_FORMAT_R8G8B8A8_UNORM_SRGB;
+
+// Every post target apart from the back buffer, transient in the graph.
+pub const INTERMEDIATE_TARGETS: [PostTarget; 4] = [
+    PostTarget::Scene,
+    PostTarget::BloomA,
+    PostTarget::BloomB,
+    PostTarget::Ldr,
+];
+
+pub fn target_format(target: PostTarget) -> DXGI_FORMAT {
+    match target {
+        PostTarget::BackBuffer => BACK_BUFFER_VIEW_FORMAT,
+        PostTarget::Ldr => LDR_FORMAT,
+        _ => SCENE_FORMAT,
+    }
+}
+
+fn target_name(target: PostTarget) -> &'static str {
+    match target {
+        PostTarget::Scene => "scene",
+        PostTarget::BloomA => "bloom a",
+        PostTarget::BloomB => "bloom b",
+        PostTarget::Ldr => "ldr",
+        PostTarget::BackBuffer => "back buffer",
+    }
+}
+
+// Resources that live outside the graph, which change from frame to frame
+// or belong to other passes.
+pub struct ImportedResources<'a> {
+    pub shadow_map: &'a ID3D12Resource,
+    pub back_buffer: &'a ID3D12Resource,
+}
+
+// The rasterized frame as a render_graph::RenderGraph: shadow map, main
+// pass, the MSAA resolve and the post processing chain. Compiled once per
+// chain, it records every barrier between the passes and places the targets
+// in one heap, where targets that are never alive at the same time share
+// memory.
+pub struct FrameGraph {
+    compiled: CompiledGraph,
+    // Keeps the memory of the placed resources alive.
+    #[allow(dead_code)]
+    heap: ID3D12Heap,
+    // By graph resource, None for imported and unused ones.
+    transients: Vec<Option<ID3D12Resource>>,
+    targets: HashMap<PostTarget, ResourceId>,
+    // What the main pass renders into, resolved into the scene target with
+    // MSAA.
+    multisampled_scene: Option<ResourceId>,
+    scene_rtv_heap: ID3D12DescriptorHeap,
+    shadow_map: ResourceId,
+    back_buffer: ResourceId,
+    shadow_pass: PassId,
+    main_pass: PassId,
+    resolve_pass: Option<PassId>,
+    post_passes: Vec<PassId>,
+}
+
+impl FrameGraph {
+    // `sample_count` of the main pass, 1 renders straight into the scene
+    // target.
+    pub fn new(
+        device: &ID3D12Device,
+        width: u32,
+        height: u32,
+        sample_count: u32,
+        post_passes: &[PostPass],
+    ) -> Result<Self> {
+        let mut graph = RenderGraph::new();
+        let shadow_map = graph.import(
+            "shadow map",
+            ResourceState::PIXEL_SHADER_RESOURCE,
+            ResourceState::PIXEL_SHADER_RESOURCE,
+        );
+        let back_buffer = graph.import("back buffer", ResourceState::PRESENT, ResourceState::PRESENT);
+
+        let mut descs = HashMap::new();
+        // Multisampled resources need the larger alignment from the heap too.
+        let mut heap_alignment = 0;
+        let mut create_transient = |graph: &mut RenderGraph, name, desc: D3D12_RESOURCE_DESC| {
+            let info = unsafe { device.GetResourceAllocationInfo(0, &[desc]) };
+            heap_alignment = heap_alignment.max(info.Alignment);
+            let id = graph.create_transient(name, info.SizeInBytes, info.Alignment);
+            descs.insert(id, desc);
+            id
+        };
+
+        let mut targets = HashMap::new();
+        targets.insert(PostTarget::BackBuffer, back_buffer);
+        for target in INTERMEDIATE_TARGETS {
+            let id = create_transient(&mut graph, target_name(target), target_desc(target, width, height));
+            targets.insert(target, id);
+        }
+        let scene = targets[&PostTarget::Scene];
+        let multisampled_scene = if sample_count > 1 {
+            let mut desc = target_desc(PostTarget::Scene, width, height);
+            desc.SampleDesc.Count = sample_count;
+            Some(create_transient(&mut graph, "scene msaa", desc))
+        } else {
+            None
+        };
+
+        let shadow_pass = graph.add_pass("shadow map", &[(shadow_map, ResourceState::DEPTH_WRITE)]);
+        let main_pass = graph.add_pass(
+            "main",
+            &[
+                (shadow_map, ResourceState::PIXEL_SHADER_RESOURCE),
+                (multisampled_scene.unwrap_or(scene), ResourceState::RENDER_TARGET),
+            ],
+        );
+        let resolve_pass = multisampled_scene.map(|multisampled_scene| {
+            graph.add_pass(
+                "resolve",
+                &[
+                    (multisampled_scene, ResourceState::RESOLVE_SOURCE),
+                    (scene, ResourceState::RESOLVE_DEST),
+                ],
+            )
+        });
+        let post_passes = post_passes
+            .iter()
+            .map(|pass| {
+                let mut uses: Vec<(ResourceId, ResourceState)> = pass
+                    .inputs
+                    .iter()
+                    .map(|input| (targets[input], ResourceState::PIXEL_SHADER_RESOURCE))
+                    .collect();
+                uses.push((targets[&pass.output], ResourceState::RENDER_TARGET));
+                graph.add_pass(pass.name, &uses)
+            })
+            .collect();
+
+        let compiled = graph
+            .compile()
+            .map_err(|error| Error::new(E_INVALIDARG, error.to_string().into()))?;
+        debug!("frame graph:\n{}", compiled);
+
+        let heap = unsafe {
+            let mut heap: Option<ID3D12Heap> = None;
+            device.CreateHeap(
+                &D3D12_HEAP_DESC {
+                    SizeInBytes: compiled.heap_size,
+                    Properties: D3D12_HEAP_PROPERTIES {
+                        Type: D3D12_HEAP_TYPE_DEFAULT,
+                        ..Default::default()
+                    },
+                    Alignment: heap_alignment,
+                    Flags: D3D12_HEAP_FLAG_ALLOW_ONLY_RT_DS_TEXTURES,
+                },
+                &mut heap,
+            )?;
+            created(heap, "frame graph heap")?
+        };
+        set_name(&heap, "frame graph heap");
+
+        let mut transients = Vec::with_capacity(compiled.placements.len());
+        for (i, placement) in compiled.placements.iter().enumerate() {
+            let resource = match placement {
+                Some(placement) => unsafe {
+                    let mut resource: Option<ID3D12Resource> = None;
+                    device.CreatePlacedResource(
+                        &heap,
+                        placement.offset,
+                        &descs[&ResourceId(i)],
+                        D3D12_RESOURCE_STATES(placement.initial_state.0 as i32),
+                        None,
+                        &mut resource,
+                    )?;
+                    resource
+                },
+                None => None,
+            };
+            if let Some(resource) = &resource {
+                set_name(resource, compiled.resource_name(ResourceId(i)));
+            }
+            transients.push(resource);
+        }
+
+        let scene_rtv_heap: ID3D12DescriptorHeap = unsafe {
+            device.CreateDescriptorHeap(&D3D12_DESCRIPTOR_HEAP_DESC {
+                NumDescriptors: 1,
+                Type: D3D12_DESCRIPTOR_HEAP_TYPE_RTV,
+                ..Default::default()
+            })
+        }?;
+        set_name(&scene_rtv_heap, "scene rtv heap");
+        unsafe {
+            device.CreateRenderTargetView(
+                transients[multisampled_scene.unwrap_or(scene).0].as_ref(),
+                None,
+                scene_rtv_heap.GetCPUDescriptorHandleForHeapStart(),
+            )
+        };
+
+        Ok(FrameGraph {
+            compiled,
+            heap,
+            transients,
+            targets,
+            multisampled_scene,
+            scene_rtv_heap,
+            shadow_map,
+            back_buffer,
+            shadow_pass,
+            main_pass,
+            resolve_pass,
+            post_passes,
+        })
+    }
+
+    // View of the target the main pass renders into.
+    pub fn scene_rtv(&self) -> D3D12_CPU_DESCRIPTOR_HANDLE {
+        unsafe { self.scene_rtv_heap.GetCPUDescriptorHandleForHeapStart() }
+    }
+
+    // A post target, None for the back buffer and targets the chain does
+    // not use.
+    pub fn target(&self, target: PostTarget) -> Option<&ID3D12Resource> {
+        self.transients[self.targets[&target].0].as_ref()
+    }
+
+    fn resource<'a>(&'a self, id: ResourceId, imported: &ImportedResources<'a>) -> &'a ID3D12Resource {
+        if id == self.shadow_map {
+            imported.shadow_map
+        } else if id == self.back_buffer {
+            imported.back_buffer
+        } else {
+            self.transients[id.0]
+                .as_ref()
+                .expect("barriers only reference used resources")
+        }
+    }
+
+    fn record_barriers(
+        &self,
+        command_list: &ID3D12GraphicsCommandList,
+        barriers: &[Barrier],
+        imported: &ImportedResources,
+    ) {
+        if barriers.is_empty() {
+            return;
+        }
+        let barriers: Vec<D3D12_RESOURCE_BARRIER> = barriers
+            .iter()
+            .map(|barrier| match *barrier {
+                Barrier::Transition {
+                    resource,
+                    before,
+                    after,
+                } => transition_barrier(
+                    self.resource(resource, imported),
+                    D3D12_RESOURCE_STATES(before.0 as i32),
+                    D3D12_RESOURCE_STATES(after.0 as i32),
+                ),
+                Barrier::Aliasing { before, after } => aliasing_barrier(
+                    before.map(|before| self.resource(before, imported)),
+                    self.resource(after, imported),
+                ),
+                Barrier::Uav { resource } => uav_barrier(self.resource(resource, imported)),
+            })
+            .collect();
+        unsafe { command_list.ResourceBarrier(&barriers) };
+    }
+
+    // Records what has to happen before `pass`: its barriers, and discards
+    // of the targets it is the first to use with the transitions after them.
+    fn begin_pass(&self, command_list: &ID3D12GraphicsCommandList, pass: PassId, imported: &ImportedResources) {
+        let compiled = match self.compiled.passes.iter().find(|compiled| compiled.pass == pass) {
+            Some(compiled) => compiled,
+            None => return,
+        };
+        self.record_barriers(command_list, &compiled.barriers, imported);
+        for &resource in &compiled.discards {
+            unsafe { command_list.DiscardResource(self.resource(resource, imported), None) };
+        }
+        self.record_barriers(command_list, &compiled.discard_barriers, imported);
+    }
+
+    pub fn begin_shadow_pass(&self, command_list: &ID3D12GraphicsCommandList, imported: &ImportedResources) {
+        self.begin_pass(command_list, self.shadow_pass, imported);
+    }
+
+    pub fn begin_main_pass(&self, command_list: &ID3D12GraphicsCommandList, imported: &ImportedResources) {
+        self.begin_pass(command_list, self.main_pass, imported);
+    }
+
+    // Resolves the multisampled scene into the scene target after the main
+    // pass, does nothing without MSAA.
+    pub fn record_resolve(&self, command_list: &ID3D12GraphicsCommandList, imported: &ImportedResources) {
+        if let (Some(resolve_pass), Some(multisampled_scene)) = (self.resolve_pass, self.multisampled_scene) {
+            self.begin_pass(command_list, resolve_pass, imported);
+            unsafe {
+                command_list.ResolveSubresource(
+                    self.resource(self.targets[&PostTarget::Scene], imported),
+                    0,
+                    self.resource(multisampled_scene, imported),
+                    0,
+                    SCENE_FORMAT,
+                )
+            };
+        }
+    }
+
+    // `index` into the chain the graph was built with.
+    pub fn begin_post_pass(
+        &self,
+        command_list: &ID3D12GraphicsCommandList,
+        index: usize,
+        imported: &ImportedResources,
+    ) {
+        self.begin_pass(command_list, self.post_passes[index], imported);
+    }
+
+    // After the last pass: leaves the back buffer ready to present.
+    pub fn finish(&self, command_list: &ID3D12GraphicsCommandList, imported: &ImportedResources) {
+        self.record_barriers(command_list, &self.compiled.final_barriers, imported);
+    }
+}
+
+fn target_desc(target: PostTarget, width: u32, height: u32) -> D3D12_RESOURCE_DESC {
+    let (width, height) = target.size(width, height);
+    D3D12_RESOURCE_DESC {
+        Dimension: D3D12_RESOURCE_DIMENSION_TEXTURE2D,
+        Width: width as u64,
+        Height: height,
+        DepthOrArraySize: 1,
+        MipLevels: 1,
+        Format: target_format(target),
+        SampleDesc: DXGI_SAMPLE_DESC {
+            Count: 1,
+            Quality: 0,
+        },
+        Flags: D3D12_RESOURCE_FLAG_ALLOW_RENDER_TARGET,
+        ..Default::default()
+    }
+}
+
+fn aliasing_barrier(before: Option<&ID3D12Resource>, after: &ID3D12Resource) -> D3D12_RESOURCE_BARRIER {
+    D3D12_RESOURCE_BARRIER {
+        Type: D3D12_RESOURCE_BARRIER_TYPE_ALIASING,
+        Flags: D3D12_RESOURCE_BARRIER_FLAG_NONE,
+        Anonymous: D3D12_RESOURCE_BARRIER_0 {
+            Aliasing: std::mem::ManuallyDrop::new(D3D12_RESOURCE_ALIASING_BARRIER {
+                pResourceBefore: match before {
+                    Some(before) => unsafe { std::mem::transmute_copy(before) },
+                    None => std::mem::ManuallyDrop::new(None),
+                },
+                pResourceAfter: unsafe { std::mem::transmute_copy(after) },
+            }),
+        },
+    }
+}
+
+fn uav_barrier(resource: &ID3D12Resource) -> D3D12_RESOURCE_BARRIER {
+    D3D12_RESOURCE_BARRIER {
+        Type: D3D12_RESOURCE_BARRIER_TYPE_UAV,
+        Flags: D3D12_RESOURCE_BARRIER_FLAG_NONE,
+        Anonymous: D3D12_RESOURCE_BARRIER_0 {
+            UAV: std::mem::ManuallyDrop::new(D3D12_RESOURCE_UAV_BARRIER {
+                pResource: unsafe { std::mem::transmute_copy(resource) },
+            }),
+        },
+    }
+}
@@ -0,0 +1,358 @@
+use backend::{DrawEncoder, DrawIndexedArguments};
+use windows::{
+    core::*, Win32::Foundation::*, Win32::Graphics::Direct3D12::*,
+};
+
+use crate::hzb::HzbPass;
+use crate::indirect::{ChunkRecord, CullConstants};
+use crate::markers::{set_name, Event};
+use crate::math::{Frustum, Mat4};
+use crate::{
+    convert_to_bytecode, create_buffer, created, create_upload_buffer, serialize_root_signature,
+    transition_barrier,
+};
+
+const _: () = assert!(
+    std::mem::size_of::<DrawIndexedArguments>() == std::mem::size_of::<D3D12_DRAW_INDEXED_ARGUMENTS>()
+);
+
+const CULL_GROUP_SIZE: u32 = 64;
+
+// Compute pass that culls chunk bounds against the frustum and the depth
+// pyramid of the previous frame, and compacts the draw arguments of the
+// visible ones for ExecuteIndirect.
+pub struct GpuCulling {
+    root_signature: ID3D12RootSignature,
+    pso: ID3D12PipelineState,
+    command_signature: ID3D12CommandSignature,
+    chunk_buffer: ID3D12Resource,
+    argument_buffer: ID3D12Resource,
+    count_buffer: ID3D12Resource,
+    count_reset_buffer: ID3D12Resource,
+    count_readback: ID3D12Resource,
+    chunk_count: u32,
+    hzb: HzbPass,
+}
+
+impl GpuCulling {
+    pub fn new(
+        device: &ID3D12Device,
+        chunks: &[ChunkRecord],
+        depth_buffer: &ID3D12Resource,
+        width: u32,
+        height: u32,
+    ) -> Result<Self> {
+        let cs_bin = std::fs::read("resources/cs_cull.bin")
+            .map_err(|error| Error::new(E_FAIL, error.to_string().into()))?;
+
+        let root_signature = create_cull_root_signature(device)?;
+
+        let desc = D3D12_COMPUTE_PIPELINE_STATE_DESC {
+            pRootSignature: unsafe { std::mem::transmute_copy(&root_signature) },
+            CS: convert_to_bytecode(&cs_bin),
+            ..Default::default()
+        };
+        let pso = unsafe { device.CreateComputePipelineState(&desc) }?;
+
+        let argument_desc = D3D12_INDIRECT_ARGUMENT_DESC {
+            Type: D3D12_INDIRECT_ARGUMENT_TYPE_DRAW_INDEXED,
+            ..Default::default()
+        };
+        let mut command_signature: Option<ID3D12CommandSignature> = None;
+        unsafe {
+            device.CreateCommandSignature(
+                &D3D12_COMMAND_SIGNATURE_DESC {
+                    ByteStride: std::mem::size_of::<DrawIndexedArguments>() as u32,
+                    NumArgumentDescs: 1,
+                    pArgumentDescs: &argument_desc,
+                    NodeMask: 0,
+                },
+                None,
+                &mut command_signature,
+            )
+        }?;
+        let command_signature = created(command_signature, "command signature")?;
+
+        let (chunk_buffer, argument_buffer) = create_chunk_buffers(device, chunks)?;
+        let count_buffer = create_buffer(
+            device,
+            D3D12_HEAP_TYPE_DEFAULT,
+            4,
+            D3D12_RESOURCE_FLAG_ALLOW_UNORDERED_ACCESS,
+            D3D12_RESOURCE_STATE_COMMON,
+        )?;
+        let count_reset_buffer = create_upload_buffer(device, &[0u32])?;
+        let hzb = HzbPass::new(device, depth_buffer, width, height)?;
+
+        let count_readback = create_buffer(
+            device,
+            D3D12_HEAP_TYPE_READBACK,
+            4,
+            D3D12_RESOURCE_FLAG_NONE,
+            D3D12_RESOURCE_STATE_COPY_DEST,
+        )?;
+
+        set_name(&root_signature, "cull root signature");
+        set_name(&pso, "cull pso");
+        set_name(&command_signature, "chunk draw command signature");
+        set_name(&count_buffer, "cull visible count");
+        set_name(&count_reset_buffer, "cull visible count reset");
+        set_name(&count_readback, "cull visible count readback");
+
+        Ok(GpuCulling {
+            root_signature,
+            pso,
+            command_signature,
+            chunk_buffer,
+            argument_buffer,
+            count_buffer,
+            count_reset_buffer,
+            count_readback,
+            chunk_count: chunks.len() as u32,
+            hzb,
+        })
+    }
+
+    // Replaces the chunk list after the resident meshes changed. Only valid
+    // while the GPU is not using the previous buffers.
+    pub fn set_chunks(&mut self, device: &ID3D12Device, chunks: &[ChunkRecord]) -> Result<()> {
+        let (chunk_buffer, argument_buffer) = create_chunk_buffers(device, chunks)?;
+        self.chunk_buffer = chunk_buffer;
+        self.argument_buffer = argument_buffer;
+        self.chunk_count = chunks.len() as u32;
+        Ok(())
+    }
+
+    pub fn max_draw_count(&self) -> u32 {
+        self.chunk_count
+    }
+
+    // Records the cull dispatch. Leaves the graphics pipeline unbound, the
+    // caller has to set its own pipeline state afterwards.
+    //
+    // occlusion is the depth buffer still holding the previous frame and the
+    // view projection it was rendered with, None when it has no valid content.
+    //
+    // Buffers decay to COMMON at the end of every ExecuteCommandLists, so
+    // each frame starts from there.
+    pub fn record_cull_pass(
+        &self,
+        command_list: &ID3D12GraphicsCommandList,
+        frustum: &Frustum,
+        occlusion: Option<(&ID3D12Resource, &Mat4)>,
+    ) {
+        let mut constants = CullConstants::new(frustum, self.chunk_count);
+        if let Some((depth_buffer, view_projection)) = occlusion {
+            let _event = Event::new(command_list, "hzb");
+            self.hzb.record_build(command_list, depth_buffer);
+            constants = constants.with_occlusion(view_projection, self.hzb.width(), self.hzb.height());
+        }
+
+        unsafe {
+            command_list.CopyBufferRegion(&self.count_buffer, 0, &self.count_reset_buffer, 0, 4);
+            command_list.ResourceBarrier(&[
+                transition_barrier(
+                    &self.count_buffer,
+                    D3D12_RESOURCE_STATE_COPY_DEST,
+                    D3D12_RESOURCE_STATE_UNORDERED_ACCESS,
+                ),
+                transition_barrier(
+                    &self.argument_buffer,
+                    D3D12_RESOURCE_STATE_COMMON,
+                    D3D12_RESOURCE_STATE_UNORDERED_ACCESS,
+                ),
+            ]);
+
+            command_list.SetDescriptorHeaps(&[Some(self.hzb.descriptor_heap().clone())]);
+            command_list.SetComputeRootSignature(&self.root_signature);
+            command_list.SetPipelineState(&self.pso);
+            command_list.SetComputeRoot32BitConstants(
+                0,
+                (std::mem::size_of::<CullConstants>() / 4) as u32,
+                &constants as *const CullConstants as *const _,
+                0,
+            );
+            command_list.SetComputeRootShaderResourceView(1, self.chunk_buffer.GetGPUVirtualAddress());
+            command_list.SetComputeRootUnorderedAccessView(2, self.argument_buffer.GetGPUVirtualAddress());
+            command_list.SetComputeRootUnorderedAccessView(3, self.count_buffer.GetGPUVirtualAddress());
+            command_list.SetComputeRootDescriptorTable(4, self.hzb.pyramid_srv());
+            command_list.Dispatch(self.chunk_count.div_ceil(CULL_GROUP_SIZE), 1, 1);
+
+            command_list.ResourceBarrier(&[
+                transition_barrier(
+                    &self.argument_buffer,
+                    D3D12_RESOURCE_STATE_UNORDERED_ACCESS,
+                    D3D12_RESOURCE_STATE_INDIRECT_ARGUMENT,
+                ),
+                transition_barrier(
+                    &self.count_buffer,
+                    D3D12_RESOURCE_STATE_UNORDERED_ACCESS,
+                    D3D12_RESOURCE_STATE_INDIRECT_ARGUMENT,
+                ),
+            ]);
+        }
+    }
+
+    // Copies the visible chunk count out for the debug counters, to be read
+    // with last_visible_count once the frame has completed.
+    pub fn record_count_readback(&self, command_list: &ID3D12GraphicsCommandList) {
+        unsafe {
+            command_list.ResourceBarrier(&[transition_barrier(
+                &self.count_buffer,
+                D3D12_RESOURCE_STATE_INDIRECT_ARGUMENT,
+                D3D12_RESOURCE_STATE_COPY_SOURCE,
+            )]);
+            command_list.CopyBufferRegion(&self.count_readback, 0, &self.count_buffer, 0, 4);
+        }
+    }
+
+    pub fn last_visible_count(&self) -> Result<u32> {
+        let mut data = std::ptr::null_mut();
+        unsafe {
+            self.count_readback.Map(0, Some(&D3D12_RANGE { Begin: 0, End: 4 }), Some(&mut data))?;
+            let count = *(data as *const u32);
+            self.count_readback.Unmap(0, Some(&D3D12_RANGE { Begin: 0, End: 0 }));
+            Ok(count)
+        }
+    }
+}
+
+fn create_chunk_buffers(
+    device: &ID3D12Device,
+    chunks: &[ChunkRecord],
+) -> Result<(ID3D12Resource, ID3D12Resource)> {
+    // Zero sized buffers are invalid, keep room for at least one record.
+    let capacity = chunks.len().max(1) as u64;
+    let chunk_buffer = if chunks.is_empty() {
+        create_upload_buffer(device, &[ChunkRecord::default()])?
+    } else {
+        create_upload_buffer(device, chunks)?
+    };
+    let argument_buffer = create_buffer(
+        device,
+        D3D12_HEAP_TYPE_DEFAULT,
+        capacity * std::mem::size_of::<DrawIndexedArguments>() as u64,
+        D3D12_RESOURCE_FLAG_ALLOW_UNORDERED_ACCESS,
+        D3D12_RESOURCE_STATE_COMMON,
+    )?;
+    set_name(&chunk_buffer, "cull chunk records");
+    set_name(&argument_buffer, "cull draw arguments");
+    Ok((chunk_buffer, argument_buffer))
+}
+
+fn create_cull_root_signature(device: &ID3D12Device) -> Result<ID3D12RootSignature> {
+    let hzb_range = D3D12_DESCRIPTOR_RANGE {
+        RangeType: D3D12_DESCRIPTOR_RANGE_TYPE_SRV,
+        NumDescriptors: 1,
+        BaseShaderRegister: 1,
+        RegisterSpace: 0,
+        OffsetInDescriptorsFromTableStart: 0,
+    };
+
+    let parameters = [
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_32BIT_CONSTANTS,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Constants: D3D12_ROOT_CONSTANTS {
+                    ShaderRegister: 0,
+                    RegisterSpace: 0,
+                    Num32BitValues: (std::mem::size_of::<CullConstants>() / 4) as u32,
+                },
+            },
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_ALL,
+        },
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_SRV,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Descriptor: D3D12_ROOT_DESCRIPTOR {
+                    ShaderRegister: 0,
+                    RegisterSpace: 0,
+                },
+            },
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_ALL,
+        },
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_UAV,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Descriptor: D3D12_ROOT_DESCRIPTOR {
+                    ShaderRegister: 0,
+                    RegisterSpace: 0,
+                },
+            },
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_ALL,
+        },
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_UAV,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Descriptor: D3D12_ROOT_DESCRIPTOR {
+                    ShaderRegister: 1,
+                    RegisterSpace: 0,
+                },
+            },
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_ALL,
+        },
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_DESCRIPTOR_TABLE,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                DescriptorTable: D3D12_ROOT_DESCRIPTOR_TABLE {
+                    NumDescriptorRanges: 1,
+                    pDescriptorRanges: &hzb_range,
+                },
+            },
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_ALL,
+        },
+    ];
+
+    let desc = D3D12_ROOT_SIGNATURE_DESC {
+        NumParameters: parameters.len() as u32,
+        pParameters: parameters.as_ptr(),
+        ..Default::default()
+    };
+
+    serialize_root_signature(device, &desc)
+}
+
+// D3D12 implementation of the backend draw interface on top of a graphics
+// command list.
+pub struct CommandListEncoder<'a> {
+    command_list: &'a ID3D12GraphicsCommandList,
+    culling: Option<&'a GpuCulling>,
+}
+
+impl<'a> CommandListEncoder<'a> {
+    pub fn new(command_list: &'a ID3D12GraphicsCommandList, culling: Option<&'a GpuCulling>) -> Self {
+        CommandListEncoder {
+            command_list,
+            culling,
+        }
+    }
+}
+
+impl DrawEncoder for CommandListEncoder<'_> {
+    fn draw_indexed(&mut self, args: &DrawIndexedArguments) {
+        unsafe {
+            self.command_list.DrawIndexedInstanced(
+                args.index_count_per_instance,
+                args.instance_count,
+                args.start_index_location,
+                args.base_vertex_location,
+                args.start_instance_location,
+            )
+        };
+    }
+
+    fn draw_indexed_indirect(&mut self, max_draw_count: u32) {
+        if let Some(culling) = self.culling {
+            unsafe {
+                self.command_list.ExecuteIndirect(
+                    &culling.command_signature,
+                    max_draw_count,
+                    &culling.argument_buffer,
+                    0,
+                    &culling.count_buffer,
+                    0,
+                )
+            };
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use std::cell::RefCell;
+
+use windows::{core::*, Win32::Graphics::Direct3D12::*};
+
+use crate::{create_buffer, created};
+use crate::markers::{set_name, Event};
+use crate::profiler::{QueryScopes, Scope};
+
+// Timestamp scopes a frame can have, further ones are not timed.
+const MAX_SCOPES: usize = 32;
+const QUERIES_PER_FRAME: u32 = 2 * MAX_SCOPES as u32;
+
+// Frames of timestamps in flight. A frame's timestamps are read when its
+// slot comes around again, FRAME_SLOTS - 1 frames later, by which point the
+// GPU is done with them without the CPU having to wait.
+const FRAME_SLOTS: usize = 3;
+
+struct FrameSlot {
+    // The frame whose timestamps are in the slot, None when already read.
+    frame: Option<u64>,
+    // Behind a RefCell so nested passes can share the profiler.
+    scopes: RefCell<QueryScopes>,
+    // CPU time the frame was submitted at, where its GPU scopes start.
+    submitted: u64,
+}
+
+// Timestamp queries around the passes of a frame, resolved into a readback
+// buffer with one slot per frame in flight.
+pub struct GpuProfiler {
+    query_heap: ID3D12QueryHeap,
+    readback: ID3D12Resource,
+    frequency: u64,
+    slots: Vec<FrameSlot>,
+    current: usize,
+}
+
+impl GpuProfiler {
+    pub fn new(device: &ID3D12Device, command_queue: &ID3D12CommandQueue) -> Result<Self> {
+        let query_count = QUERIES_PER_FRAME * FRAME_SLOTS as u32;
+        let mut query_heap: Option<ID3D12QueryHeap> = None;
+        unsafe {
+            device.CreateQueryHeap(
+                &D3D12_QUERY_HEAP_DESC {
+                    Type: D3D12_QUERY_HEAP_TYPE_TIMESTAMP,
+                    Count: query_count,
+                    NodeMask: 0,
+                },
+                &mut query_heap,
+            )
+        }?;
+        let query_heap = created(query_heap, "query heap")?;
+
+        let readback = create_buffer(
+            device,
+            D3D12_HEAP_TYPE_READBACK,
+            query_count as u64 * std::mem::size_of::<u64>() as u64,
+            D3D12_RESOURCE_FLAG_NONE,
+            D3D12_RESOURCE_STATE_COPY_DEST,
+        )?;
+        let frequency = unsafe { command_queue.GetTimestampFrequency() }?;
+        set_name(&query_heap, "profiler timestamp heap");
+        set_name(&readback, "profiler timestamp readback");
+
+        Ok(GpuProfiler {
+            query_heap,
+            readback,
+            frequency,
+            slots: (0..FRAME_SLOTS)
+                .map(|_| FrameSlot {
+                    frame: None,
+                    scopes: RefCell::new(QueryScopes::new(MAX_SCOPES)),
+                    submitted: 0,
+                })
+                .collect(),
+            current: 0,
+        })
+    }
+
+    // Starts recording the scopes of `frame` into its slot, which has to
+    // have been read by `collect` since it was last used.
+    pub fn begin_frame(&mut self, frame: u64) {
+        self.current = (frame % FRAME_SLOTS as u64) as usize;
+        let slot = &mut self.slots[self.current];
+        slot.frame = Some(frame);
+        slot.scopes.get_mut().clear();
+    }
+
+    // Times the commands recorded until the returned pass is dropped and
+    // marks them as an event for captures.
+    pub fn pass<'a>(&'a self, command_list: &'a ID3D12GraphicsCommandList, name: &str) -> Pass<'a> {
+        let event = Event::new(command_list, name);
+        let query = self.slots[self.current].scopes.borrow_mut().begin(name);
+        if let Some(query) = query {
+            self.end_query(command_list, query);
+        }
+        Pass {
+            profiler: self,
+            command_list,
+            _event: event,
+        }
+    }
+
+    fn end_query(&self, command_list: &ID3D12GraphicsCommandList, query: u32) {
+        let first = self.current as u32 * QUERIES_PER_FRAME;
+        unsafe { command_list.EndQuery(&self.query_heap, D3D12_QUERY_TYPE_TIMESTAMP, first + query) };
+    }
+
+    // Copies the frame's timestamps into its readback slot, recorded last.
+    pub fn resolve(&self, command_list: &ID3D12GraphicsCommandList) {
+        let count = self.slots[self.current].scopes.borrow().query_count();
+        if count == 0 {
+            return;
+        }
+        let first = self.current as u32 * QUERIES_PER_FRAME;
+        unsafe {
+            command_list.ResolveQueryData(
+                &self.query_heap,
+                D3D12_QUERY_TYPE_TIMESTAMP,
+                first,
+                count,
+                &self.readback,
+                first as u64 * std::mem::size_of::<u64>() as u64,
+            )
+        };
+    }
+
+    // Marks the frame as submitted at `now`, in profiler::now_ns time.
+    pub fn submitted(&mut self, now: u64) {
+        self.slots[self.current].submitted = now;
+    }
+
+    // The GPU scopes of the frame FRAME_SLOTS - 1 frames back, whose slot the
+    // next frame reuses. Only valid once the GPU has finished that frame.
+    pub fn collect(&mut self) -> Result<Option<(u64, Vec<Scope>)>> {
+        let next = (self.current + 1) % FRAME_SLOTS;
+        let slot = &mut self.slots[next];
+        let frame = match slot.frame.take() {
+            Some(frame) => frame,
+            None => return Ok(None),
+        };
+        let count = slot.scopes.get_mut().query_count() as usize;
+        if count == 0 {
+            return Ok(Some((frame, Vec::new())));
+        }
+
+        let size = std::mem::size_of::<u64>();
+        let begin = next * QUERIES_PER_FRAME as usize * size;
+        let range = D3D12_RANGE {
+            Begin: begin,
+            End: begin + count * size,
+        };
+        let mut data = std::ptr::null_mut();
+        let timestamps = unsafe {
+            self.readback.Map(0, Some(&range), Some(&mut data))?;
+            let timestamps = std::slice::from_raw_parts((data as *const u8).add(begin) as *const u64, count).to_vec();
+            self.readback.Unmap(0, Some(&D3D12_RANGE { Begin: 0, End: 0 }));
+            timestamps
+        };
+        Ok(Some((frame, slot.scopes.get_mut().resolve(&timestamps, self.frequency, slot.submitted))))
+    }
+}
+
+// A pass being recorded, see GpuProfiler::pass.
+pub struct Pass<'a> {
+    profiler: &'a GpuProfiler,
+    command_list: &'a ID3D12GraphicsCommandList,
+    // Dropped after the end timestamp, which then falls inside the event.
+    _event: Event<'a>,
+}
+
+impl Drop for Pass<'_> {
+    fn drop(&mut self) {
+        let query = self.profiler.slots[self.profiler.current].scopes.borrow_mut().end();
+        if let Some(query) = query {
+            self.profiler.end_query(self.command_list, query);
+        }
+    }
+}
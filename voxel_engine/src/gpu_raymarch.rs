@@ -0,0 +1,273 @@
+use windows::{
+    core::*, Win32::Foundation::*, Win32::Graphics::Direct3D12::*,
+    Win32::Graphics::Dxgi::Common::*,
+};
+
+use crate::markers::set_name;
+use crate::raymarch::RayMarchConstants;
+use crate::svo::SparseVoxelOctree;
+use crate::{
+    convert_to_bytecode, create_texture, create_upload_buffer, serialize_root_signature,
+    transition_barrier,
+};
+
+const RAY_MARCH_GROUP_SIZE: u32 = 8;
+
+// Renders the world by marching one ray per pixel through the sparse voxel
+// octree with shaders/raymarch.hlsl, then copies the result into the back
+// buffer. See raymarch::trace_pixel for the CPU reference.
+pub struct RayMarchPass {
+    root_signature: ID3D12RootSignature,
+    pso: ID3D12PipelineState,
+    // Same format as the back buffer, CopyResource needs them to match.
+    output: ID3D12Resource,
+    descriptor_heap: ID3D12DescriptorHeap,
+    node_buffer: ID3D12Resource,
+    palette_buffer: ID3D12Resource,
+    palette_count: u32,
+    width: u32,
+    height: u32,
+}
+
+impl RayMarchPass {
+    pub fn new(device: &ID3D12Device, width: u32, height: u32) -> Result<Self> {
+        let cs_bin = std::fs::read("resources/cs_raymarch.bin")
+            .map_err(|error| Error::new(E_FAIL, error.to_string().into()))?;
+
+        let root_signature = create_ray_march_root_signature(device)?;
+
+        let desc = D3D12_COMPUTE_PIPELINE_STATE_DESC {
+            pRootSignature: unsafe { std::mem::transmute_copy(&root_signature) },
+            CS: convert_to_bytecode(&cs_bin),
+            ..Default::default()
+        };
+        let pso = unsafe { device.CreateComputePipelineState(&desc) }?;
+
+        let output = create_texture(
+            device,
+            &D3D12_RESOURCE_DESC {
+                Dimension: D3D12_RESOURCE_DIMENSION_TEXTURE2D,
+                Width: width as u64,
+                Height: height,
+                DepthOrArraySize: 1,
+                MipLevels: 1,
+                Format: DXGI_FORMAT_R8G8B8A8_UNORM,
+                SampleDesc: DXGI_SAMPLE_DESC {
+                    Count: 1,
+                    Quality: 0,
+                },
+                Flags: D3D12_RESOURCE_FLAG_ALLOW_UNORDERED_ACCESS,
+                ..Default::default()
+            },
+            D3D12_RESOURCE_STATE_UNORDERED_ACCESS,
+            None,
+        )?;
+
+        let descriptor_heap: ID3D12DescriptorHeap = unsafe {
+            device.CreateDescriptorHeap(&D3D12_DESCRIPTOR_HEAP_DESC {
+                NumDescriptors: 1,
+                Type: D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV,
+                Flags: D3D12_DESCRIPTOR_HEAP_FLAG_SHADER_VISIBLE,
+                ..Default::default()
+            })
+        }?;
+        unsafe {
+            device.CreateUnorderedAccessView(
+                &output,
+                None,
+                Some(&D3D12_UNORDERED_ACCESS_VIEW_DESC {
+                    Format: DXGI_FORMAT_R8G8B8A8_UNORM,
+                    ViewDimension: D3D12_UAV_DIMENSION_TEXTURE2D,
+                    Anonymous: D3D12_UNORDERED_ACCESS_VIEW_DESC_0 {
+                        Texture2D: D3D12_TEX2D_UAV {
+                            MipSlice: 0,
+                            PlaneSlice: 0,
+                        },
+                    },
+                }),
+                descriptor_heap.GetCPUDescriptorHandleForHeapStart(),
+            );
+        }
+
+        let (node_buffer, palette_buffer) = create_octree_buffers(device, None, &[])?;
+        set_name(&root_signature, "ray march root signature");
+        set_name(&pso, "ray march pso");
+        set_name(&output, "ray march output");
+        set_name(&descriptor_heap, "ray march descriptor heap");
+
+        Ok(RayMarchPass {
+            root_signature,
+            pso,
+            output,
+            descriptor_heap,
+            node_buffer,
+            palette_buffer,
+            palette_count: 0,
+            width,
+            height,
+        })
+    }
+
+    // Replaces the octree and the block colors, indexed by block id. Only
+    // valid while the GPU is not using the previous buffers.
+    pub fn set_octree(
+        &mut self,
+        device: &ID3D12Device,
+        octree: Option<&SparseVoxelOctree>,
+        palette: &[[f32; 4]],
+    ) -> Result<()> {
+        let (node_buffer, palette_buffer) = create_octree_buffers(device, octree, palette)?;
+        self.node_buffer = node_buffer;
+        self.palette_buffer = palette_buffer;
+        self.palette_count = palette.len() as u32;
+        Ok(())
+    }
+
+    pub fn palette_count(&self) -> u32 {
+        self.palette_count
+    }
+
+    // Records the dispatch and the copy into the back buffer, which is
+    // expected in PRESENT and left there. The output rests in
+    // UNORDERED_ACCESS between frames.
+    pub fn record(
+        &self,
+        command_list: &ID3D12GraphicsCommandList,
+        constants: &RayMarchConstants,
+        back_buffer: &ID3D12Resource,
+    ) {
+        unsafe {
+            command_list.SetDescriptorHeaps(&[Some(self.descriptor_heap.clone())]);
+            command_list.SetComputeRootSignature(&self.root_signature);
+            command_list.SetPipelineState(&self.pso);
+            command_list.SetComputeRoot32BitConstants(
+                0,
+                (std::mem::size_of::<RayMarchConstants>() / 4) as u32,
+                constants as *const RayMarchConstants as *const _,
+                0,
+            );
+            command_list.SetComputeRootShaderResourceView(1, self.node_buffer.GetGPUVirtualAddress());
+            command_list.SetComputeRootShaderResourceView(2, self.palette_buffer.GetGPUVirtualAddress());
+            command_list.SetComputeRootDescriptorTable(
+                3,
+                self.descriptor_heap.GetGPUDescriptorHandleForHeapStart(),
+            );
+            command_list.Dispatch(
+                self.width.div_ceil(RAY_MARCH_GROUP_SIZE),
+                self.height.div_ceil(RAY_MARCH_GROUP_SIZE),
+                1,
+            );
+
+            command_list.ResourceBarrier(&[
+                transition_barrier(
+                    &self.output,
+                    D3D12_RESOURCE_STATE_UNORDERED_ACCESS,
+                    D3D12_RESOURCE_STATE_COPY_SOURCE,
+                ),
+                transition_barrier(
+                    back_buffer,
+                    D3D12_RESOURCE_STATE_PRESENT,
+                    D3D12_RESOURCE_STATE_COPY_DEST,
+                ),
+            ]);
+            command_list.CopyResource(back_buffer, &self.output);
+            command_list.ResourceBarrier(&[
+                transition_barrier(
+                    &self.output,
+                    D3D12_RESOURCE_STATE_COPY_SOURCE,
+                    D3D12_RESOURCE_STATE_UNORDERED_ACCESS,
+                ),
+                transition_barrier(
+                    back_buffer,
+                    D3D12_RESOURCE_STATE_COPY_DEST,
+                    D3D12_RESOURCE_STATE_PRESENT,
+                ),
+            ]);
+        }
+    }
+}
+
+fn create_octree_buffers(
+    device: &ID3D12Device,
+    octree: Option<&SparseVoxelOctree>,
+    palette: &[[f32; 4]],
+) -> Result<(ID3D12Resource, ID3D12Resource)> {
+    // Zero sized buffers are invalid, keep room for at least one element.
+    // An octree that is a single uniform cube has no nodes besides the
+    // root, which travels in the root constants.
+    let mut nodes = octree.map_or_else(Vec::new, |octree| octree.encoded_nodes());
+    if nodes.is_empty() {
+        nodes.push(0);
+    }
+    let node_buffer = create_upload_buffer(device, &nodes)?;
+    let palette_buffer = if palette.is_empty() {
+        create_upload_buffer(device, &[[0.0_f32; 4]])?
+    } else {
+        create_upload_buffer(device, palette)?
+    };
+    set_name(&node_buffer, "octree nodes");
+    set_name(&palette_buffer, "octree palette");
+    Ok((node_buffer, palette_buffer))
+}
+
+fn create_ray_march_root_signature(device: &ID3D12Device) -> Result<ID3D12RootSignature> {
+    let output_range = D3D12_DESCRIPTOR_RANGE {
+        RangeType: D3D12_DESCRIPTOR_RANGE_TYPE_UAV,
+        NumDescriptors: 1,
+        BaseShaderRegister: 0,
+        RegisterSpace: 0,
+        OffsetInDescriptorsFromTableStart: 0,
+    };
+
+    let parameters = [
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_32BIT_CONSTANTS,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Constants: D3D12_ROOT_CONSTANTS {
+                    ShaderRegister: 0,
+                    RegisterSpace: 0,
+                    Num32BitValues: (std::mem::size_of::<RayMarchConstants>() / 4) as u32,
+                },
+            },
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_ALL,
+        },
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_SRV,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Descriptor: D3D12_ROOT_DESCRIPTOR {
+                    ShaderRegister: 0,
+                    RegisterSpace: 0,
+                },
+            },
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_ALL,
+        },
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_SRV,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Descriptor: D3D12_ROOT_DESCRIPTOR {
+                    ShaderRegister: 1,
+                    RegisterSpace: 0,
+                },
+            },
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_ALL,
+        },
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_DESCRIPTOR_TABLE,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                DescriptorTable: D3D12_ROOT_DESCRIPTOR_TABLE {
+                    NumDescriptorRanges: 1,
+                    pDescriptorRanges: &output_range,
+                },
+            },
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_ALL,
+        },
+    ];
+
+    let desc = D3D12_ROOT_SIGNATURE_DESC {
+        NumParameters: parameters.len() as u32,
+        pParameters: parameters.as_ptr(),
+        ..Default::default()
+    };
+
+    serialize_root_signature(device, &desc)
+}
@@ -0,0 +1,325 @@
+use windows::{
+    core::*, Win32::Foundation::*, Win32::Graphics::Direct3D12::*,
+    Win32::Graphics::Dxgi::Common::*,
+};
+
+use crate::markers::set_name;
+use crate::occlusion::{mip_count, mip_size};
+use crate::{
+    convert_to_bytecode, create_texture, serialize_root_signature, transition_barrier,
+    transition_barrier_subresource,
+};
+
+const DOWNSAMPLE_GROUP_SIZE: u32 = 8;
+
+// Depth pyramid built with shaders/hzb.hlsl from the depth buffer of the
+// previous frame. See occlusion::DepthPyramid for the CPU reference.
+//
+// Descriptor layout: the SRV of the whole pyramid, then for every level the
+// SRV of its source followed by the UAV of the level itself.
+pub struct HzbPass {
+    root_signature: ID3D12RootSignature,
+    pso: ID3D12PipelineState,
+    // Builds level 0 instead of pso when the depth buffer is multisampled.
+    multisampled_pso: Option<ID3D12PipelineState>,
+    pyramid: ID3D12Resource,
+    descriptor_heap: ID3D12DescriptorHeap,
+    descriptor_size: usize,
+    width: u32,
+    height: u32,
+    mip_count: u32,
+}
+
+impl HzbPass {
+    pub fn new(
+        device: &ID3D12Device,
+        depth_buffer: &ID3D12Resource,
+        width: u32,
+        height: u32,
+    ) -> Result<Self> {
+        let root_signature = create_downsample_root_signature(device)?;
+        let pso = create_downsample_pipeline_state(device, &root_signature, "resources/cs_hzb.bin")?;
+        let multisampled = unsafe { depth_buffer.GetDesc() }.SampleDesc.Count > 1;
+        let multisampled_pso = if multisampled {
+            Some(create_downsample_pipeline_state(
+                device,
+                &root_signature,
+                "resources/cs_hzb_ms.bin",
+            )?)
+        } else {
+            None
+        };
+
+        let mip_count = mip_count(width, height);
+        let pyramid = create_texture(
+            device,
+            &D3D12_RESOURCE_DESC {
+                Dimension: D3D12_RESOURCE_DIMENSION_TEXTURE2D,
+                Width: width as u64,
+                Height: height,
+                DepthOrArraySize: 1,
+                MipLevels: mip_count as u16,
+                Format: DXGI_FORMAT_R32_FLOAT,
+                SampleDesc: DXGI_SAMPLE_DESC {
+                    Count: 1,
+                    Quality: 0,
+                },
+                Flags: D3D12_RESOURCE_FLAG_ALLOW_UNORDERED_ACCESS,
+                ..Default::default()
+            },
+            D3D12_RESOURCE_STATE_NON_PIXEL_SHADER_RESOURCE,
+            None,
+        )?;
+
+        let descriptor_heap: ID3D12DescriptorHeap = unsafe {
+            device.CreateDescriptorHeap(&D3D12_DESCRIPTOR_HEAP_DESC {
+                NumDescriptors: 1 + 2 * mip_count,
+                Type: D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV,
+                Flags: D3D12_DESCRIPTOR_HEAP_FLAG_SHADER_VISIBLE,
+                ..Default::default()
+            })
+        }?;
+        let descriptor_size = unsafe {
+            device.GetDescriptorHandleIncrementSize(D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV)
+        } as usize;
+        let heap_start = unsafe { descriptor_heap.GetCPUDescriptorHandleForHeapStart() };
+        let cpu_handle = |index: u32| D3D12_CPU_DESCRIPTOR_HANDLE {
+            ptr: heap_start.ptr + index as usize * descriptor_size,
+        };
+
+        unsafe {
+            device.CreateShaderResourceView(
+                &pyramid,
+                Some(&texture_srv_desc(0, mip_count)),
+                cpu_handle(0),
+            );
+
+            for mip in 0..mip_count {
+                if mip == 0 {
+                    let desc = if multisampled {
+                        D3D12_SHADER_RESOURCE_VIEW_DESC {
+                            Format: DXGI_FORMAT_R32_FLOAT,
+                            ViewDimension: D3D12_SRV_DIMENSION_TEXTURE2DMS,
+                            Shader4ComponentMapping: D3D12_DEFAULT_SHADER_4_COMPONENT_MAPPING,
+                            ..Default::default()
+                        }
+                    } else {
+                        texture_srv_desc(0, 1)
+                    };
+                    device.CreateShaderResourceView(depth_buffer, Some(&desc), cpu_handle(1));
+                } else {
+                    device.CreateShaderResourceView(
+                        &pyramid,
+                        Some(&texture_srv_desc(mip - 1, 1)),
+                        cpu_handle(1 + 2 * mip),
+                    );
+                }
+
+                device.CreateUnorderedAccessView(
+                    &pyramid,
+                    None,
+                    Some(&D3D12_UNORDERED_ACCESS_VIEW_DESC {
+                        Format: DXGI_FORMAT_R32_FLOAT,
+                        ViewDimension: D3D12_UAV_DIMENSION_TEXTURE2D,
+                        Anonymous: D3D12_UNORDERED_ACCESS_VIEW_DESC_0 {
+                            Texture2D: D3D12_TEX2D_UAV {
+                                MipSlice: mip,
+                                PlaneSlice: 0,
+                            },
+                        },
+                    }),
+                    cpu_handle(2 + 2 * mip),
+                );
+            }
+        }
+
+        set_name(&root_signature, "hzb root signature");
+        set_name(&pso, "hzb pso");
+        if let Some(multisampled_pso) = &multisampled_pso {
+            set_name(multisampled_pso, "hzb multisampled pso");
+        }
+        set_name(&pyramid, "hzb pyramid");
+        set_name(&descriptor_heap, "hzb descriptor heap");
+
+        Ok(HzbPass {
+            root_signature,
+            pso,
+            multisampled_pso,
+            pyramid,
+            descriptor_heap,
+            descriptor_size,
+            width,
+            height,
+            mip_count,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn descriptor_heap(&self) -> &ID3D12DescriptorHeap {
+        &self.descriptor_heap
+    }
+
+    pub fn pyramid_srv(&self) -> D3D12_GPU_DESCRIPTOR_HANDLE {
+        self.gpu_handle(0)
+    }
+
+    fn gpu_handle(&self, index: u32) -> D3D12_GPU_DESCRIPTOR_HANDLE {
+        D3D12_GPU_DESCRIPTOR_HANDLE {
+            ptr: unsafe { self.descriptor_heap.GetGPUDescriptorHandleForHeapStart() }.ptr
+                + index as u64 * self.descriptor_size as u64,
+        }
+    }
+
+    // Expects the depth buffer in DEPTH_WRITE and leaves it there. The
+    // pyramid rests in NON_PIXEL_SHADER_RESOURCE between frames.
+    pub fn record_build(&self, command_list: &ID3D12GraphicsCommandList, depth_buffer: &ID3D12Resource) {
+        unsafe {
+            command_list.SetDescriptorHeaps(&[Some(self.descriptor_heap.clone())]);
+            command_list.ResourceBarrier(&[
+                transition_barrier(
+                    depth_buffer,
+                    D3D12_RESOURCE_STATE_DEPTH_WRITE,
+                    D3D12_RESOURCE_STATE_NON_PIXEL_SHADER_RESOURCE,
+                ),
+                transition_barrier(
+                    &self.pyramid,
+                    D3D12_RESOURCE_STATE_NON_PIXEL_SHADER_RESOURCE,
+                    D3D12_RESOURCE_STATE_UNORDERED_ACCESS,
+                ),
+            ]);
+
+            command_list.SetComputeRootSignature(&self.root_signature);
+
+            for mip in 0..self.mip_count {
+                let pso = match (mip, &self.multisampled_pso) {
+                    (0, Some(multisampled_pso)) => multisampled_pso,
+                    _ => &self.pso,
+                };
+                command_list.SetPipelineState(pso);
+                let (source_width, source_height) = if mip == 0 {
+                    (self.width, self.height)
+                } else {
+                    mip_size(self.width, self.height, mip - 1)
+                };
+                let (width, height) = mip_size(self.width, self.height, mip);
+                let constants = [source_width, source_height, width, height];
+
+                command_list.SetComputeRoot32BitConstants(
+                    0,
+                    constants.len() as u32,
+                    constants.as_ptr() as *const _,
+                    0,
+                );
+                command_list.SetComputeRootDescriptorTable(1, self.gpu_handle(1 + 2 * mip));
+                command_list.Dispatch(
+                    width.div_ceil(DOWNSAMPLE_GROUP_SIZE),
+                    height.div_ceil(DOWNSAMPLE_GROUP_SIZE),
+                    1,
+                );
+
+                // The next level reads this one.
+                command_list.ResourceBarrier(&[transition_barrier_subresource(
+                    &self.pyramid,
+                    mip,
+                    D3D12_RESOURCE_STATE_UNORDERED_ACCESS,
+                    D3D12_RESOURCE_STATE_NON_PIXEL_SHADER_RESOURCE,
+                )]);
+            }
+
+            command_list.ResourceBarrier(&[transition_barrier(
+                depth_buffer,
+                D3D12_RESOURCE_STATE_NON_PIXEL_SHADER_RESOURCE,
+                D3D12_RESOURCE_STATE_DEPTH_WRITE,
+            )]);
+        }
+    }
+}
+
+fn texture_srv_desc(most_detailed_mip: u32, mip_levels: u32) -> D3D12_SHADER_RESOURCE_VIEW_DESC {
+    D3D12_SHADER_RESOURCE_VIEW_DESC {
+        Format: DXGI_FORMAT_R32_FLOAT,
+        ViewDimension: D3D12_SRV_DIMENSION_TEXTURE2D,
+        Shader4ComponentMapping: D3D12_DEFAULT_SHADER_4_COMPONENT_MAPPING,
+        Anonymous: D3D12_SHADER_RESOURCE_VIEW_DESC_0 {
+            Texture2D: D3D12_TEX2D_SRV {
+                MostDetailedMip: most_detailed_mip,
+                MipLevels: mip_levels,
+                PlaneSlice: 0,
+                ResourceMinLODClamp: 0.0,
+            },
+        },
+    }
+}
+
+fn create_downsample_pipeline_state(
+    device: &ID3D12Device,
+    root_signature: &ID3D12RootSignature,
+    path: &str,
+) -> Result<ID3D12PipelineState> {
+    let cs_bin = std::fs::read(path).map_err(|error| Error::new(E_FAIL, error.to_string().into()))?;
+
+    let desc = D3D12_COMPUTE_PIPELINE_STATE_DESC {
+        pRootSignature: unsafe { std::mem::transmute_copy(root_signature) },
+        CS: convert_to_bytecode(&cs_bin),
+        ..Default::default()
+    };
+    unsafe { device.CreateComputePipelineState(&desc) }
+}
+
+fn create_downsample_root_signature(device: &ID3D12Device) -> Result<ID3D12RootSignature> {
+    let ranges = [
+        D3D12_DESCRIPTOR_RANGE {
+            RangeType: D3D12_DESCRIPTOR_RANGE_TYPE_SRV,
+            NumDescriptors: 1,
+            BaseShaderRegister: 0,
+            RegisterSpace: 0,
+            OffsetInDescriptorsFromTableStart: 0,
+        },
+        D3D12_DESCRIPTOR_RANGE {
+            RangeType: D3D12_DESCRIPTOR_RANGE_TYPE_UAV,
+            NumDescriptors: 1,
+            BaseShaderRegister: 0,
+            RegisterSpace: 0,
+            OffsetInDescriptorsFromTableStart: 1,
+        },
+    ];
+
+    let parameters = [
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_32BIT_CONSTANTS,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Constants: D3D12_ROOT_CONSTANTS {
+                    ShaderRegister: 0,
+                    RegisterSpace: 0,
+                    Num32BitValues: 4,
+                },
+            },
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_ALL,
+        },
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_DESCRIPTOR_TABLE,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                DescriptorTable: D3D12_ROOT_DESCRIPTOR_TABLE {
+                    NumDescriptorRanges: ranges.len() as u32,
+                    pDescriptorRanges: ranges.as_ptr(),
+                },
+            },
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_ALL,
+        },
+    ];
+
+    let desc = D3D12_ROOT_SIGNATURE_DESC {
+        NumParameters: parameters.len() as u32,
+        pParameters: parameters.as_ptr(),
+        ..Default::default()
+    };
+
+    serialize_root_signature(device, &desc)
+}
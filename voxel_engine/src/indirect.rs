@@ -0,0 +1,305 @@
+use backend::{DrawEncoder, DrawIndexedArguments};
+
+use crate::math::{Aabb, Frustum, Mat4, Vec3};
+use crate::occlusion::mip_count;
+
+// Mirrors ChunkRecord in shaders/cull.hlsl, one per chunk mesh in the shared
+// vertex and index buffers.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ChunkRecord {
+    pub bounds_min: [f32; 3],
+    pub index_count: u32,
+    pub bounds_max: [f32; 3],
+    pub first_index: u32,
+    pub base_vertex: i32,
+    pub padding: [u32; 3],
+}
+
+impl ChunkRecord {
+    pub fn new(bounds: &Aabb, index_count: u32, first_index: u32, base_vertex: i32) -> Self {
+        ChunkRecord {
+            bounds_min: [bounds.min.x, bounds.min.y, bounds.min.z],
+            index_count,
+            bounds_max: [bounds.max.x, bounds.max.y, bounds.max.z],
+            first_index,
+            base_vertex,
+            padding: [0; 3],
+        }
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        let [min_x, min_y, min_z] = self.bounds_min;
+        let [max_x, max_y, max_z] = self.bounds_max;
+        Aabb::new(Vec3::new(min_x, min_y, min_z), Vec3::new(max_x, max_y, max_z))
+    }
+
+    pub fn draw_arguments(&self) -> DrawIndexedArguments {
+        DrawIndexedArguments {
+            index_count_per_instance: self.index_count,
+            instance_count: 1,
+            start_index_location: self.first_index,
+            base_vertex_location: self.base_vertex,
+            start_instance_location: 0,
+        }
+    }
+}
+
+// Root constants of the cull pass, laid out like the CullConstants cbuffer.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CullConstants {
+    pub planes: [[f32; 4]; 6],
+    pub occlusion_view_projection: [[f32; 4]; 4],
+    pub chunk_count: u32,
+    pub occlusion_enabled: u32,
+    pub hzb_width: u32,
+    pub hzb_height: u32,
+    pub hzb_mip_count: u32,
+    pub padding: [u32; 3],
+}
+
+impl CullConstants {
+    pub fn new(frustum: &Frustum, chunk_count: u32) -> Self {
+        let mut planes = [[0.0; 4]; 6];
+        for (out, plane) in planes.iter_mut().zip(frustum.planes.iter()) {
+            *out = [plane.normal.x, plane.normal.y, plane.normal.z, plane.d];
+        }
+        CullConstants {
+            planes,
+            occlusion_view_projection: Mat4::IDENTITY.rows,
+            chunk_count,
+            occlusion_enabled: 0,
+            hzb_width: 0,
+            hzb_height: 0,
+            hzb_mip_count: 0,
+            padding: [0; 3],
+        }
+    }
+
+    // view_projection is the one the depth pyramid was rendered with.
+    pub fn with_occlusion(self, view_projection: &Mat4, hzb_width: u32, hzb_height: u32) -> Self {
+        CullConstants {
+            occlusion_view_projection: view_projection.rows,
+            occlusion_enabled: 1,
+            hzb_width,
+            hzb_height,
+            hzb_mip_count: mip_count(hzb_width, hzb_height),
+            ..self
+        }
+    }
+}
+
+// CPU reference of CSMain in cull.hlsl: appends the arguments of every chunk
+// that is neither outside the frustum nor `occluded`, which stands in for
+// the depth pyramid test. The GPU appends through an atomic counter, so it
+// produces the same set of records but not necessarily in this order.
+pub fn cull_and_compact(
+    chunks: &[ChunkRecord],
+    frustum: &Frustum,
+    occluded: impl Fn(&Aabb) -> bool,
+    arguments: &mut Vec<DrawIndexedArguments>,
+) -> u32 {
+    arguments.clear();
+    arguments.extend(
+        chunks
+            .iter()
+            .filter(|chunk| {
+                let bounds = chunk.bounds();
+                frustum.is_visible(&bounds) && !occluded(&bounds)
+            })
+            .map(ChunkRecord::draw_arguments),
+    );
+    arguments.len() as u32
+}
+
+// Fallback when the indirect path is unavailable: cull on the CPU and issue
+// one draw per visible chunk. Returns the number of chunks drawn.
+pub fn submit_cpu(
+    encoder: &mut impl DrawEncoder,
+    chunks: &[ChunkRecord],
+    frustum: &Frustum,
+    arguments: &mut Vec<DrawIndexedArguments>,
+) -> u32 {
+    let drawn = cull_and_compact(chunks, frustum, |_| false, arguments);
+    for args in arguments.iter() {
+        encoder.draw_indexed(args);
+    }
+    drawn
+}
+
+// Arguments of the chunks inside the frustum, farthest from eye first, for
+// layers that blend over what is behind them. Chunks at the same distance
+// keep their order in `chunks`, so the result does not flicker between
+// frames.
+pub fn sort_back_to_front(
+    chunks: &[ChunkRecord],
+    frustum: &Frustum,
+    eye: Vec3,
+    arguments: &mut Vec<DrawIndexedArguments>,
+) -> u32 {
+    let mut visible: Vec<(f32, &ChunkRecord)> = chunks
+        .iter()
+        .filter(|chunk| frustum.is_visible(&chunk.bounds()))
+        .map(|chunk| {
+            let bounds = chunk.bounds();
+            let offset = (bounds.min + bounds.max) * 0.5 - eye;
+            (offset.dot(offset), chunk)
+        })
+        .collect();
+    visible.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+
+    arguments.clear();
+    arguments.extend(visible.into_iter().map(|(_, chunk)| chunk.draw_arguments()));
+    arguments.len() as u32
+}
+
+// Issues one draw per visible chunk in back to front order. Returns the
+// number of chunks drawn.
+pub fn submit_back_to_front(
+    encoder: &mut impl DrawEncoder,
+    chunks: &[ChunkRecord],
+    frustum: &Frustum,
+    eye: Vec3,
+    arguments: &mut Vec<DrawIndexedArguments>,
+) -> u32 {
+    let drawn = sort_back_to_front(chunks, frustum, eye, arguments);
+    for args in arguments.iter() {
+        encoder.draw_indexed(args);
+    }
+    drawn
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::{offset_of, size_of};
+
+    use backend::{HeadlessEncoder, RecordedCommand};
+
+    use super::*;
+
+    // Looking down +z from the origin, 90 degrees wide, 1 to 100 deep.
+    fn frustum() -> Frustum {
+        let view = Mat4::look_to_lh(Vec3::default(), Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 1.0, 0.0));
+        let projection = Mat4::perspective_lh(std::f32::consts::FRAC_PI_2, 1.0, 1.0, 100.0);
+        Frustum::from_view_projection(&(projection * view))
+    }
+
+    fn chunk(center: Vec3, first_index: u32) -> ChunkRecord {
+        let half = Vec3::new(1.0, 1.0, 1.0);
+        ChunkRecord::new(&Aabb::new(center - half, center + half), 36, first_index, first_index as i32 * 8)
+    }
+
+    #[test]
+    fn chunk_record_matches_cull_hlsl() {
+        assert_eq!(size_of::<ChunkRecord>(), 48);
+        assert_eq!(offset_of!(ChunkRecord, bounds_min), 0);
+        assert_eq!(offset_of!(ChunkRecord, index_count), 12);
+        assert_eq!(offset_of!(ChunkRecord, bounds_max), 16);
+        assert_eq!(offset_of!(ChunkRecord, first_index), 28);
+        assert_eq!(offset_of!(ChunkRecord, base_vertex), 32);
+        assert_eq!(offset_of!(ChunkRecord, padding), 36);
+    }
+
+    #[test]
+    fn cull_constants_match_cull_hlsl() {
+        assert_eq!(offset_of!(CullConstants, occlusion_view_projection), 96);
+        assert_eq!(offset_of!(CullConstants, chunk_count), 160);
+        assert_eq!(offset_of!(CullConstants, hzb_mip_count), 176);
+        assert_eq!(size_of::<CullConstants>() % 16, 0);
+    }
+
+    #[test]
+    fn compaction_keeps_visible_chunks_in_order() {
+        let chunks = [
+            chunk(Vec3::new(0.0, 0.0, 10.0), 0),
+            chunk(Vec3::new(0.0, 0.0, -10.0), 1),
+            chunk(Vec3::new(50.0, 0.0, 10.0), 2),
+            chunk(Vec3::new(10.0, 0.0, 10.0), 3),
+            chunk(Vec3::new(0.0, 0.0, 500.0), 4),
+        ];
+        let mut arguments = vec![DrawIndexedArguments::default(); 8];
+        assert_eq!(cull_and_compact(&chunks, &frustum(), |_| false, &mut arguments), 2);
+        assert_eq!(arguments, [chunks[0].draw_arguments(), chunks[3].draw_arguments()]);
+        assert_eq!(
+            arguments[1],
+            DrawIndexedArguments {
+                index_count_per_instance: 36,
+                instance_count: 1,
+                start_index_location: 3,
+                base_vertex_location: 24,
+                start_instance_location: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn cpu_submission_draws_each_visible_chunk() {
+        let chunks = [chunk(Vec3::new(0.0, 0.0, 10.0), 0), chunk(Vec3::new(0.0, 0.0, -10.0), 1)];
+        let mut encoder = HeadlessEncoder::new();
+        let mut arguments = Vec::new();
+        assert_eq!(submit_cpu(&mut encoder, &chunks, &frustum(), &mut arguments), 1);
+        assert_eq!(encoder.commands, [RecordedCommand::DrawIndexed(chunks[0].draw_arguments())]);
+    }
+
+    #[test]
+    fn translucent_chunks_sort_far_to_near() {
+        let chunks = [
+            chunk(Vec3::new(0.0, 0.0, 10.0), 0),
+            chunk(Vec3::new(0.0, 0.0, 40.0), 1),
+            chunk(Vec3::new(3.0, 0.0, 20.0), 2),
+            chunk(Vec3::new(0.0, 2.0, 80.0), 3),
+        ];
+        let mut arguments = Vec::new();
+        assert_eq!(sort_back_to_front(&chunks, &frustum(), Vec3::default(), &mut arguments), 4);
+        let order: Vec<u32> = arguments.iter().map(|args| args.start_index_location).collect();
+        assert_eq!(order, [3, 1, 2, 0]);
+
+        // Seen from beyond the far chunk the order turns around.
+        let eye = Vec3::new(0.0, 0.0, 90.0);
+        sort_back_to_front(&chunks, &frustum(), eye, &mut arguments);
+        let order: Vec<u32> = arguments.iter().map(|args| args.start_index_location).collect();
+        assert_eq!(order, [0, 2, 1, 3]);
+    }
+
+    #[test]
+    fn equal_distances_keep_their_order() {
+        let left = chunk(Vec3::new(-5.0, 0.0, 20.0), 0);
+        let right = chunk(Vec3::new(5.0, 0.0, 20.0), 1);
+        let above = chunk(Vec3::new(0.0, 5.0, 20.0), 2);
+        let near = chunk(Vec3::new(0.0, 0.0, 5.0), 3);
+        let mut arguments = Vec::new();
+        for chunks in [[left, right, near, above], [above, near, right, left]] {
+            sort_back_to_front(&chunks, &frustum(), Vec3::default(), &mut arguments);
+            let expected: Vec<DrawIndexedArguments> = chunks
+                .iter()
+                .filter(|chunk| chunk.first_index != 3)
+                .chain([&near])
+                .map(ChunkRecord::draw_arguments)
+                .collect();
+            assert_eq!(arguments, expected);
+        }
+    }
+
+    #[test]
+    fn sorting_skips_chunks_outside_the_frustum() {
+        let chunks = [
+            chunk(Vec3::new(0.0, 0.0, -10.0), 0),
+            chunk(Vec3::new(0.0, 0.0, 10.0), 1),
+            chunk(Vec3::new(60.0, 0.0, 10.0), 2),
+            chunk(Vec3::new(0.0, 0.0, 30.0), 3),
+            chunk(Vec3::new(0.0, 0.0, 200.0), 4),
+        ];
+        let mut arguments = vec![DrawIndexedArguments::default(); 8];
+        let mut encoder = HeadlessEncoder::new();
+        assert_eq!(submit_back_to_front(&mut encoder, &chunks, &frustum(), Vec3::default(), &mut arguments), 2);
+        assert_eq!(arguments, [chunks[3].draw_arguments(), chunks[1].draw_arguments()]);
+        assert_eq!(
+            encoder.commands,
+            [
+                RecordedCommand::DrawIndexed(chunks[3].draw_arguments()),
+                RecordedCommand::DrawIndexed(chunks[1].draw_arguments()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,215 @@
+use crate::block::BlockId;
+use crate::chunk::{Chunk, CHUNK_SIZE};
+
+// Levels 1, 2 and 3 merge 2x2x2, 4x4x4 and 8x8x8 blocks into one.
+pub const MAX_LOD: u32 = 3;
+
+pub fn lod_factor(lod: u32) -> usize {
+    1 << lod
+}
+
+// Cubic block grid of one chunk at some level of detail, indexed like Chunk.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LodVolume {
+    pub size: usize,
+    pub blocks: Vec<BlockId>,
+}
+
+impl LodVolume {
+    pub fn index(&self, x: usize, y: usize, z: usize) -> usize {
+        x + z * self.size + y * self.size * self.size
+    }
+
+    pub fn get(&self, x: usize, y: usize, z: usize) -> BlockId {
+        self.blocks[self.index(x, y, z)]
+    }
+}
+
+// Reduces a chunk to CHUNK_SIZE >> lod blocks per side. Each output block
+// covers a cell of factor^3 input blocks:
+//
+// - it is solid when at least half of the cell is solid, or when the cell
+//   holds a surface block (solid with air right above it), so thin layers
+//   and floors do not vanish in the distance;
+// - its block is the most common one among the surface blocks of the cell,
+//   so grass stays grass from afar, otherwise the most common one among all
+//   solid blocks. Ties go to the lowest id to keep the result deterministic.
+//
+// The blocks above the top layer are the bottom layer of `above`, the chunk
+// on top of this one. Without it, as at the top of the world, the top layer
+// is open to the sky.
+pub fn downsample(chunk: &Chunk, above: Option<&Chunk>, lod: u32) -> LodVolume {
+    let factor = lod_factor(lod);
+    let size = CHUNK_SIZE / factor;
+    let cell_volume = factor * factor * factor;
+
+    let mut blocks = Vec::with_capacity(size * size * size);
+    let mut solid_counts: Vec<(BlockId, usize)> = Vec::new();
+    let mut surface_counts: Vec<(BlockId, usize)> = Vec::new();
+
+    for y in 0..size {
+        for z in 0..size {
+            for x in 0..size {
+                solid_counts.clear();
+                surface_counts.clear();
+                let mut solid = 0;
+
+                for cy in y * factor..(y + 1) * factor {
+                    for cz in z * factor..(z + 1) * factor {
+                        for cx in x * factor..(x + 1) * factor {
+                            let block = chunk.get(cx, cy, cz);
+                            if block.is_air() {
+                                continue;
+                            }
+                            solid += 1;
+                            count(&mut solid_counts, block);
+                            let over = if cy + 1 < CHUNK_SIZE {
+                                Some(chunk.get(cx, cy + 1, cz))
+                            } else {
+                                above.map(|above| above.get(cx, 0, cz))
+                            };
+                            if over.is_none_or(BlockId::is_air) {
+                                count(&mut surface_counts, block);
+                            }
+                        }
+                    }
+                }
+
+                let block = if !surface_counts.is_empty() {
+                    most_common(&surface_counts)
+                } else if solid * 2 >= cell_volume {
+                    most_common(&solid_counts)
+                } else {
+                    BlockId::AIR
+                };
+                blocks.push(block);
+            }
+        }
+    }
+
+    LodVolume { size, blocks }
+}
+
+fn count(counts: &mut Vec<(BlockId, usize)>, block: BlockId) {
+    match counts.iter_mut().find(|(id, _)| *id == block) {
+        Some((_, n)) => *n += 1,
+        None => counts.push((block, 1)),
+    }
+}
+
+fn most_common(counts: &[(BlockId, usize)]) -> BlockId {
+    counts
+        .iter()
+        .max_by(|(a_id, a_n), (b_id, b_n)| a_n.cmp(b_n).then(b_id.cmp(a_id)))
+        .map_or(BlockId::AIR, |(id, _)| *id)
+}
+
+// Distances, in chunks from the camera, at which levels 1, 2 and 3 start.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LodSettings {
+    pub distances: [f32; MAX_LOD as usize],
+}
+
+impl LodSettings {
+    pub fn select(&self, distance: f32) -> u32 {
+        self.distances
+            .iter()
+            .take_while(|start| distance >= **start)
+            .count() as u32
+    }
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        LodSettings {
+            distances: [3.0, 6.0, 10.0],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{DIRT, GRASS, SAND, STONE};
+
+    // Sets the blocks of the 2x2x2 cell at the origin whose index, in Chunk
+    // order within the cell, is listed.
+    fn cell(blocks: &[(usize, BlockId)]) -> Chunk {
+        let mut chunk = Chunk::new();
+        for &(i, block) in blocks {
+            chunk.set(i & 1, i >> 2 & 1, i >> 1 & 1, block);
+        }
+        chunk
+    }
+
+    #[test]
+    fn cells_need_half_their_blocks_to_stay_solid() {
+        // Solid from y 1 up and under a full chunk, so the cells of the bottom
+        // layer are half solid and none of their blocks is a surface.
+        let above = Chunk::filled(STONE);
+        let mut chunk = Chunk::filled(STONE);
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                chunk.set(x, 0, z, BlockId::AIR);
+            }
+        }
+        // Three of eight in the second cell.
+        chunk.set(2, 1, 0, BlockId::AIR);
+
+        let volume = downsample(&chunk, Some(&above), 1);
+        assert_eq!(volume.size, CHUNK_SIZE / 2);
+        assert_eq!(volume.get(0, 0, 0), STONE);
+        assert!(volume.get(1, 0, 0).is_air());
+        assert_eq!(volume.get(1, 1, 0), STONE);
+    }
+
+    #[test]
+    fn thin_floors_are_kept() {
+        let mut chunk = Chunk::new();
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                chunk.set(x, 4, z, STONE);
+            }
+        }
+        // One layer in a cell eight high at level 3.
+        let volume = downsample(&chunk, None, 3);
+        assert_eq!(volume.size, 4);
+        assert!(volume.blocks[..16].iter().all(|&block| block == STONE));
+        assert!(volume.blocks[16..].iter().all(|block| block.is_air()));
+    }
+
+    #[test]
+    fn surface_blocks_decide_the_block() {
+        // Grass on three of four blocks of dirt: dirt is the majority,
+        // grass most of the surface.
+        let chunk = cell(&[(0, DIRT), (1, DIRT), (2, DIRT), (3, DIRT), (4, GRASS), (5, GRASS), (6, GRASS)]);
+        assert_eq!(downsample(&chunk, None, 1).get(0, 0, 0), GRASS);
+    }
+
+    #[test]
+    fn ties_go_to_the_lowest_id() {
+        let chunk = cell(&[(4, SAND), (5, STONE), (6, SAND), (7, STONE)]);
+        assert_eq!(downsample(&chunk, None, 1).get(0, 0, 0), STONE);
+    }
+
+    #[test]
+    fn the_chunk_above_covers_the_top_layer() {
+        let mut chunk = Chunk::new();
+        chunk.set(0, CHUNK_SIZE - 1, 0, GRASS);
+        // Open to the sky, the lone block is a surface and keeps its cell.
+        assert_eq!(downsample(&chunk, None, 1).get(0, CHUNK_SIZE / 2 - 1, 0), GRASS);
+        assert_eq!(downsample(&chunk, Some(&Chunk::new()), 1).get(0, CHUNK_SIZE / 2 - 1, 0), GRASS);
+        // Buried under the chunk above, one block of eight is not enough.
+        let above = Chunk::filled(DIRT);
+        assert!(downsample(&chunk, Some(&above), 1).get(0, CHUNK_SIZE / 2 - 1, 0).is_air());
+    }
+
+    #[test]
+    fn levels_start_at_their_distances() {
+        let settings = LodSettings::default();
+        assert_eq!(settings.select(0.0), 0);
+        assert_eq!(settings.select(3.0), 1);
+        assert_eq!(settings.select(9.9), 2);
+        assert_eq!(settings.select(50.0), MAX_LOD);
+    }
+}
@@ -0,0 +1,48 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+use env_logger::{Env, Target};
+use log::LevelFilter;
+
+// Sends every record to stderr and to the log file.
+struct Tee {
+    file: File,
+}
+
+impl Write for Tee {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        io::stderr().write_all(bytes)?;
+        self.file.write_all(bytes)?;
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()?;
+        self.file.flush()
+    }
+}
+
+// Installs the logger, recording `level` and more severe records. RUST_LOG
+// replaces that filter the usual env_logger way, e.g.
+// `RUST_LOG=info,d3d12=warn`. Records go to stderr and are written to
+// `path` too, which is replaced on every run; when it cannot be created the
+// log only goes to stderr.
+pub fn init(path: &Path, level: LevelFilter) {
+    let mut builder = env_logger::Builder::from_env(Env::default().default_filter_or(level.as_str()));
+    let file = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|()| File::create(path));
+    let file_error = match file {
+        Ok(file) => {
+            builder.target(Target::Pipe(Box::new(Tee { file })));
+            None
+        }
+        Err(error) => Some(error),
+    };
+    builder.init();
+    if let Some(error) = file_error {
+        log::warn!("failed to create the log file {}: {}", path.display(), error);
+    }
+}
@@ -1,168 +1,565 @@
 use winit::{
-    event::{Event, WindowEvent},
+    dpi::PhysicalSize,
+    event::{ElementState, Event, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent},
     event_loop::EventLoop,
     window::WindowBuilder,
     window::Window,
     platform::windows::WindowExtWindows,
 };
-use log::{info};
+use log::{debug, error, info, warn};
 
 use windows::{
     core::*, Win32::Foundation::*, Win32::Graphics::Direct3D::*,
     Win32::Graphics::Direct3D12::*, Win32::Graphics::Dxgi::Common::*, Win32::Graphics::Dxgi::*,
     Win32::System::Threading::*,
+    Win32::UI::WindowsAndMessaging::{MessageBoxW, MB_ICONERROR, MB_OK},
 };
 
-fn get_hardware_adapter(factory: &IDXGIFactory4) -> Result<IDXGIAdapter1> {
-    for i in 0.. {
-        let adapter = unsafe { factory.EnumAdapters1(i)? };
-
-        let mut desc = Default::default();
-        unsafe { adapter.GetDesc1(&mut desc)? };
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
 
-        if (DXGI_ADAPTER_FLAG(desc.Flags) & DXGI_ADAPTER_FLAG_SOFTWARE) != DXGI_ADAPTER_FLAG_NONE {
-            // Don't select the Basic Render Driver adapter. If you want a
-            // software adapter, pass in "/warp" on the command line.
-            continue;
-        }
+use backend::{
+    DeviceRecovery, DeviceState, DrawEncoder, DrawIndexedArguments, DrawSubmission, PresentMode, RecoverableDevice,
+};
 
+mod adapter;
+mod block;
+mod capture;
+mod camera;
+mod chunk;
+mod config;
+mod debug_device;
+mod debug_draw;
+mod debug_draw_pass;
+mod debug_layer;
+mod dev_ui;
+mod dev_ui_pass;
+mod error;
+mod frame_graph;
+mod gpu_culling;
+mod gpu_profiler;
+mod gpu_raymarch;
+mod hzb;
+mod indirect;
+mod lod;
+mod logger;
+mod markers;
+mod math;
+mod mesher;
+mod msaa;
+mod occlusion;
+mod post;
+mod post_process;
+mod profiler;
+mod raycast;
+mod raymarch;
+mod region;
+mod render_graph;
+mod shadow;
+mod shadow_map;
+mod sky;
+mod sky_pass;
+mod streaming;
+mod svo;
+mod vox;
+mod world;
+
+use adapter::{
+    choose_adapter, AdapterChoice, AdapterInfo, AdapterPreference,
+    DeviceCapabilities, FeatureLevel, ShaderModel,
+};
+use block::{BlockId, BlockRegistry, DIRT, GLASS, GRASS, SAND, STONE, WATER};
+use camera::Camera;
+use capture::CaptureTool;
+use chunk::{chunks_touching, ChunkPos, CHUNK_SIZE};
+use config::EngineConfig;
+use debug_device::{dred_report, enable_debug_layer, InfoQueue};
+use debug_draw::{debug_draw, take_debug_primitives, DebugPrimitives, Depth};
+use debug_draw_pass::DebugDrawPass;
+use debug_layer::{debug_settings_from_args, DebugSettings};
+use dev_ui::{DevUi, UiFrame, UiState, UiStats};
+use dev_ui_pass::DevUiPass;
+use error::{Context, EngineError, EngineResult};
+use frame_graph::{FrameGraph, ImportedResources};
+use gpu_culling::{CommandListEncoder, GpuCulling};
+use gpu_profiler::GpuProfiler;
+use gpu_raymarch::RayMarchPass;
+use markers::set_name;
+use indirect::{submit_back_to_front, submit_cpu, ChunkRecord};
+use math::{Aabb, Frustum, Mat4, Vec3};
+use mesher::{mesh_chunk, outline_mesh, ChunkMesh, Vertex};
+use msaa::MsaaSettings;
+use post::{BloomSettings, PostSettings};
+use post_process::PostProcess;
+use profiler::{profile_scope, take_cpu_scopes, ProfileHistory};
+use raycast::{raycast, RaycastHit};
+use raymarch::RayMarchConstants;
+use region::RegionStorage;
+use shadow::{ShadowCascades, ShadowConstants, ShadowSettings};
+use shadow_map::ShadowMapPass;
+use sky::{DayCycle, DaySettings, FogSettings, LightingConstants};
+use sky_pass::SkyPass;
+use streaming::{ChunkStreamer, StreamingSettings};
+use svo::SparseVoxelOctree;
+use vox::{capture, map_palette, stamp, VoxFile};
+use world::{generate_chunk, World};
+
+fn adapter_info(adapter: &IDXGIAdapter1, index: u32) -> Result<AdapterInfo> {
+    let mut desc = Default::default();
+    unsafe { adapter.GetDesc1(&mut desc)? };
+
+    let name_length = desc
+        .Description
+        .iter()
+        .position(|&c| c == 0)
+        .unwrap_or(desc.Description.len());
+    Ok(AdapterInfo {
+        index,
+        name: String::from_utf16_lossy(&desc.Description[..name_length]),
+        dedicated_video_memory: desc.DedicatedVideoMemory as u64,
+        software: (DXGI_ADAPTER_FLAG(desc.Flags) & DXGI_ADAPTER_FLAG_SOFTWARE) != DXGI_ADAPTER_FLAG_NONE,
         // Check to see whether the adapter supports Direct3D 12, but don't
         // create the actual device yet.
-        if unsafe {
+        supports_d3d12: unsafe {
             D3D12CreateDevice(
-                &adapter,
+                adapter,
                 D3D_FEATURE_LEVEL_11_0,
                 std::ptr::null_mut::<Option<ID3D12Device>>(),
             )
         }
-        .is_ok()
-        {
-            return Ok(adapter);
-        }
+        .is_ok(),
+    })
+}
+
+// The adapter `preference` asks for. A specific index or name no adapter
+// fits is an error, rather than running on an adapter nobody asked for.
+fn get_adapter(factory: &IDXGIFactory4, preference: &AdapterPreference) -> Result<(IDXGIAdapter1, AdapterInfo)> {
+    let mut adapters = Vec::new();
+    for i in 0.. {
+        let adapter = match unsafe { factory.EnumAdapters1(i) } {
+            Ok(adapter) => adapter,
+            Err(error) if error.code() == DXGI_ERROR_NOT_FOUND => break,
+            Err(error) => return Err(error),
+        };
+        let info = adapter_info(&adapter, i)?;
+        info!(
+            "adapter {}: {}, {} MiB{}",
+            info.index,
+            info.name,
+            info.dedicated_video_memory / (1024 * 1024),
+            if info.supports_d3d12 { "" } else { ", no Direct3D 12" }
+        );
+        adapters.push((adapter, info));
     }
 
-    unreachable!()
+    let infos: Vec<AdapterInfo> = adapters.iter().map(|(_, info)| info.clone()).collect();
+    let choice = choose_adapter(&infos, preference).map_err(|message| Error::new(E_INVALIDARG, message.into()))?;
+    match choice {
+        AdapterChoice::Adapter(i) => Ok(adapters.swap_remove(i)),
+        AdapterChoice::Warp => {
+            let adapter: IDXGIAdapter1 = unsafe { factory.EnumWarpAdapter() }?;
+            let mut info = adapter_info(&adapter, infos.len() as u32)?;
+            // WARP is usually enumerated as well, report it by that index.
+            if let Some(enumerated) = infos.iter().find(|enumerated| enumerated.software && enumerated.name == info.name) {
+                info.index = enumerated.index;
+            }
+            Ok((adapter, info))
+        }
+    }
 }
 
-const FRAME_COUNT: u32 = 2;
+// How often recreating a lost device is tried, and the delay before the
+// second try, which doubles with every further one.
+const DEVICE_RECOVERY_ATTEMPTS: u32 = 8;
+const DEVICE_RECOVERY_DELAY: Duration = Duration::from_millis(250);
+
+const DEPTH_FORMAT: DXGI_FORMAT = DXGI_FORMAT_D32_FLOAT;
+
+// HDR color the main pass renders into, post processing brings it to the
+// back buffer.
+const SCENE_FORMAT: DXGI_FORMAT = DXGI_FORMAT_R16G16B16A16_FLOAT;
+
+// Flip model swap chains cannot have sRGB buffers, their render target
+// views can.
+const BACK_BUFFER_VIEW_FORMAT: DXGI_FORMAT = DXGI_FORMAT_R8G8B8A8_UNORM_SRGB;
+
+// How far the cursor reaches into the world, in blocks. Well inside the full
+// detail range, so the highlight matches what is on screen.
+const PICK_DISTANCE: f32 = 64.0;
+
+// Region files of the world, relative to the working directory like the
+// compiled shaders in resources/.
+const SAVE_DIRECTORY: &str = "saves/world";
+
+// Written on every run, see logger::init.
+const LOG_PATH: &str = "logs/engine.log";
+
+// Frames of CPU and GPU scopes kept. F2 logs their average, F3 writes them
+// here as a Chrome trace.
+const PROFILE_FRAMES: usize = 240;
+const PROFILE_PATH: &str = "logs/profile.json";
+
+// The window title shows the frame times averaged over OVERLAY_FRAMES,
+// updated every OVERLAY_INTERVAL.
+const OVERLAY_FRAMES: usize = 60;
+const OVERLAY_INTERVAL: Duration = Duration::from_millis(500);
+
+// Stamped against the targeted face with V.
+const MODEL_PATH: &str = "resources/model.vox";
+
+// E writes the blocks around the target, EXPORT_SIZE per side, here.
+const EXPORT_PATH: &str = "saves/export.vox";
+const EXPORT_SIZE: u32 = 32;
+
+// Longest frame time the day cycle advances by, so a stall does not skip
+// through the day.
+const MAX_FRAME_TIME: f32 = 0.25;
+
+// Longest wait for the swap chain before a frame, in case it never signals.
+const FRAME_LATENCY_TIMEOUT_MS: u32 = 1000;
+
+const HIGHLIGHT_COLOR: [f32; 4] = [0.05, 0.05, 0.05, 1.0];
+
+// The debug view, toggled with F4.
+const CHUNK_BORDER_COLOR: [f32; 4] = [1.0, 0.8, 0.1, 1.0];
+const TARGET_COLOR: [f32; 4] = [0.1, 1.0, 1.0, 1.0];
+const FRUSTUM_COLOR: [f32; 4] = [1.0, 0.3, 1.0, 1.0];
+const DEBUG_TEXT_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+// Blocks placed with the number keys 1 to 6.
+const PLACEABLE_BLOCKS: [BlockId; 6] = [STONE, DIRT, GRASS, SAND, GLASS, WATER];
 
 pub struct Sample {
-    dxgi_factory: IDXGIFactory4,
-    device: ID3D12Device,
+    config: EngineConfig,
+    debug_settings: DebugSettings,
+    // Everything created on the device, the device included. Dropped when
+    // the device is lost and built again from the CPU side state here.
     resources: Option<Resources>,
+    registry: BlockRegistry,
+    world: World,
+    storage: RegionStorage,
+    streamer: ChunkStreamer,
+    meshes: HashMap<ChunkPos, ChunkMesh>,
+    camera: Camera,
+    // Cursor position in uv, None while it is outside the window.
+    cursor: Option<[f32; 2]>,
+    target: Option<RaycastHit>,
+    selected_block: BlockId,
+    // Octree over the resident chunks for the ray marched mode, rebuilt
+    // whenever the world changed while that mode is active.
+    octree: Option<SparseVoxelOctree>,
+    octree_stale: bool,
+    shadow_settings: ShadowSettings,
+    day_cycle: DayCycle,
+    fog: FogSettings,
+    post_settings: PostSettings,
+    msaa_settings: MsaaSettings,
+    last_frame: Option<Instant>,
+    // Water waves run on wall clock time from here.
+    start: Instant,
+    // Counts rendered frames, the key of their profiles.
+    frame_number: u64,
+    profile: ProfileHistory,
+    // When the profile summary in the title was last updated.
+    overlay_updated: Option<Instant>,
+    // The frame debugger the engine was started under, F9 captures the
+    // next frame with it.
+    capture: Option<CaptureTool>,
+    capture_requested: bool,
+    // On while Some, with the view projection the camera had when it was
+    // turned on, drawn as a frustum to look at from outside.
+    debug_view: Option<Mat4>,
+    // Panels for the stats and settings, F1 shows and hides them.
+    dev_ui: DevUi,
+    // Starts as configured, F5 cycles through the modes.
+    present_mode: PresentMode,
+}
+
+// Toggled with R.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RenderMode {
+    Rasterized,
+    RayMarched,
+}
+
+// The chunk meshes are drawn in two layers with their own pipeline states:
+// opaque faces first, then translucent ones blended over them back to front.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ChunkLayer {
+    Opaque,
+    Translucent,
 }
 
 struct Resources {
+    device: ID3D12Device,
+    // Only with the debug layer on.
+    info_queue: Option<InfoQueue>,
+    gpu_profiler: GpuProfiler,
     command_queue: ID3D12CommandQueue,
     swap_chain: IDXGISwapChain3,
+    // Whether presents may tear, for displays with variable refresh.
+    tearing_supported: bool,
+    // Signalled when the swap chain can queue another frame.
+    frame_latency_waitable: HANDLE,
     frame_index: u32,
-    render_targets: [ID3D12Resource; FRAME_COUNT as usize],
+    // One per frame in flight.
+    render_targets: Vec<ID3D12Resource>,
     rtv_heap: ID3D12DescriptorHeap,
     rtv_descriptor_size: usize,
+    // Of the main pass, what the device supports of the MSAA settings.
+    sample_count: u32,
+    depth_buffer: ID3D12Resource,
+    dsv_heap: ID3D12DescriptorHeap,
     viewport: D3D12_VIEWPORT,
     scissor_rect: RECT,
     command_allocator: ID3D12CommandAllocator,
     root_signature: ID3D12RootSignature,
     pso: ID3D12PipelineState,
+    translucent_pso: ID3D12PipelineState,
     command_list: ID3D12GraphicsCommandList,
+    chunk_buffers: ChunkBuffers,
+    highlight: Option<MeshBuffers>,
+    draw_arguments: Vec<DrawIndexedArguments>,
+    translucent_arguments: Vec<DrawIndexedArguments>,
+    draw_submission: DrawSubmission,
+    gpu_culling: Option<GpuCulling>,
+    view_projection: Mat4,
+    camera_position: Vec3,
+    // Seconds since start, animates the water.
+    time: f32,
+    // View projection the depth buffer content was rendered with, None until
+    // the first frame has been drawn.
+    occlusion_view_projection: Option<Mat4>,
+    cull_stats: CullStats,
+    ray_march: Option<RayMarchPass>,
+    render_mode: RenderMode,
+    ray_march_constants: RayMarchConstants,
+    shadow_map: ShadowMapPass,
+    shadow_cascades: ShadowCascades,
+    sky: SkyPass,
+    debug_draw: DebugDrawPass,
+    dev_ui: DevUiPass,
+    frame_graph: FrameGraph,
+    post_process: PostProcess,
+    clear_color: [f32; 4],
+    fence: ID3D12Fence,
+    fence_value: u64,
+    fence_event: HANDLE,
+}
 
+// Meshes of all resident chunks packed into one vertex and one index buffer.
+struct ChunkBuffers {
     // we need to keep this around to keep the reference alive, even though
     // nothing reads from it
     #[allow(dead_code)]
     vertex_buffer: ID3D12Resource,
 
     vbv: D3D12_VERTEX_BUFFER_VIEW,
-    fence: ID3D12Fence,
-    fence_value: u64,
-    fence_event: HANDLE,
+    #[allow(dead_code)]
+    index_buffer: ID3D12Resource,
+    ibv: D3D12_INDEX_BUFFER_VIEW,
+    // Opaque layer of every chunk that has one, culled and drawn in any
+    // order.
+    chunks: Vec<ChunkRecord>,
+    // Translucent layer of every chunk that has one, sorted every frame.
+    translucent_chunks: Vec<ChunkRecord>,
+}
+
+// A mesh drawn on its own, outside the chunk buffers.
+struct MeshBuffers {
+    #[allow(dead_code)]
+    vertex_buffer: ID3D12Resource,
+    vbv: D3D12_VERTEX_BUFFER_VIEW,
+    #[allow(dead_code)]
+    index_buffer: ID3D12Resource,
+    ibv: D3D12_INDEX_BUFFER_VIEW,
+    index_count: u32,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct CullStats {
+    drawn: u32,
+    culled: u32,
 }
 
 impl Sample {
-    fn new() -> Result<Self> {
-        let (dxgi_factory, device) = create_device()?;
+    fn new(config: EngineConfig, debug_settings: DebugSettings) -> Self {
+        let streaming_settings = StreamingSettings {
+            view_distance: config.view_distance,
+            ..Default::default()
+        };
 
-        Ok(Sample {
-            dxgi_factory,
-            device,
+        Sample {
+            debug_settings,
             resources: None,
-        })
+            registry: BlockRegistry::default(),
+            world: World::new(),
+            storage: RegionStorage::new(SAVE_DIRECTORY),
+            streamer: ChunkStreamer::new(streaming_settings),
+            meshes: HashMap::new(),
+            camera: Camera::default(),
+            cursor: None,
+            target: None,
+            selected_block: STONE,
+            octree: None,
+            octree_stale: true,
+            shadow_settings: ShadowSettings::default(),
+            day_cycle: DayCycle::new(&DaySettings::default()),
+            fog: FogSettings::for_view_distance(streaming_settings.view_distance, CHUNK_SIZE),
+            post_settings: PostSettings::default(),
+            msaa_settings: config.msaa,
+            last_frame: None,
+            start: Instant::now(),
+            frame_number: 0,
+            profile: ProfileHistory::new(PROFILE_FRAMES),
+            overlay_updated: None,
+            capture: CaptureTool::attached(),
+            capture_requested: false,
+            present_mode: config.present_mode,
+            debug_view: None,
+            dev_ui: DevUi::new(1.0),
+            config,
+        }
     }
 
-    fn bind_to_window(&mut self, window: &Window) -> Result<()> {
+    // Creates the device and everything rendering to `window` needs, also
+    // to recover from a lost device.
+    fn bind_to_window(&mut self, window: &Window) -> EngineResult<()> {
+        let (dxgi_factory, device, info_queue, capabilities) =
+            create_device(&self.config.adapter, &self.debug_settings)?;
+
         let command_queue: ID3D12CommandQueue = unsafe {
-            self.device.CreateCommandQueue(&D3D12_COMMAND_QUEUE_DESC {
+            device.CreateCommandQueue(&D3D12_COMMAND_QUEUE_DESC {
                 Type: D3D12_COMMAND_LIST_TYPE_DIRECT,
                 ..Default::default()
-            })?
-        };
+            })
+        }
+        .context("creating the command queue")?;
+        set_name(&command_queue, "direct queue");
 
         let physical_size = window.inner_size();
 
         // let (width, height) = self.window_size();
 
+        // Tearing has to be allowed when the swap chain is created for any
+        // present to tear, the present mode decides at each present.
+        let tearing_supported = tearing_supported(&dxgi_factory);
+        let mut swap_chain_flags = DXGI_SWAP_CHAIN_FLAG_FRAME_LATENCY_WAITABLE_OBJECT.0;
+        if tearing_supported {
+            swap_chain_flags |= DXGI_SWAP_CHAIN_FLAG_ALLOW_TEARING.0;
+        }
         let swap_chain_desc = DXGI_SWAP_CHAIN_DESC1 {
-            BufferCount: FRAME_COUNT,
+            BufferCount: self.config.frames_in_flight,
             Width: physical_size.width as u32,
             Height: physical_size.height as u32,
             Format: DXGI_FORMAT_R8G8B8A8_UNORM,
             BufferUsage: DXGI_USAGE_RENDER_TARGET_OUTPUT,
             SwapEffect: DXGI_SWAP_EFFECT_FLIP_DISCARD,
+            // Flip model swap chains are never multisampled, the main pass
+            // is resolved before post processing instead.
             SampleDesc: DXGI_SAMPLE_DESC {
                 Count: 1,
                 ..Default::default()
             },
+            Flags: swap_chain_flags as u32,
             ..Default::default()
         };
 
         let hwnd = HWND(window.hwnd());
         let swap_chain: IDXGISwapChain3 = unsafe {
-            self.dxgi_factory.CreateSwapChainForHwnd(
+            dxgi_factory.CreateSwapChainForHwnd(
                 &command_queue,
                 hwnd,
                 &swap_chain_desc,
                 None,
                 None,
-            )?
+            )
         }
-        .cast()?;
+        .and_then(|swap_chain| swap_chain.cast())
+        .context("creating the swap chain")?;
 
         // This sample does not support fullscreen transitions
         unsafe {
-            self.dxgi_factory
+            dxgi_factory
                 .MakeWindowAssociation(hwnd, DXGI_MWA_NO_ALT_ENTER)?;
         }
 
+        unsafe { swap_chain.SetMaximumFrameLatency(self.config.max_frame_latency) }
+            .context("setting the maximum frame latency")?;
+        let frame_latency_waitable = unsafe { swap_chain.GetFrameLatencyWaitableObject() };
+        info!(
+            "{} presented frames may queue up, tearing {}",
+            self.config.max_frame_latency,
+            if tearing_supported { "supported" } else { "not supported" }
+        );
+
         let frame_index = unsafe { swap_chain.GetCurrentBackBufferIndex() };
 
         let rtv_heap: ID3D12DescriptorHeap = unsafe {
-            self.device
+            device
                 .CreateDescriptorHeap(&D3D12_DESCRIPTOR_HEAP_DESC {
-                    NumDescriptors: FRAME_COUNT,
+                    NumDescriptors: self.config.frames_in_flight,
                     Type: D3D12_DESCRIPTOR_HEAP_TYPE_RTV,
                     ..Default::default()
                 })
         }?;
+        set_name(&rtv_heap, "back buffer rtv heap");
 
         let rtv_descriptor_size = unsafe {
-            self.device
+            device
                 .GetDescriptorHandleIncrementSize(D3D12_DESCRIPTOR_HEAP_TYPE_RTV)
         } as usize;
         let rtv_handle = unsafe { rtv_heap.GetCPUDescriptorHandleForHeapStart() };
 
-        let render_targets: [ID3D12Resource; FRAME_COUNT as usize] =
-            array_init::try_array_init(|i: usize| -> Result<ID3D12Resource> {
+        let render_targets = (0..self.config.frames_in_flight as usize)
+            .map(|i| -> Result<ID3D12Resource> {
                 let render_target: ID3D12Resource = unsafe { swap_chain.GetBuffer(i as u32) }?;
+                set_name(&render_target, &format!("back buffer {}", i));
                 unsafe {
-                    self.device.CreateRenderTargetView(
+                    device.CreateRenderTargetView(
                         &render_target,
-                        None,
+                        Some(&D3D12_RENDER_TARGET_VIEW_DESC {
+                            Format: BACK_BUFFER_VIEW_FORMAT,
+                            ViewDimension: D3D12_RTV_DIMENSION_TEXTURE2D,
+                            ..Default::default()
+                        }),
                         D3D12_CPU_DESCRIPTOR_HANDLE {
                             ptr: rtv_handle.ptr + i * rtv_descriptor_size,
                         },
                     )
                 };
                 Ok(render_target)
-            })?;
+            })
+            .collect::<Result<Vec<_>>>()
+            .context("getting the back buffers")?;
+
+        self.msaa_settings.validate().map_err(EngineError::Config)?;
+        let sample_count = self.msaa_settings.supported_sample_count(|count| {
+            [SCENE_FORMAT, DEPTH_FORMAT].iter().all(|&format| {
+                multisample_quality_levels(&device, format, count).unwrap_or(0) > 0
+            })
+        });
+        if sample_count != self.msaa_settings.sample_count {
+            warn!(
+                "{}x MSAA is not supported, falling back to {}x",
+                self.msaa_settings.sample_count, sample_count
+            );
+        }
+        info!(
+            "MSAA {}x, {} quality levels",
+            sample_count,
+            multisample_quality_levels(&device, SCENE_FORMAT, sample_count)?
+        );
+
+        let (depth_buffer, dsv_heap) = create_depth_buffer(
+            &device,
+            physical_size.width,
+            physical_size.height,
+            sample_count,
+        )
+        .context("creating the depth buffer")?;
 
         let viewport = D3D12_VIEWPORT {
             TopLeftX: 0.0,
@@ -181,15 +578,27 @@ impl Sample {
         };
 
         let command_allocator = unsafe {
-            self.device
+            device
                 .CreateCommandAllocator(D3D12_COMMAND_LIST_TYPE_DIRECT)
         }?;
-
-        let root_signature = create_root_signature(&self.device)?;
-        let pso = create_pipeline_state(&self.device, &root_signature)?;
+        set_name(&command_allocator, "frame command allocator");
+
+        let root_signature = create_root_signature(&device).context("creating the root signature")?;
+        let pso = create_pipeline_state(&device, &root_signature, ChunkLayer::Opaque, sample_count)
+            .context("creating the opaque pipeline state")?;
+        let translucent_pso = create_pipeline_state(
+            &device,
+            &root_signature,
+            ChunkLayer::Translucent,
+            sample_count,
+        )
+        .context("creating the translucent pipeline state")?;
+        set_name(&root_signature, "chunk root signature");
+        set_name(&pso, "chunk opaque pso");
+        set_name(&translucent_pso, "chunk translucent pso");
 
         let command_list: ID3D12GraphicsCommandList = unsafe {
-            self.device.CreateCommandList(
+            device.CreateCommandList(
                 0,
                 D3D12_COMMAND_LIST_TYPE_DIRECT,
                 &command_allocator,
@@ -199,62 +608,901 @@ impl Sample {
         unsafe {
             command_list.Close()?;
         };
+        set_name(&command_list, "frame command list");
+
+        let chunk_buffers =
+            create_chunk_buffers(&device, &self.meshes).context("uploading the chunk meshes")?;
+
+        let shadow_map = ShadowMapPass::new(&device, &root_signature, self.shadow_settings.resolution)
+            .context("creating the shadow map pass")?;
+        let sky = SkyPass::new(&device, &root_signature, sample_count).context("creating the sky pass")?;
+        let debug_draw =
+            DebugDrawPass::new(&device, &root_signature, sample_count).context("creating the debug draw pass")?;
+        let dev_ui = DevUiPass::new(&device).context("creating the developer UI pass")?;
+        // The new pass has none of the UI's textures, start it over.
+        self.dev_ui.reset(window.scale_factor() as f32);
+        let post_passes = self.post_settings.passes();
+        let frame_graph = FrameGraph::new(
+            &device,
+            physical_size.width,
+            physical_size.height,
+            sample_count,
+            &post_passes,
+        )
+        .context("building the frame graph")?;
+        let post_process = PostProcess::new(
+            &device,
+            physical_size.width,
+            physical_size.height,
+            &post_passes,
+            &frame_graph,
+        )
+        .context("creating the post processing chain")?;
+        let lighting = self.day_cycle.lighting();
+
+        let gpu_culling = match GpuCulling::new(
+            &device,
+            &chunk_buffers.chunks,
+            &depth_buffer,
+            physical_size.width,
+            physical_size.height,
+        ) {
+            Ok(gpu_culling) => Some(gpu_culling),
+            Err(error) => {
+                warn!("GPU culling unavailable, falling back to CPU submission: {}", error);
+                None
+            }
+        };
+        let draw_submission = if gpu_culling.is_some() {
+            capabilities.preferred_draw_submission()
+        } else {
+            DrawSubmission::Cpu
+        };
 
-        let aspect_ratio = physical_size.width as f32 / physical_size.height as f32;
+        let ray_march = match RayMarchPass::new(
+            &device,
+            physical_size.width,
+            physical_size.height,
+        ) {
+            Ok(ray_march) => Some(ray_march),
+            Err(error) => {
+                warn!("ray marching unavailable: {}", error);
+                None
+            }
+        };
 
-        let (vertex_buffer, vbv) = create_vertex_buffer(&self.device, aspect_ratio)?;
+        let gpu_profiler = GpuProfiler::new(&device, &command_queue).context("creating the GPU profiler")?;
 
-        let fence = unsafe { self.device.CreateFence(0, D3D12_FENCE_FLAG_NONE) }?;
+        let fence = unsafe { device.CreateFence(0, D3D12_FENCE_FLAG_NONE) }.context("creating the fence")?;
+        set_name(&fence, "frame fence");
 
         let fence_value = 1;
 
         let fence_event = unsafe { CreateEventA(None, false, false, None)? };
 
         self.resources = Some(Resources {
+            device,
+            info_queue,
+            gpu_profiler,
             command_queue,
             swap_chain,
+            tearing_supported,
+            frame_latency_waitable,
             frame_index,
             render_targets,
             rtv_heap,
             rtv_descriptor_size,
+            sample_count,
+            depth_buffer,
+            dsv_heap,
             viewport,
             scissor_rect,
             command_allocator,
             root_signature,
             pso,
+            translucent_pso,
             command_list,
-            vertex_buffer,
-            vbv,
+            chunk_buffers,
+            highlight: None,
+            draw_arguments: Vec::new(),
+            translucent_arguments: Vec::new(),
+            draw_submission,
+            gpu_culling,
+            view_projection: Mat4::IDENTITY,
+            camera_position: Vec3::default(),
+            time: 0.0,
+            occlusion_view_projection: None,
+            cull_stats: CullStats::default(),
+            ray_march,
+            render_mode: RenderMode::Rasterized,
+            ray_march_constants: RayMarchConstants::default(),
+            shadow_map,
+            shadow_cascades: ShadowCascades::new(
+                &self.camera,
+                physical_size.width as f32 / physical_size.height as f32,
+                lighting.sun_direction,
+                &self.shadow_settings,
+            ),
+            sky,
+            debug_draw,
+            dev_ui,
+            frame_graph,
+            post_process,
+            clear_color: lighting.fog_color(),
             fence,
             fence_value,
             fence_event,
         });
+        self.flush_debug_messages();
 
         Ok(())
     }
 
     fn title(&self) -> String {
-        "D3D12 Hello Triangle".into()
+        self.config.title.clone()
+    }
+
+
+    // Streams chunks in and out around the camera and re-uploads the chunk
+    // meshes when anything changed.
+    fn update_world(&mut self) -> Result<()> {
+        let update = self.streamer.update(ChunkPos::from_world(self.camera.position));
+        if update.is_empty() {
+            return Ok(());
+        }
+        self.octree_stale = true;
+
+        // Every chunk the streamer let go of is saved before it is dropped:
+        // those unloaded now and those that failed to save before. The ones
+        // that fail stay in the world, dirty and unmeshed, and are tried
+        // again with the next update, so a disk error never loses edits.
+        let released: Vec<ChunkPos> = self
+            .world
+            .chunks()
+            .map(|(pos, _)| pos)
+            .filter(|pos| self.streamer.lod(*pos).is_none())
+            .collect();
+        let failed = self.save_chunks(released.iter().copied());
+        for pos in &released {
+            if !failed.contains(pos) {
+                self.world.remove_chunk(*pos);
+            }
+        }
+        for pos in &update.unload {
+            self.meshes.remove(pos);
+        }
+        for pos in &update.load {
+            // Kept after a failed save, with edits newer than the disk.
+            if self.world.chunk(*pos).is_some() {
+                continue;
+            }
+            let chunk = match self.storage.load(*pos) {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => generate_chunk(*pos),
+                Err(error) => {
+                    warn!("failed to load chunk {:?}, generating it again: {}", pos, error);
+                    generate_chunk(*pos)
+                }
+            };
+            self.world.insert_chunk(*pos, chunk);
+        }
+        for pos in &update.remesh {
+            let lod = self.streamer.lod(*pos).unwrap_or(0);
+            let mesh = mesh_chunk(&self.world, *pos, lod, &self.registry, |neighbour| {
+                self.streamer.lod(neighbour)
+            });
+            if mesh.is_empty() {
+                self.meshes.remove(pos);
+            } else {
+                self.meshes.insert(*pos, mesh);
+            }
+        }
+        debug!(
+            "streamed {} chunks in, {} out, {} resident",
+            update.load.len(),
+            update.unload.len(),
+            self.streamer.resident_count()
+        );
+
+        if let Some(resources) = &mut self.resources {
+            // The GPU is idle between frames, see wait_for_previous_frame, so
+            // the old buffers can be released right away.
+            resources.chunk_buffers = create_chunk_buffers(&resources.device, &self.meshes)?;
+            if let Some(gpu_culling) = &mut resources.gpu_culling {
+                gpu_culling.set_chunks(&resources.device, &resources.chunk_buffers.chunks)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Writes the dirty chunks among `positions` to their region files and
+    // returns the ones that could not be written. Failures are logged, the
+    // chunks stay dirty.
+    fn save_chunks(&mut self, positions: impl IntoIterator<Item = ChunkPos>) -> Vec<ChunkPos> {
+        let dirty: Vec<ChunkPos> = positions
+            .into_iter()
+            .filter(|pos| self.world.is_dirty(*pos))
+            .collect();
+        let chunks = dirty
+            .iter()
+            .filter_map(|pos| self.world.chunk(*pos).map(|chunk| (*pos, chunk)));
+        let mut failed = Vec::new();
+        for failure in self.storage.save(chunks) {
+            warn!(
+                "failed to save {} chunks into region {:?}: {}",
+                failure.chunks.len(),
+                failure.region,
+                failure.error
+            );
+            failed.extend(failure.chunks);
+        }
+        for pos in dirty {
+            if !failed.contains(&pos) {
+                self.world.mark_saved(pos);
+            }
+        }
+        failed
+    }
+
+    fn save_world(&mut self) {
+        let failed = self.save_chunks(self.world.dirty_chunks());
+        if !failed.is_empty() {
+            error!("{} edited chunks could not be saved", failed.len());
+        }
+    }
+
+    // Direction of the ray from the camera through the cursor.
+    fn cursor_ray(&self) -> Option<Vec3> {
+        let resources = self.resources.as_ref()?;
+        let aspect_ratio = resources.viewport.Width / resources.viewport.Height;
+        Some(self.camera.ray_direction(self.cursor?, aspect_ratio))
+    }
+
+    // Casts a ray through the cursor and rebuilds the highlight outline when
+    // it lands on another block. Liquids are looked through, so blocks
+    // under water can be picked.
+    fn update_target(&mut self) -> Result<()> {
+        let target = self.cursor_ray().and_then(|direction| {
+            raycast(self.camera.position, direction, PICK_DISTANCE, |block| {
+                self.world
+                    .block(block)
+                    .is_some_and(|id| !id.is_air() && !self.registry.is_liquid(id))
+            })
+        });
+
+        if target.map(|hit| hit.block) != self.target.map(|hit| hit.block) {
+            if let Some(resources) = &mut self.resources {
+                resources.highlight = match target {
+                    Some(hit) => Some(create_mesh_buffers(
+                        &resources.device,
+                        &outline_mesh(hit.block, HIGHLIGHT_COLOR),
+                        "highlight",
+                    )?),
+                    None => None,
+                };
+            }
+        }
+        self.target = target;
+        Ok(())
+    }
+
+    // Changes a block and queues its chunk, and the neighbours that share
+    // the faces it touches, for remeshing with the next world update.
+    fn set_block(&mut self, block: [i32; 3], id: BlockId) {
+        if !self.world.set_block(block, id) {
+            return;
+        }
+        for pos in chunks_touching(block) {
+            self.streamer.invalidate(pos);
+        }
+    }
+
+    fn break_block(&mut self) {
+        if let Some(hit) = self.target {
+            self.set_block(hit.block, BlockId::AIR);
+        }
+    }
+
+    fn place_block(&mut self) {
+        let hit = match self.target {
+            Some(hit) if hit.normal != [0; 3] => hit,
+            _ => return,
+        };
+        let block = hit.adjacent();
+        if self
+            .world
+            .block(block)
+            .is_some_and(|id| id.is_air() || self.registry.is_liquid(id))
+        {
+            self.set_block(block, self.selected_block);
+        }
     }
 
+    fn stamp_model(&mut self) {
+        let hit = match self.target {
+            Some(hit) => hit,
+            None => return,
+        };
+        // Colors map to existing blocks rather than new ones: region files
+        // store block ids, and blocks registered at runtime would not
+        // survive a restart.
+        let result = fs::read(MODEL_PATH)
+            .and_then(|bytes| VoxFile::parse(&bytes))
+            .and_then(|file| {
+                let blocks = map_palette(&file, &self.registry);
+                stamp(&mut self.world, &file, &blocks, hit.adjacent())
+            })
+            .map_err(|source| EngineError::Asset {
+                path: MODEL_PATH.to_string(),
+                source,
+            });
+        match result {
+            Ok(stale) => {
+                for pos in stale {
+                    self.streamer.invalidate(pos);
+                }
+            }
+            Err(error) => warn!("{}", error),
+        }
+    }
+
+    fn export_model(&self) {
+        let hit = match self.target {
+            Some(hit) => hit,
+            None => return,
+        };
+        let half = EXPORT_SIZE as i32 / 2;
+        let min = [hit.block[0] - half, hit.block[1] - half, hit.block[2] - half];
+        let result = capture(&self.world, &self.registry, min, [EXPORT_SIZE; 3]).and_then(|file| {
+            if let Some(directory) = Path::new(EXPORT_PATH).parent() {
+                fs::create_dir_all(directory)?;
+            }
+            fs::write(EXPORT_PATH, file.to_bytes())
+        });
+        match result {
+            Ok(()) => info!("exported the blocks around {:?} to {}", hit.block, EXPORT_PATH),
+            Err(error) => warn!("failed to export {}: {}", EXPORT_PATH, error),
+        }
+    }
+
+    fn log_profile(&self) {
+        info!("profile:\n{}", self.profile.report(PROFILE_FRAMES));
+    }
+
+    fn export_profile(&self) {
+        let result = Path::new(PROFILE_PATH)
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| fs::write(PROFILE_PATH, self.profile.chrome_trace()));
+        match result {
+            Ok(()) => info!("wrote the last {} frames to {}", self.profile.frames().count(), PROFILE_PATH),
+            Err(error) => warn!("failed to write {}: {}", PROFILE_PATH, error),
+        }
+    }
+
+    fn request_capture(&mut self) {
+        match &self.capture {
+            Some(capture) => {
+                info!("capturing the next frame with {}", capture.name());
+                self.capture_requested = true;
+            }
+            None => warn!("no frame debugger is attached, start the engine under RenderDoc or PIX to capture"),
+        }
+    }
+
+    // The window title with the average frame times, every OVERLAY_INTERVAL.
+    fn overlay_title(&mut self) -> Option<String> {
+        let now = Instant::now();
+        if self
+            .overlay_updated
+            .is_some_and(|updated| now - updated < OVERLAY_INTERVAL)
+        {
+            return None;
+        }
+        self.overlay_updated = Some(now);
+        let report = self.profile.report(OVERLAY_FRAMES);
+        Some(format!(
+            "{} - cpu {:.2} ms, gpu {:.2} ms",
+            self.title(),
+            report.cpu_frame_ms(),
+            report.gpu_frame_ms()
+        ))
+    }
+
+    fn toggle_debug_view(&mut self) {
+        self.debug_view = match (self.debug_view, &self.resources) {
+            (None, Some(resources)) => Some(resources.view_projection),
+            _ => None,
+        };
+        info!("debug view: {}", if self.debug_view.is_some() { "on" } else { "off" });
+    }
+
+    // Chunk borders around the camera, the picked block with the face the
+    // pick ray entered through, and the frustum the view was turned on with.
+    fn draw_debug_view(&self, frustum: &Mat4) {
+        let chunk = ChunkPos::from_world(self.camera.position);
+        let report = self.profile.report(OVERLAY_FRAMES);
+        debug_draw(|draw| {
+            draw.aabb(&chunk.bounds(), CHUNK_BORDER_COLOR, Depth::Tested);
+            draw.frustum(frustum, FRUSTUM_COLOR, Depth::Tested);
+
+            let mut text = format!(
+                "cpu {:.2} ms  gpu {:.2} ms\nposition {:.1} {:.1} {:.1}\nchunk {} {} {}",
+                report.cpu_frame_ms(),
+                report.gpu_frame_ms(),
+                self.camera.position.x,
+                self.camera.position.y,
+                self.camera.position.z,
+                chunk.x,
+                chunk.y,
+                chunk.z
+            );
+            if let (Some(hit), Some(direction)) = (self.target, self.cursor_ray()) {
+                let [x, y, z] = hit.block;
+                let min = Vec3::new(x as f32, y as f32, z as f32);
+                draw.aabb(&Aabb::new(min, min + Vec3::new(1.0, 1.0, 1.0)), TARGET_COLOR, Depth::Overlay);
+                let point = self.camera.position + direction * hit.distance;
+                let [nx, ny, nz] = hit.normal;
+                let normal = Vec3::new(nx as f32, ny as f32, nz as f32);
+                draw.sphere(point, 0.05, TARGET_COLOR, Depth::Overlay);
+                draw.line(point, point + normal * 0.5, TARGET_COLOR, Depth::Overlay);
+                text += &format!("\ntarget {} {} {}  {:.1} blocks", x, y, z, hit.distance);
+            }
+            draw.text([8.0, 8.0], &text, DEBUG_TEXT_COLOR);
+        });
+    }
+
+    fn toggle_render_mode(&mut self) {
+        if let Some(resources) = &mut self.resources {
+            resources.render_mode = match resources.render_mode {
+                RenderMode::Rasterized if resources.ray_march.is_some() => RenderMode::RayMarched,
+                _ => RenderMode::Rasterized,
+            };
+            info!("render mode: {:?}", resources.render_mode);
+        }
+    }
+
+    // Rebuilds the octree and uploads it to the ray march pass when the
+    // world changed since the last upload.
+    fn update_octree(&mut self) -> Result<()> {
+        if !self.octree_stale {
+            return Ok(());
+        }
+        let resources = match &mut self.resources {
+            Some(resources) => resources,
+            None => return Ok(()),
+        };
+        let ray_march = match &mut resources.ray_march {
+            Some(ray_march) => ray_march,
+            None => return Ok(()),
+        };
+        self.octree = SparseVoxelOctree::from_world(&self.world);
+        if let Some(octree) = &self.octree {
+            debug!("octree: {} nodes, {} KiB", octree.node_count(), octree.memory_usage() / 1024);
+        }
+        ray_march.set_octree(&resources.device, self.octree.as_ref(), &self.registry.colors())?;
+        self.octree_stale = false;
+        Ok(())
+    }
+
+    fn set_present_mode(&mut self, mode: PresentMode) {
+        self.present_mode = mode;
+        let tearing_supported = self.resources.as_ref().is_some_and(|resources| resources.tearing_supported);
+        if mode == PresentMode::Immediate && !tearing_supported {
+            info!("present mode: {}, presented like mailbox as tearing is not supported", mode);
+        } else {
+            info!("present mode: {}", mode);
+        }
+    }
+
+    fn toggle_day_cycle(&mut self) {
+        self.day_cycle.paused = !self.day_cycle.paused;
+        info!(
+            "day cycle {} at {:.3}",
+            if self.day_cycle.paused { "paused" } else { "resumed" },
+            self.day_cycle.time()
+        );
+    }
+
+    fn scale_day_cycle(&mut self, factor: f32) {
+        self.day_cycle.time_scale *= factor;
+        info!("day cycle time scale {}", self.day_cycle.time_scale);
+    }
 
-    fn render(&mut self) {
+    // Rebuilds the post processing chain after a settings change.
+    fn update_post_chain(&mut self) {
         if let Some(resources) = &mut self.resources {
-            populate_command_list(resources).unwrap();
+            let passes = self.post_settings.passes();
+            let (width, height) = (
+                resources.scissor_rect.right as u32,
+                resources.scissor_rect.bottom as u32,
+            );
+            let device = &resources.device;
+            let result = FrameGraph::new(
+                device,
+                width,
+                height,
+                resources.sample_count,
+                &passes,
+            )
+            .and_then(|frame_graph| {
+                resources
+                    .post_process
+                    .set_chain(device, &passes, &frame_graph)?;
+                Ok(frame_graph)
+            });
+            match result {
+                Ok(frame_graph) => {
+                    resources.frame_graph = frame_graph;
+                    info!("post processing: {:?}", self.post_settings);
+                }
+                Err(error) => warn!("keeping the previous post processing chain: {}", error),
+            }
+        }
+    }
+
+    fn cycle_tonemapper(&mut self) {
+        self.post_settings.tonemapper = self.post_settings.tonemapper.next();
+        self.update_post_chain();
+    }
+
+    fn toggle_fxaa(&mut self) {
+        self.post_settings.fxaa = !self.post_settings.fxaa;
+        self.update_post_chain();
+    }
+
+    fn toggle_bloom(&mut self) {
+        self.post_settings.bloom = match self.post_settings.bloom {
+            Some(_) => None,
+            None => Some(BloomSettings::default()),
+        };
+        self.update_post_chain();
+    }
+
+    // In photographic stops, each one doubles or halves the exposure.
+    fn adjust_exposure(&mut self, stops: f32) {
+        self.post_settings.exposure *= stops.exp2();
+        self.update_post_chain();
+    }
+
+    fn key_pressed(&mut self, key: VirtualKeyCode) {
+        match key {
+            VirtualKeyCode::Key1 => self.selected_block = PLACEABLE_BLOCKS[0],
+            VirtualKeyCode::Key2 => self.selected_block = PLACEABLE_BLOCKS[1],
+            VirtualKeyCode::Key3 => self.selected_block = PLACEABLE_BLOCKS[2],
+            VirtualKeyCode::Key4 => self.selected_block = PLACEABLE_BLOCKS[3],
+            VirtualKeyCode::Key5 => self.selected_block = PLACEABLE_BLOCKS[4],
+            VirtualKeyCode::Key6 => self.selected_block = PLACEABLE_BLOCKS[5],
+            VirtualKeyCode::V => self.stamp_model(),
+            VirtualKeyCode::E => self.export_model(),
+            VirtualKeyCode::R => self.toggle_render_mode(),
+            VirtualKeyCode::P => self.toggle_day_cycle(),
+            VirtualKeyCode::LBracket => self.scale_day_cycle(0.5),
+            VirtualKeyCode::RBracket => self.scale_day_cycle(2.0),
+            VirtualKeyCode::T => self.cycle_tonemapper(),
+            VirtualKeyCode::F => self.toggle_fxaa(),
+            VirtualKeyCode::B => self.toggle_bloom(),
+            VirtualKeyCode::Minus => self.adjust_exposure(-0.5),
+            VirtualKeyCode::Equals => self.adjust_exposure(0.5),
+            VirtualKeyCode::F2 => self.log_profile(),
+            VirtualKeyCode::F3 => self.export_profile(),
+            VirtualKeyCode::F1 => self.dev_ui.toggle(),
+            VirtualKeyCode::F4 => self.toggle_debug_view(),
+            VirtualKeyCode::F5 => self.set_present_mode(self.present_mode.next()),
+            VirtualKeyCode::F9 => self.request_capture(),
+            _ => (),
+        }
+    }
+
+    // Runs the developer UI over the current settings and applies what was
+    // changed in it. None while the UI is hidden or there is no device.
+    fn update_dev_ui(&mut self) -> Option<UiFrame> {
+        let resources = self.resources.as_ref()?;
+        let size = [resources.scissor_rect.right as u32, resources.scissor_rect.bottom as u32];
+        let report = self.profile.report(OVERLAY_FRAMES);
+        let before = UiState {
+            stats: UiStats {
+                cpu_ms: report.cpu_frame_ms(),
+                gpu_ms: report.gpu_frame_ms(),
+                frame: self.frame_number,
+                resident_chunks: self.streamer.resident_count(),
+                drawn_chunks: resources.cull_stats.drawn,
+                culled_chunks: resources.cull_stats.culled,
+            },
+            camera: self.camera,
+            view_distance: self.streamer.view_distance(),
+            fog: self.fog,
+            time_of_day: self.day_cycle.time(),
+            time_scale: self.day_cycle.time_scale,
+            paused: self.day_cycle.paused,
+            shadows: self.shadow_settings,
+            post: self.post_settings,
+            ray_marched: resources.render_mode == RenderMode::RayMarched,
+            ray_march_available: resources.ray_march.is_some(),
+            debug_view: self.debug_view.is_some(),
+            present_mode: self.present_mode,
+        };
+        let mut state = before;
+        let frame = self.dev_ui.run(size, &mut state)?;
+
+        self.camera = state.camera;
+        self.fog = state.fog;
+        if state.view_distance != before.view_distance {
+            self.streamer.set_view_distance(state.view_distance);
+            self.fog = FogSettings::for_view_distance(state.view_distance, CHUNK_SIZE);
+        }
+        if state.time_of_day != before.time_of_day {
+            self.day_cycle.set_time(state.time_of_day);
+        }
+        self.day_cycle.time_scale = state.time_scale;
+        self.day_cycle.paused = state.paused;
+        self.shadow_settings = state.shadows;
+        if state.post != before.post {
+            self.post_settings = state.post;
+            self.update_post_chain();
+        }
+        if state.ray_marched != before.ray_marched {
+            self.toggle_render_mode();
+        }
+        if state.debug_view != before.debug_view {
+            self.toggle_debug_view();
+        }
+        if state.present_mode != before.present_mode {
+            self.set_present_mode(state.present_mode);
+        }
+        Some(frame)
+    }
+
+    // Logs what the debug layer reported since the last call.
+    fn flush_debug_messages(&self) {
+        if let Some(info_queue) = self.resources.as_ref().and_then(|resources| resources.info_queue.as_ref()) {
+            info_queue.drain();
+        }
+    }
+
+    // Drops everything created on the device after it was lost. What the
+    // GPU held is rebuilt from the CPU side state when the device is back.
+    fn release_resources(&mut self) {
+        self.resources = None;
+        // Forgotten so the highlight and octree are uploaded again.
+        self.target = None;
+        self.octree_stale = true;
+    }
+
+    // Whether `error` came from the device going away rather than from
+    // something the renderer did.
+    fn device_lost(&self, error: &EngineError) -> bool {
+        let code = error.device_error().map(|error| error.code());
+        code == Some(DXGI_ERROR_DEVICE_REMOVED)
+            || code == Some(DXGI_ERROR_DEVICE_RESET)
+            || self
+                .resources
+                .as_ref()
+                .is_some_and(|resources| unsafe { resources.device.GetDeviceRemovedReason() }.is_err())
+    }
+
+    // Renders a frame and adds its CPU scopes to the profile, and the GPU
+    // scopes of an earlier frame, see GpuProfiler::collect.
+    fn render(&mut self) -> EngineResult<()> {
+        let frame = self.frame_number;
+        self.frame_number += 1;
+        let result = {
+            let _scope = profile_scope("frame");
+            self.render_frame(frame)
+        };
+        self.profile.add_cpu(frame, take_cpu_scopes());
+        result?;
+
+        if let Some(resources) = &mut self.resources {
+            if let Some((frame, scopes)) = resources.gpu_profiler.collect()? {
+                self.profile.add_gpu(frame, scopes);
+            }
+        }
+        Ok(())
+    }
+
+    fn render_frame(&mut self, frame: u64) -> EngineResult<()> {
+        // Until the swap chain can take another frame, so the frame is
+        // built from the latest input rather than queued behind others.
+        //
+        // This does not replace the wait for the GPU at the end of the
+        // frame. That one ends when the GPU finished the frame, this one
+        // when its present left the queue, which with vsync is up to a
+        // refresh later; without it the CPU would run ahead and block in
+        // Present with stale input instead. The GPU wait stays as long as
+        // there is a single command allocator, constant buffers written in
+        // place and meshes released as soon as they are replaced.
+        if let Some(resources) = &self.resources {
+            let _scope = profile_scope("wait for swap chain");
+            unsafe { WaitForSingleObjectEx(resources.frame_latency_waitable, FRAME_LATENCY_TIMEOUT_MS, true) };
+        }
+        let now = Instant::now();
+        if let Some(last_frame) = self.last_frame {
+            let frame_time = (now - last_frame).as_secs_f32().min(MAX_FRAME_TIME);
+            self.day_cycle.advance(frame_time);
+        }
+        self.last_frame = Some(now);
+
+        let ui = {
+            let _scope = profile_scope("dev ui");
+            self.update_dev_ui()
+        };
+        {
+            let _scope = profile_scope("update world");
+            self.update_world()?;
+        }
+        {
+            let _scope = profile_scope("update target");
+            self.update_target()?;
+        }
+        let ray_marched = self
+            .resources
+            .as_ref()
+            .is_some_and(|resources| resources.render_mode == RenderMode::RayMarched);
+        if ray_marched {
+            let _scope = profile_scope("update octree");
+            self.update_octree()?;
+        }
+
+        if let Some(frustum) = self.debug_view {
+            self.draw_debug_view(&frustum);
+        }
+        // Taken every frame, so nothing piles up while there is no device.
+        let debug = take_debug_primitives();
+
+        if let Some(resources) = &mut self.resources {
+            let aspect_ratio = resources.viewport.Width / resources.viewport.Height;
+            resources.view_projection = self.camera.view_projection(aspect_ratio);
+            resources.camera_position = self.camera.position;
+            resources.time = (now - self.start).as_secs_f32();
+            let lighting = self.day_cycle.lighting();
+            resources.shadow_cascades = ShadowCascades::new(
+                &self.camera,
+                aspect_ratio,
+                lighting.sun_direction,
+                &self.shadow_settings,
+            );
+            resources
+                .shadow_map
+                .set_constants(&ShadowConstants::new(
+                    &resources.shadow_cascades,
+                    self.camera.position,
+                    &self.shadow_settings,
+                ))?;
+            resources
+                .sky
+                .set_constants(&LightingConstants::new(&self.camera, aspect_ratio, &lighting, &self.fog))?;
+            resources.clear_color = lighting.fog_color();
+            resources.ray_march_constants = RayMarchConstants::new(
+                &self.camera,
+                self.octree.as_ref(),
+                resources.ray_march.as_ref().map_or(0, |ray_march| ray_march.palette_count()),
+                resources.viewport.Width as u32,
+                resources.viewport.Height as u32,
+                lighting.fog_color(),
+            );
+
+            resources.gpu_profiler.begin_frame(frame);
+            let capture = match self.capture.as_ref() {
+                Some(capture) if std::mem::take(&mut self.capture_requested) => {
+                    capture.begin();
+                    Some(capture)
+                }
+                _ => None,
+            };
+            {
+                let _scope = profile_scope("record");
+                populate_command_list(resources, &debug, ui.as_ref()).context("recording the frame")?;
+            }
+            resources.debug_draw.finish_frame(frame);
 
             // Execute the command list.
             let command_list = Some(resources.command_list.can_clone_into());
+            resources.gpu_profiler.submitted(profiler::now_ns());
             unsafe { resources.command_queue.ExecuteCommandLists(&[command_list]) };
 
-            // Present the frame.
-            unsafe { resources.swap_chain.Present(1, 0) }.ok().unwrap();
+            // Present the frame as the present mode says.
+            {
+                let _scope = profile_scope("present");
+                let parameters = self.present_mode.parameters(resources.tearing_supported);
+                let flags = if parameters.allow_tearing { DXGI_PRESENT_ALLOW_TEARING } else { 0 };
+                unsafe { resources.swap_chain.Present(parameters.sync_interval, flags) }
+                    .ok()
+                    .context("presenting the frame")?;
+            }
+            if let Some(capture) = capture {
+                if capture.end() {
+                    info!("captured frame {}", frame);
+                } else {
+                    warn!("{} did not capture frame {}", capture.name(), frame);
+                }
+            }
+
+            let _scope = profile_scope("wait for gpu");
+            wait_for_previous_frame(resources)?;
+            resources.debug_draw.retire(frame);
+        }
+        self.flush_debug_messages();
+        Ok(())
+    }
+}
+
+impl Drop for Resources {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.fence_event) };
+        unsafe { CloseHandle(self.frame_latency_waitable) };
+    }
+}
+
+// The sample on its window as backend::DeviceRecovery sees it: releasing
+// drops every GPU resource, recreating binds to the same window again.
+struct WindowDevice<'a> {
+    sample: &'a mut Sample,
+    window: &'a Window,
+}
+
+impl RecoverableDevice for WindowDevice<'_> {
+    type Error = EngineError;
+
+    fn removed_reason(&self) -> Option<String> {
+        let resources = self.sample.resources.as_ref()?;
+        let reason = unsafe { resources.device.GetDeviceRemovedReason() }.err()?;
+        let report = if self.sample.debug_settings.dred {
+            dred_report(&resources.device)
+        } else {
+            None
+        };
+        match report {
+            Some(report) => Some(format!("{}\n{}", reason, report)),
+            None => Some(reason.to_string()),
+        }
+    }
+
+    fn release(&mut self) {
+        self.sample.release_resources();
+    }
+
+    fn recreate(&mut self) -> EngineResult<()> {
+        self.sample.bind_to_window(self.window)
+    }
+}
 
-            wait_for_previous_frame(resources);
+// Renders a frame, or while the device is lost tries to bring it back.
+// Returns false once recovery gave up. Errors other than losing the device
+// are returned, the frame cannot be rendered again.
+fn run_frame(sample: &mut Sample, window: &Window, recovery: &mut DeviceRecovery) -> EngineResult<bool> {
+    match recovery.state() {
+        DeviceState::Ready => {
+            if let Err(error) = sample.render() {
+                sample.flush_debug_messages();
+                if !sample.device_lost(&error) {
+                    return Err(error);
+                }
+                let reason = recovery.lose(&mut WindowDevice { sample, window });
+                error!(
+                    "device lost: {}, removed reason: {}",
+                    error,
+                    reason.as_deref().unwrap_or("unknown")
+                );
+            }
+        }
+        DeviceState::Lost { attempts } => {
+            match recovery.recover(&mut WindowDevice { sample, window }, Instant::now()) {
+                Ok(DeviceState::Ready) => info!("device recreated after {} failed attempts", attempts),
+                Ok(_) => (),
+                Err(error) => warn!("recreating the device failed: {}", error),
+            }
         }
+        DeviceState::Failed => (),
     }
+    Ok(recovery.state() != DeviceState::Failed)
 }
 
-fn populate_command_list(resources: &Resources) -> Result<()> {
+fn populate_command_list(
+    resources: &mut Resources,
+    debug: &DebugPrimitives,
+    ui: Option<&UiFrame>,
+) -> Result<()> {
     // Command list allocators can only be reset when the associated
     // command lists have finished execution on the GPU; apps should use
     // fences to determine GPU execution progress.
@@ -271,48 +1519,221 @@ fn populate_command_list(resources: &Resources) -> Result<()> {
         command_list.Reset(&resources.command_allocator, &resources.pso)?;
     }
 
+    let gpu_profiler = &resources.gpu_profiler;
+    let frame = gpu_profiler.pass(command_list, "frame");
+    let back_buffer_rtv = D3D12_CPU_DESCRIPTOR_HANDLE {
+        ptr: unsafe { resources.rtv_heap.GetCPUDescriptorHandleForHeapStart() }.ptr
+            + resources.frame_index as usize * resources.rtv_descriptor_size,
+    };
+
+    if let (RenderMode::RayMarched, Some(ray_march)) = (resources.render_mode, &resources.ray_march) {
+        let back_buffer = &resources.render_targets[resources.frame_index as usize];
+        {
+            let _pass = gpu_profiler.pass(command_list, "ray march");
+            ray_march.record(command_list, &resources.ray_march_constants, back_buffer);
+        }
+        if let Some(ui) = ui {
+            let _pass = gpu_profiler.pass(command_list, "dev ui");
+            unsafe {
+                command_list.ResourceBarrier(&[transition_barrier(
+                    back_buffer,
+                    D3D12_RESOURCE_STATE_PRESENT,
+                    D3D12_RESOURCE_STATE_RENDER_TARGET,
+                )])
+            };
+            resources
+                .dev_ui
+                .record(&resources.device, command_list, ui, back_buffer_rtv, &resources.viewport)?;
+            unsafe {
+                command_list.ResourceBarrier(&[transition_barrier(
+                    back_buffer,
+                    D3D12_RESOURCE_STATE_RENDER_TARGET,
+                    D3D12_RESOURCE_STATE_PRESENT,
+                )])
+            };
+        }
+        drop(frame);
+        gpu_profiler.resolve(command_list);
+        // Nothing was drawn into the depth buffer.
+        resources.occlusion_view_projection = None;
+        return unsafe { command_list.Close() };
+    }
+
+    let frustum = Frustum::from_view_projection(&resources.view_projection);
+    let gpu_culling = match resources.draw_submission {
+        DrawSubmission::GpuIndirect => resources.gpu_culling.as_ref(),
+        DrawSubmission::Cpu => None,
+    };
+
+    if let Some(gpu_culling) = gpu_culling {
+        let occlusion = resources
+            .occlusion_view_projection
+            .as_ref()
+            .map(|view_projection| (&resources.depth_buffer, view_projection));
+        let _pass = gpu_profiler.pass(command_list, "cull");
+        gpu_culling.record_cull_pass(command_list, &frustum, occlusion);
+    }
+
+    let shadow_map = resources.shadow_map.shadow_map().clone();
+    let imported = ImportedResources {
+        shadow_map: &shadow_map,
+        back_buffer: &resources.render_targets[resources.frame_index as usize],
+    };
+
+    {
+        let _pass = gpu_profiler.pass(command_list, "shadows");
+        resources.frame_graph.begin_shadow_pass(command_list, &imported);
+        resources.shadow_map.record(
+            command_list,
+            &resources.root_signature,
+            &resources.shadow_cascades,
+            &resources.chunk_buffers.vbv,
+            &resources.chunk_buffers.ibv,
+            &resources.chunk_buffers.chunks,
+        );
+    }
+
     // Set necessary state.
     unsafe {
+        command_list.SetPipelineState(&resources.pso);
         command_list.SetGraphicsRootSignature(&resources.root_signature);
+        command_list.SetGraphicsRoot32BitConstants(
+            0,
+            16,
+            resources.view_projection.rows.as_ptr() as *const _,
+            0,
+        );
+        command_list.SetGraphicsRoot32BitConstant(0, resources.time.to_bits(), 16);
         command_list.RSSetViewports(&[resources.viewport]);
         command_list.RSSetScissorRects(&[resources.scissor_rect]);
     }
+    resources.shadow_map.bind(command_list);
+    resources.sky.bind(command_list);
 
-    // Indicate that the back buffer will be used as a render target.
-    let barrier = transition_barrier(
-        &resources.render_targets[resources.frame_index as usize],
-        D3D12_RESOURCE_STATE_PRESENT,
-        D3D12_RESOURCE_STATE_RENDER_TARGET,
-    );
-    unsafe { command_list.ResourceBarrier(&[barrier]) };
+    let drawn = {
+        let _pass = gpu_profiler.pass(command_list, "opaque");
+        resources.frame_graph.begin_main_pass(command_list, &imported);
+        let rtv_handle = resources.frame_graph.scene_rtv();
 
-    let rtv_handle = D3D12_CPU_DESCRIPTOR_HANDLE {
-        ptr: unsafe { resources.rtv_heap.GetCPUDescriptorHandleForHeapStart() }.ptr
-            + resources.frame_index as usize * resources.rtv_descriptor_size,
+        let dsv_handle = unsafe { resources.dsv_heap.GetCPUDescriptorHandleForHeapStart() };
+
+        unsafe { command_list.OMSetRenderTargets(1, Some(&rtv_handle), false, Some(&dsv_handle)) };
+
+        // Record commands.
+        unsafe {
+            // TODO: workaround for https://github.com/microsoft/win32metadata/issues/1006
+            command_list.ClearRenderTargetView(
+                rtv_handle,
+                &*resources.clear_color.as_ptr(),
+                None,
+            );
+            command_list.ClearDepthStencilView(dsv_handle, D3D12_CLEAR_FLAG_DEPTH, 1.0, 0, &[]);
+            command_list.IASetPrimitiveTopology(D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST);
+            command_list.IASetVertexBuffers(0, Some(&[resources.chunk_buffers.vbv]));
+            command_list.IASetIndexBuffer(Some(&resources.chunk_buffers.ibv));
+        }
+
+        let mut encoder = CommandListEncoder::new(command_list, gpu_culling);
+        let drawn = if let Some(gpu_culling) = gpu_culling {
+            encoder.draw_indexed_indirect(gpu_culling.max_draw_count());
+            gpu_culling.record_count_readback(command_list);
+            // The readback holds the previous frame's count, which has completed
+            // by now since we wait for the GPU at the end of every frame.
+            gpu_culling.last_visible_count()?
+        } else {
+            submit_cpu(
+                &mut encoder,
+                &resources.chunk_buffers.chunks,
+                &frustum,
+                &mut resources.draw_arguments,
+            )
+        };
+
+        if let Some(highlight) = &resources.highlight {
+            unsafe {
+                command_list.IASetVertexBuffers(0, Some(&[highlight.vbv]));
+                command_list.IASetIndexBuffer(Some(&highlight.ibv));
+                command_list.DrawIndexedInstanced(highlight.index_count, 1, 0, 0, 0);
+            }
+        }
+        drawn
     };
 
-    unsafe { command_list.OMSetRenderTargets(1, Some(&rtv_handle), false, None) };
+    {
+        let _pass = gpu_profiler.pass(command_list, "sky");
+        resources.sky.record(command_list);
+    }
 
-    // Record commands.
-    unsafe {
-        // TODO: workaround for https://github.com/microsoft/win32metadata/issues/1006
-        command_list.ClearRenderTargetView(
-            rtv_handle,
-            &*[0.0_f32, 0.2_f32, 0.4_f32, 1.0_f32].as_ptr(),
-            None,
+    // Over the sky as well, translucent faces in front of it blend with it.
+    {
+        let _pass = gpu_profiler.pass(command_list, "translucent");
+        unsafe {
+            command_list.SetPipelineState(&resources.translucent_pso);
+            command_list.IASetVertexBuffers(0, Some(&[resources.chunk_buffers.vbv]));
+            command_list.IASetIndexBuffer(Some(&resources.chunk_buffers.ibv));
+        }
+        let mut encoder = CommandListEncoder::new(command_list, None);
+        submit_back_to_front(
+            &mut encoder,
+            &resources.chunk_buffers.translucent_chunks,
+            &frustum,
+            resources.camera_position,
+            &mut resources.translucent_arguments,
         );
-        command_list.IASetPrimitiveTopology(D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST);
-        command_list.IASetVertexBuffers(0, Some(&[resources.vbv]));
-        command_list.DrawInstanced(3, 1, 0, 0);
+    }
+
+    if !debug.is_empty() {
+        let _pass = gpu_profiler.pass(command_list, "debug lines");
+        resources.debug_draw.record_lines(command_list, debug)?;
+    }
+
+    {
+        let _pass = gpu_profiler.pass(command_list, "resolve");
+        resources.frame_graph.record_resolve(command_list, &imported);
+    }
+
+    {
+        let _pass = gpu_profiler.pass(command_list, "post");
+        resources
+            .post_process
+            .record(command_list, &resources.frame_graph, &imported, back_buffer_rtv);
+    }
 
-        // Indicate that the back buffer will now be used to present.
-        command_list.ResourceBarrier(&[transition_barrier(
-            &resources.render_targets[resources.frame_index as usize],
-            D3D12_RESOURCE_STATE_RENDER_TARGET,
-            D3D12_RESOURCE_STATE_PRESENT,
-        )]);
+    if !debug.text.is_empty() {
+        let _pass = gpu_profiler.pass(command_list, "debug text");
+        resources.debug_draw.record_text(
+            command_list,
+            debug,
+            &resources.root_signature,
+            back_buffer_rtv,
+            &resources.viewport,
+            &resources.scissor_rect,
+        )?;
+    }
+
+    if let Some(ui) = ui {
+        let _pass = gpu_profiler.pass(command_list, "dev ui");
+        resources
+            .dev_ui
+            .record(&resources.device, command_list, ui, back_buffer_rtv, &resources.viewport)?;
     }
 
+    let cull_stats = CullStats {
+        drawn,
+        culled: (resources.chunk_buffers.chunks.len() as u32).saturating_sub(drawn),
+    };
+    resources.occlusion_view_projection = Some(resources.view_projection);
+
+    if cull_stats != resources.cull_stats {
+        debug!("chunks drawn: {}, culled: {}", cull_stats.drawn, cull_stats.culled);
+        resources.cull_stats = cull_stats;
+    }
+
+    // Indicate that the back buffer will now be used to present.
+    resources.frame_graph.finish(command_list, &imported);
+    drop(frame);
+    gpu_profiler.resolve(command_list);
+
     unsafe { command_list.Close() }
 }
 
@@ -320,6 +1741,20 @@ fn transition_barrier(
     resource: &ID3D12Resource,
     state_before: D3D12_RESOURCE_STATES,
     state_after: D3D12_RESOURCE_STATES,
+) -> D3D12_RESOURCE_BARRIER {
+    transition_barrier_subresource(
+        resource,
+        D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES,
+        state_before,
+        state_after,
+    )
+}
+
+fn transition_barrier_subresource(
+    resource: &ID3D12Resource,
+    subresource: u32,
+    state_before: D3D12_RESOURCE_STATES,
+    state_after: D3D12_RESOURCE_STATES,
 ) -> D3D12_RESOURCE_BARRIER {
     D3D12_RESOURCE_BARRIER {
         Type: D3D12_RESOURCE_BARRIER_TYPE_TRANSITION,
@@ -329,49 +1764,226 @@ fn transition_barrier(
                 pResource: unsafe { std::mem::transmute_copy(resource) },
                 StateBefore: state_before,
                 StateAfter: state_after,
-                Subresource: D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES,
+                Subresource: subresource,
             }),
         },
     }
 }
 
-fn create_device() -> Result<(IDXGIFactory4, ID3D12Device)> {
-    if cfg!(debug_assertions) {
-        unsafe {
-            let mut debug: Option<ID3D12Debug> = None;
-            if let Some(debug) = D3D12GetDebugInterface(&mut debug).ok().and(debug) {
-                debug.EnableDebugLayer();
-            }
-        }
-    }
+fn create_device(
+    preference: &AdapterPreference,
+    debug_settings: &DebugSettings,
+) -> EngineResult<(IDXGIFactory4, ID3D12Device, Option<InfoQueue>, DeviceCapabilities)> {
+    enable_debug_layer(debug_settings);
 
-    let dxgi_factory_flags = if cfg!(debug_assertions) {
+    let dxgi_factory_flags = if debug_settings.debug_layer {
         DXGI_CREATE_FACTORY_DEBUG
     } else {
         0
     };
 
-    let dxgi_factory: IDXGIFactory4 = unsafe { CreateDXGIFactory2(dxgi_factory_flags) }?;
+    let dxgi_factory: IDXGIFactory4 =
+        unsafe { CreateDXGIFactory2(dxgi_factory_flags) }.context("creating the DXGI factory")?;
 
-    let adapter = get_hardware_adapter(&dxgi_factory)?;
+    let (adapter, adapter_info) = get_adapter(&dxgi_factory, preference).context("choosing an adapter")?;
 
     let mut device: Option<ID3D12Device> = None;
-    unsafe { D3D12CreateDevice(&adapter, D3D_FEATURE_LEVEL_11_0, &mut device) }?;
-    Ok((dxgi_factory, device.unwrap()))
+    let device = unsafe { D3D12CreateDevice(&adapter, D3D_FEATURE_LEVEL_11_0, &mut device) }
+        .and_then(|()| device.ok_or_else(|| E_NOINTERFACE.into()))
+        .context(&format!("creating the device on {}", adapter_info.name))?;
+    let info_queue = InfoQueue::new(&device, debug_settings).context("setting up the debug layer's info queue")?;
+
+    let capabilities = query_capabilities(&device, adapter_info).context("querying the device capabilities")?;
+    info!("device capabilities:\n{}", capabilities);
+    capabilities
+        .check()
+        .map_err(|message| EngineError::Device(Error::new(DXGI_ERROR_UNSUPPORTED, message.into())))?;
+    Ok((dxgi_factory, device, info_queue, capabilities))
+}
+
+// Whether presents may tear, which needs DXGI 1.5 and a display and driver
+// that allow it. Asked of the factory, older ones do not know the feature.
+fn tearing_supported(factory: &IDXGIFactory4) -> bool {
+    let factory: IDXGIFactory5 = match factory.cast() {
+        Ok(factory) => factory,
+        Err(_) => return false,
+    };
+    let mut allow_tearing = BOOL(0);
+    unsafe {
+        factory.CheckFeatureSupport(
+            DXGI_FEATURE_PRESENT_ALLOW_TEARING,
+            &mut allow_tearing as *mut BOOL as *mut _,
+            std::mem::size_of::<BOOL>() as u32,
+        )
+    }
+    .is_ok_and(|()| allow_tearing.as_bool())
+}
+
+fn query_capabilities(device: &ID3D12Device, adapter: AdapterInfo) -> Result<DeviceCapabilities> {
+    let feature_levels = [
+        D3D_FEATURE_LEVEL_11_0,
+        D3D_FEATURE_LEVEL_11_1,
+        D3D_FEATURE_LEVEL_12_0,
+        D3D_FEATURE_LEVEL_12_1,
+        D3D_FEATURE_LEVEL_12_2,
+    ];
+    let mut levels = D3D12_FEATURE_DATA_FEATURE_LEVELS {
+        NumFeatureLevels: feature_levels.len() as u32,
+        pFeatureLevelsRequested: feature_levels.as_ptr(),
+        MaxSupportedFeatureLevel: D3D_FEATURE_LEVEL_11_0,
+    };
+    check_feature_support(device, D3D12_FEATURE_FEATURE_LEVELS, &mut levels)?;
+
+    // Runtimes reject shader models newer than they know of, so ask for
+    // lower ones until one is answered.
+    let shader_model = [
+        D3D_SHADER_MODEL_6_7,
+        D3D_SHADER_MODEL_6_6,
+        D3D_SHADER_MODEL_6_5,
+        D3D_SHADER_MODEL_6_4,
+        D3D_SHADER_MODEL_6_3,
+        D3D_SHADER_MODEL_6_2,
+        D3D_SHADER_MODEL_6_1,
+        D3D_SHADER_MODEL_6_0,
+    ]
+    .iter()
+    .find_map(|&highest| {
+        let mut shader_model = D3D12_FEATURE_DATA_SHADER_MODEL {
+            HighestShaderModel: highest,
+        };
+        check_feature_support(device, D3D12_FEATURE_SHADER_MODEL, &mut shader_model)
+            .ok()
+            .map(|()| shader_model.HighestShaderModel)
+    })
+    .unwrap_or(D3D_SHADER_MODEL_5_1);
+
+    let mut options = D3D12_FEATURE_DATA_D3D12_OPTIONS::default();
+    check_feature_support(device, D3D12_FEATURE_D3D12_OPTIONS, &mut options)?;
+    // Runtimes that do not know these options support neither feature.
+    let mut options5 = D3D12_FEATURE_DATA_D3D12_OPTIONS5::default();
+    let raytracing_tier = match check_feature_support(device, D3D12_FEATURE_D3D12_OPTIONS5, &mut options5) {
+        Ok(()) => options5.RaytracingTier.0 as u32,
+        Err(_) => 0,
+    };
+    let mut options7 = D3D12_FEATURE_DATA_D3D12_OPTIONS7::default();
+    let mesh_shader_tier = match check_feature_support(device, D3D12_FEATURE_D3D12_OPTIONS7, &mut options7) {
+        Ok(()) => options7.MeshShaderTier.0 as u32,
+        Err(_) => 0,
+    };
+
+    Ok(DeviceCapabilities {
+        adapter,
+        feature_level: FeatureLevel(levels.MaxSupportedFeatureLevel.0 as u32),
+        shader_model: ShaderModel(shader_model.0 as u32),
+        resource_binding_tier: options.ResourceBindingTier.0 as u32,
+        mesh_shader_tier,
+        raytracing_tier,
+    })
+}
+
+fn check_feature_support<T>(device: &ID3D12Device, feature: D3D12_FEATURE, data: &mut T) -> Result<()> {
+    unsafe {
+        device.CheckFeatureSupport(
+            feature,
+            data as *mut T as *mut _,
+            std::mem::size_of::<T>() as u32,
+        )
+    }
 }
 
 fn create_root_signature(device: &ID3D12Device) -> Result<ID3D12RootSignature> {
+    let shadow_map_range = D3D12_DESCRIPTOR_RANGE {
+        RangeType: D3D12_DESCRIPTOR_RANGE_TYPE_SRV,
+        NumDescriptors: 1,
+        BaseShaderRegister: 0,
+        RegisterSpace: 0,
+        OffsetInDescriptorsFromTableStart: 0,
+    };
+
+    // View projection matrix and time as root constants, then the shadow
+    // constants, the shadow map and the lighting constants.
+    let parameters = [
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_32BIT_CONSTANTS,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Constants: D3D12_ROOT_CONSTANTS {
+                    ShaderRegister: 0,
+                    RegisterSpace: 0,
+                    Num32BitValues: 17,
+                },
+            },
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_VERTEX,
+        },
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_CBV,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Descriptor: D3D12_ROOT_DESCRIPTOR {
+                    ShaderRegister: 1,
+                    RegisterSpace: 0,
+                },
+            },
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+        },
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_DESCRIPTOR_TABLE,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                DescriptorTable: D3D12_ROOT_DESCRIPTOR_TABLE {
+                    NumDescriptorRanges: 1,
+                    pDescriptorRanges: &shadow_map_range,
+                },
+            },
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+        },
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_CBV,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Descriptor: D3D12_ROOT_DESCRIPTOR {
+                    ShaderRegister: 2,
+                    RegisterSpace: 0,
+                },
+            },
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+        },
+    ];
+
+    // Hardware 2x2 PCF for every tap, lookups outside the map are lit.
+    let shadow_sampler = D3D12_STATIC_SAMPLER_DESC {
+        Filter: D3D12_FILTER_COMPARISON_MIN_MAG_LINEAR_MIP_POINT,
+        AddressU: D3D12_TEXTURE_ADDRESS_MODE_BORDER,
+        AddressV: D3D12_TEXTURE_ADDRESS_MODE_BORDER,
+        AddressW: D3D12_TEXTURE_ADDRESS_MODE_BORDER,
+        MipLODBias: 0.0,
+        MaxAnisotropy: 1,
+        ComparisonFunc: D3D12_COMPARISON_FUNC_LESS_EQUAL,
+        BorderColor: D3D12_STATIC_BORDER_COLOR_OPAQUE_WHITE,
+        MinLOD: 0.0,
+        MaxLOD: 0.0,
+        ShaderRegister: 0,
+        RegisterSpace: 0,
+        ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+    };
+
     let desc = D3D12_ROOT_SIGNATURE_DESC {
+        NumParameters: parameters.len() as u32,
+        pParameters: parameters.as_ptr(),
+        NumStaticSamplers: 1,
+        pStaticSamplers: &shadow_sampler,
         Flags: D3D12_ROOT_SIGNATURE_FLAG_ALLOW_INPUT_ASSEMBLER_INPUT_LAYOUT,
-        ..Default::default()
     };
 
+    serialize_root_signature(device, &desc)
+}
+
+fn serialize_root_signature(
+    device: &ID3D12Device,
+    desc: &D3D12_ROOT_SIGNATURE_DESC,
+) -> Result<ID3D12RootSignature> {
     let mut signature = None;
 
     let signature = unsafe {
-        D3D12SerializeRootSignature(&desc, D3D_ROOT_SIGNATURE_VERSION_1, &mut signature, None)
+        D3D12SerializeRootSignature(desc, D3D_ROOT_SIGNATURE_VERSION_1, &mut signature, None)
     }
-    .map(|()| signature.unwrap())?;
+    .and_then(|()| created(signature, "root signature"))?;
 
     unsafe {
         device.CreateRootSignature(
@@ -392,14 +2004,9 @@ fn convert_to_bytecode<'a, T>(data : &'a Vec<T>) -> D3D12_SHADER_BYTECODE
     }
 }
 
-fn create_pipeline_state(device: &ID3D12Device, root_signature: &ID3D12RootSignature) -> Result<ID3D12PipelineState> {
-    let vs_bin = std::fs::read("resources/vs.bin").unwrap();
-    let ps_bin = std::fs::read("resources/ps.bin").unwrap();
-
-    let vs_bytecode = convert_to_bytecode(&vs_bin);
-    let ps_bytecode = convert_to_bytecode(&ps_bin);
-
-    let mut input_element_descs: [D3D12_INPUT_ELEMENT_DESC; 2] = [
+// Layout of mesher::Vertex, shared by the main and the shadow pass.
+fn vertex_input_layout() -> [D3D12_INPUT_ELEMENT_DESC; 3] {
+    [
         D3D12_INPUT_ELEMENT_DESC {
             SemanticName: s!("POSITION"),
             SemanticIndex: 0,
@@ -418,7 +2025,35 @@ fn create_pipeline_state(device: &ID3D12Device, root_signature: &ID3D12RootSigna
             InputSlotClass: D3D12_INPUT_CLASSIFICATION_PER_VERTEX_DATA,
             InstanceDataStepRate: 0,
         },
-    ];
+        D3D12_INPUT_ELEMENT_DESC {
+            SemanticName: s!("WAVE"),
+            SemanticIndex: 0,
+            Format: DXGI_FORMAT_R32_FLOAT,
+            InputSlot: 0,
+            AlignedByteOffset: 28,
+            InputSlotClass: D3D12_INPUT_CLASSIFICATION_PER_VERTEX_DATA,
+            InstanceDataStepRate: 0,
+        },
+    ]
+}
+
+// Both layers use the same shaders. Translucent faces blend over what is
+// behind them and test against the opaque depth without writing it, they
+// are sorted back to front instead.
+fn create_pipeline_state(
+    device: &ID3D12Device,
+    root_signature: &ID3D12RootSignature,
+    layer: ChunkLayer,
+    sample_count: u32,
+) -> EngineResult<ID3D12PipelineState> {
+    let vs_bin = read_shader("resources/vs.bin")?;
+    let ps_bin = read_shader("resources/ps.bin")?;
+
+    let vs_bytecode = convert_to_bytecode(&vs_bin);
+    let ps_bytecode = convert_to_bytecode(&ps_bin);
+
+    let mut input_element_descs = vertex_input_layout();
+    let translucent = layer == ChunkLayer::Translucent;
 
     let mut desc = D3D12_GRAPHICS_PIPELINE_STATE_DESC {
         InputLayout: D3D12_INPUT_LAYOUT_DESC {
@@ -438,10 +2073,10 @@ fn create_pipeline_state(device: &ID3D12Device, root_signature: &ID3D12RootSigna
             IndependentBlendEnable: false.into(),
             RenderTarget: [
                 D3D12_RENDER_TARGET_BLEND_DESC {
-                    BlendEnable: false.into(),
+                    BlendEnable: translucent.into(),
                     LogicOpEnable: false.into(),
-                    SrcBlend: D3D12_BLEND_ONE,
-                    DestBlend: D3D12_BLEND_ZERO,
+                    SrcBlend: if translucent { D3D12_BLEND_SRC_ALPHA } else { D3D12_BLEND_ONE },
+                    DestBlend: if translucent { D3D12_BLEND_INV_SRC_ALPHA } else { D3D12_BLEND_ZERO },
                     BlendOp: D3D12_BLEND_OP_ADD,
                     SrcBlendAlpha: D3D12_BLEND_ONE,
                     DestBlendAlpha: D3D12_BLEND_ZERO,
@@ -458,56 +2093,63 @@ fn create_pipeline_state(device: &ID3D12Device, root_signature: &ID3D12RootSigna
                 D3D12_RENDER_TARGET_BLEND_DESC::default(),
             ],
         },
-        DepthStencilState: D3D12_DEPTH_STENCIL_DESC::default(),
+        DepthStencilState: D3D12_DEPTH_STENCIL_DESC {
+            DepthEnable: true.into(),
+            DepthWriteMask: if translucent {
+                D3D12_DEPTH_WRITE_MASK_ZERO
+            } else {
+                D3D12_DEPTH_WRITE_MASK_ALL
+            },
+            DepthFunc: D3D12_COMPARISON_FUNC_LESS,
+            ..Default::default()
+        },
+        DSVFormat: DEPTH_FORMAT,
         SampleMask: u32::max_value(),
         PrimitiveTopologyType: D3D12_PRIMITIVE_TOPOLOGY_TYPE_TRIANGLE,
         NumRenderTargets: 1,
         SampleDesc: DXGI_SAMPLE_DESC {
-            Count: 1,
+            Count: sample_count,
             ..Default::default()
         },
         ..Default::default()
     };
-    desc.RTVFormats[0] = DXGI_FORMAT_R8G8B8A8_UNORM;
+    desc.RTVFormats[0] = SCENE_FORMAT;
 
-    unsafe { device.CreateGraphicsPipelineState(&desc) }
+    Ok(unsafe { device.CreateGraphicsPipelineState(&desc) }?)
 }
 
-fn create_vertex_buffer(
-    device: &ID3D12Device,
-    aspect_ratio: f32,
-) -> Result<(ID3D12Resource, D3D12_VERTEX_BUFFER_VIEW)> {
-    let vertices = [
-        Vertex {
-            position: [0.0, 0.25 * aspect_ratio, 0.0],
-            color: [1.0, 0.0, 0.0, 1.0],
-        },
-        Vertex {
-            position: [0.25, -0.25 * aspect_ratio, 0.0],
-            color: [0.0, 1.0, 0.0, 1.0],
-        },
-        Vertex {
-            position: [-0.25, -0.25 * aspect_ratio, 0.0],
-            color: [0.0, 0.0, 1.0, 1.0],
-        },
-    ];
+// Compiled shader bytecode, written to resources/ by build.rs.
+fn read_shader(path: &str) -> EngineResult<Vec<u8>> {
+    fs::read(path).map_err(|source| EngineError::Shader {
+        path: path.to_string(),
+        source,
+    })
+}
 
-    // Note: using upload heaps to transfer static data like vert buffers is
-    // not recommended. Every time the GPU needs it, the upload heap will be
-    // marshalled over. Please read up on Default Heap usage. An upload heap
-    // is used here for code simplicity and because there are very few verts
-    // to actually transfer.
-    let mut vertex_buffer: Option<ID3D12Resource> = None;
+// Object a successful call stored in its out parameter. Null there is an
+// API bug, reported like a failed call rather than panicking.
+fn created<T>(object: Option<T>, what: &str) -> Result<T> {
+    object.ok_or_else(|| Error::new(E_POINTER, format!("no {} was returned", what).into()))
+}
+
+fn create_buffer(
+    device: &ID3D12Device,
+    heap_type: D3D12_HEAP_TYPE,
+    size: u64,
+    flags: D3D12_RESOURCE_FLAGS,
+    initial_state: D3D12_RESOURCE_STATES,
+) -> Result<ID3D12Resource> {
+    let mut buffer: Option<ID3D12Resource> = None;
     unsafe {
         device.CreateCommittedResource(
             &D3D12_HEAP_PROPERTIES {
-                Type: D3D12_HEAP_TYPE_UPLOAD,
+                Type: heap_type,
                 ..Default::default()
             },
             D3D12_HEAP_FLAG_NONE,
             &D3D12_RESOURCE_DESC {
                 Dimension: D3D12_RESOURCE_DIMENSION_BUFFER,
-                Width: std::mem::size_of_val(&vertices) as u64,
+                Width: size,
                 Height: 1,
                 DepthOrArraySize: 1,
                 MipLevels: 1,
@@ -516,39 +2158,239 @@ fn create_vertex_buffer(
                     Quality: 0,
                 },
                 Layout: D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
+                Flags: flags,
                 ..Default::default()
             },
-            D3D12_RESOURCE_STATE_GENERIC_READ,
+            initial_state,
             None,
-            &mut vertex_buffer,
+            &mut buffer,
+        )?
+    };
+    created(buffer, "buffer")
+}
+
+fn create_texture(
+    device: &ID3D12Device,
+    desc: &D3D12_RESOURCE_DESC,
+    initial_state: D3D12_RESOURCE_STATES,
+    clear_value: Option<&D3D12_CLEAR_VALUE>,
+) -> Result<ID3D12Resource> {
+    let mut texture: Option<ID3D12Resource> = None;
+    unsafe {
+        device.CreateCommittedResource(
+            &D3D12_HEAP_PROPERTIES {
+                Type: D3D12_HEAP_TYPE_DEFAULT,
+                ..Default::default()
+            },
+            D3D12_HEAP_FLAG_NONE,
+            desc,
+            initial_state,
+            clear_value.map(|value| value as *const _),
+            &mut texture,
         )?
     };
-    let vertex_buffer = vertex_buffer.unwrap();
+    created(texture, "texture")
+}
+
+// Typeless so the depth pyramid pass can read it as R32_FLOAT.
+fn create_depth_buffer(
+    device: &ID3D12Device,
+    width: u32,
+    height: u32,
+    sample_count: u32,
+) -> Result<(ID3D12Resource, ID3D12DescriptorHeap)> {
+    let depth_buffer = create_texture(
+        device,
+        &D3D12_RESOURCE_DESC {
+            Dimension: D3D12_RESOURCE_DIMENSION_TEXTURE2D,
+            Width: width as u64,
+            Height: height,
+            DepthOrArraySize: 1,
+            MipLevels: 1,
+            Format: DXGI_FORMAT_R32_TYPELESS,
+            SampleDesc: DXGI_SAMPLE_DESC {
+                Count: sample_count,
+                Quality: 0,
+            },
+            Flags: D3D12_RESOURCE_FLAG_ALLOW_DEPTH_STENCIL,
+            ..Default::default()
+        },
+        D3D12_RESOURCE_STATE_DEPTH_WRITE,
+        Some(&D3D12_CLEAR_VALUE {
+            Format: DEPTH_FORMAT,
+            Anonymous: D3D12_CLEAR_VALUE_0 {
+                DepthStencil: D3D12_DEPTH_STENCIL_VALUE {
+                    Depth: 1.0,
+                    Stencil: 0,
+                },
+            },
+        }),
+    )?;
+    set_name(&depth_buffer, "depth buffer");
+
+    let dsv_heap: ID3D12DescriptorHeap = unsafe {
+        device.CreateDescriptorHeap(&D3D12_DESCRIPTOR_HEAP_DESC {
+            NumDescriptors: 1,
+            Type: D3D12_DESCRIPTOR_HEAP_TYPE_DSV,
+            ..Default::default()
+        })
+    }?;
+    set_name(&dsv_heap, "depth dsv heap");
+
+    unsafe {
+        device.CreateDepthStencilView(
+            &depth_buffer,
+            Some(&D3D12_DEPTH_STENCIL_VIEW_DESC {
+                Format: DEPTH_FORMAT,
+                ViewDimension: if sample_count > 1 {
+                    D3D12_DSV_DIMENSION_TEXTURE2DMS
+                } else {
+                    D3D12_DSV_DIMENSION_TEXTURE2D
+                },
+                ..Default::default()
+            }),
+            dsv_heap.GetCPUDescriptorHandleForHeapStart(),
+        )
+    };
+
+    Ok((depth_buffer, dsv_heap))
+}
+
+// Quality levels the device offers for `format` at `sample_count`, 0 when it
+// does not support that count at all.
+fn multisample_quality_levels(device: &ID3D12Device, format: DXGI_FORMAT, sample_count: u32) -> Result<u32> {
+    let mut levels = D3D12_FEATURE_DATA_MULTISAMPLE_QUALITY_LEVELS {
+        Format: format,
+        SampleCount: sample_count,
+        Flags: D3D12_MULTISAMPLE_QUALITY_LEVELS_FLAG_NONE,
+        NumQualityLevels: 0,
+    };
+    check_feature_support(device, D3D12_FEATURE_MULTISAMPLE_QUALITY_LEVELS, &mut levels)?;
+    Ok(levels.NumQualityLevels)
+}
+
+fn create_upload_buffer<T>(device: &ID3D12Device, data: &[T]) -> Result<ID3D12Resource> {
+    let buffer = create_buffer(
+        device,
+        D3D12_HEAP_TYPE_UPLOAD,
+        std::mem::size_of_val(data) as u64,
+        D3D12_RESOURCE_FLAG_NONE,
+        D3D12_RESOURCE_STATE_GENERIC_READ,
+    )?;
 
-    // Copy the triangle data to the vertex buffer.
     unsafe {
-        let mut data = std::ptr::null_mut();
-        vertex_buffer.Map(0, None, Some(&mut data))?;
-        std::ptr::copy_nonoverlapping(vertices.as_ptr(), data as *mut Vertex, vertices.len());
-        vertex_buffer.Unmap(0, None);
+        let mut mapped = std::ptr::null_mut();
+        buffer.Map(0, None, Some(&mut mapped))?;
+        std::ptr::copy_nonoverlapping(data.as_ptr(), mapped as *mut T, data.len());
+        buffer.Unmap(0, None);
     }
 
+    Ok(buffer)
+}
+
+fn create_vertex_buffer(
+    device: &ID3D12Device,
+    vertices: &[Vertex],
+) -> Result<(ID3D12Resource, D3D12_VERTEX_BUFFER_VIEW)> {
+    // Note: using upload heaps to transfer static data like vert buffers is
+    // not recommended. Every time the GPU needs it, the upload heap will be
+    // marshalled over. Please read up on Default Heap usage. An upload heap
+    // is used here for code simplicity and because there are very few verts
+    // to actually transfer.
+    let vertex_buffer = create_upload_buffer(device, vertices)?;
+
     let vbv = D3D12_VERTEX_BUFFER_VIEW {
         BufferLocation: unsafe { vertex_buffer.GetGPUVirtualAddress() },
         StrideInBytes: std::mem::size_of::<Vertex>() as u32,
-        SizeInBytes: std::mem::size_of_val(&vertices) as u32,
+        SizeInBytes: std::mem::size_of_val(vertices) as u32,
     };
 
     Ok((vertex_buffer, vbv))
 }
 
-#[repr(C)]
-struct Vertex {
-    position: [f32; 3],
-    color: [f32; 4],
+fn create_index_buffer(
+    device: &ID3D12Device,
+    indices: &[u32],
+) -> Result<(ID3D12Resource, D3D12_INDEX_BUFFER_VIEW)> {
+    let index_buffer = create_upload_buffer(device, indices)?;
+
+    let ibv = D3D12_INDEX_BUFFER_VIEW {
+        BufferLocation: unsafe { index_buffer.GetGPUVirtualAddress() },
+        SizeInBytes: std::mem::size_of_val(indices) as u32,
+        Format: DXGI_FORMAT_R32_UINT,
+    };
+
+    Ok((index_buffer, ibv))
+}
+
+fn create_chunk_buffers(
+    device: &ID3D12Device,
+    meshes: &HashMap<ChunkPos, ChunkMesh>,
+) -> Result<ChunkBuffers> {
+    let mut positions: Vec<&ChunkPos> = meshes.keys().collect();
+    positions.sort();
+
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    let mut chunks = Vec::with_capacity(positions.len());
+    let mut translucent_chunks = Vec::new();
+    for pos in positions {
+        let mesh = &meshes[pos];
+        let base_vertex = vertices.len() as i32;
+        for (layer, records) in [
+            (&mesh.indices, &mut chunks),
+            (&mesh.translucent_indices, &mut translucent_chunks),
+        ] {
+            if !layer.is_empty() {
+                records.push(ChunkRecord::new(
+                    &pos.bounds(),
+                    layer.len() as u32,
+                    indices.len() as u32,
+                    base_vertex,
+                ));
+                indices.extend_from_slice(layer);
+            }
+        }
+        vertices.extend_from_slice(&mesh.vertices);
+    }
+
+    // Zero sized buffers are invalid, nothing references this padding.
+    if vertices.is_empty() {
+        vertices.push(Vertex::default());
+        indices.push(0);
+    }
+
+    let (vertex_buffer, vbv) = create_vertex_buffer(device, &vertices)?;
+    let (index_buffer, ibv) = create_index_buffer(device, &indices)?;
+    set_name(&vertex_buffer, "chunk vertices");
+    set_name(&index_buffer, "chunk indices");
+
+    Ok(ChunkBuffers {
+        vertex_buffer,
+        vbv,
+        index_buffer,
+        ibv,
+        chunks,
+        translucent_chunks,
+    })
+}
+
+fn create_mesh_buffers(device: &ID3D12Device, mesh: &ChunkMesh, name: &str) -> Result<MeshBuffers> {
+    let (vertex_buffer, vbv) = create_vertex_buffer(device, &mesh.vertices)?;
+    let (index_buffer, ibv) = create_index_buffer(device, &mesh.indices)?;
+    set_name(&vertex_buffer, &format!("{} vertices", name));
+    set_name(&index_buffer, &format!("{} indices", name));
+
+    Ok(MeshBuffers {
+        vertex_buffer,
+        vbv,
+        index_buffer,
+        ibv,
+        index_count: mesh.indices.len() as u32,
+    })
 }
 
-fn wait_for_previous_frame(resources: &mut Resources) {
+fn wait_for_previous_frame(resources: &mut Resources) -> EngineResult<()> {
     // WAITING FOR THE FRAME TO COMPLETE BEFORE CONTINUING IS NOT BEST
     // PRACTICE. This is code implemented as such for simplicity. The
     // D3D12HelloFrameBuffering sample illustrates how to use fences for
@@ -557,9 +2399,7 @@ fn wait_for_previous_frame(resources: &mut Resources) {
     // Signal and increment the fence value.
     let fence = resources.fence_value;
 
-    unsafe { resources.command_queue.Signal(&resources.fence, fence) }
-        .ok()
-        .unwrap();
+    unsafe { resources.command_queue.Signal(&resources.fence, fence) }.context("signalling the fence")?;
 
     resources.fence_value += 1;
 
@@ -570,35 +2410,91 @@ fn wait_for_previous_frame(resources: &mut Resources) {
                 .fence
                 .SetEventOnCompletion(fence, resources.fence_event)
         }
-        .ok()
-        .unwrap();
+        .context("waiting for the previous frame")?;
 
         unsafe { WaitForSingleObject(resources.fence_event, INFINITE) };
     }
 
     resources.frame_index = unsafe { resources.swap_chain.GetCurrentBackBufferIndex() };
+    Ok(())
 }
 
-fn main() -> Result<()>
-{
-
+// Everything before the event loop: a failure here is reported by main
+// instead of panicking.
+fn start(config: EngineConfig, args: &[String]) -> EngineResult<(Sample, EventLoop<()>, Window)> {
     // let instance = unsafe { GetModuleHandleA(None)? };
-    let mut sample = Sample::new()?;
+    let debug_settings = debug_settings_from_args(args).map_err(EngineError::Config)?;
+    debug_settings.validate().map_err(EngineError::Config)?;
+    let size = PhysicalSize::new(config.width, config.height);
+    let mut sample = Sample::new(config, debug_settings);
     let title = sample.title();
 
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new()
         .with_title(title)
-        .build(&event_loop).unwrap();
+        .with_inner_size(size)
+        .build(&event_loop)
+        .map_err(|error| EngineError::Io(std::io::Error::other(error.to_string())))
+        .context("creating the window")?;
 
+    sample.bind_to_window(&window).context("setting up the renderer")?;
+    Ok((sample, event_loop, window))
+}
+
+// Tells the user why the engine could not start: on the console for a run
+// from a terminal, in a message box for a double-clicked executable.
+fn report_startup_error(error: &EngineError) {
+    error!("startup failed: {}", error);
+    show_error("The engine could not start.", error);
+}
 
-    sample.bind_to_window(&window)?;
+// Same for an error that stops the engine while it runs.
+fn report_runtime_error(error: &EngineError) {
+    error!("rendering failed: {}", error);
+    show_error("The engine stopped.", error);
+}
+
+fn show_error(summary: &str, error: &EngineError) {
+    eprintln!("error: {}", error);
+    unsafe {
+        MessageBoxW(
+            None,
+            &HSTRING::from(format!("{}\n\n{}", summary, error)),
+            w!("Voxel engine"),
+            MB_OK | MB_ICONERROR,
+        )
+    };
+}
+
+fn main()
+{
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let config = EngineConfig::load(&args);
+    // Installed before a bad config is reported, at the default level then.
+    let log_level = config.as_ref().map_or(EngineConfig::default().log_level, |config| config.log_level);
+    logger::init(Path::new(LOG_PATH), log_level);
+    let (mut sample, event_loop, window) = match config.and_then(|config| start(config, &args)) {
+        Ok(started) => started,
+        Err(error) => {
+            report_startup_error(&error);
+            std::process::exit(1);
+        }
+    };
+    let mut recovery = DeviceRecovery::new(DEVICE_RECOVERY_ATTEMPTS, DEVICE_RECOVERY_DELAY);
     // unsafe { ShowWindow(hwnd, SW_SHOW) };
 
     event_loop.run(move |event, _, control_flow|
     {
-        // control_flow.set_poll();
-        control_flow.set_wait();
+        // Keep rendering without input, the day cycle moves on its own.
+        control_flow.set_poll();
+
+        // The developer UI sees window events first, those it uses are not
+        // acted on again.
+        if let Event::WindowEvent { event, .. } = &event {
+            if sample.dev_ui.handle_event(event) {
+                return;
+            }
+        }
 
         match event {
             Event::WindowEvent {
@@ -606,13 +2502,72 @@ fn main() -> Result<()>
                 ..
             } => {
                 info!("The close button was pressed; stopping");
+                sample.save_world();
                 control_flow.set_exit();
             },
+            Event::WindowEvent {
+                event: WindowEvent::CursorMoved { position, .. },
+                ..
+            } => {
+                let size = window.inner_size();
+                if size.width > 0 && size.height > 0 {
+                    sample.cursor = Some([
+                        position.x as f32 / size.width as f32,
+                        position.y as f32 / size.height as f32,
+                    ]);
+                }
+            },
+            Event::WindowEvent {
+                event: WindowEvent::CursorLeft { .. },
+                ..
+            } => {
+                sample.cursor = None;
+            },
+            Event::WindowEvent {
+                event: WindowEvent::MouseInput {
+                    state: ElementState::Pressed,
+                    button,
+                    ..
+                },
+                ..
+            } => match button {
+                MouseButton::Left => sample.break_block(),
+                MouseButton::Right => sample.place_block(),
+                _ => (),
+            },
+            Event::WindowEvent {
+                event: WindowEvent::KeyboardInput {
+                    input: KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                    ..
+                },
+                ..
+            } => {
+                sample.key_pressed(key);
+            },
             Event::MainEventsCleared =>
             {
                 // window.request_redraw();
                 // frame
-                sample.render();
+                match run_frame(&mut sample, &window, &mut recovery) {
+                    Ok(true) => (),
+                    Ok(false) => {
+                        error!("giving up on the device");
+                        sample.save_world();
+                        control_flow.set_exit_with_code(1);
+                    }
+                    Err(error) => {
+                        report_runtime_error(&error);
+                        sample.save_world();
+                        control_flow.set_exit_with_code(1);
+                    }
+                }
+                if let Some(title) = sample.overlay_title() {
+                    window.set_title(&title);
+                }
             },
             Event::RedrawRequested(_) =>
             {
@@ -620,6 +2575,4 @@ fn main() -> Result<()>
             _ => ()
         }
     });
-
-    Ok(())
 }
@@ -0,0 +1,40 @@
+use windows::{core::*, Win32::Graphics::Direct3D12::*};
+
+// Debug names and event markers, what PIX and RenderDoc show for objects
+// and command list regions. Objects are named by what owns them and what
+// they are, in lower case: "shadow map pso", "back buffer 1".
+pub fn set_name<T: ComInterface>(object: &T, name: &str) {
+    if let Ok(object) = object.cast::<ID3D12Object>() {
+        // Only fails when out of memory, a capture without the name is fine.
+        let _ = unsafe { object.SetName(&HSTRING::from(name)) };
+    }
+}
+
+// PIX_EVENT_UNICODE_VERSION: the event data is a null terminated UTF-16
+// string, which RenderDoc reads as well.
+const EVENT_UNICODE_VERSION: u32 = 0;
+
+// A named region of a command list, ended when dropped.
+pub struct Event<'a> {
+    command_list: &'a ID3D12GraphicsCommandList,
+}
+
+impl<'a> Event<'a> {
+    pub fn new(command_list: &'a ID3D12GraphicsCommandList, name: &str) -> Self {
+        let name: Vec<u16> = name.encode_utf16().chain(std::iter::once(0)).collect();
+        unsafe {
+            command_list.BeginEvent(
+                EVENT_UNICODE_VERSION,
+                Some(name.as_ptr() as *const _),
+                (name.len() * std::mem::size_of::<u16>()) as u32,
+            )
+        };
+        Event { command_list }
+    }
+}
+
+impl Drop for Event<'_> {
+    fn drop(&mut self) {
+        unsafe { self.command_list.EndEvent() };
+    }
+}
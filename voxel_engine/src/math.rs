@@ -0,0 +1,351 @@
+use std::ops::{Add, Mul, Neg, Sub};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Vec3 {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Vec3 {
+    pub const fn new(x: f32, y: f32, z: f32) -> Self {
+        Vec3 { x, y, z }
+    }
+
+    pub fn dot(self, other: Vec3) -> f32 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn cross(self, other: Vec3) -> Vec3 {
+        Vec3 {
+            x: self.y * other.z - self.z * other.y,
+            y: self.z * other.x - self.x * other.z,
+            z: self.x * other.y - self.y * other.x,
+        }
+    }
+
+    pub fn length(self) -> f32 {
+        self.dot(self).sqrt()
+    }
+
+    pub fn normalize(self) -> Vec3 {
+        let length = self.length();
+        if length > 0.0 {
+            self * (1.0 / length)
+        } else {
+            self
+        }
+    }
+}
+
+impl Add for Vec3 {
+    type Output = Vec3;
+
+    fn add(self, other: Vec3) -> Vec3 {
+        Vec3::new(self.x + other.x, self.y + other.y, self.z + other.z)
+    }
+}
+
+impl Sub for Vec3 {
+    type Output = Vec3;
+
+    fn sub(self, other: Vec3) -> Vec3 {
+        Vec3::new(self.x - other.x, self.y - other.y, self.z - other.z)
+    }
+}
+
+impl Mul<f32> for Vec3 {
+    type Output = Vec3;
+
+    fn mul(self, scale: f32) -> Vec3 {
+        Vec3::new(self.x * scale, self.y * scale, self.z * scale)
+    }
+}
+
+impl Neg for Vec3 {
+    type Output = Vec3;
+
+    fn neg(self) -> Vec3 {
+        Vec3::new(-self.x, -self.y, -self.z)
+    }
+}
+
+// Row-major storage, column-vector convention: clip = m * [x, y, z, 1].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat4 {
+    pub rows: [[f32; 4]; 4],
+}
+
+impl Mat4 {
+    pub const IDENTITY: Mat4 = Mat4 {
+        rows: [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ],
+    };
+
+    // Left handed, depth mapped to [0, 1] as D3D expects.
+    pub fn perspective_lh(fov_y: f32, aspect_ratio: f32, near: f32, far: f32) -> Mat4 {
+        let y_scale = 1.0 / (fov_y * 0.5).tan();
+        let x_scale = y_scale / aspect_ratio;
+        let z_range = far / (far - near);
+        Mat4 {
+            rows: [
+                [x_scale, 0.0, 0.0, 0.0],
+                [0.0, y_scale, 0.0, 0.0],
+                [0.0, 0.0, z_range, -near * z_range],
+                [0.0, 0.0, 1.0, 0.0],
+            ],
+        }
+    }
+
+    // Left handed, depth mapped to [0, 1] between near and far.
+    pub fn orthographic_off_center_lh(
+        left: f32,
+        right: f32,
+        bottom: f32,
+        top: f32,
+        near: f32,
+        far: f32,
+    ) -> Mat4 {
+        let z_range = 1.0 / (far - near);
+        Mat4 {
+            rows: [
+                [2.0 / (right - left), 0.0, 0.0, -(right + left) / (right - left)],
+                [0.0, 2.0 / (top - bottom), 0.0, -(top + bottom) / (top - bottom)],
+                [0.0, 0.0, z_range, -near * z_range],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    pub fn look_to_lh(eye: Vec3, direction: Vec3, up: Vec3) -> Mat4 {
+        let z_axis = direction.normalize();
+        let x_axis = up.cross(z_axis).normalize();
+        let y_axis = z_axis.cross(x_axis);
+        Mat4 {
+            rows: [
+                [x_axis.x, x_axis.y, x_axis.z, -x_axis.dot(eye)],
+                [y_axis.x, y_axis.y, y_axis.z, -y_axis.dot(eye)],
+                [z_axis.x, z_axis.y, z_axis.z, -z_axis.dot(eye)],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    pub fn transform(&self, v: [f32; 4]) -> [f32; 4] {
+        let mut out = [0.0; 4];
+        for (value, row) in out.iter_mut().zip(self.rows.iter()) {
+            *value = row[0] * v[0] + row[1] * v[1] + row[2] * v[2] + row[3] * v[3];
+        }
+        out
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, other: Mat4) -> Mat4 {
+        let mut rows = [[0.0; 4]; 4];
+        for (r, row) in rows.iter_mut().enumerate() {
+            for (c, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.rows[r][k] * other.rows[k][c]).sum();
+            }
+        }
+        Mat4 { rows }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Aabb { min, max }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn extents(&self) -> Vec3 {
+        (self.max - self.min) * 0.5
+    }
+
+    pub fn corners(&self) -> [Vec3; 8] {
+        let (min, max) = (self.min, self.max);
+        [
+            Vec3::new(min.x, min.y, min.z),
+            Vec3::new(max.x, min.y, min.z),
+            Vec3::new(min.x, max.y, min.z),
+            Vec3::new(max.x, max.y, min.z),
+            Vec3::new(min.x, min.y, max.z),
+            Vec3::new(max.x, min.y, max.z),
+            Vec3::new(min.x, max.y, max.z),
+            Vec3::new(max.x, max.y, max.z),
+        ]
+    }
+}
+
+// Points with normal.dot(p) + d >= 0 are on the inner side.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Plane {
+    pub normal: Vec3,
+    pub d: f32,
+}
+
+impl Plane {
+    fn from_coefficients(c: [f32; 4]) -> Plane {
+        let normal = Vec3::new(c[0], c[1], c[2]);
+        let inv_length = 1.0 / normal.length();
+        Plane {
+            normal: normal * inv_length,
+            d: c[3] * inv_length,
+        }
+    }
+
+    pub fn distance(&self, p: Vec3) -> f32 {
+        self.normal.dot(p) + self.d
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Containment {
+    Outside,
+    Intersecting,
+    Inside,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frustum {
+    // left, right, bottom, top, near, far
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    // Gribb/Hartmann plane extraction for a D3D style clip space
+    // (-w <= x, y <= w and 0 <= z <= w).
+    pub fn from_view_projection(view_projection: &Mat4) -> Frustum {
+        let [r0, r1, r2, r3] = view_projection.rows;
+        let add = |a: [f32; 4], b: [f32; 4]| [a[0] + b[0], a[1] + b[1], a[2] + b[2], a[3] + b[3]];
+        let sub = |a: [f32; 4], b: [f32; 4]| [a[0] - b[0], a[1] - b[1], a[2] - b[2], a[3] - b[3]];
+        Frustum {
+            planes: [
+                Plane::from_coefficients(add(r3, r0)),
+                Plane::from_coefficients(sub(r3, r0)),
+                Plane::from_coefficients(add(r3, r1)),
+                Plane::from_coefficients(sub(r3, r1)),
+                Plane::from_coefficients(r2),
+                Plane::from_coefficients(sub(r3, r2)),
+            ],
+        }
+    }
+
+    pub fn test_aabb(&self, bounds: &Aabb) -> Containment {
+        let center = bounds.center();
+        let extents = bounds.extents();
+        let mut result = Containment::Inside;
+        for plane in &self.planes {
+            // Projected radius of the box onto the plane normal.
+            let radius = extents.x * plane.normal.x.abs()
+                + extents.y * plane.normal.y.abs()
+                + extents.z * plane.normal.z.abs();
+            let distance = plane.distance(center);
+            if distance < -radius {
+                return Containment::Outside;
+            }
+            if distance < radius {
+                result = Containment::Intersecting;
+            }
+        }
+        result
+    }
+
+    pub fn is_visible(&self, bounds: &Aabb) -> bool {
+        self.test_aabb(bounds) != Containment::Outside
+    }
+
+    // Where the side planes meet the near and far planes, in the order of
+    // Aabb::corners: left before right, bottom before top, near before far.
+    pub fn corners(&self) -> [Vec3; 8] {
+        let [left, right, bottom, top, near, far] = self.planes;
+        let mut corners = [Vec3::default(); 8];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            let x = if i & 1 != 0 { right } else { left };
+            let y = if i & 2 != 0 { top } else { bottom };
+            let z = if i & 4 != 0 { far } else { near };
+            *corner = intersect_planes(&x, &y, &z);
+        }
+        corners
+    }
+}
+
+// The point on all three planes, which must not share a line.
+fn intersect_planes(a: &Plane, b: &Plane, c: &Plane) -> Vec3 {
+    let bc = b.normal.cross(c.normal);
+    let ca = c.normal.cross(a.normal);
+    let ab = a.normal.cross(b.normal);
+    (bc * -a.d + ca * -b.d + ab * -c.d) * (1.0 / a.normal.dot(bc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 90 degree square frustum at the origin looking down +z, from 1 to 100.
+    fn frustum() -> Frustum {
+        let view = Mat4::look_to_lh(Vec3::default(), Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 1.0, 0.0));
+        let projection = Mat4::perspective_lh(std::f32::consts::FRAC_PI_2, 1.0, 1.0, 100.0);
+        Frustum::from_view_projection(&(projection * view))
+    }
+
+    fn cube(center: Vec3, half: f32) -> Aabb {
+        let extents = Vec3::new(half, half, half);
+        Aabb::new(center - extents, center + extents)
+    }
+
+    #[test]
+    fn box_in_front_is_inside() {
+        assert_eq!(frustum().test_aabb(&cube(Vec3::new(0.0, 0.0, 10.0), 1.0)), Containment::Inside);
+    }
+
+    #[test]
+    fn boxes_beyond_a_plane_are_outside() {
+        let frustum = frustum();
+        for center in [
+            Vec3::new(0.0, 0.0, -10.0),
+            Vec3::new(0.0, 0.0, 200.0),
+            Vec3::new(30.0, 0.0, 10.0),
+            Vec3::new(0.0, -30.0, 10.0),
+        ] {
+            assert_eq!(frustum.test_aabb(&cube(center, 1.0)), Containment::Outside, "{:?}", center);
+            assert!(!frustum.is_visible(&cube(center, 1.0)));
+        }
+    }
+
+    #[test]
+    fn boxes_across_a_plane_intersect() {
+        let frustum = frustum();
+        for center in [
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, 0.0, 100.0),
+            Vec3::new(10.0, 0.0, 10.0),
+            Vec3::new(0.0, 10.0, 10.0),
+        ] {
+            assert_eq!(frustum.test_aabb(&cube(center, 1.0)), Containment::Intersecting, "{:?}", center);
+            assert!(frustum.is_visible(&cube(center, 1.0)));
+        }
+    }
+
+    #[test]
+    fn corners_lie_on_the_near_and_far_planes() {
+        let corners = frustum().corners();
+        assert!((corners[0].z - 1.0).abs() < 1e-3 && (corners[0].x + 1.0).abs() < 1e-3);
+        assert!((corners[7].z - 100.0).abs() < 1e-2 && (corners[7].y - 100.0).abs() < 1e-2);
+    }
+}
@@ -0,0 +1,277 @@
+use crate::block::{BlockId, BlockRegistry};
+use crate::chunk::{ChunkPos, CHUNK_SIZE};
+use crate::lod::{downsample, lod_factor, LodVolume};
+use crate::world::World;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Vertex {
+    pub position: [f32; 3],
+    pub color: [f32; 4],
+    // How far the vertex moves with the surface waves, 1 on the top of a
+    // liquid and 0 everywhere else.
+    pub wave: f32,
+}
+
+// Opaque and translucent faces share the vertices, each layer has its own
+// indices so it can be drawn with its own pipeline state.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChunkMesh {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    pub translucent_indices: Vec<u32>,
+}
+
+impl ChunkMesh {
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty() && self.translucent_indices.is_empty()
+    }
+}
+
+struct Face {
+    normal: [i32; 3],
+    // Unit cube corners, clockwise seen from outside.
+    corners: [[f32; 3]; 4],
+    shade: f32,
+}
+
+const FACES: [Face; 6] = [
+    Face {
+        normal: [1, 0, 0],
+        corners: [[1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [1.0, 1.0, 1.0], [1.0, 0.0, 1.0]],
+        shade: 0.8,
+    },
+    Face {
+        normal: [-1, 0, 0],
+        corners: [[0.0, 0.0, 1.0], [0.0, 1.0, 1.0], [0.0, 1.0, 0.0], [0.0, 0.0, 0.0]],
+        shade: 0.8,
+    },
+    Face {
+        normal: [0, 1, 0],
+        corners: [[0.0, 1.0, 0.0], [0.0, 1.0, 1.0], [1.0, 1.0, 1.0], [1.0, 1.0, 0.0]],
+        shade: 1.0,
+    },
+    Face {
+        normal: [0, -1, 0],
+        corners: [[1.0, 0.0, 0.0], [1.0, 0.0, 1.0], [0.0, 0.0, 1.0], [0.0, 0.0, 0.0]],
+        shade: 0.5,
+    },
+    Face {
+        normal: [0, 0, 1],
+        corners: [[1.0, 0.0, 1.0], [1.0, 1.0, 1.0], [0.0, 1.0, 1.0], [0.0, 0.0, 1.0]],
+        shade: 0.7,
+    },
+    Face {
+        normal: [0, 0, -1],
+        corners: [[0.0, 0.0, 0.0], [0.0, 1.0, 0.0], [1.0, 1.0, 0.0], [1.0, 0.0, 0.0]],
+        shade: 0.7,
+    },
+];
+
+// Shade of a face by its outward normal, 1 for the zero normal of a ray
+// that starts inside a block.
+#[cfg(test)]
+pub fn face_shade(normal: [i32; 3]) -> f32 {
+    FACES
+        .iter()
+        .find(|face| face.normal == normal)
+        .map_or(1.0, |face| face.shade)
+}
+
+// Whether the face of `block` towards `neighbour` can be seen. Opaque faces
+// show through air and translucent blocks, translucent ones only through air
+// and other kinds of translucent blocks, so the inside of a body of water
+// has no faces and neither has the ground under it seen from below.
+fn face_visible(block: BlockId, neighbour: BlockId, registry: &BlockRegistry) -> bool {
+    if neighbour.is_air() {
+        return true;
+    }
+    let neighbour_translucent = registry.is_translucent(neighbour);
+    if registry.is_translucent(block) {
+        neighbour_translucent && neighbour != block
+    } else {
+        neighbour_translucent
+    }
+}
+
+// Emits a quad for every solid block face that is not hidden by its
+// neighbour, see face_visible, into the opaque or the translucent indices.
+// Blocks outside the volume are looked up through `outside`, in volume
+// coordinates; None means the neighbour is unknown or at another level of
+// detail and opaque faces are emitted anyway. Those border faces act as
+// skirts that close the cracks between chunks of different levels.
+// Translucent faces get no skirts, a crack in them shows what the face
+// would have shown through.
+pub fn mesh_volume(
+    volume: &LodVolume,
+    scale: f32,
+    origin: [i32; 3],
+    registry: &BlockRegistry,
+    outside: impl Fn(i32, i32, i32) -> Option<BlockId>,
+) -> ChunkMesh {
+    let mut mesh = ChunkMesh::default();
+    let size = volume.size as i32;
+
+    for y in 0..size {
+        for z in 0..size {
+            for x in 0..size {
+                let block = volume.get(x as usize, y as usize, z as usize);
+                if block.is_air() {
+                    continue;
+                }
+                let color = registry.color(block);
+                let translucent = registry.is_translucent(block);
+                let neighbour = |nx: i32, ny: i32, nz: i32| {
+                    let inside = (0..size).contains(&nx)
+                        && (0..size).contains(&ny)
+                        && (0..size).contains(&nz);
+                    if inside {
+                        Some(volume.get(nx as usize, ny as usize, nz as usize))
+                    } else {
+                        outside(nx, ny, nz)
+                    }
+                };
+                // Only the surface waves, the liquid below stays put.
+                let surface = registry.is_liquid(block)
+                    && !neighbour(x, y + 1, z).is_some_and(|above| registry.is_liquid(above));
+
+                for face in &FACES {
+                    let visible = match neighbour(
+                        x + face.normal[0],
+                        y + face.normal[1],
+                        z + face.normal[2],
+                    ) {
+                        Some(neighbour) => face_visible(block, neighbour, registry),
+                        None => !translucent,
+                    };
+                    if !visible {
+                        continue;
+                    }
+
+                    let base = mesh.vertices.len() as u32;
+                    for corner in &face.corners {
+                        mesh.vertices.push(Vertex {
+                            position: [
+                                origin[0] as f32 + (x as f32 + corner[0]) * scale,
+                                origin[1] as f32 + (y as f32 + corner[1]) * scale,
+                                origin[2] as f32 + (z as f32 + corner[2]) * scale,
+                            ],
+                            color: [
+                                color[0] * face.shade,
+                                color[1] * face.shade,
+                                color[2] * face.shade,
+                                color[3],
+                            ],
+                            wave: if surface && corner[1] == 1.0 { 1.0 } else { 0.0 },
+                        });
+                    }
+                    let indices = if translucent {
+                        &mut mesh.translucent_indices
+                    } else {
+                        &mut mesh.indices
+                    };
+                    indices.extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+                }
+            }
+        }
+    }
+
+    mesh
+}
+
+// Meshes a resident chunk at the given level of detail. Faces against a
+// neighbour are only culled when that neighbour is full detail as well,
+// lod_of reports the level each neighbour chunk is currently meshed at.
+pub fn mesh_chunk(
+    world: &World,
+    pos: ChunkPos,
+    lod: u32,
+    registry: &BlockRegistry,
+    lod_of: impl Fn(ChunkPos) -> Option<u32>,
+) -> ChunkMesh {
+    let chunk = match world.chunk(pos) {
+        Some(chunk) => chunk,
+        None => return ChunkMesh::default(),
+    };
+    if chunk.is_empty() {
+        return ChunkMesh::default();
+    }
+
+    let volume = if lod == 0 {
+        LodVolume {
+            size: CHUNK_SIZE,
+            blocks: chunk.blocks().to_vec(),
+        }
+    } else {
+        downsample(chunk, world.chunk(pos.offset(0, 1, 0)), lod)
+    };
+    let origin = pos.origin();
+
+    mesh_volume(&volume, lod_factor(lod) as f32, origin, registry, |x, y, z| {
+        if lod != 0 {
+            return None;
+        }
+        let block = [origin[0] + x, origin[1] + y, origin[2] + z];
+        match lod_of(ChunkPos::from_block(block)) {
+            Some(0) => world.block(block),
+            _ => None,
+        }
+    })
+}
+
+// Appends an axis-aligned box between min and max, shaded like block faces.
+fn push_box(mesh: &mut ChunkMesh, min: [f32; 3], max: [f32; 3], color: [f32; 4]) {
+    for face in &FACES {
+        let base = mesh.vertices.len() as u32;
+        for corner in &face.corners {
+            mesh.vertices.push(Vertex {
+                position: [
+                    min[0] + corner[0] * (max[0] - min[0]),
+                    min[1] + corner[1] * (max[1] - min[1]),
+                    min[2] + corner[2] * (max[2] - min[2]),
+                ],
+                color: [
+                    color[0] * face.shade,
+                    color[1] * face.shade,
+                    color[2] * face.shade,
+                    color[3],
+                ],
+                wave: 0.0,
+            });
+        }
+        mesh.indices
+            .extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+    }
+}
+
+// Wireframe of a block built from thin bars along its twelve edges, so it
+// can be drawn with the triangle pipeline. The bars sit slightly outside the
+// block to stay in front of its faces in the depth test.
+pub fn outline_mesh(block: [i32; 3], color: [f32; 4]) -> ChunkMesh {
+    const THICKNESS: f32 = 0.02;
+    const OFFSET: f32 = 0.005;
+
+    let mut mesh = ChunkMesh::default();
+    let low = [block[0] as f32, block[1] as f32, block[2] as f32];
+    for axis in 0..3 {
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        for corner in 0..4 {
+            let mut min = [0.0; 3];
+            let mut max = [0.0; 3];
+            min[axis] = low[axis] - OFFSET - THICKNESS;
+            max[axis] = low[axis] + 1.0 + OFFSET + THICKNESS;
+            for (side, other) in [(corner & 1, u), (corner >> 1, v)] {
+                let edge = low[other] + side as f32;
+                if side == 0 {
+                    min[other] = edge - OFFSET - THICKNESS;
+                    max[other] = edge - OFFSET;
+                } else {
+                    min[other] = edge + OFFSET;
+                    max[other] = edge + OFFSET + THICKNESS;
+                }
+            }
+            push_box(&mut mesh, min, max, color);
+        }
+    }
+    mesh
+}
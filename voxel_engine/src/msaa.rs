@@ -0,0 +1,79 @@
+// Multisample anti-aliasing of the main pass. The chunks, highlight, sky
+// and translucent faces are drawn into multisampled color and depth targets,
+// which are resolved into the single sampled scene target before post
+// processing. The swap chain stays single sampled, as flip model swap chains
+// have to be.
+
+// Counts the settings accept, 1 turns MSAA off.
+pub const SAMPLE_COUNTS: [u32; 4] = [1, 2, 4, 8];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MsaaSettings {
+    pub sample_count: u32,
+}
+
+impl Default for MsaaSettings {
+    fn default() -> Self {
+        MsaaSettings { sample_count: 4 }
+    }
+}
+
+impl MsaaSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if SAMPLE_COUNTS.contains(&self.sample_count) {
+            Ok(())
+        } else {
+            Err(format!(
+                "unsupported MSAA sample count {}, expected one of {:?}",
+                self.sample_count, SAMPLE_COUNTS
+            ))
+        }
+    }
+
+    // The configured count if `is_supported`, otherwise the highest lower
+    // one that is. A single sample is always supported.
+    pub fn supported_sample_count(&self, is_supported: impl Fn(u32) -> bool) -> u32 {
+        SAMPLE_COUNTS
+            .iter()
+            .rev()
+            .copied()
+            .filter(|&count| count <= self.sample_count)
+            .find(|&count| count == 1 || is_supported(count))
+            .unwrap_or(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_listed_counts_are_valid() {
+        for sample_count in SAMPLE_COUNTS {
+            assert_eq!(MsaaSettings { sample_count }.validate(), Ok(()));
+        }
+        for sample_count in [0, 3, 16] {
+            assert_eq!(
+                MsaaSettings { sample_count }.validate(),
+                Err(format!(
+                    "unsupported MSAA sample count {}, expected one of [1, 2, 4, 8]",
+                    sample_count
+                ))
+            );
+        }
+    }
+
+    #[test]
+    fn unsupported_counts_fall_back_to_lower_ones() {
+        let settings = MsaaSettings { sample_count: 8 };
+        assert_eq!(settings.supported_sample_count(|_| true), 8);
+        assert_eq!(settings.supported_sample_count(|count| count <= 4), 4);
+        assert_eq!(settings.supported_sample_count(|count| count <= 2), 2);
+        // A single sample needs no support.
+        assert_eq!(settings.supported_sample_count(|_| false), 1);
+
+        let settings = MsaaSettings { sample_count: 4 };
+        assert_eq!(settings.supported_sample_count(|count| count != 4), 2);
+        assert_eq!(MsaaSettings { sample_count: 1 }.supported_sample_count(|_| true), 1);
+    }
+}
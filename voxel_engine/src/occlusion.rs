@@ -0,0 +1,238 @@
+#[cfg(test)]
+use crate::math::{Aabb, Mat4};
+
+// Number of levels of a pyramid whose base matches the depth buffer, down
+// to a single texel.
+pub fn mip_count(width: u32, height: u32) -> u32 {
+    32 - width.max(height).max(1).leading_zeros()
+}
+
+pub fn mip_size(width: u32, height: u32, mip: u32) -> (u32, u32) {
+    ((width >> mip).max(1), (height >> mip).max(1))
+}
+
+// Screen space extent of a box, uv in [0, 1] with y pointing down as in
+// texture space, depth in D3D's [0, 1] range. Like DepthPyramid, only the
+// tests use the CPU side of the occlusion test.
+#[cfg(test)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScreenBounds {
+    pub uv_min: [f32; 2],
+    pub uv_max: [f32; 2],
+    pub min_depth: f32,
+}
+
+// Returns None when the box reaches behind the camera, in which case its
+// projection is unbounded and it has to be treated as visible.
+#[cfg(test)]
+pub fn project_bounds(bounds: &Aabb, view_projection: &Mat4) -> Option<ScreenBounds> {
+    let mut uv_min = [1.0_f32; 2];
+    let mut uv_max = [0.0_f32; 2];
+    let mut min_depth = 1.0_f32;
+    for corner in bounds.corners() {
+        let [x, y, z, w] = view_projection.transform([corner.x, corner.y, corner.z, 1.0]);
+        if w <= 0.0 {
+            return None;
+        }
+        let uv = [x / w * 0.5 + 0.5, y / w * -0.5 + 0.5];
+        for axis in 0..2 {
+            uv_min[axis] = uv_min[axis].min(uv[axis]);
+            uv_max[axis] = uv_max[axis].max(uv[axis]);
+        }
+        min_depth = min_depth.min(z / w);
+    }
+    Some(ScreenBounds {
+        uv_min: [uv_min[0].clamp(0.0, 1.0), uv_min[1].clamp(0.0, 1.0)],
+        uv_max: [uv_max[0].clamp(0.0, 1.0), uv_max[1].clamp(0.0, 1.0)],
+        min_depth,
+    })
+}
+
+// CPU reference of the hierarchical Z buffer built by shaders/hzb.hlsl.
+// Level 0 is a copy of the depth buffer, every further level keeps the
+// farthest depth of the texels it covers, so a box whose nearest point is
+// behind that depth is hidden by what was drawn.
+//
+// Not used by the renderer itself, the tests check the culling math against
+// it with synthetic depth maps.
+#[cfg(test)]
+pub struct DepthPyramid {
+    width: u32,
+    height: u32,
+    levels: Vec<Vec<f32>>,
+}
+
+#[cfg(test)]
+impl DepthPyramid {
+    pub fn build(depth: &[f32], width: u32, height: u32) -> Self {
+        assert_eq!(depth.len(), (width * height) as usize);
+
+        let mut levels = vec![depth.to_vec()];
+        for mip in 1..mip_count(width, height) {
+            let (src_width, src_height) = mip_size(width, height, mip - 1);
+            let (dst_width, dst_height) = mip_size(width, height, mip);
+            let source = &levels[mip as usize - 1];
+            let mut level = Vec::with_capacity((dst_width * dst_height) as usize);
+            for y in 0..dst_height {
+                for x in 0..dst_width {
+                    let (first_x, last_x) = footprint(x, dst_width, src_width);
+                    let (first_y, last_y) = footprint(y, dst_height, src_height);
+                    let mut farthest = 0.0_f32;
+                    for sy in first_y..=last_y {
+                        for sx in first_x..=last_x {
+                            farthest = farthest.max(source[(sy * src_width + sx) as usize]);
+                        }
+                    }
+                    level.push(farthest);
+                }
+            }
+            levels.push(level);
+        }
+
+        DepthPyramid {
+            width,
+            height,
+            levels,
+        }
+    }
+
+    pub fn mip_count(&self) -> u32 {
+        self.levels.len() as u32
+    }
+
+    pub fn load(&self, mip: u32, x: u32, y: u32) -> f32 {
+        let (width, _) = mip_size(self.width, self.height, mip);
+        self.levels[mip as usize][(y * width + x) as usize]
+    }
+
+    pub fn is_occluded(&self, bounds: &Aabb, view_projection: &Mat4) -> bool {
+        match project_bounds(bounds, view_projection) {
+            Some(screen) => self.is_screen_bounds_occluded(&screen),
+            None => false,
+        }
+    }
+
+    // Mirrors IsOccluded in cull.hlsl: pick the level where the rectangle
+    // covers at most 2x2 texels and compare against the farthest of them.
+    pub fn is_screen_bounds_occluded(&self, screen: &ScreenBounds) -> bool {
+        let size_x = (screen.uv_max[0] - screen.uv_min[0]) * self.width as f32;
+        let size_y = (screen.uv_max[1] - screen.uv_min[1]) * self.height as f32;
+        let mip = (size_x.max(size_y).max(1.0).log2().ceil() as u32).min(self.mip_count() - 1);
+
+        let (mip_width, mip_height) = mip_size(self.width, self.height, mip);
+        let texel = |uv: f32, size: u32| ((uv * size as f32) as u32).min(size - 1);
+        let xs = [texel(screen.uv_min[0], mip_width), texel(screen.uv_max[0], mip_width)];
+        let ys = [texel(screen.uv_min[1], mip_height), texel(screen.uv_max[1], mip_height)];
+
+        let mut farthest = 0.0_f32;
+        for y in ys {
+            for x in xs {
+                farthest = farthest.max(self.load(mip, x, y));
+            }
+        }
+        screen.min_depth > farthest
+    }
+}
+
+// Source texels covered by a destination texel. With an odd source size the
+// last destination texel also takes the leftover row or column.
+#[cfg(test)]
+fn footprint(dst: u32, dst_size: u32, src_size: u32) -> (u32, u32) {
+    let first = (dst * 2).min(src_size - 1);
+    let mut last = dst * 2 + 1;
+    if dst == dst_size - 1 && src_size % 2 == 1 {
+        last += 1;
+    }
+    (first, last.min(src_size - 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Vec3;
+
+    const SIZE: u32 = 16;
+
+    // Looking down +z from the origin, 90 degrees wide, 1 to 100 deep. A
+    // depth of 0.8 is about 4.8 in front of the camera.
+    fn view_projection() -> Mat4 {
+        let view = Mat4::look_to_lh(Vec3::default(), Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 1.0, 0.0));
+        Mat4::perspective_lh(std::f32::consts::FRAC_PI_2, 1.0, 1.0, 100.0) * view
+    }
+
+    fn cube(center: Vec3, half: f32) -> Aabb {
+        let extents = Vec3::new(half, half, half);
+        Aabb::new(center - extents, center + extents)
+    }
+
+    // Cleared to the far plane with a wall at `wall` over the left half.
+    fn half_wall(wall: f32) -> Vec<f32> {
+        (0..SIZE * SIZE)
+            .map(|i| if i % SIZE < SIZE / 2 { wall } else { 1.0 })
+            .collect()
+    }
+
+    #[test]
+    fn mip_chain_goes_down_to_one_texel() {
+        assert_eq!(mip_count(16, 16), 5);
+        assert_eq!(mip_count(1920, 1080), 11);
+        assert_eq!(mip_size(1920, 1080, 10), (1, 1));
+        assert_eq!(mip_size(5, 3, 2), (1, 1));
+    }
+
+    #[test]
+    fn levels_keep_the_farthest_depth() {
+        let depth: Vec<f32> = (0..SIZE * SIZE).map(|i| i as f32 / (SIZE * SIZE) as f32).collect();
+        let pyramid = DepthPyramid::build(&depth, SIZE, SIZE);
+        assert_eq!(pyramid.mip_count(), 5);
+        assert_eq!(pyramid.load(1, 0, 0), depth[(SIZE + 1) as usize]);
+        assert_eq!(pyramid.load(4, 0, 0), *depth.last().unwrap());
+    }
+
+    #[test]
+    fn odd_sizes_fold_the_leftover_texels_into_the_last_one() {
+        // 3x1: the single texel of level 1 covers all three.
+        let pyramid = DepthPyramid::build(&[0.1, 0.2, 0.9], 3, 1);
+        assert_eq!(pyramid.mip_count(), 2);
+        assert_eq!(pyramid.load(1, 0, 0), 0.9);
+    }
+
+    #[test]
+    fn boxes_behind_the_wall_are_occluded() {
+        let pyramid = DepthPyramid::build(&vec![0.8; (SIZE * SIZE) as usize], SIZE, SIZE);
+        let view_projection = view_projection();
+        assert!(pyramid.is_occluded(&cube(Vec3::new(0.0, 0.0, 20.0), 1.0), &view_projection));
+        assert!(!pyramid.is_occluded(&cube(Vec3::new(0.0, 0.0, 3.0), 1.0), &view_projection));
+        // Reaching behind the camera is never occluded.
+        assert!(!pyramid.is_occluded(&cube(Vec3::new(0.0, 0.0, 0.0), 2.0), &view_projection));
+    }
+
+    #[test]
+    fn only_the_covered_half_occludes() {
+        let pyramid = DepthPyramid::build(&half_wall(0.8), SIZE, SIZE);
+        let view_projection = view_projection();
+        assert!(pyramid.is_occluded(&cube(Vec3::new(-10.0, 0.0, 20.0), 1.0), &view_projection));
+        assert!(!pyramid.is_occluded(&cube(Vec3::new(10.0, 0.0, 20.0), 1.0), &view_projection));
+        // Straddling the wall's edge, part of the box is in the open.
+        assert!(!pyramid.is_occluded(&cube(Vec3::new(0.0, 0.0, 20.0), 2.0), &view_projection));
+    }
+
+    #[test]
+    fn compaction_drops_occluded_chunks() {
+        use crate::indirect::{cull_and_compact, ChunkRecord};
+        use crate::math::Frustum;
+
+        let pyramid = DepthPyramid::build(&half_wall(0.8), SIZE, SIZE);
+        let view_projection = view_projection();
+        let chunks = [
+            ChunkRecord::new(&cube(Vec3::new(-10.0, 0.0, 20.0), 1.0), 6, 0, 0),
+            ChunkRecord::new(&cube(Vec3::new(10.0, 0.0, 20.0), 1.0), 6, 6, 0),
+            ChunkRecord::new(&cube(Vec3::new(-1.5, 0.0, 3.0), 0.5), 6, 12, 0),
+        ];
+        let mut arguments = Vec::new();
+        let frustum = Frustum::from_view_projection(&view_projection);
+        let occluded = |bounds: &Aabb| pyramid.is_occluded(bounds, &view_projection);
+        assert_eq!(cull_and_compact(&chunks, &frustum, occluded, &mut arguments), 2);
+        assert_eq!(arguments, [chunks[1].draw_arguments(), chunks[2].draw_arguments()]);
+    }
+}
@@ -0,0 +1,318 @@
+// Post processing as data: the settings expand into a list of full screen
+// passes, each naming its shader, the targets it reads and the one it
+// writes. The post_process module runs whatever list it is given.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tonemapper {
+    // Narkowicz's fit of the ACES filmic curve.
+    Aces,
+    Reinhard,
+}
+
+impl Tonemapper {
+    pub fn next(self) -> Self {
+        match self {
+            Tonemapper::Aces => Tonemapper::Reinhard,
+            Tonemapper::Reinhard => Tonemapper::Aces,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BloomSettings {
+    // Exposed brightness where bloom starts.
+    pub threshold: f32,
+    // Width of the soft transition below the threshold.
+    pub knee: f32,
+    pub intensity: f32,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        BloomSettings {
+            threshold: 1.0,
+            knee: 0.5,
+            intensity: 0.6,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PostSettings {
+    pub tonemapper: Tonemapper,
+    // Multiplies the scene before bloom and tonemapping.
+    pub exposure: f32,
+    // Display gamma. The back buffer view encodes to sRGB, anything other
+    // than 2.2 is applied on top of that.
+    pub gamma: f32,
+    pub fxaa: bool,
+    pub bloom: Option<BloomSettings>,
+}
+
+impl Default for PostSettings {
+    fn default() -> Self {
+        PostSettings {
+            tonemapper: Tonemapper::Aces,
+            exposure: 1.0,
+            gamma: 2.2,
+            fxaa: true,
+            bloom: Some(BloomSettings::default()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PostTarget {
+    // HDR color the main pass renders into.
+    Scene,
+    // Half resolution HDR targets the bloom is blurred back and forth
+    // between.
+    BloomA,
+    BloomB,
+    // Tonemapped color, sRGB encoded.
+    Ldr,
+    BackBuffer,
+}
+
+impl PostTarget {
+    // Divisor of the back buffer size.
+    pub fn scale(self) -> u32 {
+        match self {
+            PostTarget::BloomA | PostTarget::BloomB => 2,
+            _ => 1,
+        }
+    }
+
+    pub fn is_hdr(self) -> bool {
+        matches!(self, PostTarget::Scene | PostTarget::BloomA | PostTarget::BloomB)
+    }
+
+    pub fn size(self, width: u32, height: u32) -> (u32, u32) {
+        let scale = self.scale();
+        ((width / scale).max(1), (height / scale).max(1))
+    }
+}
+
+// Pixel shaders of shaders/post.hlsl, all drawn with the same full screen
+// triangle vertex shader.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PostShader {
+    // parameters: threshold, knee, exposure.
+    BloomExtract,
+    // parameters: direction in texels of the input.
+    Blur,
+    // parameters: exposure, tonemapper, bloom intensity, gamma.
+    Tonemap,
+    Fxaa,
+}
+
+// Textures a pass can read, t0 and up.
+pub const POST_INPUT_COUNT: usize = 2;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PostPass {
+    pub name: &'static str,
+    pub shader: PostShader,
+    pub inputs: Vec<PostTarget>,
+    pub output: PostTarget,
+    pub parameters: [f32; 4],
+}
+
+impl PostSettings {
+    pub fn passes(&self) -> Vec<PostPass> {
+        let mut passes = Vec::new();
+        let mut tonemap_inputs = vec![PostTarget::Scene];
+        let mut bloom_intensity = 0.0;
+
+        if let Some(bloom) = &self.bloom {
+            passes.push(PostPass {
+                name: "bloom extract",
+                shader: PostShader::BloomExtract,
+                inputs: vec![PostTarget::Scene],
+                output: PostTarget::BloomA,
+                parameters: [bloom.threshold, bloom.knee, self.exposure, 0.0],
+            });
+            passes.push(PostPass {
+                name: "bloom blur horizontal",
+                shader: PostShader::Blur,
+                inputs: vec![PostTarget::BloomA],
+                output: PostTarget::BloomB,
+                parameters: [1.0, 0.0, 0.0, 0.0],
+            });
+            passes.push(PostPass {
+                name: "bloom blur vertical",
+                shader: PostShader::Blur,
+                inputs: vec![PostTarget::BloomB],
+                output: PostTarget::BloomA,
+                parameters: [0.0, 1.0, 0.0, 0.0],
+            });
+            tonemap_inputs.push(PostTarget::BloomA);
+            bloom_intensity = bloom.intensity;
+        }
+
+        let tonemapper = match self.tonemapper {
+            Tonemapper::Aces => 0.0,
+            Tonemapper::Reinhard => 1.0,
+        };
+        passes.push(PostPass {
+            name: "tonemap",
+            shader: PostShader::Tonemap,
+            inputs: tonemap_inputs,
+            output: if self.fxaa {
+                PostTarget::Ldr
+            } else {
+                PostTarget::BackBuffer
+            },
+            parameters: [self.exposure, tonemapper, bloom_intensity, self.gamma],
+        });
+
+        if self.fxaa {
+            passes.push(PostPass {
+                name: "fxaa",
+                shader: PostShader::Fxaa,
+                inputs: vec![PostTarget::Ldr],
+                output: PostTarget::BackBuffer,
+                parameters: [0.0; 4],
+            });
+        }
+
+        passes
+    }
+}
+
+// Checks that a chain can run: every input was rendered before it is read,
+// no pass reads what it writes, and the last pass writes the back buffer.
+pub fn validate_chain(passes: &[PostPass]) -> Result<(), String> {
+    let mut written = vec![PostTarget::Scene];
+    for pass in passes {
+        if pass.inputs.is_empty() || pass.inputs.len() > POST_INPUT_COUNT {
+            return Err(format!("{} reads {} inputs", pass.name, pass.inputs.len()));
+        }
+        if let Some(input) = pass.inputs.iter().find(|input| !written.contains(input)) {
+            return Err(format!("{} reads {:?} before it is written", pass.name, input));
+        }
+        if pass.inputs.contains(&pass.output) {
+            return Err(format!("{} reads and writes {:?}", pass.name, pass.output));
+        }
+        if pass.inputs.contains(&PostTarget::BackBuffer) {
+            return Err(format!("{} reads the back buffer", pass.name));
+        }
+        written.push(pass.output);
+    }
+    match passes.last() {
+        Some(pass) if pass.output == PostTarget::BackBuffer => Ok(()),
+        _ => Err("the chain does not end in the back buffer".into()),
+    }
+}
+
+// Root constants of every post pass, laid out like the PostConstants
+// cbuffer in shaders/post.hlsl.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PostConstants {
+    // Size of one texel of the first input in uv.
+    pub texel_size: [f32; 2],
+    pub padding: [f32; 2],
+    pub parameters: [f32; 4],
+}
+
+impl PostConstants {
+    pub fn new(pass: &PostPass, width: u32, height: u32) -> Self {
+        let (input_width, input_height) = pass.inputs[0].size(width, height);
+        PostConstants {
+            texel_size: [1.0 / input_width as f32, 1.0 / input_height as f32],
+            padding: [0.0; 2],
+            parameters: pass.parameters,
+        }
+    }
+}
+
+// CPU reference of Tonemap in post.hlsl, before gamma and sRGB encoding.
+#[cfg(test)]
+pub fn tonemap(color: [f32; 3], tonemapper: Tonemapper) -> [f32; 3] {
+    color.map(|x| match tonemapper {
+        Tonemapper::Aces => {
+            ((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)).clamp(0.0, 1.0)
+        }
+        Tonemapper::Reinhard => x / (1.0 + x),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: [f32; 3], b: [f32; 3]) {
+        assert!(a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-3), "{:?} against {:?}", a, b);
+    }
+
+    #[test]
+    fn aces_maps_black_to_black_and_saturates() {
+        assert_close(tonemap([0.0; 3], Tonemapper::Aces), [0.0; 3]);
+        assert_close(tonemap([100.0; 3], Tonemapper::Aces), [1.0; 3]);
+        // Narkowicz's fit is a little above 0.8 at 1 and rises monotonically.
+        assert_close(tonemap([1.0, 0.18, 4.0], Tonemapper::Aces), [0.8038, 0.2669, 0.9734]);
+        let ramp: Vec<f32> = (0..32).map(|i| tonemap([i as f32 * 0.25; 3], Tonemapper::Aces)[0]).collect();
+        assert!(ramp.windows(2).all(|pair| pair[0] <= pair[1]), "{:?}", ramp);
+    }
+
+    #[test]
+    fn reinhard_halves_one_and_never_saturates() {
+        assert_close(tonemap([0.0, 1.0, 3.0], Tonemapper::Reinhard), [0.0, 0.5, 0.75]);
+        assert!(tonemap([1000.0; 3], Tonemapper::Reinhard)[0] < 1.0);
+    }
+
+    #[test]
+    fn default_chain_runs_bloom_tonemap_and_fxaa() {
+        let passes = PostSettings::default().passes();
+        let names: Vec<&str> = passes.iter().map(|pass| pass.name).collect();
+        assert_eq!(names, ["bloom extract", "bloom blur horizontal", "bloom blur vertical", "tonemap", "fxaa"]);
+        assert_eq!(passes[3].inputs, [PostTarget::Scene, PostTarget::BloomA]);
+        assert_eq!(passes[3].output, PostTarget::Ldr);
+        assert_eq!(passes[3].parameters, [1.0, 0.0, 0.6, 2.2]);
+        assert_eq!(validate_chain(&passes), Ok(()));
+    }
+
+    #[test]
+    fn tonemap_writes_the_back_buffer_without_fxaa_or_bloom() {
+        let settings = PostSettings {
+            tonemapper: Tonemapper::Reinhard,
+            fxaa: false,
+            bloom: None,
+            ..PostSettings::default()
+        };
+        let passes = settings.passes();
+        assert_eq!(passes.len(), 1);
+        assert_eq!(passes[0].inputs, [PostTarget::Scene]);
+        assert_eq!(passes[0].output, PostTarget::BackBuffer);
+        assert_eq!(passes[0].parameters, [1.0, 1.0, 0.0, 2.2]);
+        assert_eq!(validate_chain(&passes), Ok(()));
+    }
+
+    #[test]
+    fn broken_chains_are_rejected() {
+        let mut passes = PostSettings::default().passes();
+        passes.remove(0);
+        assert_eq!(
+            validate_chain(&passes),
+            Err("bloom blur horizontal reads BloomA before it is written".into())
+        );
+
+        let mut passes = PostSettings::default().passes();
+        passes.pop();
+        assert_eq!(validate_chain(&passes), Err("the chain does not end in the back buffer".into()));
+
+        let mut passes = PostSettings::default().passes();
+        passes[1].output = PostTarget::BloomA;
+        assert_eq!(validate_chain(&passes), Err("bloom blur horizontal reads and writes BloomA".into()));
+    }
+
+    #[test]
+    fn bloom_targets_are_half_size() {
+        assert_eq!(PostTarget::BloomA.size(1920, 1080), (960, 540));
+        assert_eq!(PostTarget::Ldr.size(1920, 1080), (1920, 1080));
+        assert_eq!(PostTarget::BloomB.size(1, 1), (1, 1));
+        let constants = PostConstants::new(&PostSettings::default().passes()[1], 1920, 1080);
+        assert_eq!(constants.texel_size, [1.0 / 960.0, 1.0 / 540.0]);
+    }
+}
@@ -0,0 +1,364 @@
+use std::collections::HashMap;
+
+use windows::{
+    core::*, Win32::Foundation::*, Win32::Graphics::Direct3D::*,
+    Win32::Graphics::Direct3D12::*, Win32::Graphics::Dxgi::Common::*,
+};
+
+use crate::frame_graph::{target_format, FrameGraph, ImportedResources, INTERMEDIATE_TARGETS};
+use crate::post::{
+    validate_chain, PostConstants, PostPass, PostShader, PostTarget, POST_INPUT_COUNT,
+};
+use crate::markers::{set_name, Event};
+use crate::{convert_to_bytecode, serialize_root_signature, SCENE_FORMAT};
+
+fn target_slot(target: PostTarget) -> usize {
+    INTERMEDIATE_TARGETS
+        .iter()
+        .position(|&intermediate| intermediate == target)
+        .expect("the back buffer has no slot")
+}
+
+struct CompiledPass {
+    pass: PostPass,
+    pso: ID3D12PipelineState,
+    srv_table: D3D12_GPU_DESCRIPTOR_HANDLE,
+}
+
+// Runs a chain of post.rs passes from the HDR scene target into the back
+// buffer with shaders/post.hlsl. The targets and the barriers between the
+// passes come from the frame graph the chain was built into.
+pub struct PostProcess {
+    root_signature: ID3D12RootSignature,
+    // By shader and whether the output is HDR, which decides the format.
+    psos: HashMap<(PostShader, bool), ID3D12PipelineState>,
+    rtv_heap: Option<ID3D12DescriptorHeap>,
+    rtv_descriptor_size: usize,
+    srv_heap: Option<ID3D12DescriptorHeap>,
+    passes: Vec<CompiledPass>,
+    width: u32,
+    height: u32,
+}
+
+impl PostProcess {
+    pub fn new(
+        device: &ID3D12Device,
+        width: u32,
+        height: u32,
+        passes: &[PostPass],
+        graph: &FrameGraph,
+    ) -> Result<Self> {
+        let root_signature = create_post_root_signature(device)?;
+        set_name(&root_signature, "post root signature");
+        let mut post_process = PostProcess {
+            root_signature,
+            psos: HashMap::new(),
+            rtv_heap: None,
+            rtv_descriptor_size: unsafe {
+                device.GetDescriptorHandleIncrementSize(D3D12_DESCRIPTOR_HEAP_TYPE_RTV)
+            } as usize,
+            srv_heap: None,
+            passes: Vec::new(),
+            width,
+            height,
+        };
+        post_process.set_chain(device, passes, graph)?;
+        Ok(post_process)
+    }
+
+    // Replaces the chain, `graph` has to be built from the same passes.
+    // Only valid while the GPU is not running the previous one, which holds
+    // between frames.
+    pub fn set_chain(&mut self, device: &ID3D12Device, passes: &[PostPass], graph: &FrameGraph) -> Result<()> {
+        validate_chain(passes).map_err(|message| Error::new(E_INVALIDARG, message.into()))?;
+
+        let mut psos = Vec::with_capacity(passes.len());
+        for pass in passes {
+            let key = (pass.shader, pass.output.is_hdr());
+            let pso = match self.psos.get(&key) {
+                Some(pso) => pso.clone(),
+                None => {
+                    let pso = create_post_pipeline_state(
+                        device,
+                        &self.root_signature,
+                        pass.shader,
+                        target_format(pass.output),
+                    )?;
+                    set_name(&pso, &format!("post {:?} pso", pass.shader).to_lowercase());
+                    self.psos.insert(key, pso.clone());
+                    pso
+                }
+            };
+            psos.push(pso);
+        }
+
+        let rtv_heap: ID3D12DescriptorHeap = unsafe {
+            device.CreateDescriptorHeap(&D3D12_DESCRIPTOR_HEAP_DESC {
+                NumDescriptors: INTERMEDIATE_TARGETS.len() as u32,
+                Type: D3D12_DESCRIPTOR_HEAP_TYPE_RTV,
+                ..Default::default()
+            })
+        }?;
+        set_name(&rtv_heap, "post rtv heap");
+        let rtv_start = unsafe { rtv_heap.GetCPUDescriptorHandleForHeapStart() };
+        for (i, &target) in INTERMEDIATE_TARGETS.iter().enumerate() {
+            if let Some(resource) = graph.target(target) {
+                unsafe {
+                    device.CreateRenderTargetView(
+                        resource,
+                        None,
+                        D3D12_CPU_DESCRIPTOR_HANDLE {
+                            ptr: rtv_start.ptr + i * self.rtv_descriptor_size,
+                        },
+                    )
+                };
+            }
+        }
+
+        // One table of POST_INPUT_COUNT views per pass, inputs it does not
+        // use get null views that read as zero.
+        let srv_heap: ID3D12DescriptorHeap = unsafe {
+            device.CreateDescriptorHeap(&D3D12_DESCRIPTOR_HEAP_DESC {
+                NumDescriptors: (passes.len() * POST_INPUT_COUNT) as u32,
+                Type: D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV,
+                Flags: D3D12_DESCRIPTOR_HEAP_FLAG_SHADER_VISIBLE,
+                ..Default::default()
+            })
+        }?;
+        set_name(&srv_heap, "post srv heap");
+        let srv_descriptor_size = unsafe {
+            device.GetDescriptorHandleIncrementSize(D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV)
+        } as usize;
+        let cpu_start = unsafe { srv_heap.GetCPUDescriptorHandleForHeapStart() };
+        let gpu_start = unsafe { srv_heap.GetGPUDescriptorHandleForHeapStart() };
+
+        self.passes.clear();
+        for (i, (pass, pso)) in passes.iter().zip(psos).enumerate() {
+            for slot in 0..POST_INPUT_COUNT {
+                let input = pass.inputs.get(slot).copied();
+                let format = input.map_or(SCENE_FORMAT, target_format);
+                unsafe {
+                    device.CreateShaderResourceView(
+                        input.and_then(|input| graph.target(input)),
+                        Some(&D3D12_SHADER_RESOURCE_VIEW_DESC {
+                            Format: format,
+                            ViewDimension: D3D12_SRV_DIMENSION_TEXTURE2D,
+                            Shader4ComponentMapping: D3D12_DEFAULT_SHADER_4_COMPONENT_MAPPING,
+                            Anonymous: D3D12_SHADER_RESOURCE_VIEW_DESC_0 {
+                                Texture2D: D3D12_TEX2D_SRV {
+                                    MostDetailedMip: 0,
+                                    MipLevels: 1,
+                                    PlaneSlice: 0,
+                                    ResourceMinLODClamp: 0.0,
+                                },
+                            },
+                        }),
+                        D3D12_CPU_DESCRIPTOR_HANDLE {
+                            ptr: cpu_start.ptr + (i * POST_INPUT_COUNT + slot) * srv_descriptor_size,
+                        },
+                    )
+                };
+            }
+            self.passes.push(CompiledPass {
+                pass: pass.clone(),
+                pso,
+                srv_table: D3D12_GPU_DESCRIPTOR_HANDLE {
+                    ptr: gpu_start.ptr + (i * POST_INPUT_COUNT * srv_descriptor_size) as u64,
+                },
+            });
+        }
+        self.srv_heap = Some(srv_heap);
+        self.rtv_heap = Some(rtv_heap);
+        Ok(())
+    }
+
+    fn rtv(&self, target: PostTarget) -> D3D12_CPU_DESCRIPTOR_HANDLE {
+        let rtv_heap = self.rtv_heap.as_ref().expect("set_chain creates the views");
+        D3D12_CPU_DESCRIPTOR_HANDLE {
+            ptr: unsafe { rtv_heap.GetCPUDescriptorHandleForHeapStart() }.ptr
+                + target_slot(target) * self.rtv_descriptor_size,
+        }
+    }
+
+    // Runs the chain after the main pass, leaving the back buffer in
+    // RENDER_TARGET for the graph to finish. Changes the root signature,
+    // descriptor heaps, render targets, viewport and pipeline state.
+    pub fn record(
+        &self,
+        command_list: &ID3D12GraphicsCommandList,
+        graph: &FrameGraph,
+        imported: &ImportedResources,
+        back_buffer_rtv: D3D12_CPU_DESCRIPTOR_HANDLE,
+    ) {
+        unsafe {
+            command_list.SetGraphicsRootSignature(&self.root_signature);
+            command_list.SetDescriptorHeaps(std::slice::from_ref(&self.srv_heap));
+            command_list.IASetPrimitiveTopology(D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST);
+        }
+
+        for (i, compiled) in self.passes.iter().enumerate() {
+            let pass = &compiled.pass;
+            let _event = Event::new(command_list, pass.name);
+            graph.begin_post_pass(command_list, i, imported);
+            let rtv = match pass.output {
+                PostTarget::BackBuffer => back_buffer_rtv,
+                target => self.rtv(target),
+            };
+            let (width, height) = pass.output.size(self.width, self.height);
+            let constants = PostConstants::new(pass, self.width, self.height);
+
+            unsafe {
+                command_list.SetPipelineState(&compiled.pso);
+                command_list.OMSetRenderTargets(1, Some(&rtv), false, None);
+                command_list.RSSetViewports(&[D3D12_VIEWPORT {
+                    TopLeftX: 0.0,
+                    TopLeftY: 0.0,
+                    Width: width as f32,
+                    Height: height as f32,
+                    MinDepth: D3D12_MIN_DEPTH,
+                    MaxDepth: D3D12_MAX_DEPTH,
+                }]);
+                command_list.RSSetScissorRects(&[RECT {
+                    left: 0,
+                    top: 0,
+                    right: width as i32,
+                    bottom: height as i32,
+                }]);
+                command_list.SetGraphicsRoot32BitConstants(
+                    0,
+                    (std::mem::size_of::<PostConstants>() / 4) as u32,
+                    &constants as *const PostConstants as *const _,
+                    0,
+                );
+                command_list.SetGraphicsRootDescriptorTable(1, compiled.srv_table);
+                command_list.DrawInstanced(3, 1, 0, 0);
+            }
+        }
+    }
+}
+
+fn read_shader(path: &str) -> Result<Vec<u8>> {
+    std::fs::read(path).map_err(|error| Error::new(E_FAIL, error.to_string().into()))
+}
+
+fn shader_path(shader: PostShader) -> &'static str {
+    match shader {
+        PostShader::BloomExtract => "resources/post_bloom_extract_ps.bin",
+        PostShader::Blur => "resources/post_blur_ps.bin",
+        PostShader::Tonemap => "resources/post_tonemap_ps.bin",
+        PostShader::Fxaa => "resources/post_fxaa_ps.bin",
+    }
+}
+
+// Post constants as root constants and a table of the input textures, read
+// through a bilinear clamping sampler.
+fn create_post_root_signature(device: &ID3D12Device) -> Result<ID3D12RootSignature> {
+    let input_range = D3D12_DESCRIPTOR_RANGE {
+        RangeType: D3D12_DESCRIPTOR_RANGE_TYPE_SRV,
+        NumDescriptors: POST_INPUT_COUNT as u32,
+        BaseShaderRegister: 0,
+        RegisterSpace: 0,
+        OffsetInDescriptorsFromTableStart: 0,
+    };
+
+    let parameters = [
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_32BIT_CONSTANTS,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Constants: D3D12_ROOT_CONSTANTS {
+                    ShaderRegister: 0,
+                    RegisterSpace: 0,
+                    Num32BitValues: (std::mem::size_of::<PostConstants>() / 4) as u32,
+                },
+            },
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+        },
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_DESCRIPTOR_TABLE,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                DescriptorTable: D3D12_ROOT_DESCRIPTOR_TABLE {
+                    NumDescriptorRanges: 1,
+                    pDescriptorRanges: &input_range,
+                },
+            },
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+        },
+    ];
+
+    let sampler = D3D12_STATIC_SAMPLER_DESC {
+        Filter: D3D12_FILTER_MIN_MAG_MIP_LINEAR,
+        AddressU: D3D12_TEXTURE_ADDRESS_MODE_CLAMP,
+        AddressV: D3D12_TEXTURE_ADDRESS_MODE_CLAMP,
+        AddressW: D3D12_TEXTURE_ADDRESS_MODE_CLAMP,
+        MipLODBias: 0.0,
+        MaxAnisotropy: 1,
+        ComparisonFunc: D3D12_COMPARISON_FUNC_NEVER,
+        BorderColor: D3D12_STATIC_BORDER_COLOR_TRANSPARENT_BLACK,
+        MinLOD: 0.0,
+        MaxLOD: 0.0,
+        ShaderRegister: 0,
+        RegisterSpace: 0,
+        ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+    };
+
+    let desc = D3D12_ROOT_SIGNATURE_DESC {
+        NumParameters: parameters.len() as u32,
+        pParameters: parameters.as_ptr(),
+        NumStaticSamplers: 1,
+        pStaticSamplers: &sampler,
+        Flags: D3D12_ROOT_SIGNATURE_FLAG_NONE,
+    };
+
+    serialize_root_signature(device, &desc)
+}
+
+// Full screen triangle without depth, writing `format`.
+fn create_post_pipeline_state(
+    device: &ID3D12Device,
+    root_signature: &ID3D12RootSignature,
+    shader: PostShader,
+    format: DXGI_FORMAT,
+) -> Result<ID3D12PipelineState> {
+    let vs_bin = read_shader("resources/post_vs.bin")?;
+    let ps_bin = read_shader(shader_path(shader))?;
+
+    let mut desc = D3D12_GRAPHICS_PIPELINE_STATE_DESC {
+        pRootSignature: unsafe { std::mem::transmute_copy(root_signature) },
+        VS: convert_to_bytecode(&vs_bin),
+        PS: convert_to_bytecode(&ps_bin),
+        RasterizerState: D3D12_RASTERIZER_DESC {
+            FillMode: D3D12_FILL_MODE_SOLID,
+            CullMode: D3D12_CULL_MODE_NONE,
+            ..Default::default()
+        },
+        BlendState: D3D12_BLEND_DESC {
+            RenderTarget: [D3D12_RENDER_TARGET_BLEND_DESC {
+                SrcBlend: D3D12_BLEND_ONE,
+                DestBlend: D3D12_BLEND_ZERO,
+                BlendOp: D3D12_BLEND_OP_ADD,
+                SrcBlendAlpha: D3D12_BLEND_ONE,
+                DestBlendAlpha: D3D12_BLEND_ZERO,
+                BlendOpAlpha: D3D12_BLEND_OP_ADD,
+                LogicOp: D3D12_LOGIC_OP_NOOP,
+                RenderTargetWriteMask: D3D12_COLOR_WRITE_ENABLE_ALL.0 as u8,
+                ..Default::default()
+            }; 8],
+            ..Default::default()
+        },
+        DepthStencilState: D3D12_DEPTH_STENCIL_DESC {
+            DepthEnable: false.into(),
+            ..Default::default()
+        },
+        SampleMask: u32::MAX,
+        PrimitiveTopologyType: D3D12_PRIMITIVE_TOPOLOGY_TYPE_TRIANGLE,
+        NumRenderTargets: 1,
+        SampleDesc: DXGI_SAMPLE_DESC {
+            Count: 1,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    desc.RTVFormats[0] = format;
+
+    unsafe { device.CreateGraphicsPipelineState(&desc) }
+}
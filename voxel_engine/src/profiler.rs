@@ -0,0 +1,514 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fmt::Write;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+// A timed span, nested by `depth` inside the spans recorded before it.
+// Times are nanoseconds since the profiler's origin.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Scope {
+    pub name: String,
+    pub depth: u32,
+    pub start: u64,
+    pub duration: u64,
+}
+
+static ORIGIN: OnceLock<Instant> = OnceLock::new();
+
+// Nanoseconds since the first call, the time base of every CPU scope.
+pub fn now_ns() -> u64 {
+    ORIGIN.get_or_init(Instant::now).elapsed().as_nanos() as u64
+}
+
+// Scopes of one frame on one timeline, in the order they were opened.
+#[derive(Debug, Default)]
+pub struct ScopeRecorder {
+    scopes: Vec<Scope>,
+    open: Vec<usize>,
+}
+
+impl ScopeRecorder {
+    pub fn begin(&mut self, name: &str, now: u64) {
+        self.open.push(self.scopes.len());
+        self.scopes.push(Scope {
+            name: name.to_string(),
+            depth: self.open.len() as u32 - 1,
+            start: now,
+            duration: 0,
+        });
+    }
+
+    pub fn end(&mut self, now: u64) {
+        if let Some(i) = self.open.pop() {
+            let scope = &mut self.scopes[i];
+            scope.duration = now.saturating_sub(scope.start);
+        }
+    }
+
+    // The recorded scopes, scopes still open are ended at `now`.
+    pub fn take(&mut self, now: u64) -> Vec<Scope> {
+        while !self.open.is_empty() {
+            self.end(now);
+        }
+        std::mem::take(&mut self.scopes)
+    }
+}
+
+thread_local! {
+    static CPU_SCOPES: RefCell<ScopeRecorder> = RefCell::new(ScopeRecorder::default());
+}
+
+// Ends the CPU scope it was returned for when dropped.
+pub struct CpuScope {
+    _private: (),
+}
+
+impl Drop for CpuScope {
+    fn drop(&mut self) {
+        let now = now_ns();
+        CPU_SCOPES.with(|scopes| scopes.borrow_mut().end(now));
+    }
+}
+
+// Times the rest of the enclosing block on this thread:
+// `let _scope = profile_scope("update world");`
+pub fn profile_scope(name: &str) -> CpuScope {
+    let now = now_ns();
+    CPU_SCOPES.with(|scopes| scopes.borrow_mut().begin(name, now));
+    CpuScope { _private: () }
+}
+
+// The CPU scopes this thread recorded since the last call.
+pub fn take_cpu_scopes() -> Vec<Scope> {
+    let now = now_ns();
+    CPU_SCOPES.with(|scopes| scopes.borrow_mut().take(now))
+}
+
+// Pairs of timestamp queries around GPU scopes: scope i is timed by
+// queries 2i and 2i + 1 of the frame.
+#[derive(Debug)]
+pub struct QueryScopes {
+    // Name and depth of every scope.
+    scopes: Vec<(String, u32)>,
+    open: Vec<usize>,
+    max_scopes: usize,
+}
+
+impl QueryScopes {
+    pub fn new(max_scopes: usize) -> Self {
+        QueryScopes {
+            scopes: Vec::new(),
+            open: Vec::new(),
+            max_scopes,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.scopes.clear();
+        self.open.clear();
+    }
+
+    // The query to end at the scope's start, None once the frame has as
+    // many scopes as there are queries; its end query is skipped then too.
+    pub fn begin(&mut self, name: &str) -> Option<u32> {
+        if self.scopes.len() == self.max_scopes {
+            self.open.push(usize::MAX);
+            return None;
+        }
+        let i = self.scopes.len();
+        self.scopes.push((name.to_string(), self.open.len() as u32));
+        self.open.push(i);
+        Some(2 * i as u32)
+    }
+
+    // The query to end at the scope's end.
+    pub fn end(&mut self) -> Option<u32> {
+        match self.open.pop() {
+            Some(i) if i != usize::MAX => Some(2 * i as u32 + 1),
+            _ => None,
+        }
+    }
+
+    // Queries written this frame, all of them have to be resolved.
+    pub fn query_count(&self) -> u32 {
+        2 * self.scopes.len() as u32
+    }
+
+    // Converts the resolved timestamps, ticks at `frequency` per second,
+    // into scopes. The GPU clock is not the CPU's, so the frame's first
+    // timestamp is placed at `start`.
+    pub fn resolve(&self, timestamps: &[u64], frequency: u64, start: u64) -> Vec<Scope> {
+        let first = match timestamps.iter().min() {
+            Some(&first) if frequency > 0 => first,
+            _ => return Vec::new(),
+        };
+        let to_ns = |ticks: u64| (ticks as u128 * 1_000_000_000 / frequency as u128) as u64;
+        self.scopes
+            .iter()
+            .enumerate()
+            .filter_map(|(i, (name, depth))| {
+                let begin = *timestamps.get(2 * i)?;
+                let end = *timestamps.get(2 * i + 1)?;
+                Some(Scope {
+                    name: name.clone(),
+                    depth: *depth,
+                    start: start + to_ns(begin.saturating_sub(first)),
+                    duration: to_ns(end.saturating_sub(begin)),
+                })
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FrameProfile {
+    pub frame: u64,
+    pub cpu: Vec<Scope>,
+    // Arrives a few frames after the CPU scopes, see GpuProfiler.
+    pub gpu: Vec<Scope>,
+}
+
+// Scopes of the same name under the same parent, summed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProfileNode {
+    pub name: String,
+    pub total: u64,
+    // Longest single scope, where spikes show that the average hides.
+    pub max: u64,
+    pub calls: u32,
+    pub children: Vec<ProfileNode>,
+}
+
+// Merges `scopes` into `nodes` by name and nesting.
+pub fn merge_scopes(nodes: &mut Vec<ProfileNode>, scopes: &[Scope]) {
+    let mut path: Vec<usize> = Vec::new();
+    for scope in scopes {
+        path.truncate(scope.depth as usize);
+        let mut siblings = &mut *nodes;
+        for &i in &path {
+            siblings = &mut siblings[i].children;
+        }
+        let i = match siblings.iter().position(|node| node.name == scope.name) {
+            Some(i) => i,
+            None => {
+                siblings.push(ProfileNode {
+                    name: scope.name.clone(),
+                    total: 0,
+                    max: 0,
+                    calls: 0,
+                    children: Vec::new(),
+                });
+                siblings.len() - 1
+            }
+        };
+        siblings[i].total += scope.duration;
+        siblings[i].max = siblings[i].max.max(scope.duration);
+        siblings[i].calls += 1;
+        path.push(i);
+    }
+}
+
+// The profile of the last frames, averaged per frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProfileReport {
+    pub cpu_frames: u32,
+    pub gpu_frames: u32,
+    pub cpu: Vec<ProfileNode>,
+    pub gpu: Vec<ProfileNode>,
+}
+
+impl ProfileReport {
+    // Average time per frame of the top level scopes, in milliseconds.
+    pub fn cpu_frame_ms(&self) -> f64 {
+        average_ms(&self.cpu, self.cpu_frames)
+    }
+
+    pub fn gpu_frame_ms(&self) -> f64 {
+        average_ms(&self.gpu, self.gpu_frames)
+    }
+}
+
+fn average_ms(nodes: &[ProfileNode], frames: u32) -> f64 {
+    if frames == 0 {
+        return 0.0;
+    }
+    nodes.iter().map(|node| node.total).sum::<u64>() as f64 / frames as f64 / 1e6
+}
+
+fn write_nodes(f: &mut fmt::Formatter, nodes: &[ProfileNode], frames: u32, indent: usize) -> fmt::Result {
+    for node in nodes {
+        write!(
+            f,
+            "\n{:indent$}{} {:.3} ms, max {:.3} ms",
+            "",
+            node.name,
+            node.total as f64 / frames as f64 / 1e6,
+            node.max as f64 / 1e6,
+            indent = indent
+        )?;
+        if node.calls > frames {
+            write!(f, " ({:.1} calls)", node.calls as f64 / frames as f64)?;
+        }
+        write_nodes(f, &node.children, frames, indent + 2)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for ProfileReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cpu, average of {} frames:", self.cpu_frames)?;
+        write_nodes(f, &self.cpu, self.cpu_frames.max(1), 2)?;
+        write!(f, "\ngpu, average of {} frames:", self.gpu_frames)?;
+        write_nodes(f, &self.gpu, self.gpu_frames.max(1), 2)
+    }
+}
+
+// The last `capacity` frames of scopes.
+#[derive(Debug)]
+pub struct ProfileHistory {
+    frames: VecDeque<FrameProfile>,
+    capacity: usize,
+}
+
+impl ProfileHistory {
+    pub fn new(capacity: usize) -> Self {
+        ProfileHistory {
+            frames: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn add_cpu(&mut self, frame: u64, scopes: Vec<Scope>) {
+        if self.frames.len() == self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(FrameProfile {
+            frame,
+            cpu: scopes,
+            gpu: Vec::new(),
+        });
+    }
+
+    // GPU scopes of a frame whose CPU scopes were added before. Dropped
+    // when that frame already left the history.
+    pub fn add_gpu(&mut self, frame: u64, scopes: Vec<Scope>) {
+        if let Some(profile) = self.frames.iter_mut().find(|profile| profile.frame == frame) {
+            profile.gpu = scopes;
+        }
+    }
+
+    pub fn frames(&self) -> impl Iterator<Item = &FrameProfile> {
+        self.frames.iter()
+    }
+
+    // Averages the last `count` frames. GPU times cover the frames among
+    // them whose timestamps were read back.
+    pub fn report(&self, count: usize) -> ProfileReport {
+        let mut report = ProfileReport {
+            cpu_frames: 0,
+            gpu_frames: 0,
+            cpu: Vec::new(),
+            gpu: Vec::new(),
+        };
+        for profile in self.frames.iter().rev().take(count) {
+            merge_scopes(&mut report.cpu, &profile.cpu);
+            report.cpu_frames += 1;
+            if !profile.gpu.is_empty() {
+                merge_scopes(&mut report.gpu, &profile.gpu);
+                report.gpu_frames += 1;
+            }
+        }
+        report
+    }
+
+    // The frames in the Chrome trace event format, for chrome://tracing or
+    // Perfetto. CPU and GPU scopes are two threads of one process.
+    pub fn chrome_trace(&self) -> String {
+        let mut events = vec![
+            r#"{"name":"thread_name","ph":"M","pid":1,"tid":1,"args":{"name":"cpu"}}"#.to_string(),
+            r#"{"name":"thread_name","ph":"M","pid":1,"tid":2,"args":{"name":"gpu"}}"#.to_string(),
+        ];
+        for profile in &self.frames {
+            for (tid, scopes) in [(1, &profile.cpu), (2, &profile.gpu)] {
+                for scope in scopes {
+                    events.push(format!(
+                        r#"{{"name":"{}","cat":"{}","ph":"X","pid":1,"tid":{},"ts":{:.3},"dur":{:.3},"args":{{"frame":{}}}}}"#,
+                        escape_json(&scope.name),
+                        if tid == 1 { "cpu" } else { "gpu" },
+                        tid,
+                        scope.start as f64 / 1e3,
+                        scope.duration as f64 / 1e3,
+                        profile.frame
+                    ));
+                }
+            }
+        }
+        format!("{{\"traceEvents\":[\n{}\n]}}\n", events.join(",\n"))
+    }
+}
+
+fn escape_json(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: u64 = 1_000_000;
+
+    fn scope(name: &str, depth: u32, start: u64, duration: u64) -> Scope {
+        Scope {
+            name: name.to_string(),
+            depth,
+            start,
+            duration,
+        }
+    }
+
+    // A frame of `total` ms starting at `start` ms, with an update of
+    // `update` ms and two draws of 1 ms nested in it.
+    fn frame(start: u64, total: u64, update: u64) -> Vec<Scope> {
+        vec![
+            scope("frame", 0, start * MS, total * MS),
+            scope("update", 1, start * MS, update * MS),
+            scope("draw", 1, (start + update) * MS, MS),
+            scope("draw", 1, (start + update + 1) * MS, MS),
+        ]
+    }
+
+    #[test]
+    fn recorder_nests_and_closes_scopes() {
+        let mut recorder = ScopeRecorder::default();
+        recorder.begin("frame", 100);
+        recorder.begin("update", 110);
+        recorder.end(150);
+        recorder.begin("render", 150);
+        assert_eq!(
+            recorder.take(300),
+            [
+                scope("frame", 0, 100, 200),
+                scope("update", 1, 110, 40),
+                scope("render", 1, 150, 150),
+            ]
+        );
+        assert_eq!(recorder.take(400), []);
+    }
+
+    #[test]
+    fn query_pairs_resolve_into_scopes() {
+        let mut queries = QueryScopes::new(2);
+        assert_eq!(queries.begin("frame"), Some(0));
+        assert_eq!(queries.begin("shadows"), Some(2));
+        assert_eq!(queries.end(), Some(3));
+        // Past the capacity neither query is written.
+        assert_eq!(queries.begin("main"), None);
+        assert_eq!(queries.end(), None);
+        assert_eq!(queries.end(), Some(1));
+        assert_eq!(queries.query_count(), 4);
+
+        // A 1 MHz clock, one tick per microsecond.
+        let scopes = queries.resolve(&[5000, 9000, 5500, 7000], 1_000_000, 20 * MS);
+        assert_eq!(
+            scopes,
+            [
+                scope("frame", 0, 20 * MS, 4 * MS),
+                scope("shadows", 1, 20 * MS + 500_000, 1_500_000),
+            ]
+        );
+        assert_eq!(queries.resolve(&[], 1_000_000, 0), []);
+    }
+
+    #[test]
+    fn report_averages_the_last_frames() {
+        let mut history = ProfileHistory::new(8);
+        history.add_cpu(1, frame(0, 10, 4));
+        history.add_cpu(2, frame(10, 20, 6));
+        history.add_cpu(3, frame(30, 30, 14));
+
+        let report = history.report(8);
+        assert_eq!(report.cpu_frames, 3);
+        assert_eq!(report.cpu_frame_ms(), 20.0);
+        let frame_node = &report.cpu[0];
+        assert_eq!((frame_node.total, frame_node.max, frame_node.calls), (60 * MS, 30 * MS, 3));
+        let names: Vec<&str> = frame_node.children.iter().map(|node| node.name.as_str()).collect();
+        assert_eq!(names, ["update", "draw"]);
+        let draw = &frame_node.children[1];
+        assert_eq!((draw.total, draw.max, draw.calls), (6 * MS, MS, 6));
+
+        let last_two = history.report(2);
+        assert_eq!(last_two.cpu_frames, 2);
+        assert_eq!(last_two.cpu_frame_ms(), 25.0);
+        assert_eq!(last_two.cpu[0].children[0].max, 14 * MS);
+    }
+
+    #[test]
+    fn gpu_times_cover_the_frames_read_back() {
+        let mut history = ProfileHistory::new(2);
+        history.add_cpu(1, frame(0, 10, 4));
+        history.add_cpu(2, frame(10, 10, 4));
+        history.add_gpu(2, vec![scope("frame", 0, 12 * MS, 8 * MS)]);
+        history.add_cpu(3, frame(20, 10, 4));
+        // Frame 1 already left the history.
+        history.add_gpu(1, vec![scope("frame", 0, 2 * MS, 100 * MS)]);
+
+        let report = history.report(8);
+        assert_eq!((report.cpu_frames, report.gpu_frames), (2, 1));
+        assert_eq!(report.gpu_frame_ms(), 8.0);
+        assert_eq!(history.frames().map(|profile| profile.frame).collect::<Vec<_>>(), [2, 3]);
+        assert_eq!(ProfileHistory::new(4).report(4).gpu_frame_ms(), 0.0);
+    }
+
+    #[test]
+    fn report_text_shows_averages_maxima_and_calls() {
+        let mut history = ProfileHistory::new(4);
+        history.add_cpu(1, frame(0, 10, 4));
+        history.add_cpu(2, frame(10, 20, 6));
+        history.add_gpu(2, vec![scope("main", 0, 10 * MS, 3 * MS)]);
+        assert_eq!(
+            history.report(4).to_string(),
+            "cpu, average of 2 frames:\n  \
+             frame 15.000 ms, max 20.000 ms\n    \
+             update 5.000 ms, max 6.000 ms\n    \
+             draw 2.000 ms, max 1.000 ms (2.0 calls)\n\
+             gpu, average of 1 frames:\n  \
+             main 3.000 ms, max 3.000 ms"
+        );
+    }
+
+    #[test]
+    fn chrome_trace_lists_every_scope() {
+        let mut history = ProfileHistory::new(4);
+        history.add_cpu(7, vec![scope("frame", 0, 1000, 2500), scope("say \"hi\"\n", 1, 1500, 500)]);
+        history.add_gpu(7, vec![scope("main", 0, 2000, 1000)]);
+        assert_eq!(
+            history.chrome_trace(),
+            concat!(
+                "{\"traceEvents\":[\n",
+                r#"{"name":"thread_name","ph":"M","pid":1,"tid":1,"args":{"name":"cpu"}},"#,
+                "\n",
+                r#"{"name":"thread_name","ph":"M","pid":1,"tid":2,"args":{"name":"gpu"}},"#,
+                "\n",
+                r#"{"name":"frame","cat":"cpu","ph":"X","pid":1,"tid":1,"ts":1.000,"dur":2.500,"args":{"frame":7}},"#,
+                "\n",
+                r#"{"name":"say \"hi\"\u000a","cat":"cpu","ph":"X","pid":1,"tid":1,"ts":1.500,"dur":0.500,"args":{"frame":7}},"#,
+                "\n",
+                r#"{"name":"main","cat":"gpu","ph":"X","pid":1,"tid":2,"ts":2.000,"dur":1.000,"args":{"frame":7}}"#,
+                "\n]}\n",
+            )
+        );
+    }
+}
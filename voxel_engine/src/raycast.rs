@@ -0,0 +1,185 @@
+use crate::math::Vec3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RaycastHit {
+    // World block coordinates of the solid block that was hit.
+    pub block: [i32; 3],
+    // Outward normal of the face the ray entered through, zero when the ray
+    // starts inside the block.
+    pub normal: [i32; 3],
+    // Distance from the ray origin to the entry point, in blocks.
+    pub distance: f32,
+}
+
+impl RaycastHit {
+    // The block a placement against the hit face goes into.
+    pub fn adjacent(&self) -> [i32; 3] {
+        [
+            self.block[0] + self.normal[0],
+            self.block[1] + self.normal[1],
+            self.block[2] + self.normal[2],
+        ]
+    }
+}
+
+// Walks the block grid along the ray one cell at a time (Amanatides & Woo)
+// and returns the first block `is_solid` accepts within max_distance.
+//
+// Cells are visited in the order the ray enters them, so crossing a chunk
+// border is no different from crossing any other cell boundary. Components
+// of `direction` that are zero never step, which keeps axis-aligned rays in
+// their row instead of dividing by zero.
+pub fn raycast(
+    origin: Vec3,
+    direction: Vec3,
+    max_distance: f32,
+    is_solid: impl Fn([i32; 3]) -> bool,
+) -> Option<RaycastHit> {
+    let direction = direction.normalize();
+    let origin = [origin.x, origin.y, origin.z];
+    let direction = [direction.x, direction.y, direction.z];
+
+    let mut block = [
+        origin[0].floor() as i32,
+        origin[1].floor() as i32,
+        origin[2].floor() as i32,
+    ];
+    let mut step = [0; 3];
+    // Distance at which the ray crosses the next boundary on each axis, and
+    // the distance between two boundaries on that axis.
+    let mut next = [f32::INFINITY; 3];
+    let mut delta = [f32::INFINITY; 3];
+    for axis in 0..3 {
+        if direction[axis] > 0.0 {
+            step[axis] = 1;
+            delta[axis] = 1.0 / direction[axis];
+            next[axis] = (block[axis] as f32 + 1.0 - origin[axis]) * delta[axis];
+        } else if direction[axis] < 0.0 {
+            step[axis] = -1;
+            delta[axis] = -1.0 / direction[axis];
+            next[axis] = (origin[axis] - block[axis] as f32) * delta[axis];
+        }
+    }
+
+    let mut normal = [0; 3];
+    let mut distance = 0.0;
+    loop {
+        if is_solid(block) {
+            return Some(RaycastHit {
+                block,
+                normal,
+                distance,
+            });
+        }
+
+        let axis = if next[0] < next[1] {
+            if next[0] < next[2] {
+                0
+            } else {
+                2
+            }
+        } else if next[1] < next[2] {
+            1
+        } else {
+            2
+        };
+        distance = next[axis];
+        if distance > max_distance {
+            return None;
+        }
+
+        block[axis] += step[axis];
+        next[axis] += delta[axis];
+        normal = [0; 3];
+        normal[axis] = -step[axis];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::STONE;
+    use crate::chunk::{Chunk, ChunkPos, CHUNK_SIZE};
+    use crate::world::World;
+
+    // Empty chunks around the origin with `blocks` set to stone.
+    fn world_with(blocks: &[[i32; 3]]) -> World {
+        let mut world = World::new();
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    world.insert_chunk(ChunkPos::new(x, y, z), Chunk::new());
+                }
+            }
+        }
+        for &block in blocks {
+            assert!(world.set_block(block, STONE));
+        }
+        world
+    }
+
+    fn cast(world: &World, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<RaycastHit> {
+        raycast(origin, direction, max_distance, |block| world.is_solid(block))
+    }
+
+    #[test]
+    fn rays_cross_chunk_borders() {
+        let border = CHUNK_SIZE as i32;
+        let world = world_with(&[[border, 5, 5], [-1, 5, 5]]);
+
+        let hit = cast(&world, Vec3::new(30.5, 5.5, 5.5), Vec3::new(1.0, 0.0, 0.0), 10.0).unwrap();
+        assert_eq!((hit.block, hit.normal), ([border, 5, 5], [-1, 0, 0]));
+        assert!((hit.distance - 1.5).abs() < 1e-5);
+
+        let hit = cast(&world, Vec3::new(1.5, 5.5, 5.5), Vec3::new(-1.0, 0.0, 0.0), 10.0).unwrap();
+        assert_eq!((hit.block, hit.normal), ([-1, 5, 5], [1, 0, 0]));
+        assert!((hit.distance - 1.5).abs() < 1e-5);
+        assert_eq!(hit.adjacent(), [0, 5, 5]);
+    }
+
+    #[test]
+    fn diagonal_rays_visit_cells_in_order() {
+        let border = CHUNK_SIZE as i32;
+        // Both on the ray's path, the nearer one across the border is hit.
+        let world = world_with(&[[border, 3, border], [border + 1, 3, border + 1]]);
+        let hit = cast(&world, Vec3::new(30.2, 3.5, 30.6), Vec3::new(1.0, 0.0, 1.0), 10.0).unwrap();
+        assert_eq!((hit.block, hit.normal), ([border, 3, border], [-1, 0, 0]));
+    }
+
+    #[test]
+    fn axis_aligned_rays_stay_in_their_row() {
+        // Solid blocks right next to the column must not be hit.
+        let world = world_with(&[[4, 0, 4], [5, 8, 4], [4, 8, 5], [3, 8, 3]]);
+        let hit = cast(&world, Vec3::new(4.5, 9.5, 4.5), Vec3::new(0.0, -1.0, 0.0), 20.0).unwrap();
+        assert_eq!((hit.block, hit.normal), ([4, 0, 4], [0, 1, 0]));
+        assert!((hit.distance - 8.5).abs() < 1e-5);
+
+        // Straight along z from a negative coordinate.
+        let world = world_with(&[[-3, -2, 7]]);
+        let hit = cast(&world, Vec3::new(-2.5, -1.5, -6.0), Vec3::new(0.0, 0.0, 1.0), 20.0).unwrap();
+        assert_eq!((hit.block, hit.normal), ([-3, -2, 7], [0, 0, -1]));
+        assert!((hit.distance - 13.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn a_ray_starting_in_a_solid_block_hits_it_at_once() {
+        let world = world_with(&[[2, 2, 2]]);
+        let hit = cast(&world, Vec3::new(2.5, 2.5, 2.5), Vec3::new(0.3, -0.4, 1.0), 10.0).unwrap();
+        assert_eq!(hit, RaycastHit { block: [2, 2, 2], normal: [0; 3], distance: 0.0 });
+    }
+
+    #[test]
+    fn the_first_step_can_hit() {
+        let world = world_with(&[[3, 2, 2]]);
+        let hit = cast(&world, Vec3::new(2.9, 2.5, 2.5), Vec3::new(1.0, 0.0, 0.0), 10.0).unwrap();
+        assert_eq!((hit.block, hit.normal), ([3, 2, 2], [-1, 0, 0]));
+        assert!((hit.distance - 0.1).abs() < 1e-5);
+    }
+
+    #[test]
+    fn blocks_beyond_the_distance_are_missed() {
+        let world = world_with(&[[10, 0, 0]]);
+        assert_eq!(cast(&world, Vec3::new(0.5, 0.5, 0.5), Vec3::new(1.0, 0.0, 0.0), 9.0), None);
+        assert!(cast(&world, Vec3::new(0.5, 0.5, 0.5), Vec3::new(1.0, 0.0, 0.0), 9.5).is_some());
+    }
+}
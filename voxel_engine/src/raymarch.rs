@@ -0,0 +1,233 @@
+#[cfg(test)]
+use crate::block::MISSING_COLOR;
+use crate::camera::Camera;
+#[cfg(test)]
+use crate::math::Vec3;
+#[cfg(test)]
+use crate::mesher::face_shade;
+use crate::svo::SparseVoxelOctree;
+
+// Root constants of the ray march pass, laid out like the
+// RayMarchConstants cbuffer in shaders/raymarch.hlsl.
+//
+// The camera axes are the ones of Camera::screen_axes.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RayMarchConstants {
+    pub camera_position: [f32; 3],
+    pub width: u32,
+    pub camera_forward: [f32; 3],
+    pub height: u32,
+    pub camera_right: [f32; 3],
+    pub max_distance: f32,
+    pub camera_up: [f32; 3],
+    pub root: u32,
+    pub octree_origin: [i32; 3],
+    pub octree_depth: u32,
+    pub sky_color: [f32; 4],
+    pub palette_count: u32,
+    pub padding: [u32; 3],
+}
+
+impl RayMarchConstants {
+    // Without an octree every ray misses. sky_color is the color of the
+    // rays that do.
+    pub fn new(
+        camera: &Camera,
+        octree: Option<&SparseVoxelOctree>,
+        palette_count: u32,
+        width: u32,
+        height: u32,
+        sky_color: [f32; 4],
+    ) -> Self {
+        let (forward, right, up) = camera.screen_axes(width as f32 / height as f32);
+
+        RayMarchConstants {
+            camera_position: [camera.position.x, camera.position.y, camera.position.z],
+            width,
+            camera_forward: [forward.x, forward.y, forward.z],
+            height,
+            camera_right: [right.x, right.y, right.z],
+            max_distance: camera.far,
+            camera_up: [up.x, up.y, up.z],
+            root: octree.map_or(0, |octree| octree.encoded_root()),
+            octree_origin: octree.map_or([0; 3], |octree| octree.origin()),
+            octree_depth: octree.map_or(0, |octree| octree.depth()),
+            sky_color,
+            palette_count,
+            padding: [0; 3],
+        }
+    }
+}
+
+// CPU reference of CSMain in raymarch.hlsl: same rays, same traversal,
+// same shading.
+#[cfg(test)]
+pub fn trace_pixel(
+    octree: &SparseVoxelOctree,
+    palette: &[[f32; 4]],
+    constants: &RayMarchConstants,
+    x: u32,
+    y: u32,
+) -> [f32; 4] {
+    let ndc_x = (x as f32 + 0.5) / constants.width as f32 * 2.0 - 1.0;
+    let ndc_y = 1.0 - (y as f32 + 0.5) / constants.height as f32 * 2.0;
+    let [fx, fy, fz] = constants.camera_forward;
+    let [rx, ry, rz] = constants.camera_right;
+    let [ux, uy, uz] = constants.camera_up;
+    let direction = Vec3::new(
+        fx + rx * ndc_x + ux * ndc_y,
+        fy + ry * ndc_x + uy * ndc_y,
+        fz + rz * ndc_x + uz * ndc_y,
+    );
+    let [px, py, pz] = constants.camera_position;
+    let origin = Vec3::new(px, py, pz);
+
+    match octree.raycast(origin, direction, constants.max_distance) {
+        Some(hit) => {
+            let block = octree.get(hit.block);
+            let color = palette
+                .get(block.0 as usize)
+                .filter(|_| (block.0 as u32) < constants.palette_count)
+                .copied()
+                .unwrap_or(MISSING_COLOR);
+            let shade = face_shade(hit.normal);
+            [color[0] * shade, color[1] * shade, color[2] * shade, color[3]]
+        }
+        None => constants.sky_color,
+    }
+}
+
+// The whole image as R8G8B8A8_UNORM rows, the format of the back buffer.
+#[cfg(test)]
+pub fn render(
+    octree: &SparseVoxelOctree,
+    palette: &[[f32; 4]],
+    constants: &RayMarchConstants,
+) -> Vec<[u8; 4]> {
+    let mut image = Vec::with_capacity((constants.width * constants.height) as usize);
+    for y in 0..constants.height {
+        for x in 0..constants.width {
+            let color = trace_pixel(octree, palette, constants, x, y);
+            image.push(color.map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8));
+        }
+    }
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{DIRT, STONE};
+    use crate::chunk::{Chunk, ChunkPos};
+    use crate::world::World;
+    use std::f32::consts::FRAC_PI_2;
+    use std::mem::{offset_of, size_of};
+
+    const SKY: [f32; 4] = [0.5, 0.7, 1.0, 1.0];
+    const PALETTE: [[f32; 4]; 3] = [[0.0; 4], [0.5, 0.5, 0.5, 1.0], [0.4, 0.3, 0.2, 1.0]];
+
+    // A stone wall across x and y 8 to 24 at z 12 with a dirt block in front
+    // of it, seen head on from z 0.5 by a 9 by 9 pixel camera a little off
+    // the middle of the wall.
+    fn scene(palette_count: u32) -> (SparseVoxelOctree, RayMarchConstants) {
+        let mut chunk = Chunk::new();
+        for y in 8..24 {
+            for x in 8..24 {
+                chunk.set(x, y, 12, STONE);
+            }
+        }
+        chunk.set(16, 16, 11, DIRT);
+        let mut world = World::new();
+        world.insert_chunk(ChunkPos::new(0, 0, 0), chunk);
+        let octree = SparseVoxelOctree::from_world(&world).unwrap();
+        let constants = RayMarchConstants::new(&camera(), Some(&octree), palette_count, 9, 9, SKY);
+        (octree, constants)
+    }
+
+    fn camera() -> Camera {
+        Camera {
+            position: Vec3::new(16.5, 16.5, 0.5),
+            yaw: 0.0,
+            pitch: 0.0,
+            fov_y: FRAC_PI_2,
+            near: 0.1,
+            far: 100.0,
+        }
+    }
+
+    fn shaded(color: [f32; 4], normal: [i32; 3]) -> [f32; 4] {
+        let shade = face_shade(normal);
+        [color[0] * shade, color[1] * shade, color[2] * shade, color[3]]
+    }
+
+    #[test]
+    fn constants_match_the_shader_layout() {
+        assert_eq!(size_of::<RayMarchConstants>(), 112);
+        assert_eq!(offset_of!(RayMarchConstants, width), 12);
+        assert_eq!(offset_of!(RayMarchConstants, root), 60);
+        assert_eq!(offset_of!(RayMarchConstants, octree_origin), 64);
+        assert_eq!(offset_of!(RayMarchConstants, sky_color), 80);
+        assert_eq!(offset_of!(RayMarchConstants, palette_count), 96);
+    }
+
+    #[test]
+    fn traces_a_known_scene() {
+        let (octree, constants) = scene(3);
+        let front = [0, 0, -1];
+        assert_eq!(trace_pixel(&octree, &PALETTE, &constants, 4, 4), shaded(PALETTE[2], front));
+        assert_eq!(trace_pixel(&octree, &PALETTE, &constants, 1, 4), shaded(PALETTE[1], front));
+        assert_eq!(trace_pixel(&octree, &PALETTE, &constants, 4, 7), shaded(PALETTE[1], front));
+        assert_eq!(trace_pixel(&octree, &PALETTE, &constants, 0, 0), SKY);
+        assert_eq!(trace_pixel(&octree, &PALETTE, &constants, 8, 4), SKY);
+    }
+
+    #[test]
+    fn renders_the_whole_image() {
+        let (octree, constants) = scene(3);
+        let image = render(&octree, &PALETTE, &constants);
+        let sky = [128, 179, 255, 255];
+        let wall = shaded(PALETTE[1], [0, 0, -1]).map(|channel| (channel * 255.0).round() as u8);
+        let rows: Vec<String> = image
+            .chunks(9)
+            .map(|row| {
+                row.iter()
+                    .map(|pixel| match *pixel {
+                        pixel if pixel == sky => '.',
+                        pixel if pixel == wall => '#',
+                        _ => 'o',
+                    })
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                ".........",
+                ".........",
+                ".######..",
+                ".######..",
+                ".###o##..",
+                ".######..",
+                ".######..",
+                ".######..",
+                ".........",
+            ]
+        );
+    }
+
+    #[test]
+    fn blocks_past_the_palette_are_missing() {
+        let (octree, constants) = scene(2);
+        assert_eq!(trace_pixel(&octree, &PALETTE, &constants, 4, 4), shaded(MISSING_COLOR, [0, 0, -1]));
+        assert_eq!(trace_pixel(&octree, &PALETTE, &constants, 1, 4), shaded(PALETTE[1], [0, 0, -1]));
+    }
+
+    #[test]
+    fn constants_without_an_octree() {
+        let constants = RayMarchConstants::new(&camera(), None, 3, 9, 9, SKY);
+        assert_eq!(constants.root, 0);
+        assert_eq!(constants.octree_depth, 0);
+        assert_eq!(constants.camera_forward, [0.0, 0.0, 1.0]);
+    }
+}
@@ -0,0 +1,555 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+
+use crate::block::BlockId;
+use crate::chunk::{Chunk, ChunkPos, CHUNK_VOLUME};
+
+// Regions group REGION_SIZE^3 chunks into one file.
+pub const REGION_SIZE: i32 = 8;
+const REGION_VOLUME: usize = (REGION_SIZE * REGION_SIZE * REGION_SIZE) as usize;
+
+const MAGIC: [u8; 4] = *b"VXRG";
+
+// Version of the region layout and chunk encoding written by this build.
+// Older versions stay readable through decode_entry, see there.
+pub const FORMAT_VERSION: u16 = 1;
+
+// Magic, format version, reserved, then an (offset, length) pair per chunk.
+const HEADER_SIZE: usize = 8 + 8 * REGION_VOLUME;
+
+const COMPRESSION_NONE: u8 = 0;
+const COMPRESSION_LZ4: u8 = 1;
+
+// Position of a region in the region grid, in regions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RegionPos {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+}
+
+impl RegionPos {
+    pub fn from_chunk(pos: ChunkPos) -> Self {
+        RegionPos {
+            x: pos.x.div_euclid(REGION_SIZE),
+            y: pos.y.div_euclid(REGION_SIZE),
+            z: pos.z.div_euclid(REGION_SIZE),
+        }
+    }
+
+    pub fn file_name(&self) -> String {
+        format!("r.{}.{}.{}.region", self.x, self.y, self.z)
+    }
+}
+
+// Slot of a chunk in the offset table of its region.
+fn entry_index(pos: ChunkPos) -> usize {
+    let x = pos.x.rem_euclid(REGION_SIZE);
+    let y = pos.y.rem_euclid(REGION_SIZE);
+    let z = pos.z.rem_euclid(REGION_SIZE);
+    (x + z * REGION_SIZE + y * REGION_SIZE * REGION_SIZE) as usize
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+// Palette encoding: the distinct blocks of the chunk, then one index into
+// that list per block, packed into 64-bit words without straddling word
+// boundaries. A chunk of a single block type needs no indices at all.
+//
+//   u16 palette length, u16 per palette entry,
+//   u8 bits per index, u64 words
+pub fn encode_chunk(chunk: &Chunk) -> Vec<u8> {
+    let mut palette: Vec<BlockId> = Vec::new();
+    let mut lookup: BTreeMap<BlockId, u64> = BTreeMap::new();
+    let indices: Vec<u64> = chunk
+        .blocks()
+        .iter()
+        .map(|block| {
+            *lookup.entry(*block).or_insert_with(|| {
+                palette.push(*block);
+                palette.len() as u64 - 1
+            })
+        })
+        .collect();
+
+    let bits = bits_per_index(palette.len());
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&(palette.len() as u16).to_le_bytes());
+    for block in &palette {
+        bytes.extend_from_slice(&block.0.to_le_bytes());
+    }
+    bytes.push(bits as u8);
+    // Zero bits, and no words, for a single entry palette.
+    if let Some(per_word) = 64_usize.checked_div(bits) {
+        for group in indices.chunks(per_word) {
+            let word = group
+                .iter()
+                .enumerate()
+                .fold(0_u64, |word, (i, index)| word | index << (i * bits));
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+    }
+    bytes
+}
+
+pub fn decode_chunk(bytes: &[u8]) -> io::Result<Chunk> {
+    let mut reader = ByteReader { bytes };
+    let palette_len = reader.u16()? as usize;
+    if palette_len == 0 {
+        return Err(invalid_data("empty chunk palette"));
+    }
+    let palette = (0..palette_len)
+        .map(|_| reader.u16().map(BlockId))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let bits = reader.u8()? as usize;
+    if bits != bits_per_index(palette_len) {
+        return Err(invalid_data(format!(
+            "{} bits per index for a palette of {}",
+            bits, palette_len
+        )));
+    }
+
+    let mut blocks = Vec::with_capacity(CHUNK_VOLUME);
+    if let Some(per_word) = 64_usize.checked_div(bits) {
+        let mask = (1_u64 << bits) - 1;
+        while blocks.len() < CHUNK_VOLUME {
+            let word = reader.u64()?;
+            for i in 0..per_word.min(CHUNK_VOLUME - blocks.len()) {
+                let index = ((word >> (i * bits)) & mask) as usize;
+                let block = *palette
+                    .get(index)
+                    .ok_or_else(|| invalid_data("palette index out of range"))?;
+                blocks.push(block);
+            }
+        }
+    } else {
+        blocks.resize(CHUNK_VOLUME, palette[0]);
+    }
+
+    if !reader.bytes.is_empty() {
+        return Err(invalid_data("trailing bytes after chunk data"));
+    }
+    Ok(Chunk::from_blocks(blocks))
+}
+
+fn bits_per_index(palette_len: usize) -> usize {
+    (usize::BITS - (palette_len.max(1) - 1).leading_zeros()) as usize
+}
+
+// A stored chunk: compression, CRC-32 of the stored payload, payload. Data
+// LZ4 does not shrink is stored as is.
+fn compress(data: &[u8]) -> Vec<u8> {
+    let compressed = lz4_flex::compress_prepend_size(data);
+    let (compression, payload) = if compressed.len() < data.len() {
+        (COMPRESSION_LZ4, compressed)
+    } else {
+        (COMPRESSION_NONE, data.to_vec())
+    };
+    let mut entry = Vec::with_capacity(5 + payload.len());
+    entry.push(compression);
+    entry.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+    entry.extend_from_slice(&payload);
+    entry
+}
+
+fn decompress(entry: &[u8]) -> io::Result<Vec<u8>> {
+    if entry.len() < 5 {
+        return Err(invalid_data("truncated chunk entry"));
+    }
+    let checksum = u32::from_le_bytes(entry[1..5].try_into().unwrap());
+    let payload = &entry[5..];
+    if crc32fast::hash(payload) != checksum {
+        return Err(invalid_data("chunk checksum mismatch"));
+    }
+    match entry[0] {
+        COMPRESSION_NONE => Ok(payload.to_vec()),
+        COMPRESSION_LZ4 => lz4_flex::decompress_size_prepended(payload)
+            .map_err(|error| invalid_data(error.to_string())),
+        compression => Err(invalid_data(format!("unknown compression {}", compression))),
+    }
+}
+
+// Decodes a chunk entry written with any format version this build knows.
+// When the format changes, bump FORMAT_VERSION and keep the previous
+// decoder here: regions are rewritten in the current version the next time
+// a chunk is saved into them, see RegionStorage::save.
+fn decode_entry(version: u16, entry: &[u8]) -> io::Result<Chunk> {
+    match version {
+        1 => decode_chunk(&decompress(entry)?),
+        _ => Err(invalid_data(format!(
+            "unsupported region format version {}",
+            version
+        ))),
+    }
+}
+
+// In-memory image of a region file.
+struct Region {
+    version: u16,
+    entries: Vec<Option<Vec<u8>>>,
+}
+
+impl Region {
+    fn new() -> Self {
+        Region {
+            version: FORMAT_VERSION,
+            entries: vec![None; REGION_VOLUME],
+        }
+    }
+
+    fn parse(bytes: &[u8]) -> io::Result<Self> {
+        let table = read_header(bytes)?;
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        let entries = table
+            .iter()
+            .map(|&(offset, length)| {
+                if length == 0 {
+                    return Ok(None);
+                }
+                bytes
+                    .get(offset..offset + length)
+                    .map(|entry| Some(entry.to_vec()))
+                    .ok_or_else(|| invalid_data("chunk entry past the end of the region"))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(Region { version, entries })
+    }
+
+    // Brings every entry to FORMAT_VERSION.
+    fn migrate(&mut self) -> io::Result<()> {
+        if self.version == FORMAT_VERSION {
+            return Ok(());
+        }
+        for entry in self.entries.iter_mut().flatten() {
+            *entry = compress(&encode_chunk(&decode_entry(self.version, entry)?));
+        }
+        self.version = FORMAT_VERSION;
+        Ok(())
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE);
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&self.version.to_le_bytes());
+        bytes.extend_from_slice(&[0, 0]);
+        let mut offset = HEADER_SIZE;
+        for entry in &self.entries {
+            let length = entry.as_ref().map_or(0, |entry| entry.len());
+            let start = if length == 0 { 0 } else { offset };
+            bytes.extend_from_slice(&(start as u32).to_le_bytes());
+            bytes.extend_from_slice(&(length as u32).to_le_bytes());
+            offset += length;
+        }
+        for entry in self.entries.iter().flatten() {
+            bytes.extend_from_slice(entry);
+        }
+        bytes
+    }
+}
+
+// Checks magic and version and returns the offset table.
+fn read_header(bytes: &[u8]) -> io::Result<Vec<(usize, usize)>> {
+    if bytes.len() < HEADER_SIZE || bytes[0..4] != MAGIC {
+        return Err(invalid_data("not a region file"));
+    }
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version == 0 || version > FORMAT_VERSION {
+        return Err(invalid_data(format!(
+            "unsupported region format version {}",
+            version
+        )));
+    }
+    Ok(bytes[8..HEADER_SIZE]
+        .chunks_exact(8)
+        .map(|pair| {
+            let offset = u32::from_le_bytes(pair[0..4].try_into().unwrap()) as usize;
+            let length = u32::from_le_bytes(pair[4..8].try_into().unwrap()) as usize;
+            (offset, length)
+        })
+        .collect())
+}
+
+// A region RegionStorage::save could not write, with the chunks that were
+// meant to go into it.
+#[derive(Debug)]
+pub struct SaveFailure {
+    pub region: RegionPos,
+    pub chunks: Vec<ChunkPos>,
+    pub error: io::Error,
+}
+
+// Region files in one directory. Loading reads only the header and the one
+// chunk entry, saving rewrites the whole region through a temporary file so
+// a failed write never leaves a half written region behind.
+pub struct RegionStorage {
+    directory: PathBuf,
+}
+
+impl RegionStorage {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        RegionStorage {
+            directory: directory.into(),
+        }
+    }
+
+    fn path(&self, region: RegionPos) -> PathBuf {
+        self.directory.join(region.file_name())
+    }
+
+    // Ok(None) when the chunk was never saved.
+    pub fn load(&self, pos: ChunkPos) -> io::Result<Option<Chunk>> {
+        let mut file = match File::open(self.path(RegionPos::from_chunk(pos))) {
+            Ok(file) => file,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error),
+        };
+
+        let mut header = vec![0; HEADER_SIZE];
+        file.read_exact(&mut header)
+            .map_err(|_| invalid_data("truncated region header"))?;
+        let table = read_header(&header)?;
+        let version = u16::from_le_bytes([header[4], header[5]]);
+
+        let (offset, length) = table[entry_index(pos)];
+        if length == 0 {
+            return Ok(None);
+        }
+        let mut entry = vec![0; length];
+        file.seek(SeekFrom::Start(offset as u64))?;
+        file.read_exact(&mut entry)
+            .map_err(|_| invalid_data("chunk entry past the end of the region"))?;
+        decode_entry(version, &entry).map(Some)
+    }
+
+    // Writes the chunks region by region, so a region that cannot be
+    // written, say because its file is corrupt, does not keep the others
+    // from being saved. Returns the regions that failed.
+    pub fn save<'a>(&self, chunks: impl IntoIterator<Item = (ChunkPos, &'a Chunk)>) -> Vec<SaveFailure> {
+        let mut regions: BTreeMap<RegionPos, Vec<(ChunkPos, &Chunk)>> = BTreeMap::new();
+        for (pos, chunk) in chunks {
+            regions
+                .entry(RegionPos::from_chunk(pos))
+                .or_default()
+                .push((pos, chunk));
+        }
+        regions
+            .into_iter()
+            .filter_map(|(region, chunks)| {
+                self.save_region(region, &chunks).err().map(|error| SaveFailure {
+                    region,
+                    chunks: chunks.iter().map(|(pos, _)| *pos).collect(),
+                    error,
+                })
+            })
+            .collect()
+    }
+
+    fn save_region(&self, region_pos: RegionPos, chunks: &[(ChunkPos, &Chunk)]) -> io::Result<()> {
+        fs::create_dir_all(&self.directory)?;
+        let path = self.path(region_pos);
+        let mut region = match fs::read(&path) {
+            Ok(bytes) => Region::parse(&bytes)?,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Region::new(),
+            Err(error) => return Err(error),
+        };
+        region.migrate()?;
+        for (pos, chunk) in chunks {
+            region.entries[entry_index(*pos)] = Some(compress(&encode_chunk(chunk)));
+        }
+
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, region.serialize())?;
+        fs::rename(&temporary, &path)
+    }
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl ByteReader<'_> {
+    fn take<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        if self.bytes.len() < N {
+            return Err(invalid_data("truncated chunk data"));
+        }
+        let (head, rest) = self.bytes.split_at(N);
+        self.bytes = rest;
+        Ok(head.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        self.take().map(u16::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        self.take().map(u64::from_le_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{DIRT, GRASS, STONE};
+    use crate::chunk::CHUNK_SIZE;
+
+    // An empty directory of its own for each test.
+    fn directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("voxel_region_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    fn terrain(seed: u16) -> Chunk {
+        let mut chunk = Chunk::new();
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                let height = (x * 3 + z * 5 + seed as usize) % CHUNK_SIZE;
+                for y in 0..height {
+                    chunk.set(x, y, z, if y + 1 == height { GRASS } else { DIRT });
+                }
+                chunk.set(x, 0, z, BlockId(10 + (x as u16 + seed) % 7));
+            }
+        }
+        chunk
+    }
+
+    // Whole region file with a single chunk at the origin.
+    fn saved_region(name: &str) -> (RegionStorage, PathBuf) {
+        let directory = directory(name);
+        let storage = RegionStorage::new(&directory);
+        assert!(storage.save([(ChunkPos::new(0, 0, 0), &terrain(0))]).is_empty());
+        let path = directory.join(RegionPos::from_chunk(ChunkPos::new(0, 0, 0)).file_name());
+        (storage, path)
+    }
+
+    fn error_message(result: io::Result<Option<Chunk>>) -> String {
+        let error = result.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        error.to_string()
+    }
+
+    #[test]
+    fn chunks_survive_encoding() {
+        for chunk in [Chunk::new(), Chunk::filled(STONE), terrain(0), terrain(3)] {
+            assert_eq!(decode_chunk(&encode_chunk(&chunk)).unwrap(), chunk);
+            assert_eq!(decode_chunk(&decompress(&compress(&encode_chunk(&chunk))).unwrap()).unwrap(), chunk);
+        }
+        // A single block type is stored without indices.
+        assert_eq!(encode_chunk(&Chunk::filled(STONE)).len(), 5);
+    }
+
+    #[test]
+    fn saved_chunks_load_back() {
+        let storage = RegionStorage::new(directory("round_trip"));
+        let a = ChunkPos::new(0, 0, 0);
+        let b = ChunkPos::new(-1, 2, 9);
+        let c = ChunkPos::new(7, 0, 7);
+        assert!(storage.save([(a, &terrain(0)), (b, &terrain(1))]).is_empty());
+        assert_eq!(storage.load(a).unwrap(), Some(terrain(0)));
+        assert_eq!(storage.load(b).unwrap(), Some(terrain(1)));
+        assert_eq!(storage.load(c).unwrap(), None);
+
+        // Saving into an existing region keeps the chunks already in it.
+        assert!(storage.save([(c, &terrain(2)), (a, &Chunk::filled(STONE))]).is_empty());
+        assert_eq!(storage.load(a).unwrap(), Some(Chunk::filled(STONE)));
+        assert_eq!(storage.load(b).unwrap(), Some(terrain(1)));
+        assert_eq!(storage.load(c).unwrap(), Some(terrain(2)));
+    }
+
+    #[test]
+    fn nothing_saved_loads_nothing() {
+        let storage = RegionStorage::new(directory("empty"));
+        assert_eq!(storage.load(ChunkPos::new(3, 0, 3)).unwrap(), None);
+        assert!(storage.save([]).is_empty());
+    }
+
+    #[test]
+    fn checksum_mismatches_are_detected() {
+        let (storage, path) = saved_region("checksum");
+        let mut bytes = fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0x40;
+        fs::write(&path, bytes).unwrap();
+        assert_eq!(error_message(storage.load(ChunkPos::new(0, 0, 0))), "chunk checksum mismatch");
+    }
+
+    #[test]
+    fn truncated_entries_are_detected() {
+        let (storage, path) = saved_region("truncated");
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 10]).unwrap();
+        assert_eq!(
+            error_message(storage.load(ChunkPos::new(0, 0, 0))),
+            "chunk entry past the end of the region"
+        );
+        fs::write(&path, &bytes[..HEADER_SIZE / 2]).unwrap();
+        assert_eq!(error_message(storage.load(ChunkPos::new(0, 0, 0))), "truncated region header");
+
+        assert_eq!(decompress(&[COMPRESSION_NONE, 1, 2]).unwrap_err().to_string(), "truncated chunk entry");
+        let encoded = encode_chunk(&terrain(0));
+        assert_eq!(
+            decode_chunk(&encoded[..encoded.len() - 3]).unwrap_err().to_string(),
+            "truncated chunk data"
+        );
+    }
+
+    #[test]
+    fn unknown_versions_are_rejected() {
+        let (storage, path) = saved_region("version");
+        let mut bytes = fs::read(&path).unwrap();
+        for version in [0, FORMAT_VERSION + 1] {
+            bytes[4..6].copy_from_slice(&version.to_le_bytes());
+            fs::write(&path, &bytes).unwrap();
+            assert_eq!(
+                error_message(storage.load(ChunkPos::new(0, 0, 0))),
+                format!("unsupported region format version {}", version)
+            );
+        }
+    }
+
+    #[test]
+    fn migrate_rewrites_known_versions_only() {
+        let entry = compress(&encode_chunk(&terrain(0)));
+        let mut region = Region::new();
+        region.entries[0] = Some(entry.clone());
+        region.migrate().unwrap();
+        assert_eq!(region.entries[0].as_ref(), Some(&entry));
+        let parsed = Region::parse(&region.serialize()).unwrap();
+        assert_eq!((parsed.version, parsed.entries[0].as_ref()), (FORMAT_VERSION, Some(&entry)));
+
+        // A version without a decoder fails and leaves the region as it was.
+        let mut region = Region::new();
+        region.version = FORMAT_VERSION + 1;
+        region.entries[0] = Some(entry.clone());
+        let error = region.migrate().unwrap_err();
+        assert_eq!(error.to_string(), format!("unsupported region format version {}", FORMAT_VERSION + 1));
+        assert_eq!(region.version, FORMAT_VERSION + 1);
+    }
+
+    #[test]
+    fn failed_regions_report_their_chunks() {
+        let (storage, path) = saved_region("failure");
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[0] = b'X';
+        fs::write(&path, bytes).unwrap();
+
+        let broken = ChunkPos::new(1, 0, 0);
+        let elsewhere = ChunkPos::new(REGION_SIZE, 0, 0);
+        let failures = storage.save([(broken, &terrain(1)), (elsewhere, &terrain(2))]);
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].region, RegionPos { x: 0, y: 0, z: 0 });
+        assert_eq!(failures[0].chunks, [broken]);
+        assert_eq!(failures[0].error.to_string(), "not a region file");
+        // The other region was written regardless.
+        assert_eq!(storage.load(elsewhere).unwrap(), Some(terrain(2)));
+    }
+}
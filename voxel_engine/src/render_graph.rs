@@ -0,0 +1,782 @@
+use std::fmt;
+
+// Resource state bits with the values of D3D12_RESOURCE_STATES, so the
+// executor passes them on unchanged. Read states can be combined.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ResourceState(pub u32);
+
+impl ResourceState {
+    pub const COMMON: ResourceState = ResourceState(0);
+    pub const PRESENT: ResourceState = ResourceState(0);
+    pub const RENDER_TARGET: ResourceState = ResourceState(0x4);
+    pub const UNORDERED_ACCESS: ResourceState = ResourceState(0x8);
+    pub const DEPTH_WRITE: ResourceState = ResourceState(0x10);
+    pub const DEPTH_READ: ResourceState = ResourceState(0x20);
+    pub const NON_PIXEL_SHADER_RESOURCE: ResourceState = ResourceState(0x40);
+    pub const PIXEL_SHADER_RESOURCE: ResourceState = ResourceState(0x80);
+    pub const INDIRECT_ARGUMENT: ResourceState = ResourceState(0x200);
+    pub const COPY_DEST: ResourceState = ResourceState(0x400);
+    pub const COPY_SOURCE: ResourceState = ResourceState(0x800);
+    pub const RESOLVE_DEST: ResourceState = ResourceState(0x1000);
+    pub const RESOLVE_SOURCE: ResourceState = ResourceState(0x2000);
+
+    const WRITE_BITS: u32 = 0x4 | 0x8 | 0x10 | 0x400 | 0x1000;
+
+    const NAMES: [(ResourceState, &'static str); 12] = [
+        (ResourceState::RENDER_TARGET, "RENDER_TARGET"),
+        (ResourceState::UNORDERED_ACCESS, "UNORDERED_ACCESS"),
+        (ResourceState::DEPTH_WRITE, "DEPTH_WRITE"),
+        (ResourceState::DEPTH_READ, "DEPTH_READ"),
+        (ResourceState::NON_PIXEL_SHADER_RESOURCE, "NON_PIXEL_SHADER_RESOURCE"),
+        (ResourceState::PIXEL_SHADER_RESOURCE, "PIXEL_SHADER_RESOURCE"),
+        (ResourceState::INDIRECT_ARGUMENT, "INDIRECT_ARGUMENT"),
+        (ResourceState::COPY_DEST, "COPY_DEST"),
+        (ResourceState::COPY_SOURCE, "COPY_SOURCE"),
+        (ResourceState::RESOLVE_DEST, "RESOLVE_DEST"),
+        (ResourceState::RESOLVE_SOURCE, "RESOLVE_SOURCE"),
+        (ResourceState::COMMON, "COMMON"),
+    ];
+
+    pub fn is_write(self) -> bool {
+        self.0 & Self::WRITE_BITS != 0
+    }
+
+    fn can_discard(self) -> bool {
+        self == Self::RENDER_TARGET || self == Self::DEPTH_WRITE || self == Self::UNORDERED_ACCESS
+    }
+
+    // The state a transient resource first used in this state is created
+    // and discarded in. D3D12 does not discard copy or resolve destinations,
+    // those are discarded as render targets, the only kind the heap holds
+    // besides depth, and then transitioned.
+    fn discard_state(self) -> ResourceState {
+        if self.can_discard() {
+            self
+        } else {
+            Self::RENDER_TARGET
+        }
+    }
+
+    pub fn union(self, other: ResourceState) -> ResourceState {
+        ResourceState(self.0 | other.0)
+    }
+
+    // Whether a resource in this state can already be used as `other`
+    // without a barrier: same state, or read states that include it.
+    fn covers(self, other: ResourceState) -> bool {
+        self == other
+            || (!self.is_write() && !other.is_write() && other != Self::COMMON && self.0 & other.0 == other.0)
+    }
+}
+
+impl fmt::Display for ResourceState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if *self == ResourceState::COMMON {
+            return write!(f, "COMMON");
+        }
+        let names: Vec<&str> = Self::NAMES
+            .iter()
+            .filter(|(state, _)| state.0 != 0 && self.0 & state.0 == state.0)
+            .map(|(_, name)| *name)
+            .collect();
+        write!(f, "{}", names.join("|"))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ResourceId(pub usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PassId(pub usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResourceKind {
+    // Lives outside the graph, like the back buffer: enters every frame in
+    // `initial` and is left in `final_state`.
+    Imported {
+        initial: ResourceState,
+        final_state: ResourceState,
+    },
+    // Only lives while the graph runs and shares a heap with the other
+    // transient resources whose lifetimes do not overlap. Its content does
+    // not survive the frame, the first pass using it must write all of it.
+    Transient { size: u64, alignment: u64 },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct ResourceDecl {
+    name: String,
+    kind: ResourceKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct PassDecl {
+    name: String,
+    uses: Vec<(ResourceId, ResourceState)>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GraphError {
+    // A pass writes a resource and also uses it in another state.
+    ConflictingStates { pass: String, resource: String },
+    // A transient resource is read before any pass wrote it.
+    ReadBeforeWrite { pass: String, resource: String },
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GraphError::ConflictingStates { pass, resource } => {
+                write!(f, "{} writes {} and uses it in another state", pass, resource)
+            }
+            GraphError::ReadBeforeWrite { pass, resource } => {
+                write!(f, "{} reads {} before anything wrote it", pass, resource)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Barrier {
+    Transition {
+        resource: ResourceId,
+        before: ResourceState,
+        after: ResourceState,
+    },
+    // `after` takes over memory last used by `before`, None when that was
+    // some resource of the previous frame.
+    Aliasing {
+        before: Option<ResourceId>,
+        after: ResourceId,
+    },
+    // Orders unordered access of consecutive passes.
+    Uav { resource: ResourceId },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompiledPass {
+    pub pass: PassId,
+    // Recorded before the pass.
+    pub barriers: Vec<Barrier>,
+    // Transient resources the pass is the first to use this frame, to be
+    // discarded after `barriers` since aliasing left them undefined.
+    pub discards: Vec<ResourceId>,
+    // Recorded after the discards: moves resources discarded as render
+    // targets into the copy or resolve destination state the pass uses.
+    pub discard_barriers: Vec<Barrier>,
+}
+
+// Where a transient resource lives in the shared heap, and the state it is
+// created in, the one it is discarded in before its first pass.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Placement {
+    pub offset: u64,
+    pub size: u64,
+    pub initial_state: ResourceState,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompiledGraph {
+    pub passes: Vec<CompiledPass>,
+    // Passes nothing that leaves the graph depends on.
+    pub culled: Vec<PassId>,
+    // Recorded after the last pass: imported resources go to their final
+    // state and transient ones back to their initial state for next frame.
+    pub final_barriers: Vec<Barrier>,
+    // By resource, None for imported resources and unused transient ones.
+    pub placements: Vec<Option<Placement>>,
+    pub heap_size: u64,
+    resource_names: Vec<String>,
+    pass_names: Vec<String>,
+}
+
+// Passes declare the resources they use and the state they need them in.
+// Compiling drops the passes whose results are never used, derives every
+// barrier between the rest and packs the transient resources into as
+// little memory as their lifetimes allow.
+//
+// Passes run in the order they are added. A pass only depends on earlier
+// ones, it reads what the last earlier pass wrote, so that order always
+// respects the dependencies.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RenderGraph {
+    resources: Vec<ResourceDecl>,
+    passes: Vec<PassDecl>,
+}
+
+impl RenderGraph {
+    pub fn new() -> Self {
+        RenderGraph::default()
+    }
+
+    pub fn import(&mut self, name: &str, initial: ResourceState, final_state: ResourceState) -> ResourceId {
+        self.add_resource(name, ResourceKind::Imported { initial, final_state })
+    }
+
+    pub fn create_transient(&mut self, name: &str, size: u64, alignment: u64) -> ResourceId {
+        self.add_resource(name, ResourceKind::Transient { size, alignment })
+    }
+
+    fn add_resource(&mut self, name: &str, kind: ResourceKind) -> ResourceId {
+        self.resources.push(ResourceDecl {
+            name: name.into(),
+            kind,
+        });
+        ResourceId(self.resources.len() - 1)
+    }
+
+    // A pass using the resources in the given states. Reads of one resource
+    // in several states are combined.
+    pub fn add_pass(&mut self, name: &str, uses: &[(ResourceId, ResourceState)]) -> PassId {
+        self.passes.push(PassDecl {
+            name: name.into(),
+            uses: uses.to_vec(),
+        });
+        PassId(self.passes.len() - 1)
+    }
+
+    pub fn compile(&self) -> Result<CompiledGraph, GraphError> {
+        let uses = self.merged_uses()?;
+        let kept = self.kept_passes(&uses);
+        let order: Vec<usize> = (0..self.passes.len()).filter(|&pass| kept[pass]).collect();
+        let culled = (0..self.passes.len())
+            .filter(|&pass| !kept[pass])
+            .map(PassId)
+            .collect();
+
+        // Position in `order` of the first and last use of every resource.
+        let mut lifetimes: Vec<Option<(usize, usize)>> = vec![None; self.resources.len()];
+        for (position, &pass) in order.iter().enumerate() {
+            for &(resource, state) in &uses[pass] {
+                let lifetime = &mut lifetimes[resource.0];
+                match lifetime {
+                    Some((_, last)) => *last = position,
+                    None => {
+                        if self.is_transient(resource) && !state.is_write() {
+                            return Err(GraphError::ReadBeforeWrite {
+                                pass: self.passes[pass].name.clone(),
+                                resource: self.resources[resource.0].name.clone(),
+                            });
+                        }
+                        *lifetime = Some((position, position));
+                    }
+                }
+            }
+        }
+
+        let (placements, heap_size) = self.place_transients(&order, &uses, &lifetimes);
+
+        let mut states: Vec<ResourceState> = self
+            .resources
+            .iter()
+            .enumerate()
+            .map(|(i, resource)| match resource.kind {
+                ResourceKind::Imported { initial, .. } => initial,
+                ResourceKind::Transient { .. } => {
+                    placements[i].map_or(ResourceState::COMMON, |placement| placement.initial_state)
+                }
+            })
+            .collect();
+
+        let mut passes = Vec::with_capacity(order.len());
+        for (position, &pass) in order.iter().enumerate() {
+            let mut barriers = Vec::new();
+            let mut discards = Vec::new();
+            let mut discard_barriers = Vec::new();
+            for &(resource, state) in &uses[pass] {
+                let (first, _) = lifetimes[resource.0].expect("used resources have a lifetime");
+                if position == first && self.is_transient(resource) {
+                    if let Some(before) = self.aliased_predecessor(resource, position, &placements, &lifetimes) {
+                        barriers.push(Barrier::Aliasing { before, after: resource });
+                    }
+                    discards.push(resource);
+                    if states[resource.0] != state {
+                        discard_barriers.push(Barrier::Transition {
+                            resource,
+                            before: states[resource.0],
+                            after: state,
+                        });
+                        states[resource.0] = state;
+                    }
+                    continue;
+                }
+
+                let current = states[resource.0];
+                if current.covers(state) {
+                    if state == ResourceState::UNORDERED_ACCESS {
+                        barriers.push(Barrier::Uav { resource });
+                    }
+                    continue;
+                }
+                // Reads that follow get their states in the same transition.
+                let mut after = state;
+                if !state.is_write() {
+                    for &later in &order[position + 1..] {
+                        match uses[later].iter().find(|(used, _)| *used == resource) {
+                            Some((_, later_state)) if later_state.is_write() => break,
+                            Some((_, later_state)) => after = after.union(*later_state),
+                            None => (),
+                        }
+                    }
+                }
+                barriers.push(Barrier::Transition {
+                    resource,
+                    before: current,
+                    after,
+                });
+                states[resource.0] = after;
+            }
+            passes.push(CompiledPass {
+                pass: PassId(pass),
+                barriers,
+                discards,
+                discard_barriers,
+            });
+        }
+
+        let mut final_barriers = Vec::new();
+        for (i, resource) in self.resources.iter().enumerate() {
+            let target = match resource.kind {
+                ResourceKind::Imported { final_state, .. } => final_state,
+                ResourceKind::Transient { .. } => match placements[i] {
+                    Some(placement) => placement.initial_state,
+                    None => continue,
+                },
+            };
+            if states[i] != target {
+                final_barriers.push(Barrier::Transition {
+                    resource: ResourceId(i),
+                    before: states[i],
+                    after: target,
+                });
+            }
+        }
+
+        Ok(CompiledGraph {
+            passes,
+            culled,
+            final_barriers,
+            placements,
+            heap_size,
+            resource_names: self.resources.iter().map(|resource| resource.name.clone()).collect(),
+            pass_names: self.passes.iter().map(|pass| pass.name.clone()).collect(),
+        })
+    }
+
+    fn is_transient(&self, resource: ResourceId) -> bool {
+        matches!(self.resources[resource.0].kind, ResourceKind::Transient { .. })
+    }
+
+    // One state per resource and pass, sorted by resource.
+    fn merged_uses(&self) -> Result<Vec<Vec<(ResourceId, ResourceState)>>, GraphError> {
+        let mut merged = Vec::with_capacity(self.passes.len());
+        for pass in &self.passes {
+            let mut uses: Vec<(ResourceId, ResourceState)> = Vec::new();
+            for &(resource, state) in &pass.uses {
+                match uses.iter_mut().find(|(used, _)| *used == resource) {
+                    Some((_, merged_state)) => {
+                        if *merged_state != state && (merged_state.is_write() || state.is_write()) {
+                            return Err(GraphError::ConflictingStates {
+                                pass: pass.name.clone(),
+                                resource: self.resources[resource.0].name.clone(),
+                            });
+                        }
+                        *merged_state = merged_state.union(state);
+                    }
+                    None => uses.push((resource, state)),
+                }
+            }
+            uses.sort_by_key(|(resource, _)| *resource);
+            merged.push(uses);
+        }
+        Ok(merged)
+    }
+
+    // Keeps the passes that write imported resources and, walking back,
+    // every earlier pass that writes something a kept pass uses.
+    fn kept_passes(&self, uses: &[Vec<(ResourceId, ResourceState)>]) -> Vec<bool> {
+        let mut kept = vec![false; self.passes.len()];
+        let mut needed = vec![false; self.resources.len()];
+        for pass in (0..self.passes.len()).rev() {
+            let writes_needed = uses[pass].iter().any(|&(resource, state)| {
+                state.is_write() && (needed[resource.0] || !self.is_transient(resource))
+            });
+            if !writes_needed {
+                continue;
+            }
+            kept[pass] = true;
+            // Writes count as well, blending and depth testing build on
+            // what earlier passes left in the target.
+            for &(resource, _) in &uses[pass] {
+                needed[resource.0] = true;
+            }
+        }
+        kept
+    }
+
+    // Largest resources first, each at the lowest offset that does not
+    // overlap a resource placed before it whose lifetime overlaps its own.
+    fn place_transients(
+        &self,
+        order: &[usize],
+        uses: &[Vec<(ResourceId, ResourceState)>],
+        lifetimes: &[Option<(usize, usize)>],
+    ) -> (Vec<Option<Placement>>, u64) {
+        let mut candidates: Vec<(usize, u64, u64)> = self
+            .resources
+            .iter()
+            .enumerate()
+            .filter_map(|(i, resource)| match resource.kind {
+                ResourceKind::Transient { size, alignment } if lifetimes[i].is_some() => {
+                    Some((i, size, alignment.max(1)))
+                }
+                _ => None,
+            })
+            .collect();
+        candidates.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        let mut placements: Vec<Option<Placement>> = vec![None; self.resources.len()];
+        let mut placed: Vec<(usize, u64, u64)> = Vec::new();
+        let mut heap_size = 0;
+        for (i, size, alignment) in candidates {
+            let (first, last) = lifetimes[i].unwrap();
+            let mut blocking: Vec<(u64, u64)> = placed
+                .iter()
+                .filter(|&&(other, _, _)| {
+                    let (other_first, other_last) = lifetimes[other].unwrap();
+                    first <= other_last && other_first <= last
+                })
+                .map(|&(_, offset, other_size)| (offset, offset + other_size))
+                .collect();
+            blocking.sort();
+
+            let mut offset = 0;
+            for (start, end) in blocking {
+                if offset + size <= start {
+                    break;
+                }
+                offset = offset.max(end.div_ceil(alignment) * alignment);
+            }
+
+            let first_pass = order[first];
+            let initial_state = uses[first_pass]
+                .iter()
+                .find(|(resource, _)| resource.0 == i)
+                .map(|&(_, state)| state.discard_state())
+                .unwrap();
+            placements[i] = Some(Placement {
+                offset,
+                size,
+                initial_state,
+            });
+            placed.push((i, offset, size));
+            heap_size = heap_size.max(offset + size);
+        }
+        (placements, heap_size)
+    }
+
+    // The transient resource that last used memory `resource` takes over
+    // at `position`. Some(None) when the memory is shared but nothing used
+    // it earlier this frame, None when it is not shared at all.
+    fn aliased_predecessor(
+        &self,
+        resource: ResourceId,
+        position: usize,
+        placements: &[Option<Placement>],
+        lifetimes: &[Option<(usize, usize)>],
+    ) -> Option<Option<ResourceId>> {
+        let placement = placements[resource.0]?;
+        let overlapping: Vec<usize> = placements
+            .iter()
+            .enumerate()
+            .filter(|&(other, other_placement)| {
+                other != resource.0
+                    && other_placement.is_some_and(|other_placement| {
+                        placement.offset < other_placement.offset + other_placement.size
+                            && other_placement.offset < placement.offset + placement.size
+                    })
+            })
+            .map(|(other, _)| other)
+            .collect();
+        if overlapping.is_empty() {
+            return None;
+        }
+        let before = overlapping
+            .into_iter()
+            .filter(|&other| lifetimes[other].is_some_and(|(_, last)| last < position))
+            .max_by_key(|&other| (lifetimes[other].unwrap().1, other))
+            .map(ResourceId);
+        Some(before)
+    }
+}
+
+impl CompiledGraph {
+    pub fn resource_name(&self, resource: ResourceId) -> &str {
+        &self.resource_names[resource.0]
+    }
+
+    pub fn pass_name(&self, pass: PassId) -> &str {
+        &self.pass_names[pass.0]
+    }
+
+    fn fmt_barrier(&self, f: &mut fmt::Formatter, barrier: &Barrier) -> fmt::Result {
+        match *barrier {
+            Barrier::Transition {
+                resource,
+                before,
+                after,
+            } => writeln!(f, "  transition {}: {} -> {}", self.resource_name(resource), before, after),
+            Barrier::Aliasing { before, after } => writeln!(
+                f,
+                "  aliasing {} -> {}",
+                before.map_or("any", |before| self.resource_name(before)),
+                self.resource_name(after)
+            ),
+            Barrier::Uav { resource } => writeln!(f, "  uav {}", self.resource_name(resource)),
+        }
+    }
+}
+
+// Stable text form of the compiled graph, for logs and for comparing
+// compilations against a known good one.
+impl fmt::Display for CompiledGraph {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for pass in &self.passes {
+            for barrier in &pass.barriers {
+                self.fmt_barrier(f, barrier)?;
+            }
+            for &resource in &pass.discards {
+                writeln!(f, "  discard {}", self.resource_name(resource))?;
+            }
+            for barrier in &pass.discard_barriers {
+                self.fmt_barrier(f, barrier)?;
+            }
+            writeln!(f, "pass {}", self.pass_name(pass.pass))?;
+        }
+        for barrier in &self.final_barriers {
+            self.fmt_barrier(f, barrier)?;
+        }
+        for &pass in &self.culled {
+            writeln!(f, "culled {}", self.pass_name(pass))?;
+        }
+        writeln!(f, "heap {} bytes", self.heap_size)?;
+        for (i, placement) in self.placements.iter().enumerate() {
+            if let Some(placement) = placement {
+                writeln!(
+                    f,
+                    "  {} at {}..{} created {}",
+                    self.resource_names[i],
+                    placement.offset,
+                    placement.offset + placement.size,
+                    placement.initial_state
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RT: ResourceState = ResourceState::RENDER_TARGET;
+    const SRV: ResourceState = ResourceState::PIXEL_SHADER_RESOURCE;
+
+    fn assert_compiles_to(graph: &RenderGraph, expected: &[&str]) {
+        let compiled = graph.compile().unwrap().to_string();
+        assert_eq!(compiled.lines().collect::<Vec<_>>(), expected, "{}", compiled);
+    }
+
+    #[test]
+    fn unused_passes_are_culled() {
+        let mut graph = RenderGraph::new();
+        let back_buffer = graph.import("back buffer", ResourceState::PRESENT, ResourceState::PRESENT);
+        let shadow = graph.create_transient("shadow map", 4096, 256);
+        let scratch = graph.create_transient("scratch", 1024, 256);
+        graph.add_pass("shadows", &[(shadow, ResourceState::DEPTH_WRITE)]);
+        graph.add_pass("unread", &[(scratch, ResourceState::UNORDERED_ACCESS)]);
+        graph.add_pass("main", &[(shadow, SRV), (back_buffer, RT)]);
+        // Only reads, so nothing depends on it.
+        graph.add_pass("readback", &[(back_buffer, ResourceState::COPY_SOURCE)]);
+        assert_compiles_to(
+            &graph,
+            &[
+                "  discard shadow map",
+                "pass shadows",
+                "  transition back buffer: COMMON -> RENDER_TARGET",
+                "  transition shadow map: DEPTH_WRITE -> PIXEL_SHADER_RESOURCE",
+                "pass main",
+                "  transition back buffer: RENDER_TARGET -> COMMON",
+                "  transition shadow map: PIXEL_SHADER_RESOURCE -> DEPTH_WRITE",
+                "culled unread",
+                "culled readback",
+                "heap 4096 bytes",
+                "  shadow map at 0..4096 created DEPTH_WRITE",
+            ],
+        );
+    }
+
+    #[test]
+    fn transients_with_disjoint_lifetimes_alias() {
+        let mut graph = RenderGraph::new();
+        let back_buffer = graph.import("back buffer", ResourceState::PRESENT, ResourceState::PRESENT);
+        let scene = graph.create_transient("scene", 2048, 256);
+        let bloom = graph.create_transient("bloom", 1024, 256);
+        let ldr = graph.create_transient("ldr", 1024, 256);
+        graph.add_pass("main", &[(scene, RT)]);
+        graph.add_pass("bloom", &[(scene, SRV), (bloom, RT)]);
+        graph.add_pass("tonemap", &[(bloom, SRV), (ldr, RT)]);
+        graph.add_pass("fxaa", &[(ldr, SRV), (back_buffer, RT)]);
+        assert_compiles_to(
+            &graph,
+            &[
+                "  aliasing any -> scene",
+                "  discard scene",
+                "pass main",
+                "  transition scene: RENDER_TARGET -> PIXEL_SHADER_RESOURCE",
+                "  discard bloom",
+                "pass bloom",
+                "  transition bloom: RENDER_TARGET -> PIXEL_SHADER_RESOURCE",
+                "  aliasing scene -> ldr",
+                "  discard ldr",
+                "pass tonemap",
+                "  transition back buffer: COMMON -> RENDER_TARGET",
+                "  transition ldr: RENDER_TARGET -> PIXEL_SHADER_RESOURCE",
+                "pass fxaa",
+                "  transition back buffer: RENDER_TARGET -> COMMON",
+                "  transition scene: PIXEL_SHADER_RESOURCE -> RENDER_TARGET",
+                "  transition bloom: PIXEL_SHADER_RESOURCE -> RENDER_TARGET",
+                "  transition ldr: PIXEL_SHADER_RESOURCE -> RENDER_TARGET",
+                "heap 3072 bytes",
+                "  scene at 0..2048 created RENDER_TARGET",
+                "  bloom at 2048..3072 created RENDER_TARGET",
+                "  ldr at 0..1024 created RENDER_TARGET",
+            ],
+        );
+    }
+
+    #[test]
+    fn consecutive_reads_share_one_transition() {
+        let mut graph = RenderGraph::new();
+        let back_buffer = graph.import("back buffer", ResourceState::PRESENT, ResourceState::PRESENT);
+        let depth = graph.create_transient("depth", 1024, 256);
+        let arguments = graph.import("arguments", ResourceState::COMMON, ResourceState::COMMON);
+        graph.add_pass("depth", &[(depth, ResourceState::DEPTH_WRITE)]);
+        graph.add_pass(
+            "cull",
+            &[
+                (depth, ResourceState::NON_PIXEL_SHADER_RESOURCE),
+                (arguments, ResourceState::UNORDERED_ACCESS),
+            ],
+        );
+        graph.add_pass("compact", &[(arguments, ResourceState::UNORDERED_ACCESS)]);
+        graph.add_pass(
+            "main",
+            &[
+                (depth, SRV),
+                (depth, ResourceState::DEPTH_READ),
+                (arguments, ResourceState::INDIRECT_ARGUMENT),
+                (back_buffer, RT),
+            ],
+        );
+        assert_compiles_to(
+            &graph,
+            &[
+                "  discard depth",
+                "pass depth",
+                "  transition depth: DEPTH_WRITE -> DEPTH_READ|NON_PIXEL_SHADER_RESOURCE|PIXEL_SHADER_RESOURCE",
+                "  transition arguments: COMMON -> UNORDERED_ACCESS",
+                "pass cull",
+                "  uav arguments",
+                "pass compact",
+                "  transition back buffer: COMMON -> RENDER_TARGET",
+                "  transition arguments: UNORDERED_ACCESS -> INDIRECT_ARGUMENT",
+                "pass main",
+                "  transition back buffer: RENDER_TARGET -> COMMON",
+                "  transition depth: DEPTH_READ|NON_PIXEL_SHADER_RESOURCE|PIXEL_SHADER_RESOURCE -> DEPTH_WRITE",
+                "  transition arguments: INDIRECT_ARGUMENT -> COMMON",
+                "heap 1024 bytes",
+                "  depth at 0..1024 created DEPTH_WRITE",
+            ],
+        );
+    }
+
+    #[test]
+    fn resolve_destinations_are_discarded_as_render_targets() {
+        let mut graph = RenderGraph::new();
+        let back_buffer = graph.import("back buffer", ResourceState::PRESENT, ResourceState::PRESENT);
+        let shadow = graph.create_transient("shadow map", 1024, 256);
+        let multisampled_scene = graph.create_transient("scene msaa", 4096, 256);
+        let scene = graph.create_transient("scene", 1024, 256);
+        graph.add_pass("shadows", &[(shadow, ResourceState::DEPTH_WRITE)]);
+        graph.add_pass("main", &[(shadow, SRV), (multisampled_scene, RT)]);
+        graph.add_pass(
+            "resolve",
+            &[
+                (multisampled_scene, ResourceState::RESOLVE_SOURCE),
+                (scene, ResourceState::RESOLVE_DEST),
+            ],
+        );
+        graph.add_pass("tonemap", &[(scene, SRV), (back_buffer, RT)]);
+        // The scene takes over the shadow map memory and is discarded as a
+        // render target before it becomes the resolve destination.
+        assert_compiles_to(
+            &graph,
+            &[
+                "  aliasing any -> shadow map",
+                "  discard shadow map",
+                "pass shadows",
+                "  transition shadow map: DEPTH_WRITE -> PIXEL_SHADER_RESOURCE",
+                "  discard scene msaa",
+                "pass main",
+                "  transition scene msaa: RENDER_TARGET -> RESOLVE_SOURCE",
+                "  aliasing shadow map -> scene",
+                "  discard scene",
+                "  transition scene: RENDER_TARGET -> RESOLVE_DEST",
+                "pass resolve",
+                "  transition back buffer: COMMON -> RENDER_TARGET",
+                "  transition scene: RESOLVE_DEST -> PIXEL_SHADER_RESOURCE",
+                "pass tonemap",
+                "  transition back buffer: RENDER_TARGET -> COMMON",
+                "  transition shadow map: PIXEL_SHADER_RESOURCE -> DEPTH_WRITE",
+                "  transition scene msaa: RESOLVE_SOURCE -> RENDER_TARGET",
+                "  transition scene: PIXEL_SHADER_RESOURCE -> RENDER_TARGET",
+                "heap 5120 bytes",
+                "  shadow map at 4096..5120 created DEPTH_WRITE",
+                "  scene msaa at 0..4096 created RENDER_TARGET",
+                "  scene at 4096..5120 created RENDER_TARGET",
+            ],
+        );
+    }
+
+    #[test]
+    fn invalid_uses_are_rejected() {
+        let mut graph = RenderGraph::new();
+        let target = graph.create_transient("target", 1024, 256);
+        graph.add_pass("feedback", &[(target, RT), (target, SRV)]);
+        let error = graph.compile().unwrap_err();
+        assert_eq!(
+            error,
+            GraphError::ConflictingStates {
+                pass: "feedback".into(),
+                resource: "target".into()
+            }
+        );
+        assert_eq!(error.to_string(), "feedback writes target and uses it in another state");
+
+        let mut graph = RenderGraph::new();
+        let back_buffer = graph.import("back buffer", ResourceState::PRESENT, ResourceState::PRESENT);
+        let history = graph.create_transient("history", 1024, 256);
+        graph.add_pass("resolve", &[(history, SRV), (back_buffer, RT)]);
+        let error = graph.compile().unwrap_err();
+        assert_eq!(
+            error,
+            GraphError::ReadBeforeWrite {
+                pass: "resolve".into(),
+                resource: "history".into()
+            }
+        );
+        assert_eq!(error.to_string(), "resolve reads history before anything wrote it");
+    }
+}
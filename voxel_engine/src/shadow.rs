@@ -0,0 +1,297 @@
+use crate::camera::Camera;
+use crate::math::{Mat4, Vec3};
+
+pub const CASCADE_COUNT: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShadowSettings {
+    // Width and height of every cascade, in texels.
+    pub resolution: u32,
+    // View depth past which nothing receives shadows.
+    pub max_distance: f32,
+    // Blend between uniform (0) and logarithmic (1) cascade splits.
+    pub split_lambda: f32,
+    // How far towards the light casters are captured beyond the cascade
+    // bounds, so terrain outside the view still shadows what is inside.
+    pub caster_distance: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        ShadowSettings {
+            resolution: 2048,
+            max_distance: 192.0,
+            split_lambda: 0.75,
+            caster_distance: 256.0,
+        }
+    }
+}
+
+// Far view depth of every cascade with the practical split scheme
+// (GPU Gems 3, chapter 10): a blend of logarithmic splits, which keep the
+// texel density even in screen space, and uniform ones, which keep the far
+// cascades from growing too large.
+pub fn cascade_splits(near: f32, far: f32, lambda: f32) -> [f32; CASCADE_COUNT] {
+    let mut splits = [far; CASCADE_COUNT];
+    for (i, split) in splits.iter_mut().enumerate().take(CASCADE_COUNT - 1) {
+        let fraction = (i + 1) as f32 / CASCADE_COUNT as f32;
+        let logarithmic = near * (far / near).powf(fraction);
+        let uniform = near + (far - near) * fraction;
+        *split = lambda * logarithmic + (1.0 - lambda) * uniform;
+    }
+    splits
+}
+
+// World space corners of the part of the view frustum between the view
+// depths near and far, near ones first.
+pub fn frustum_slice_corners(camera: &Camera, aspect_ratio: f32, near: f32, far: f32) -> [Vec3; 8] {
+    let (forward, right, up) = camera.screen_axes(aspect_ratio);
+    let mut corners = [Vec3::default(); 8];
+    for (i, corner) in corners.iter_mut().enumerate() {
+        let depth = if i < 4 { near } else { far };
+        let x = if i & 1 != 0 { 1.0 } else { -1.0 };
+        let y = if i & 2 != 0 { 1.0 } else { -1.0 };
+        *corner = camera.position + (forward + right * x + up * y) * depth;
+    }
+    corners
+}
+
+// Orthographic light view projection covering `corners`, looking along
+// -sun_direction.
+//
+// The cascade is fitted around the bounding sphere of the slice rather than
+// its box, so its size does not change as the camera turns, and its center
+// is snapped to whole texels in light space, so the texel grid does not
+// slide as the camera moves. Together they keep shadow edges from
+// shimmering. Returns the matrix and the size of one texel in world units.
+pub fn fit_cascade(
+    corners: &[Vec3; 8],
+    sun_direction: Vec3,
+    resolution: u32,
+    caster_distance: f32,
+) -> (Mat4, f32) {
+    let center = corners.iter().fold(Vec3::default(), |sum, &corner| sum + corner) * (1.0 / 8.0);
+    let radius = corners
+        .iter()
+        .map(|&corner| (corner - center).length())
+        .fold(0.0, f32::max);
+    // Rounded up so float noise in the corners does not change the size.
+    let radius = (radius * 16.0).ceil() / 16.0;
+    let texel_size = 2.0 * radius / resolution as f32;
+
+    // Rotation only, the translation goes into the projection so that the
+    // snapping below happens in a grid that is fixed in the world.
+    let up = if sun_direction.y.abs() > 0.99 {
+        Vec3::new(0.0, 0.0, 1.0)
+    } else {
+        Vec3::new(0.0, 1.0, 0.0)
+    };
+    let light_view = Mat4::look_to_lh(Vec3::default(), -sun_direction, up);
+
+    let [x, y, z, _] = light_view.transform([center.x, center.y, center.z, 1.0]);
+    let x = (x / texel_size).floor() * texel_size;
+    let y = (y / texel_size).floor() * texel_size;
+
+    let projection = Mat4::orthographic_off_center_lh(
+        x - radius,
+        x + radius,
+        y - radius,
+        y + radius,
+        z - radius - caster_distance,
+        z + radius,
+    );
+    (projection * light_view, texel_size)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShadowCascades {
+    pub view_projections: [Mat4; CASCADE_COUNT],
+    // Far view depth of every cascade.
+    pub splits: [f32; CASCADE_COUNT],
+    // Size of one shadow map texel in world units.
+    pub texel_sizes: [f32; CASCADE_COUNT],
+}
+
+impl ShadowCascades {
+    pub fn new(camera: &Camera, aspect_ratio: f32, sun_direction: Vec3, settings: &ShadowSettings) -> Self {
+        let far = settings.max_distance.min(camera.far);
+        let splits = cascade_splits(camera.near, far, settings.split_lambda);
+        let mut view_projections = [Mat4::IDENTITY; CASCADE_COUNT];
+        let mut texel_sizes = [0.0; CASCADE_COUNT];
+        let mut near = camera.near;
+        for i in 0..CASCADE_COUNT {
+            let corners = frustum_slice_corners(camera, aspect_ratio, near, splits[i]);
+            (view_projections[i], texel_sizes[i]) =
+                fit_cascade(&corners, sun_direction, settings.resolution, settings.caster_distance);
+            near = splits[i];
+        }
+        ShadowCascades {
+            view_projections,
+            splits,
+            texel_sizes,
+        }
+    }
+
+    // Index of the cascade a view depth falls into, None past the last one.
+    // CPU reference of the cascade selection in PSMain.
+    #[cfg(test)]
+    pub fn cascade_at(&self, depth: f32) -> Option<usize> {
+        self.splits.iter().position(|&split| depth <= split)
+    }
+}
+
+// Constant buffer of the main pass, laid out like the ShadowConstants
+// cbuffer in shaders/shaders.hlsl.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShadowConstants {
+    pub light_view_projections: [[[f32; 4]; 4]; CASCADE_COUNT],
+    pub cascade_splits: [f32; CASCADE_COUNT],
+    pub cascade_texel_sizes: [f32; CASCADE_COUNT],
+    pub camera_position: [f32; 3],
+    pub shadow_map_texel: f32,
+}
+
+impl ShadowConstants {
+    pub fn new(cascades: &ShadowCascades, camera_position: Vec3, settings: &ShadowSettings) -> Self {
+        ShadowConstants {
+            light_view_projections: cascades.view_projections.map(|matrix| matrix.rows),
+            cascade_splits: cascades.splits,
+            cascade_texel_sizes: cascades.texel_sizes,
+            camera_position: [camera_position.x, camera_position.y, camera_position.z],
+            shadow_map_texel: 1.0 / settings.resolution as f32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+    use std::mem::{offset_of, size_of};
+
+    fn camera(yaw: f32, pitch: f32) -> Camera {
+        Camera {
+            position: Vec3::new(10.0, 50.0, -20.0),
+            yaw,
+            pitch,
+            fov_y: FRAC_PI_2,
+            near: 0.5,
+            far: 500.0,
+        }
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-3 * b.abs().max(1.0), "{} against {}", a, b);
+    }
+
+    #[test]
+    fn splits_blend_uniform_and_logarithmic() {
+        let uniform = cascade_splits(1.0, 101.0, 0.0);
+        for (split, expected) in uniform.iter().zip([26.0, 51.0, 76.0, 101.0]) {
+            assert_close(*split, expected);
+        }
+
+        let logarithmic = cascade_splits(1.0, 256.0, 1.0);
+        for (split, expected) in logarithmic.iter().zip([4.0, 16.0, 64.0, 256.0]) {
+            assert_close(*split, expected);
+        }
+
+        let practical = cascade_splits(1.0, 256.0, 0.5);
+        let uniform = cascade_splits(1.0, 256.0, 0.0);
+        for i in 0..CASCADE_COUNT {
+            assert_close(practical[i], (logarithmic[i] + uniform[i]) / 2.0);
+        }
+    }
+
+    #[test]
+    fn splits_increase_up_to_far() {
+        for lambda in [0.0, 0.25, 0.75, 1.0] {
+            let splits = cascade_splits(0.1, 192.0, lambda);
+            assert!(splits[0] > 0.1);
+            assert!(splits.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", splits);
+            assert_eq!(splits[CASCADE_COUNT - 1], 192.0);
+        }
+    }
+
+    #[test]
+    fn slice_corners_lie_at_their_depths() {
+        let camera = camera(0.0, 0.0);
+        let corners = frustum_slice_corners(&camera, 2.0, 1.0, 10.0);
+        // Forward is +z with a 90 degree vertical field of view, so a slice
+        // is twice its depth high and four times its depth wide.
+        assert_eq!(corners[0], camera.position + Vec3::new(-2.0, -1.0, 1.0));
+        assert_eq!(corners[3], camera.position + Vec3::new(2.0, 1.0, 1.0));
+        assert_eq!(corners[4], camera.position + Vec3::new(-20.0, -10.0, 10.0));
+        assert_eq!(corners[7], camera.position + Vec3::new(20.0, 10.0, 10.0));
+    }
+
+    #[test]
+    fn cascades_contain_their_frustum_slices() {
+        let settings = ShadowSettings::default();
+        let suns = [
+            Vec3::new(0.3, 0.8, 0.5).normalize(),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(-0.9, 0.1, 0.2).normalize(),
+        ];
+        for (yaw, pitch) in [(0.0, 0.0), (1.3, -0.4), (-2.5, 0.9)] {
+            let camera = camera(yaw, pitch);
+            for sun in suns {
+                let cascades = ShadowCascades::new(&camera, 16.0 / 9.0, sun, &settings);
+                let mut near = camera.near;
+                for i in 0..CASCADE_COUNT {
+                    let corners = frustum_slice_corners(&camera, 16.0 / 9.0, near, cascades.splits[i]);
+                    for corner in corners {
+                        let [x, y, z, w] =
+                            cascades.view_projections[i].transform([corner.x, corner.y, corner.z, 1.0]);
+                        assert_eq!(w, 1.0);
+                        assert!(x.abs() <= 1.0 && y.abs() <= 1.0, "cascade {}: {:?}", i, [x, y]);
+                        assert!((0.0..=1.0).contains(&z), "cascade {}: depth {}", i, z);
+                    }
+                    near = cascades.splits[i];
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn casters_towards_the_sun_are_captured() {
+        let corners = frustum_slice_corners(&camera(0.0, 0.0), 1.0, 1.0, 20.0);
+        let sun = Vec3::new(0.0, 1.0, 0.0);
+        let (view_projection, _) = fit_cascade(&corners, sun, 1024, 100.0);
+        let caster = corners[7] + sun * 90.0;
+        let [_, _, z, _] = view_projection.transform([caster.x, caster.y, caster.z, 1.0]);
+        assert!((0.0..=1.0).contains(&z), "depth {}", z);
+    }
+
+    #[test]
+    fn cascades_do_not_resize_as_the_camera_turns() {
+        let settings = ShadowSettings::default();
+        let sun = Vec3::new(0.3, 0.8, 0.5).normalize();
+        let first = ShadowCascades::new(&camera(0.0, 0.0), 1.5, sun, &settings);
+        let turned = ShadowCascades::new(&camera(2.0, 0.3), 1.5, sun, &settings);
+        assert_eq!(first.texel_sizes, turned.texel_sizes);
+        assert_eq!(first.splits, turned.splits);
+    }
+
+    #[test]
+    fn cascade_selection_follows_the_splits() {
+        let settings = ShadowSettings::default();
+        let cascades = ShadowCascades::new(&camera(0.0, 0.0), 1.5, Vec3::new(0.0, 1.0, 0.0), &settings);
+        assert_eq!(cascades.splits[CASCADE_COUNT - 1], settings.max_distance);
+        assert_eq!(cascades.cascade_at(0.5), Some(0));
+        assert_eq!(cascades.cascade_at(cascades.splits[0]), Some(0));
+        assert_eq!(cascades.cascade_at(cascades.splits[0] + 0.01), Some(1));
+        assert_eq!(cascades.cascade_at(cascades.splits[2] + 0.01), Some(3));
+        assert_eq!(cascades.cascade_at(settings.max_distance + 0.01), None);
+    }
+
+    #[test]
+    fn constants_match_the_shader_layout() {
+        assert_eq!(offset_of!(ShadowConstants, cascade_splits), 256);
+        assert_eq!(offset_of!(ShadowConstants, cascade_texel_sizes), 272);
+        assert_eq!(offset_of!(ShadowConstants, camera_position), 288);
+        assert_eq!(offset_of!(ShadowConstants, shadow_map_texel), 300);
+        assert_eq!(size_of::<ShadowConstants>(), 304);
+    }
+}
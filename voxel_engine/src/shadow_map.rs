@@ -0,0 +1,287 @@
+use backend::DrawIndexedArguments;
+use windows::{
+    core::*, Win32::Foundation::*, Win32::Graphics::Direct3D::*,
+    Win32::Graphics::Direct3D12::*, Win32::Graphics::Dxgi::Common::*,
+};
+
+use crate::gpu_culling::CommandListEncoder;
+use crate::indirect::{submit_cpu, ChunkRecord};
+use crate::markers::{set_name, Event};
+use crate::math::Frustum;
+use crate::shadow::{ShadowCascades, ShadowConstants, CASCADE_COUNT};
+use crate::{
+    convert_to_bytecode, create_buffer, create_texture, vertex_input_layout,
+};
+
+const SHADOW_FORMAT: DXGI_FORMAT = DXGI_FORMAT_D32_FLOAT;
+
+// Depth of the chunks as seen from the sun, one array slice per cascade,
+// and the constants the main pass samples it with. Drawn with the main root
+// signature and vertex shader, the light view projection takes the place of
+// the camera's in the root constants.
+pub struct ShadowMapPass {
+    pso: ID3D12PipelineState,
+    // Typeless so the main pass can read it as R32_FLOAT.
+    shadow_map: ID3D12Resource,
+    dsv_heap: ID3D12DescriptorHeap,
+    dsv_descriptor_size: usize,
+    srv_heap: ID3D12DescriptorHeap,
+    constant_buffer: ID3D12Resource,
+    resolution: u32,
+    draw_arguments: Vec<DrawIndexedArguments>,
+}
+
+impl ShadowMapPass {
+    pub fn new(device: &ID3D12Device, root_signature: &ID3D12RootSignature, resolution: u32) -> Result<Self> {
+        let pso = create_shadow_pipeline_state(device, root_signature)?;
+
+        let shadow_map = create_texture(
+            device,
+            &D3D12_RESOURCE_DESC {
+                Dimension: D3D12_RESOURCE_DIMENSION_TEXTURE2D,
+                Width: resolution as u64,
+                Height: resolution,
+                DepthOrArraySize: CASCADE_COUNT as u16,
+                MipLevels: 1,
+                Format: DXGI_FORMAT_R32_TYPELESS,
+                SampleDesc: DXGI_SAMPLE_DESC {
+                    Count: 1,
+                    Quality: 0,
+                },
+                Flags: D3D12_RESOURCE_FLAG_ALLOW_DEPTH_STENCIL,
+                ..Default::default()
+            },
+            D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE,
+            Some(&D3D12_CLEAR_VALUE {
+                Format: SHADOW_FORMAT,
+                Anonymous: D3D12_CLEAR_VALUE_0 {
+                    DepthStencil: D3D12_DEPTH_STENCIL_VALUE {
+                        Depth: 1.0,
+                        Stencil: 0,
+                    },
+                },
+            }),
+        )?;
+
+        let dsv_heap: ID3D12DescriptorHeap = unsafe {
+            device.CreateDescriptorHeap(&D3D12_DESCRIPTOR_HEAP_DESC {
+                NumDescriptors: CASCADE_COUNT as u32,
+                Type: D3D12_DESCRIPTOR_HEAP_TYPE_DSV,
+                ..Default::default()
+            })
+        }?;
+        let dsv_descriptor_size =
+            unsafe { device.GetDescriptorHandleIncrementSize(D3D12_DESCRIPTOR_HEAP_TYPE_DSV) } as usize;
+        let dsv_start = unsafe { dsv_heap.GetCPUDescriptorHandleForHeapStart() };
+        for cascade in 0..CASCADE_COUNT {
+            unsafe {
+                device.CreateDepthStencilView(
+                    &shadow_map,
+                    Some(&D3D12_DEPTH_STENCIL_VIEW_DESC {
+                        Format: SHADOW_FORMAT,
+                        ViewDimension: D3D12_DSV_DIMENSION_TEXTURE2DARRAY,
+                        Flags: D3D12_DSV_FLAG_NONE,
+                        Anonymous: D3D12_DEPTH_STENCIL_VIEW_DESC_0 {
+                            Texture2DArray: D3D12_TEX2D_ARRAY_DSV {
+                                MipSlice: 0,
+                                FirstArraySlice: cascade as u32,
+                                ArraySize: 1,
+                            },
+                        },
+                    }),
+                    D3D12_CPU_DESCRIPTOR_HANDLE {
+                        ptr: dsv_start.ptr + cascade * dsv_descriptor_size,
+                    },
+                )
+            };
+        }
+
+        let srv_heap: ID3D12DescriptorHeap = unsafe {
+            device.CreateDescriptorHeap(&D3D12_DESCRIPTOR_HEAP_DESC {
+                NumDescriptors: 1,
+                Type: D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV,
+                Flags: D3D12_DESCRIPTOR_HEAP_FLAG_SHADER_VISIBLE,
+                ..Default::default()
+            })
+        }?;
+        unsafe {
+            device.CreateShaderResourceView(
+                &shadow_map,
+                Some(&D3D12_SHADER_RESOURCE_VIEW_DESC {
+                    Format: DXGI_FORMAT_R32_FLOAT,
+                    ViewDimension: D3D12_SRV_DIMENSION_TEXTURE2DARRAY,
+                    Shader4ComponentMapping: D3D12_DEFAULT_SHADER_4_COMPONENT_MAPPING,
+                    Anonymous: D3D12_SHADER_RESOURCE_VIEW_DESC_0 {
+                        Texture2DArray: D3D12_TEX2D_ARRAY_SRV {
+                            MostDetailedMip: 0,
+                            MipLevels: 1,
+                            FirstArraySlice: 0,
+                            ArraySize: CASCADE_COUNT as u32,
+                            PlaneSlice: 0,
+                            ResourceMinLODClamp: 0.0,
+                        },
+                    },
+                }),
+                srv_heap.GetCPUDescriptorHandleForHeapStart(),
+            )
+        };
+
+        let constant_buffer = create_buffer(
+            device,
+            D3D12_HEAP_TYPE_UPLOAD,
+            std::mem::size_of::<ShadowConstants>() as u64,
+            D3D12_RESOURCE_FLAG_NONE,
+            D3D12_RESOURCE_STATE_GENERIC_READ,
+        )?;
+
+        set_name(&pso, "shadow map pso");
+        set_name(&shadow_map, "shadow map");
+        set_name(&dsv_heap, "shadow map dsv heap");
+        set_name(&srv_heap, "shadow map srv heap");
+        set_name(&constant_buffer, "shadow constants");
+
+        Ok(ShadowMapPass {
+            pso,
+            shadow_map,
+            dsv_heap,
+            dsv_descriptor_size,
+            srv_heap,
+            constant_buffer,
+            resolution,
+            draw_arguments: Vec::new(),
+        })
+    }
+
+    // Only valid while the GPU is not reading the previous constants, which
+    // holds between frames.
+    pub fn set_constants(&self, constants: &ShadowConstants) -> Result<()> {
+        unsafe {
+            let mut mapped = std::ptr::null_mut();
+            self.constant_buffer.Map(0, Some(&D3D12_RANGE { Begin: 0, End: 0 }), Some(&mut mapped))?;
+            std::ptr::write(mapped as *mut ShadowConstants, *constants);
+            self.constant_buffer.Unmap(0, None);
+        }
+        Ok(())
+    }
+
+    // Draws the opaque chunks into every cascade, each culled against the
+    // cascade's own bounds. Expects the shadow map in DEPTH_WRITE, the frame
+    // graph moves it there and back to PIXEL_SHADER_RESOURCE. Leaves the
+    // render targets, viewport and pipeline state for the caller to set again.
+    pub fn record(
+        &mut self,
+        command_list: &ID3D12GraphicsCommandList,
+        root_signature: &ID3D12RootSignature,
+        cascades: &ShadowCascades,
+        vbv: &D3D12_VERTEX_BUFFER_VIEW,
+        ibv: &D3D12_INDEX_BUFFER_VIEW,
+        chunks: &[ChunkRecord],
+    ) {
+        let size = self.resolution as f32;
+        unsafe {
+            command_list.SetPipelineState(&self.pso);
+            command_list.SetGraphicsRootSignature(root_signature);
+            command_list.RSSetViewports(&[D3D12_VIEWPORT {
+                TopLeftX: 0.0,
+                TopLeftY: 0.0,
+                Width: size,
+                Height: size,
+                MinDepth: D3D12_MIN_DEPTH,
+                MaxDepth: D3D12_MAX_DEPTH,
+            }]);
+            command_list.RSSetScissorRects(&[RECT {
+                left: 0,
+                top: 0,
+                right: self.resolution as i32,
+                bottom: self.resolution as i32,
+            }]);
+            command_list.IASetPrimitiveTopology(D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST);
+            command_list.IASetVertexBuffers(0, Some(&[*vbv]));
+            command_list.IASetIndexBuffer(Some(ibv));
+        }
+
+        let dsv_start = unsafe { self.dsv_heap.GetCPUDescriptorHandleForHeapStart() };
+        for (cascade, view_projection) in cascades.view_projections.iter().enumerate() {
+            let _event = Event::new(command_list, &format!("cascade {}", cascade));
+            let dsv_handle = D3D12_CPU_DESCRIPTOR_HANDLE {
+                ptr: dsv_start.ptr + cascade * self.dsv_descriptor_size,
+            };
+            unsafe {
+                command_list.OMSetRenderTargets(0, None, false, Some(&dsv_handle));
+                command_list.ClearDepthStencilView(dsv_handle, D3D12_CLEAR_FLAG_DEPTH, 1.0, 0, &[]);
+                command_list.SetGraphicsRoot32BitConstants(
+                    0,
+                    16,
+                    view_projection.rows.as_ptr() as *const _,
+                    0,
+                );
+                // Time, nothing opaque waves.
+                command_list.SetGraphicsRoot32BitConstant(0, 0, 16);
+            }
+            let frustum = Frustum::from_view_projection(view_projection);
+            let mut encoder = CommandListEncoder::new(command_list, None);
+            submit_cpu(&mut encoder, chunks, &frustum, &mut self.draw_arguments);
+        }
+    }
+
+    pub fn shadow_map(&self) -> &ID3D12Resource {
+        &self.shadow_map
+    }
+
+    // Binds the shadow map and constants for the main pass, after its root
+    // signature is set.
+    pub fn bind(&self, command_list: &ID3D12GraphicsCommandList) {
+        unsafe {
+            command_list.SetDescriptorHeaps(&[Some(self.srv_heap.clone())]);
+            command_list.SetGraphicsRootConstantBufferView(1, self.constant_buffer.GetGPUVirtualAddress());
+            command_list.SetGraphicsRootDescriptorTable(2, self.srv_heap.GetGPUDescriptorHandleForHeapStart());
+        }
+    }
+}
+
+// Depth only, with the vertex shader of the main pass and no pixel shader.
+fn create_shadow_pipeline_state(
+    device: &ID3D12Device,
+    root_signature: &ID3D12RootSignature,
+) -> Result<ID3D12PipelineState> {
+    let vs_bin = std::fs::read("resources/vs.bin")
+        .map_err(|error| Error::new(E_FAIL, error.to_string().into()))?;
+
+    let mut input_element_descs = vertex_input_layout();
+
+    let desc = D3D12_GRAPHICS_PIPELINE_STATE_DESC {
+        InputLayout: D3D12_INPUT_LAYOUT_DESC {
+            pInputElementDescs: input_element_descs.as_mut_ptr(),
+            NumElements: input_element_descs.len() as u32,
+        },
+        pRootSignature: unsafe { std::mem::transmute_copy(root_signature) },
+        VS: convert_to_bytecode(&vs_bin),
+        RasterizerState: D3D12_RASTERIZER_DESC {
+            FillMode: D3D12_FILL_MODE_SOLID,
+            CullMode: D3D12_CULL_MODE_NONE,
+            // The pixel shader offsets its lookups along the normal as
+            // well, this only takes care of the slope within a texel.
+            DepthBias: 0,
+            SlopeScaledDepthBias: 1.5,
+            DepthClipEnable: true.into(),
+            ..Default::default()
+        },
+        DepthStencilState: D3D12_DEPTH_STENCIL_DESC {
+            DepthEnable: true.into(),
+            DepthWriteMask: D3D12_DEPTH_WRITE_MASK_ALL,
+            DepthFunc: D3D12_COMPARISON_FUNC_LESS,
+            ..Default::default()
+        },
+        DSVFormat: SHADOW_FORMAT,
+        SampleMask: u32::MAX,
+        PrimitiveTopologyType: D3D12_PRIMITIVE_TOPOLOGY_TYPE_TRIANGLE,
+        NumRenderTargets: 0,
+        SampleDesc: DXGI_SAMPLE_DESC {
+            Count: 1,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    unsafe { device.CreateGraphicsPipelineState(&desc) }
+}
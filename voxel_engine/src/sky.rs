@@ -0,0 +1,323 @@
+use std::f32::consts::TAU;
+
+use crate::camera::Camera;
+use crate::math::Vec3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DaySettings {
+    // Real seconds a full day takes at time scale 1.
+    pub day_length: f32,
+    // Fraction of the day the world starts at, 0 is midnight and 0.5 noon.
+    pub start_time: f32,
+    pub time_scale: f32,
+}
+
+impl Default for DaySettings {
+    fn default() -> Self {
+        DaySettings {
+            day_length: 600.0,
+            start_time: 0.35,
+            time_scale: 1.0,
+        }
+    }
+}
+
+// Angle between the plane the sun moves in and the vertical, so that the
+// noon sun still casts shadows a block face can show.
+const SUN_TILT: f32 = 0.4;
+
+// Time of day, advanced by the frame time. Drives the sun and the colors of
+// sky, ambient light and fog.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DayCycle {
+    time: f32,
+    day_length: f32,
+    pub time_scale: f32,
+    pub paused: bool,
+}
+
+impl DayCycle {
+    pub fn new(settings: &DaySettings) -> Self {
+        DayCycle {
+            time: settings.start_time.rem_euclid(1.0),
+            day_length: settings.day_length,
+            time_scale: settings.time_scale,
+            paused: false,
+        }
+    }
+
+    // Fraction of the day, 0 at midnight and 0.5 at noon.
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn set_time(&mut self, time: f32) {
+        self.time = time.rem_euclid(1.0);
+    }
+
+    // seconds is real time since the last call.
+    pub fn advance(&mut self, seconds: f32) {
+        if self.paused || self.day_length <= 0.0 {
+            return;
+        }
+        self.time = (self.time + seconds * self.time_scale / self.day_length).rem_euclid(1.0);
+    }
+
+    // Towards the sun: rises at +x at a quarter of the day, peaks at noon
+    // and sets at -x, below the horizon for the other half.
+    pub fn sun_direction(&self) -> Vec3 {
+        let angle = (self.time - 0.25) * TAU;
+        Vec3::new(
+            angle.cos(),
+            angle.sin() * SUN_TILT.cos(),
+            angle.sin() * SUN_TILT.sin(),
+        )
+    }
+
+    pub fn lighting(&self) -> Lighting {
+        Lighting::for_sun(self.sun_direction())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SkyColors {
+    pub zenith: [f32; 3],
+    pub horizon: [f32; 3],
+    pub ambient: [f32; 3],
+    // Sun light reaching lit faces, zero once the sun is down.
+    pub sun: [f32; 3],
+}
+
+// Colors by sine of the sun elevation, interpolated in between and held
+// past the ends.
+const SKY_KEYS: [(f32, SkyColors); 4] = [
+    (
+        -0.25,
+        SkyColors {
+            zenith: [0.005, 0.008, 0.03],
+            horizon: [0.02, 0.03, 0.07],
+            ambient: [0.05, 0.06, 0.1],
+            sun: [0.0, 0.0, 0.0],
+        },
+    ),
+    (
+        0.0,
+        SkyColors {
+            zenith: [0.12, 0.16, 0.35],
+            horizon: [0.85, 0.45, 0.25],
+            ambient: [0.25, 0.2, 0.22],
+            sun: [0.0, 0.0, 0.0],
+        },
+    ),
+    (
+        0.15,
+        SkyColors {
+            zenith: [0.15, 0.3, 0.65],
+            horizon: [0.75, 0.65, 0.55],
+            ambient: [0.4, 0.38, 0.4],
+            sun: [0.55, 0.4, 0.25],
+        },
+    ),
+    (
+        0.5,
+        SkyColors {
+            zenith: [0.12, 0.32, 0.75],
+            horizon: [0.6, 0.75, 0.9],
+            ambient: [0.5, 0.5, 0.55],
+            sun: [0.55, 0.53, 0.5],
+        },
+    ),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Lighting {
+    pub sun_direction: Vec3,
+    pub colors: SkyColors,
+}
+
+impl Lighting {
+    pub fn for_sun(sun_direction: Vec3) -> Self {
+        let elevation = sun_direction.y;
+        let colors = match SKY_KEYS.iter().position(|(key, _)| elevation < *key) {
+            Some(0) => SKY_KEYS[0].1,
+            Some(next) => {
+                let (low, a) = SKY_KEYS[next - 1];
+                let (high, b) = SKY_KEYS[next];
+                a.lerp(&b, (elevation - low) / (high - low))
+            }
+            None => SKY_KEYS[SKY_KEYS.len() - 1].1,
+        };
+        Lighting {
+            sun_direction,
+            colors,
+        }
+    }
+
+    // Fog fades into the horizon so the far edge of the world blends into
+    // the sky.
+    pub fn fog_color(&self) -> [f32; 4] {
+        let [r, g, b] = self.colors.horizon;
+        [r, g, b, 1.0]
+    }
+}
+
+impl SkyColors {
+    fn lerp(&self, other: &SkyColors, t: f32) -> SkyColors {
+        let mix = |a: [f32; 3], b: [f32; 3]| {
+            [
+                a[0] + (b[0] - a[0]) * t,
+                a[1] + (b[1] - a[1]) * t,
+                a[2] + (b[2] - a[2]) * t,
+            ]
+        };
+        SkyColors {
+            zenith: mix(self.zenith, other.zenith),
+            horizon: mix(self.horizon, other.horizon),
+            ambient: mix(self.ambient, other.ambient),
+            sun: mix(self.sun, other.sun),
+        }
+    }
+}
+
+// Distance fog in blocks from the camera, fully fogged at end.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FogSettings {
+    pub start: f32,
+    pub end: f32,
+}
+
+impl FogSettings {
+    // Ends where the first unloaded chunks can be, view_distance chunks
+    // out minus the chunk the camera is in, so streaming edges are never
+    // visible.
+    pub fn for_view_distance(view_distance: i32, chunk_size: usize) -> Self {
+        let end = ((view_distance - 1).max(1) as usize * chunk_size) as f32;
+        FogSettings {
+            start: end * 0.5,
+            end,
+        }
+    }
+
+    // CPU reference of the fog term in PSMain: 0 for no fog, 1 for only fog.
+    #[cfg(test)]
+    pub fn factor(&self, distance: f32) -> f32 {
+        ((distance - self.start) / (self.end - self.start)).clamp(0.0, 1.0)
+    }
+}
+
+// Constant buffer of the main and the sky pass, laid out like the
+// LightingConstants cbuffer in shaders/lighting.hlsli.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LightingConstants {
+    pub camera_forward: [f32; 3],
+    pub fog_start: f32,
+    pub camera_right: [f32; 3],
+    pub fog_end: f32,
+    pub camera_up: [f32; 3],
+    pub padding0: f32,
+    pub sun_direction: [f32; 3],
+    pub padding1: f32,
+    pub sun_color: [f32; 3],
+    pub padding2: f32,
+    pub ambient: [f32; 3],
+    pub padding3: f32,
+    pub zenith: [f32; 3],
+    pub padding4: f32,
+    pub horizon: [f32; 3],
+    pub padding5: f32,
+}
+
+impl LightingConstants {
+    pub fn new(camera: &Camera, aspect_ratio: f32, lighting: &Lighting, fog: &FogSettings) -> Self {
+        let (forward, right, up) = camera.screen_axes(aspect_ratio);
+        let sun = lighting.sun_direction;
+        LightingConstants {
+            camera_forward: [forward.x, forward.y, forward.z],
+            fog_start: fog.start,
+            camera_right: [right.x, right.y, right.z],
+            fog_end: fog.end,
+            camera_up: [up.x, up.y, up.z],
+            sun_direction: [sun.x, sun.y, sun.z],
+            sun_color: lighting.colors.sun,
+            ambient: lighting.colors.ambient,
+            zenith: lighting.colors.zenith,
+            horizon: lighting.colors.horizon,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-4, "{} against {}", a, b);
+    }
+
+    #[test]
+    fn fog_ends_before_the_last_loaded_chunks() {
+        let fog = FogSettings::for_view_distance(8, 32);
+        assert_eq!(fog, FogSettings { start: 112.0, end: 224.0 });
+        assert_eq!(FogSettings::for_view_distance(1, 32).end, 32.0);
+    }
+
+    #[test]
+    fn fog_factor_ramps_between_start_and_end() {
+        let fog = FogSettings { start: 100.0, end: 200.0 };
+        assert_eq!(fog.factor(0.0), 0.0);
+        assert_eq!(fog.factor(100.0), 0.0);
+        assert_close(fog.factor(125.0), 0.25);
+        assert_close(fog.factor(150.0), 0.5);
+        assert_eq!(fog.factor(200.0), 1.0);
+        assert_eq!(fog.factor(1000.0), 1.0);
+    }
+
+    #[test]
+    fn sun_follows_the_time_of_day() {
+        let mut cycle = DayCycle::new(&DaySettings::default());
+        cycle.set_time(0.25);
+        let sunrise = cycle.sun_direction();
+        assert_close(sunrise.x, 1.0);
+        assert_close(sunrise.y, 0.0);
+
+        cycle.set_time(0.5);
+        let noon = cycle.sun_direction();
+        assert_close(noon.y, SUN_TILT.cos());
+        assert!(noon.z > 0.0);
+
+        cycle.set_time(0.0);
+        assert!(cycle.sun_direction().y < 0.0);
+    }
+
+    #[test]
+    fn days_wrap_and_pause() {
+        let mut cycle = DayCycle::new(&DaySettings {
+            day_length: 100.0,
+            start_time: 0.9,
+            time_scale: 2.0,
+        });
+        cycle.advance(10.0);
+        assert_close(cycle.time(), 0.1);
+        cycle.paused = true;
+        cycle.advance(10.0);
+        assert_close(cycle.time(), 0.1);
+        cycle.set_time(-0.25);
+        assert_close(cycle.time(), 0.75);
+    }
+
+    #[test]
+    fn sky_colors_interpolate_between_keys() {
+        let (low, a) = SKY_KEYS[2];
+        let (high, b) = SKY_KEYS[3];
+        let middle = Lighting::for_sun(Vec3::new(0.0, (low + high) / 2.0, 1.0));
+        for channel in 0..3 {
+            assert_close(middle.colors.zenith[channel], (a.zenith[channel] + b.zenith[channel]) / 2.0);
+        }
+        assert_eq!(Lighting::for_sun(Vec3::new(0.0, 1.0, 0.0)).colors, b);
+        assert_eq!(Lighting::for_sun(Vec3::new(0.0, -1.0, 0.0)).colors, SKY_KEYS[0].1);
+        let lighting = Lighting::for_sun(Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(lighting.fog_color(), [b.horizon[0], b.horizon[1], b.horizon[2], 1.0]);
+    }
+}
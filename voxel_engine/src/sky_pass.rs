@@ -0,0 +1,119 @@
+use windows::{
+    core::*, Win32::Foundation::*, Win32::Graphics::Direct3D::*,
+    Win32::Graphics::Direct3D12::*, Win32::Graphics::Dxgi::Common::*,
+};
+
+use crate::markers::set_name;
+use crate::sky::LightingConstants;
+use crate::{convert_to_bytecode, create_buffer, DEPTH_FORMAT, SCENE_FORMAT};
+
+// Sky gradient drawn behind the chunks with shaders/sky.hlsl, and the
+// lighting constants the main pass shades and fogs the chunks with.
+pub struct SkyPass {
+    pso: ID3D12PipelineState,
+    constant_buffer: ID3D12Resource,
+}
+
+impl SkyPass {
+    pub fn new(device: &ID3D12Device, root_signature: &ID3D12RootSignature, sample_count: u32) -> Result<Self> {
+        let pso = create_sky_pipeline_state(device, root_signature, sample_count)?;
+        let constant_buffer = create_buffer(
+            device,
+            D3D12_HEAP_TYPE_UPLOAD,
+            std::mem::size_of::<LightingConstants>() as u64,
+            D3D12_RESOURCE_FLAG_NONE,
+            D3D12_RESOURCE_STATE_GENERIC_READ,
+        )?;
+        set_name(&pso, "sky pso");
+        set_name(&constant_buffer, "lighting constants");
+        Ok(SkyPass {
+            pso,
+            constant_buffer,
+        })
+    }
+
+    // Only valid while the GPU is not reading the previous constants, which
+    // holds between frames.
+    pub fn set_constants(&self, constants: &LightingConstants) -> Result<()> {
+        unsafe {
+            let mut mapped = std::ptr::null_mut();
+            self.constant_buffer.Map(0, Some(&D3D12_RANGE { Begin: 0, End: 0 }), Some(&mut mapped))?;
+            std::ptr::write(mapped as *mut LightingConstants, *constants);
+            self.constant_buffer.Unmap(0, None);
+        }
+        Ok(())
+    }
+
+    // Binds the lighting constants, after the main root signature is set.
+    pub fn bind(&self, command_list: &ID3D12GraphicsCommandList) {
+        unsafe {
+            command_list.SetGraphicsRootConstantBufferView(3, self.constant_buffer.GetGPUVirtualAddress());
+        }
+    }
+
+    // Expects the main pass state: render target, depth buffer, viewport
+    // and bound constants. Changes the pipeline state and topology.
+    pub fn record(&self, command_list: &ID3D12GraphicsCommandList) {
+        unsafe {
+            command_list.SetPipelineState(&self.pso);
+            command_list.IASetPrimitiveTopology(D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST);
+            command_list.DrawInstanced(3, 1, 0, 0);
+        }
+    }
+}
+
+// No vertex input, depth tested against the chunks but never written.
+fn create_sky_pipeline_state(
+    device: &ID3D12Device,
+    root_signature: &ID3D12RootSignature,
+    sample_count: u32,
+) -> Result<ID3D12PipelineState> {
+    let read = |path: &str| {
+        std::fs::read(path).map_err(|error| Error::new(E_FAIL, error.to_string().into()))
+    };
+    let vs_bin = read("resources/sky_vs.bin")?;
+    let ps_bin = read("resources/sky_ps.bin")?;
+
+    let mut desc = D3D12_GRAPHICS_PIPELINE_STATE_DESC {
+        pRootSignature: unsafe { std::mem::transmute_copy(root_signature) },
+        VS: convert_to_bytecode(&vs_bin),
+        PS: convert_to_bytecode(&ps_bin),
+        RasterizerState: D3D12_RASTERIZER_DESC {
+            FillMode: D3D12_FILL_MODE_SOLID,
+            CullMode: D3D12_CULL_MODE_NONE,
+            ..Default::default()
+        },
+        BlendState: D3D12_BLEND_DESC {
+            RenderTarget: [D3D12_RENDER_TARGET_BLEND_DESC {
+                SrcBlend: D3D12_BLEND_ONE,
+                DestBlend: D3D12_BLEND_ZERO,
+                BlendOp: D3D12_BLEND_OP_ADD,
+                SrcBlendAlpha: D3D12_BLEND_ONE,
+                DestBlendAlpha: D3D12_BLEND_ZERO,
+                BlendOpAlpha: D3D12_BLEND_OP_ADD,
+                LogicOp: D3D12_LOGIC_OP_NOOP,
+                RenderTargetWriteMask: D3D12_COLOR_WRITE_ENABLE_ALL.0 as u8,
+                ..Default::default()
+            }; 8],
+            ..Default::default()
+        },
+        DepthStencilState: D3D12_DEPTH_STENCIL_DESC {
+            DepthEnable: true.into(),
+            DepthWriteMask: D3D12_DEPTH_WRITE_MASK_ZERO,
+            DepthFunc: D3D12_COMPARISON_FUNC_LESS_EQUAL,
+            ..Default::default()
+        },
+        DSVFormat: DEPTH_FORMAT,
+        SampleMask: u32::MAX,
+        PrimitiveTopologyType: D3D12_PRIMITIVE_TOPOLOGY_TYPE_TRIANGLE,
+        NumRenderTargets: 1,
+        SampleDesc: DXGI_SAMPLE_DESC {
+            Count: sample_count,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    desc.RTVFormats[0] = SCENE_FORMAT;
+
+    unsafe { device.CreateGraphicsPipelineState(&desc) }
+}
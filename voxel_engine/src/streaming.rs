@@ -0,0 +1,161 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ops::RangeInclusive;
+
+use crate::chunk::ChunkPos;
+use crate::lod::LodSettings;
+
+// View distances the settings accept, in chunks.
+pub const VIEW_DISTANCES: RangeInclusive<i32> = 2..=32;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StreamingSettings {
+    // Horizontal radius around the camera, in chunks.
+    pub view_distance: i32,
+    // Vertical range of chunk rows kept resident.
+    pub min_chunk_y: i32,
+    pub max_chunk_y: i32,
+    pub max_loads_per_update: usize,
+    pub lod: LodSettings,
+}
+
+impl Default for StreamingSettings {
+    fn default() -> Self {
+        StreamingSettings {
+            view_distance: 8,
+            min_chunk_y: 0,
+            max_chunk_y: 2,
+            max_loads_per_update: 16,
+            lod: LodSettings::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StreamingUpdate {
+    pub load: Vec<ChunkPos>,
+    pub unload: Vec<ChunkPos>,
+    // Resident chunks whose mesh is stale, including neighbours of chunks
+    // that were loaded, unloaded or changed level of detail, since the skirts
+    // at a border depend on both sides.
+    pub remesh: Vec<ChunkPos>,
+}
+
+impl StreamingUpdate {
+    pub fn is_empty(&self) -> bool {
+        self.load.is_empty() && self.unload.is_empty() && self.remesh.is_empty()
+    }
+}
+
+// Decides which chunks are resident around the camera and at which level of
+// detail they are meshed. Loading, meshing and uploading are left to the
+// caller.
+pub struct ChunkStreamer {
+    settings: StreamingSettings,
+    resident: HashMap<ChunkPos, u32>,
+    // Chunks edited since the last update.
+    invalidated: BTreeSet<ChunkPos>,
+}
+
+impl ChunkStreamer {
+    pub fn new(settings: StreamingSettings) -> Self {
+        ChunkStreamer {
+            settings,
+            resident: HashMap::new(),
+            invalidated: BTreeSet::new(),
+        }
+    }
+
+    pub fn lod(&self, pos: ChunkPos) -> Option<u32> {
+        self.resident.get(&pos).copied()
+    }
+
+    pub fn resident_count(&self) -> usize {
+        self.resident.len()
+    }
+
+    pub fn view_distance(&self) -> i32 {
+        self.settings.view_distance
+    }
+
+    // Takes effect with the next update, which loads and unloads chunks to
+    // match.
+    pub fn set_view_distance(&mut self, view_distance: i32) {
+        self.settings.view_distance = view_distance;
+    }
+
+    // Queues a resident chunk for remeshing with the next update, after its
+    // blocks were changed.
+    pub fn invalidate(&mut self, pos: ChunkPos) {
+        self.invalidated.insert(pos);
+    }
+
+    pub fn update(&mut self, center: ChunkPos) -> StreamingUpdate {
+        let radius = self.settings.view_distance;
+        let mut desired = Vec::new();
+        for y in self.settings.min_chunk_y..=self.settings.max_chunk_y {
+            for z in center.z - radius..=center.z + radius {
+                for x in center.x - radius..=center.x + radius {
+                    let (dx, dz) = ((x - center.x) as f32, (z - center.z) as f32);
+                    if (dx * dx + dz * dz).sqrt() > radius as f32 {
+                        continue;
+                    }
+                    let dy = (y - center.y) as f32;
+                    let distance = (dx * dx + dy * dy + dz * dz).sqrt();
+                    desired.push((distance, ChunkPos::new(x, y, z)));
+                }
+            }
+        }
+        // Nearest first, so the load budget goes to what is in front of us.
+        desired.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+
+        let mut update = StreamingUpdate::default();
+        let mut stale = std::mem::take(&mut self.invalidated);
+
+        let keep: HashSet<ChunkPos> = desired.iter().map(|(_, pos)| *pos).collect();
+        let mut unload: Vec<ChunkPos> = self
+            .resident
+            .keys()
+            .filter(|pos| !keep.contains(pos))
+            .copied()
+            .collect();
+        unload.sort();
+        for pos in unload {
+            self.resident.remove(&pos);
+            mark_neighbours(&mut stale, pos);
+            update.unload.push(pos);
+        }
+
+        for (distance, pos) in desired {
+            let lod = self.settings.lod.select(distance);
+            match self.resident.get(&pos).copied() {
+                Some(current) if current == lod => {}
+                Some(_) => {
+                    self.resident.insert(pos, lod);
+                    stale.insert(pos);
+                    mark_neighbours(&mut stale, pos);
+                }
+                None => {
+                    if update.load.len() >= self.settings.max_loads_per_update {
+                        continue;
+                    }
+                    self.resident.insert(pos, lod);
+                    update.load.push(pos);
+                    stale.insert(pos);
+                    mark_neighbours(&mut stale, pos);
+                }
+            }
+        }
+
+        update.remesh = stale
+            .into_iter()
+            .filter(|pos| self.resident.contains_key(pos))
+            .collect();
+        update
+    }
+}
+
+fn mark_neighbours(stale: &mut BTreeSet<ChunkPos>, pos: ChunkPos) {
+    for (dx, dy, dz) in [(1, 0, 0), (-1, 0, 0), (0, 1, 0), (0, -1, 0), (0, 0, 1), (0, 0, -1)] {
+        stale.insert(pos.offset(dx, dy, dz));
+    }
+}
@@ -0,0 +1,619 @@
+#[cfg(test)]
+use std::io;
+
+use crate::block::BlockId;
+use crate::chunk::{Chunk, ChunkPos, CHUNK_SIZE};
+#[cfg(test)]
+use crate::math::Vec3;
+#[cfg(test)]
+use crate::raycast::RaycastHit;
+use crate::world::World;
+
+#[cfg(test)]
+const MAGIC: [u8; 4] = *b"VSVO";
+#[cfg(test)]
+const FORMAT_VERSION: u32 = 1;
+
+// Deepest tree from_bytes accepts, 65536 blocks per side.
+#[cfg(test)]
+const MAX_DEPTH: u32 = 16;
+
+const BRANCH_BIT: u32 = 1 << 31;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Node {
+    // The whole cube is a single block type, air included.
+    Uniform(BlockId),
+    // Index of the first of eight consecutive children, ordered like
+    // Aabb::corners: bit 0 selects the upper half in x, bit 1 in y, bit 2
+    // in z.
+    Branch(u32),
+}
+
+impl Node {
+    fn encode(self) -> u32 {
+        match self {
+            Node::Uniform(block) => block.0 as u32,
+            Node::Branch(first) => BRANCH_BIT | first,
+        }
+    }
+
+    #[cfg(test)]
+    fn decode(value: u32) -> io::Result<Node> {
+        if value & BRANCH_BIT != 0 {
+            Ok(Node::Branch(value & !BRANCH_BIT))
+        } else if value <= u16::MAX as u32 {
+            Ok(Node::Uniform(BlockId(value as u16)))
+        } else {
+            Err(invalid_data(format!("invalid octree node {:#x}", value)))
+        }
+    }
+}
+
+#[cfg(test)]
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+// Sparse voxel octree over a cube of 2^depth blocks. Cubes of a single block
+// type collapse into one node, so empty sky and solid ground cost next to
+// nothing compared to the 2 bytes per block of a chunk.
+//
+// Children are stored before their parent, which is what from_bytes relies
+// on to reject cycles.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SparseVoxelOctree {
+    // World block coordinates of the minimum corner.
+    origin: [i32; 3],
+    depth: u32,
+    root: Node,
+    nodes: Vec<Node>,
+}
+
+impl SparseVoxelOctree {
+    // Covers `chunks` chunks per side starting at `min`, rounded up to a
+    // power of two. Chunks that are not loaded read as air.
+    pub fn build(world: &World, min: ChunkPos, chunks: u32) -> Self {
+        let size = chunks.max(1).next_power_of_two() as usize * CHUNK_SIZE;
+        let origin = min.origin();
+        let mut nodes = Vec::new();
+        let root = build_node(world, origin, size, &mut nodes);
+        SparseVoxelOctree {
+            origin,
+            depth: size.trailing_zeros(),
+            root,
+            nodes,
+        }
+    }
+
+    // Covers every loaded chunk, None when there is none.
+    pub fn from_world(world: &World) -> Option<Self> {
+        let positions: Vec<ChunkPos> = world.chunks().map(|(pos, _)| pos).collect();
+        let min = positions.iter().copied().reduce(|a, b| {
+            ChunkPos::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z))
+        })?;
+        let extent = positions
+            .iter()
+            .map(|pos| (pos.x - min.x).max(pos.y - min.y).max(pos.z - min.z) + 1)
+            .max()?;
+        Some(SparseVoxelOctree::build(world, min, extent as u32))
+    }
+
+    pub fn origin(&self) -> [i32; 3] {
+        self.origin
+    }
+
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    // Nodes as stored by to_bytes, for upload to the GPU.
+    pub fn encoded_root(&self) -> u32 {
+        self.root.encode()
+    }
+
+    pub fn encoded_nodes(&self) -> Vec<u32> {
+        self.nodes.iter().map(|node| node.encode()).collect()
+    }
+
+    #[cfg(test)]
+    pub fn size(&self) -> i32 {
+        1 << self.depth
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len() + 1
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>() + self.nodes.len() * std::mem::size_of::<Node>()
+    }
+
+    // Air outside the octree.
+    #[cfg(test)]
+    pub fn get(&self, block: [i32; 3]) -> BlockId {
+        let local = [
+            block[0] - self.origin[0],
+            block[1] - self.origin[1],
+            block[2] - self.origin[2],
+        ];
+        if local.iter().any(|&coordinate| !(0..self.size()).contains(&coordinate)) {
+            return BlockId::AIR;
+        }
+        self.leaf(local).0
+    }
+
+    // The leaf holding a block given in local coordinates, with its minimum
+    // corner, also local, and size.
+    #[cfg(test)]
+    fn leaf(&self, local: [i32; 3]) -> (BlockId, [i32; 3], i32) {
+        let mut node = self.root;
+        let mut min = [0; 3];
+        let mut size = self.size();
+        loop {
+            match node {
+                Node::Uniform(block) => return (block, min, size),
+                Node::Branch(first) => {
+                    size /= 2;
+                    let mut child = 0;
+                    for axis in 0..3 {
+                        if local[axis] & size != 0 {
+                            child |= 1 << axis;
+                            min[axis] += size;
+                        }
+                    }
+                    node = self.nodes[first as usize + child];
+                }
+            }
+        }
+    }
+
+    // Same contract as raycast::raycast over the blocks of the octree, but
+    // empty cubes are crossed in a single step instead of block by block.
+    #[cfg(test)]
+    pub fn raycast(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<RaycastHit> {
+        let direction = direction.normalize();
+        let o = [origin.x, origin.y, origin.z];
+        let d = [direction.x, direction.y, direction.z];
+        let size = self.size();
+        let low = self.origin.map(|coordinate| coordinate as f32);
+
+        // Clip the ray against the bounds of the octree.
+        let mut enter = 0.0_f32;
+        let mut exit = max_distance;
+        let mut enter_axis = None;
+        for axis in 0..3 {
+            if d[axis] == 0.0 {
+                if o[axis] < low[axis] || o[axis] >= low[axis] + size as f32 {
+                    return None;
+                }
+                continue;
+            }
+            let near = (low[axis] - o[axis]) / d[axis];
+            let far = (low[axis] + size as f32 - o[axis]) / d[axis];
+            let (near, far) = (near.min(far), near.max(far));
+            if near > enter {
+                enter = near;
+                enter_axis = Some(axis);
+            }
+            exit = exit.min(far);
+        }
+        if enter > exit {
+            return None;
+        }
+
+        let mut cell = [0; 3];
+        for axis in 0..3 {
+            let coordinate = (o[axis] + d[axis] * enter).floor() as i32 - self.origin[axis];
+            cell[axis] = coordinate.clamp(0, size - 1);
+        }
+        let mut normal = [0; 3];
+        if let Some(axis) = enter_axis {
+            cell[axis] = if d[axis] > 0.0 { 0 } else { size - 1 };
+            normal[axis] = if d[axis] > 0.0 { -1 } else { 1 };
+        }
+        let mut distance = enter;
+
+        loop {
+            let (block, min, extent) = self.leaf(cell);
+            if !block.is_air() {
+                return Some(RaycastHit {
+                    block: [
+                        cell[0] + self.origin[0],
+                        cell[1] + self.origin[1],
+                        cell[2] + self.origin[2],
+                    ],
+                    normal,
+                    distance,
+                });
+            }
+
+            // Leave the empty cube through the nearest face.
+            let mut leave = f32::INFINITY;
+            let mut leave_axis = 0;
+            for axis in 0..3 {
+                let face = if d[axis] > 0.0 {
+                    min[axis] + extent
+                } else if d[axis] < 0.0 {
+                    min[axis]
+                } else {
+                    continue;
+                };
+                let t = ((face + self.origin[axis]) as f32 - o[axis]) / d[axis];
+                if t < leave {
+                    leave = t;
+                    leave_axis = axis;
+                }
+            }
+            if leave > max_distance {
+                return None;
+            }
+            distance = leave;
+
+            for axis in 0..3 {
+                cell[axis] = if axis != leave_axis {
+                    let coordinate = (o[axis] + d[axis] * leave).floor() as i32 - self.origin[axis];
+                    coordinate.clamp(min[axis], min[axis] + extent - 1)
+                } else if d[axis] > 0.0 {
+                    min[axis] + extent
+                } else {
+                    min[axis] - 1
+                };
+            }
+            normal = [0; 3];
+            normal[leave_axis] = if d[leave_axis] > 0.0 { -1 } else { 1 };
+            if cell.iter().any(|&coordinate| !(0..size).contains(&coordinate)) {
+                return None;
+            }
+        }
+    }
+
+    //   magic, u32 version, i32 origin[3], u32 depth, u32 node count,
+    //   u32 root, u32 per node
+    //
+    // A node is a block id, or the index of its first child with the top
+    // bit set.
+    #[cfg(test)]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(32 + 4 * self.nodes.len());
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        for coordinate in self.origin {
+            bytes.extend_from_slice(&coordinate.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.depth.to_le_bytes());
+        bytes.extend_from_slice(&(self.nodes.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.root.encode().to_le_bytes());
+        for node in &self.nodes {
+            bytes.extend_from_slice(&node.encode().to_le_bytes());
+        }
+        bytes
+    }
+
+    #[cfg(test)]
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let words: Vec<u32> = bytes
+            .get(4..)
+            .filter(|_| bytes[0..4] == MAGIC)
+            .ok_or_else(|| invalid_data("not an octree"))?
+            .chunks(4)
+            .map(|word| {
+                word.try_into()
+                    .map(u32::from_le_bytes)
+                    .map_err(|_| invalid_data("truncated octree"))
+            })
+            .collect::<io::Result<_>>()?;
+        if words.len() < 7 {
+            return Err(invalid_data("truncated octree"));
+        }
+        if words[0] != FORMAT_VERSION {
+            return Err(invalid_data(format!(
+                "unsupported octree format version {}",
+                words[0]
+            )));
+        }
+        let origin = [words[1] as i32, words[2] as i32, words[3] as i32];
+        let depth = words[4];
+        let node_count = words[5] as usize;
+        if depth > MAX_DEPTH {
+            return Err(invalid_data(format!("octree depth {}", depth)));
+        }
+        if words.len() != 7 + node_count || !node_count.is_multiple_of(8) {
+            return Err(invalid_data("octree node count mismatch"));
+        }
+        let root = Node::decode(words[6])?;
+        let nodes = words[7..]
+            .iter()
+            .map(|word| Node::decode(*word))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        // Children come before their parent's group, so heights resolve in
+        // index order and no node can reach itself.
+        let mut heights = vec![0_u32; nodes.len()];
+        let height = |node: Node, limit: usize, heights: &[u32]| -> io::Result<u32> {
+            match node {
+                Node::Uniform(_) => Ok(0),
+                Node::Branch(first) => {
+                    let first = first as usize;
+                    if !first.is_multiple_of(8) || first + 8 > limit {
+                        return Err(invalid_data("octree child out of order"));
+                    }
+                    Ok(1 + heights[first..first + 8].iter().max().unwrap())
+                }
+            }
+        };
+        for (index, node) in nodes.iter().enumerate() {
+            heights[index] = height(*node, index - index % 8, &heights)?;
+        }
+        if height(root, nodes.len(), &heights)? > depth {
+            return Err(invalid_data("octree deeper than its size"));
+        }
+
+        Ok(SparseVoxelOctree {
+            origin,
+            depth,
+            root,
+            nodes,
+        })
+    }
+}
+
+// Collapses eight children of the same uniform block into one node,
+// otherwise appends them as a group.
+fn join(children: [Node; 8], nodes: &mut Vec<Node>) -> Node {
+    if let Node::Uniform(block) = children[0] {
+        if children.iter().all(|child| *child == Node::Uniform(block)) {
+            return Node::Uniform(block);
+        }
+    }
+    let first = nodes.len() as u32;
+    nodes.extend_from_slice(&children);
+    Node::Branch(first)
+}
+
+// Cubes of at least a chunk, `min` in world block coordinates.
+fn build_node(world: &World, min: [i32; 3], size: usize, nodes: &mut Vec<Node>) -> Node {
+    if size == CHUNK_SIZE {
+        return match world.chunk(ChunkPos::from_block(min)) {
+            Some(chunk) => build_chunk_node(chunk, [0; 3], size, nodes),
+            None => Node::Uniform(BlockId::AIR),
+        };
+    }
+    let half = size / 2;
+    let children = std::array::from_fn(|child| {
+        let offset = corner_offset(child, half);
+        let child_min = [
+            min[0] + offset[0] as i32,
+            min[1] + offset[1] as i32,
+            min[2] + offset[2] as i32,
+        ];
+        build_node(world, child_min, half, nodes)
+    });
+    join(children, nodes)
+}
+
+// Cubes inside a chunk, `min` in chunk local coordinates.
+fn build_chunk_node(chunk: &Chunk, min: [usize; 3], size: usize, nodes: &mut Vec<Node>) -> Node {
+    if size == 1 {
+        return Node::Uniform(chunk.get(min[0], min[1], min[2]));
+    }
+    let half = size / 2;
+    let children = std::array::from_fn(|child| {
+        let offset = corner_offset(child, half);
+        let child_min = [min[0] + offset[0], min[1] + offset[1], min[2] + offset[2]];
+        build_chunk_node(chunk, child_min, half, nodes)
+    });
+    join(children, nodes)
+}
+
+fn corner_offset(child: usize, half: usize) -> [usize; 3] {
+    [child & 1, (child >> 1) & 1, (child >> 2) & 1].map(|bit| bit * half)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::STONE;
+    use crate::chunk::CHUNK_VOLUME;
+    use crate::raycast::raycast;
+    use crate::world::generate_chunk;
+    use std::time::Instant;
+
+    // Generated terrain over 4 by 2 by 4 chunks, with a few blocks carved
+    // out and placed so that cubes inside chunks split as well.
+    fn terrain() -> World {
+        let mut world = World::new();
+        for x in -2..2 {
+            for y in 0..2 {
+                for z in -2..2 {
+                    let pos = ChunkPos::new(x, y, z);
+                    world.insert_chunk(pos, generate_chunk(pos));
+                }
+            }
+        }
+        for x in -10..10 {
+            assert!(world.set_block([x, 60, 3], STONE));
+            assert!(world.set_block([x, 30, 3], BlockId::AIR));
+        }
+        world
+    }
+
+    // xorshift, enough to spread test rays without a dependency.
+    struct Random(u32);
+
+    impl Random {
+        fn next(&mut self) -> f32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0 as f32 / u32::MAX as f32
+        }
+
+        fn range(&mut self, min: f32, max: f32) -> f32 {
+            min + (max - min) * self.next()
+        }
+    }
+
+    #[test]
+    fn get_matches_the_world() {
+        let world = terrain();
+        let octree = SparseVoxelOctree::from_world(&world).unwrap();
+        assert_eq!(octree.origin(), [-64, 0, -64]);
+        assert_eq!(octree.size(), 128);
+
+        for y in -1..octree.size() + 1 {
+            for z in -65..65 {
+                for x in -65..65 {
+                    let dense = world.block([x, y, z]).unwrap_or(BlockId::AIR);
+                    assert_eq!(octree.get([x, y, z]), dense, "block {:?}", [x, y, z]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn uniform_chunks_collapse() {
+        let mut world = World::new();
+        world.insert_chunk(ChunkPos::new(0, 0, 0), Chunk::filled(STONE));
+        world.insert_chunk(ChunkPos::new(1, 0, 0), Chunk::new());
+        let octree = SparseVoxelOctree::from_world(&world).unwrap();
+        assert_eq!(octree.node_count(), 9);
+        assert_eq!(octree.get([5, 5, 5]), STONE);
+        assert_eq!(octree.get([40, 5, 5]), BlockId::AIR);
+        assert_eq!(octree.get([5, 40, 5]), BlockId::AIR);
+    }
+
+    #[test]
+    fn raycasts_match_the_world() {
+        let world = terrain();
+        let octree = SparseVoxelOctree::from_world(&world).unwrap();
+        let mut random = Random(0x9e37_79b9);
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let origin = Vec3::new(
+                random.range(-80.0, 80.0),
+                random.range(-10.0, 80.0),
+                random.range(-80.0, 80.0),
+            );
+            let direction = Vec3::new(
+                random.range(-1.0, 1.0),
+                random.range(-1.0, 1.0),
+                random.range(-1.0, 1.0),
+            );
+            if direction.length() < 0.01 {
+                continue;
+            }
+            let sparse = octree.raycast(origin, direction, 200.0);
+            let dense = raycast(origin, direction, 200.0, |block| world.is_solid(block));
+            match (sparse, dense) {
+                (Some(sparse), Some(dense)) => {
+                    assert_eq!(
+                        (sparse.block, sparse.normal),
+                        (dense.block, dense.normal),
+                        "ray from {:?} along {:?}",
+                        origin,
+                        direction
+                    );
+                    assert!((sparse.distance - dense.distance).abs() < 1e-3);
+                    hits += 1;
+                }
+                (None, None) => {}
+                _ => panic!("ray from {:?} along {:?}: {:?} against {:?}", origin, direction, sparse, dense),
+            }
+        }
+        assert!(hits > 500, "only {} rays hit", hits);
+    }
+
+    #[test]
+    fn axis_aligned_raycasts_match_the_world() {
+        let world = terrain();
+        let octree = SparseVoxelOctree::from_world(&world).unwrap();
+        let directions = [
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(-1.0, 0.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        ];
+        for x in (-70..70).step_by(7) {
+            for z in (-70..70).step_by(11) {
+                let origin = Vec3::new(x as f32 + 0.5, 45.5, z as f32 + 0.5);
+                for direction in directions {
+                    let sparse = octree.raycast(origin, direction, 200.0);
+                    let dense = raycast(origin, direction, 200.0, |block| world.is_solid(block));
+                    assert_eq!(
+                        sparse.map(|hit| (hit.block, hit.normal)),
+                        dense.map(|hit| (hit.block, hit.normal)),
+                        "ray from {:?} along {:?}",
+                        origin,
+                        direction
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let octree = SparseVoxelOctree::from_world(&terrain()).unwrap();
+        let bytes = octree.to_bytes();
+        assert_eq!(SparseVoxelOctree::from_bytes(&bytes).unwrap(), octree);
+
+        assert!(SparseVoxelOctree::from_bytes(&bytes[..bytes.len() - 2]).is_err());
+        let mut cycle = bytes.clone();
+        cycle[32..36].copy_from_slice(&Node::Branch(0).encode().to_le_bytes());
+        assert!(SparseVoxelOctree::from_bytes(&cycle).is_err());
+    }
+
+    // Memory and raycast timings against the chunks, run with
+    // cargo test --release -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_against_chunks() {
+        let world = terrain();
+        let start = Instant::now();
+        let octree = SparseVoxelOctree::from_world(&world).unwrap();
+        let build_time = start.elapsed();
+        let chunk_count = world.chunks().count();
+        let dense = chunk_count * CHUNK_VOLUME * std::mem::size_of::<BlockId>();
+        println!(
+            "octree over {} chunks: {} nodes, {} KiB in memory, {} KiB serialized, built in {:.1?}; chunks hold {} KiB",
+            chunk_count,
+            octree.node_count(),
+            octree.memory_usage() / 1024,
+            octree.to_bytes().len() / 1024,
+            build_time,
+            dense / 1024
+        );
+
+        let mut random = Random(0x2545_f491);
+        let rays: Vec<(Vec3, Vec3)> = (0..10000)
+            .map(|_| {
+                let origin = Vec3::new(random.range(-60.0, 60.0), 70.0, random.range(-60.0, 60.0));
+                let direction = Vec3::new(random.range(-1.0, 1.0), -0.3, random.range(-1.0, 1.0));
+                (origin, direction)
+            })
+            .collect();
+        let start = Instant::now();
+        let sparse_hits = rays
+            .iter()
+            .filter(|(origin, direction)| octree.raycast(*origin, *direction, 200.0).is_some())
+            .count();
+        let sparse_time = start.elapsed();
+        let start = Instant::now();
+        let dense_hits = rays
+            .iter()
+            .filter(|(origin, direction)| {
+                raycast(*origin, *direction, 200.0, |block| world.is_solid(block)).is_some()
+            })
+            .count();
+        let dense_time = start.elapsed();
+        println!(
+            "{} rays: octree {:.1?} ({} hits), chunks {:.1?} ({} hits)",
+            rays.len(),
+            sparse_time,
+            sparse_hits,
+            dense_time,
+            dense_hits
+        );
+    }
+}
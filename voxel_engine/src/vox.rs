@@ -0,0 +1,776 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+
+use crate::block::{BlockId, BlockRegistry};
+use crate::chunk::{chunks_touching, ChunkPos};
+use crate::world::World;
+
+// MagicaVoxel .vox files, see
+// https://github.com/ephtracy/voxel-model/blob/master/MagicaVoxel-file-format-vox.txt
+//
+// MagicaVoxel is z-up, the engine is y-up: a voxel at (x, y, z) in a model
+// ends up at block (x, z, y), which also turns its right-handed frame into
+// our left-handed one.
+
+const MAGIC: [u8; 4] = *b"VOX ";
+const VERSION: u32 = 150;
+
+// Largest model extent the format allows per axis.
+pub const MAX_MODEL_SIZE: u32 = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Voxel {
+    pub position: [u8; 3],
+    // 1 to 255, an index into VoxFile::palette.
+    pub color_index: u8,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VoxModel {
+    pub size: [u32; 3],
+    pub voxels: Vec<Voxel>,
+}
+
+// Key-value attributes of scene nodes, in file order.
+pub type Dict = Vec<(String, String)>;
+
+fn lookup<'a>(dict: &'a Dict, key: &str) -> Option<&'a str> {
+    dict.iter()
+        .find(|(name, _)| name == key)
+        .map(|(_, value)| value.as_str())
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SceneNode {
+    // nTRN, frames hold the _t translation and _r rotation attributes.
+    Transform {
+        attributes: Dict,
+        child: u32,
+        layer: i32,
+        frames: Vec<Dict>,
+    },
+    // nGRP
+    Group { attributes: Dict, children: Vec<u32> },
+    // nSHP, the model ids with their per-model attributes.
+    Shape {
+        attributes: Dict,
+        models: Vec<(u32, Dict)>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VoxFile {
+    pub version: u32,
+    pub models: Vec<VoxModel>,
+    // RGBA of every color index. Index 0 is empty space and never used by a
+    // voxel.
+    pub palette: [[u8; 4]; 256],
+    // Scene graph by node id, node 0 is the root transform. Files without
+    // one hold a single model.
+    pub nodes: BTreeMap<u32, SceneNode>,
+}
+
+// A model placed by the scene graph: block = rotation * (voxel - pivot) +
+// translation, in MagicaVoxel coordinates, with the pivot at the model's
+// center.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VoxInstance {
+    pub model: usize,
+    pub rotation: [[i32; 3]; 3],
+    pub translation: [i32; 3],
+}
+
+const IDENTITY: [[i32; 3]; 3] = [[1, 0, 0], [0, 1, 0], [0, 0, 1]];
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+impl VoxFile {
+    pub fn new() -> Self {
+        VoxFile {
+            version: VERSION,
+            models: Vec::new(),
+            palette: default_palette(),
+            nodes: BTreeMap::new(),
+        }
+    }
+
+    pub fn parse(bytes: &[u8]) -> io::Result<Self> {
+        let mut reader = Reader { bytes };
+        if reader.take::<4>()? != MAGIC {
+            return Err(invalid_data("not a .vox file"));
+        }
+        let version = reader.u32()?;
+
+        let (id, _, main) = reader.chunk()?;
+        if id != *b"MAIN" {
+            return Err(invalid_data("missing MAIN chunk"));
+        }
+        let mut children = Reader { bytes: main };
+
+        let mut file = VoxFile {
+            version,
+            ..VoxFile::new()
+        };
+        let mut size = None;
+        while !children.bytes.is_empty() {
+            let (id, content, _) = children.chunk()?;
+            let mut content = Reader { bytes: content };
+            match &id {
+                b"SIZE" => {
+                    let model_size = [content.u32()?, content.u32()?, content.u32()?];
+                    if model_size.iter().any(|&extent| extent > MAX_MODEL_SIZE) {
+                        return Err(invalid_data(format!("model size {:?}", model_size)));
+                    }
+                    size = Some(model_size);
+                }
+                b"XYZI" => {
+                    let size = size
+                        .take()
+                        .ok_or_else(|| invalid_data("XYZI chunk without SIZE"))?;
+                    let count = content.u32()?;
+                    let voxels = (0..count)
+                        .map(|_| {
+                            let [x, y, z, color_index] = content.take::<4>()?;
+                            Ok(Voxel {
+                                position: [x, y, z],
+                                color_index,
+                            })
+                        })
+                        .collect::<io::Result<_>>()?;
+                    file.models.push(VoxModel { size, voxels });
+                }
+                b"RGBA" => {
+                    // Entry i describes color index i + 1.
+                    for i in 0..256 {
+                        file.palette[(i + 1) % 256] = content.take::<4>()?;
+                    }
+                }
+                b"nTRN" => {
+                    let id = content.u32()?;
+                    let attributes = content.dict()?;
+                    let child = content.u32()?;
+                    let _reserved = content.u32()?;
+                    let layer = content.u32()? as i32;
+                    let frame_count = content.u32()?;
+                    let frames = (0..frame_count)
+                        .map(|_| content.dict())
+                        .collect::<io::Result<_>>()?;
+                    file.nodes.insert(
+                        id,
+                        SceneNode::Transform {
+                            attributes,
+                            child,
+                            layer,
+                            frames,
+                        },
+                    );
+                }
+                b"nGRP" => {
+                    let id = content.u32()?;
+                    let attributes = content.dict()?;
+                    let child_count = content.u32()?;
+                    let children = (0..child_count)
+                        .map(|_| content.u32())
+                        .collect::<io::Result<_>>()?;
+                    file.nodes
+                        .insert(id, SceneNode::Group { attributes, children });
+                }
+                b"nSHP" => {
+                    let id = content.u32()?;
+                    let attributes = content.dict()?;
+                    let model_count = content.u32()?;
+                    let models = (0..model_count)
+                        .map(|_| Ok((content.u32()?, content.dict()?)))
+                        .collect::<io::Result<_>>()?;
+                    file.nodes.insert(id, SceneNode::Shape { attributes, models });
+                }
+                // PACK, materials, layers, cameras and notes carry nothing
+                // the engine uses.
+                _ => {}
+            }
+        }
+
+        for model in &file.models {
+            let outside = model.voxels.iter().any(|voxel| {
+                (0..3).any(|axis| voxel.position[axis] as u32 >= model.size[axis])
+            });
+            if outside {
+                return Err(invalid_data("voxel outside its model"));
+            }
+        }
+        Ok(file)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut children = Vec::new();
+        for model in &self.models {
+            let mut size = Vec::new();
+            for extent in model.size {
+                size.extend_from_slice(&extent.to_le_bytes());
+            }
+            write_chunk(&mut children, b"SIZE", &size);
+
+            let mut xyzi = Vec::with_capacity(4 + 4 * model.voxels.len());
+            xyzi.extend_from_slice(&(model.voxels.len() as u32).to_le_bytes());
+            for voxel in &model.voxels {
+                xyzi.extend_from_slice(&voxel.position);
+                xyzi.push(voxel.color_index);
+            }
+            write_chunk(&mut children, b"XYZI", &xyzi);
+        }
+
+        for (id, node) in &self.nodes {
+            let mut content = id.to_le_bytes().to_vec();
+            match node {
+                SceneNode::Transform {
+                    attributes,
+                    child,
+                    layer,
+                    frames,
+                } => {
+                    write_dict(&mut content, attributes);
+                    content.extend_from_slice(&child.to_le_bytes());
+                    content.extend_from_slice(&(-1_i32).to_le_bytes());
+                    content.extend_from_slice(&layer.to_le_bytes());
+                    content.extend_from_slice(&(frames.len() as u32).to_le_bytes());
+                    for frame in frames {
+                        write_dict(&mut content, frame);
+                    }
+                    write_chunk(&mut children, b"nTRN", &content);
+                }
+                SceneNode::Group {
+                    attributes,
+                    children: group,
+                } => {
+                    write_dict(&mut content, attributes);
+                    content.extend_from_slice(&(group.len() as u32).to_le_bytes());
+                    for child in group {
+                        content.extend_from_slice(&child.to_le_bytes());
+                    }
+                    write_chunk(&mut children, b"nGRP", &content);
+                }
+                SceneNode::Shape { attributes, models } => {
+                    write_dict(&mut content, attributes);
+                    content.extend_from_slice(&(models.len() as u32).to_le_bytes());
+                    for (model, attributes) in models {
+                        content.extend_from_slice(&model.to_le_bytes());
+                        write_dict(&mut content, attributes);
+                    }
+                    write_chunk(&mut children, b"nSHP", &content);
+                }
+            }
+        }
+
+        let mut rgba = Vec::with_capacity(4 * 256);
+        for i in 0..256 {
+            rgba.extend_from_slice(&self.palette[(i + 1) % 256]);
+        }
+        write_chunk(&mut children, b"RGBA", &rgba);
+
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&self.version.to_le_bytes());
+        bytes.extend_from_slice(b"MAIN");
+        bytes.extend_from_slice(&0_u32.to_le_bytes());
+        bytes.extend_from_slice(&(children.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&children);
+        bytes
+    }
+
+    // Every model placement the scene graph describes. Without a scene
+    // graph each model is placed with its minimum corner at the origin.
+    pub fn instances(&self) -> io::Result<Vec<VoxInstance>> {
+        let mut instances = Vec::new();
+        if self.nodes.is_empty() {
+            for (model, data) in self.models.iter().enumerate() {
+                instances.push(VoxInstance {
+                    model,
+                    rotation: IDENTITY,
+                    translation: pivot(data.size),
+                });
+            }
+            return Ok(instances);
+        }
+
+        self.visit(0, IDENTITY, [0; 3], 0, &mut instances)?;
+        Ok(instances)
+    }
+
+    fn visit(
+        &self,
+        id: u32,
+        rotation: [[i32; 3]; 3],
+        translation: [i32; 3],
+        depth: usize,
+        instances: &mut Vec<VoxInstance>,
+    ) -> io::Result<()> {
+        // A graph deeper than it has nodes has a cycle.
+        if depth > self.nodes.len() {
+            return Err(invalid_data("cycle in the scene graph"));
+        }
+        let node = self
+            .nodes
+            .get(&id)
+            .ok_or_else(|| invalid_data(format!("missing scene node {}", id)))?;
+        match node {
+            SceneNode::Transform { child, frames, .. } => {
+                let frame = frames.first();
+                let local_rotation = match frame.and_then(|frame| lookup(frame, "_r")) {
+                    Some(value) => decode_rotation(parse_int(value)? as u8)?,
+                    None => IDENTITY,
+                };
+                let local_translation = match frame.and_then(|frame| lookup(frame, "_t")) {
+                    Some(value) => parse_vector(value)?,
+                    None => [0; 3],
+                };
+                let moved = rotate(&rotation, local_translation);
+                self.visit(
+                    *child,
+                    multiply(&rotation, &local_rotation),
+                    [
+                        translation[0] + moved[0],
+                        translation[1] + moved[1],
+                        translation[2] + moved[2],
+                    ],
+                    depth + 1,
+                    instances,
+                )
+            }
+            SceneNode::Group { children, .. } => {
+                for child in children {
+                    self.visit(*child, rotation, translation, depth + 1, instances)?;
+                }
+                Ok(())
+            }
+            SceneNode::Shape { models, .. } => {
+                for (model, _) in models {
+                    if *model as usize >= self.models.len() {
+                        return Err(invalid_data(format!("missing model {}", model)));
+                    }
+                    instances.push(VoxInstance {
+                        model: *model as usize,
+                        rotation,
+                        translation,
+                    });
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // Indices actually referenced by a voxel.
+    fn used_colors(&self) -> BTreeSet<u8> {
+        self.models
+            .iter()
+            .flat_map(|model| model.voxels.iter().map(|voxel| voxel.color_index))
+            .collect()
+    }
+}
+
+impl Default for VoxFile {
+    fn default() -> Self {
+        VoxFile::new()
+    }
+}
+
+// Block for every color index: the registered block with the closest
+// color, liquids aside. Index 0 maps to air.
+pub fn map_palette(file: &VoxFile, registry: &BlockRegistry) -> [BlockId; 256] {
+    let mut blocks = [BlockId::AIR; 256];
+    for index in file.used_colors() {
+        if index == 0 {
+            continue;
+        }
+        let rgba = file.palette[index as usize];
+        let color = rgba.map(|channel| channel as f32 / 255.0);
+        blocks[index as usize] = registry
+            .blocks()
+            .filter(|(_, definition)| !definition.liquid)
+            .min_by(|(_, a), (_, b)| {
+                color_distance(a.color, color).total_cmp(&color_distance(b.color, color))
+            })
+            .map_or(BlockId::AIR, |(id, _)| id);
+    }
+    blocks
+}
+
+fn color_distance(a: [f32; 4], b: [f32; 4]) -> f32 {
+    (0..3).map(|channel| (a[channel] - b[channel]).powi(2)).sum()
+}
+
+// Writes every instance of the file into the world, `origin` being where
+// the scene's origin lands. Voxels in chunks that are not loaded are
+// dropped. Returns the chunks whose meshes are stale.
+pub fn stamp(
+    world: &mut World,
+    file: &VoxFile,
+    blocks: &[BlockId; 256],
+    origin: [i32; 3],
+) -> io::Result<BTreeSet<ChunkPos>> {
+    let mut stale = BTreeSet::new();
+    for instance in file.instances()? {
+        let model = &file.models[instance.model];
+        let pivot = pivot(model.size);
+        for voxel in &model.voxels {
+            let local = [
+                voxel.position[0] as i32 - pivot[0],
+                voxel.position[1] as i32 - pivot[1],
+                voxel.position[2] as i32 - pivot[2],
+            ];
+            let [x, y, z] = rotate(&instance.rotation, local);
+            let [x, y, z] = [
+                x + instance.translation[0],
+                y + instance.translation[1],
+                z + instance.translation[2],
+            ];
+            let block = [origin[0] + x, origin[1] + z, origin[2] + y];
+            if world.set_block(block, blocks[voxel.color_index as usize]) {
+                stale.extend(chunks_touching(block));
+            }
+        }
+    }
+    Ok(stale)
+}
+
+// Turns a box of the world into a single model, up to MAX_MODEL_SIZE per
+// axis, with a palette built from the block colors. Fails when the box holds
+// more than 255 distinct colors.
+pub fn capture(
+    world: &World,
+    registry: &BlockRegistry,
+    min: [i32; 3],
+    size: [u32; 3],
+) -> io::Result<VoxFile> {
+    let size = size.map(|extent| extent.min(MAX_MODEL_SIZE));
+    let mut file = VoxFile::new();
+    let mut indices: BTreeMap<[u8; 4], u8> = BTreeMap::new();
+    let mut model = VoxModel {
+        size: [size[0], size[2], size[1]],
+        voxels: Vec::new(),
+    };
+
+    for y in 0..size[1] {
+        for z in 0..size[2] {
+            for x in 0..size[0] {
+                let block = [min[0] + x as i32, min[1] + y as i32, min[2] + z as i32];
+                let id = match world.block(block) {
+                    Some(id) if !id.is_air() => id,
+                    _ => continue,
+                };
+                let rgba = registry
+                    .color(id)
+                    .map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8);
+                let next = indices.len() + 1;
+                let color_index = match indices.get(&rgba) {
+                    Some(index) => *index,
+                    None if next < 256 => {
+                        file.palette[next] = rgba;
+                        indices.insert(rgba, next as u8);
+                        next as u8
+                    }
+                    None => return Err(invalid_data("more than 255 block colors")),
+                };
+                model.voxels.push(Voxel {
+                    position: [x as u8, z as u8, y as u8],
+                    color_index,
+                });
+            }
+        }
+    }
+
+    file.models.push(model);
+    Ok(file)
+}
+
+fn pivot(size: [u32; 3]) -> [i32; 3] {
+    size.map(|extent| (extent / 2) as i32)
+}
+
+// The packed rotation of the _r attribute: bits 0-1 and 2-3 hold the column
+// of the non-zero entry of the first and second row, the third row takes the
+// remaining column, bits 4, 5 and 6 make the rows' entries negative.
+fn decode_rotation(bits: u8) -> io::Result<[[i32; 3]; 3]> {
+    let first = (bits & 3) as usize;
+    let second = ((bits >> 2) & 3) as usize;
+    if first > 2 || second > 2 || first == second {
+        return Err(invalid_data(format!("invalid rotation {}", bits)));
+    }
+    let columns = [first, second, 3 - first - second];
+    let mut rotation = [[0; 3]; 3];
+    for row in 0..3 {
+        rotation[row][columns[row]] = if bits & (16 << row) != 0 { -1 } else { 1 };
+    }
+    Ok(rotation)
+}
+
+fn rotate(rotation: &[[i32; 3]; 3], v: [i32; 3]) -> [i32; 3] {
+    rotation.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
+
+fn multiply(a: &[[i32; 3]; 3], b: &[[i32; 3]; 3]) -> [[i32; 3]; 3] {
+    let mut out = [[0; 3]; 3];
+    for (row, out_row) in out.iter_mut().enumerate() {
+        for (column, value) in out_row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[row][k] * b[k][column]).sum();
+        }
+    }
+    out
+}
+
+fn parse_int(value: &str) -> io::Result<i32> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| invalid_data(format!("invalid number {:?}", value)))
+}
+
+fn parse_vector(value: &str) -> io::Result<[i32; 3]> {
+    let parts = value
+        .split_whitespace()
+        .map(parse_int)
+        .collect::<io::Result<Vec<_>>>()?;
+    parts
+        .try_into()
+        .map_err(|_| invalid_data(format!("invalid vector {:?}", value)))
+}
+
+fn write_chunk(bytes: &mut Vec<u8>, id: &[u8; 4], content: &[u8]) {
+    bytes.extend_from_slice(id);
+    bytes.extend_from_slice(&(content.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&0_u32.to_le_bytes());
+    bytes.extend_from_slice(content);
+}
+
+fn write_dict(bytes: &mut Vec<u8>, dict: &Dict) {
+    bytes.extend_from_slice(&(dict.len() as u32).to_le_bytes());
+    for (key, value) in dict {
+        for string in [key, value] {
+            bytes.extend_from_slice(&(string.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(string.as_bytes());
+        }
+    }
+}
+
+// The palette MagicaVoxel uses when a file has no RGBA chunk: a 6x6x6 color
+// cube followed by ramps of red, green, blue and gray.
+pub fn default_palette() -> [[u8; 4]; 256] {
+    const CUBE: [u8; 6] = [0xff, 0xcc, 0x99, 0x66, 0x33, 0x00];
+    const RAMP: [u8; 10] = [0xee, 0xdd, 0xbb, 0xaa, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+
+    let mut palette = [[0; 4]; 256];
+    let mut index = 1;
+    for r in CUBE {
+        for g in CUBE {
+            for b in CUBE {
+                if index < 216 {
+                    palette[index] = [r, g, b, 0xff];
+                    index += 1;
+                }
+            }
+        }
+    }
+    for channel in 0..4 {
+        for value in RAMP {
+            palette[index] = match channel {
+                0 => [value, 0, 0, 0xff],
+                1 => [0, value, 0, 0xff],
+                2 => [0, 0, value, 0xff],
+                _ => [value, value, value, 0xff],
+            };
+            index += 1;
+        }
+    }
+    palette
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        Ok(self.slice(N)?.try_into().unwrap())
+    }
+
+    fn slice(&mut self, length: usize) -> io::Result<&'a [u8]> {
+        if self.bytes.len() < length {
+            return Err(invalid_data("unexpected end of .vox data"));
+        }
+        let (head, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        self.take().map(u32::from_le_bytes)
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let length = self.u32()? as usize;
+        String::from_utf8(self.slice(length)?.to_vec())
+            .map_err(|_| invalid_data("string is not UTF-8"))
+    }
+
+    fn dict(&mut self) -> io::Result<Dict> {
+        let count = self.u32()?;
+        (0..count)
+            .map(|_| Ok((self.string()?, self.string()?)))
+            .collect()
+    }
+
+    // Chunk id, content and children.
+    fn chunk(&mut self) -> io::Result<([u8; 4], &'a [u8], &'a [u8])> {
+        let id = self.take::<4>()?;
+        let content_size = self.u32()? as usize;
+        let children_size = self.u32()? as usize;
+        let content = self.slice(content_size)?;
+        let children = self.slice(children_size)?;
+        Ok((id, content, children))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+
+    // One 3x2x1 model with a PACK chunk in front, no scene graph and no
+    // palette, so the default one applies.
+    const SINGLE: &[u8] = include_bytes!("../tests/fixtures/single.vox");
+    // A 1x1x1 and a 2x1x1 model placed by a group of two transforms, the
+    // second one rotated, a layer and a palette with red at 1 and green at 2.
+    const SCENE: &[u8] = include_bytes!("../tests/fixtures/scene.vox");
+
+    fn voxel(x: u8, y: u8, z: u8, color_index: u8) -> Voxel {
+        Voxel {
+            position: [x, y, z],
+            color_index,
+        }
+    }
+
+    #[test]
+    fn parses_a_single_model() {
+        let file = VoxFile::parse(SINGLE).unwrap();
+        assert_eq!(file.version, 150);
+        assert_eq!(
+            file.models,
+            [VoxModel {
+                size: [3, 2, 1],
+                voxels: vec![voxel(0, 0, 0, 1), voxel(1, 0, 0, 2), voxel(2, 1, 0, 2), voxel(0, 1, 0, 79)],
+            }]
+        );
+        assert_eq!(file.palette, default_palette());
+        assert!(file.nodes.is_empty());
+        assert_eq!(
+            file.instances().unwrap(),
+            [VoxInstance {
+                model: 0,
+                rotation: IDENTITY,
+                translation: [1, 1, 0],
+            }]
+        );
+    }
+
+    #[test]
+    fn parses_a_scene_graph() {
+        let file = VoxFile::parse(SCENE).unwrap();
+        assert_eq!(file.models.len(), 2);
+        assert_eq!(file.models[1].voxels, [voxel(0, 0, 0, 2), voxel(1, 0, 0, 2)]);
+        assert_eq!(file.palette[1], [255, 0, 0, 255]);
+        assert_eq!(file.palette[2], [0, 255, 0, 255]);
+        assert_eq!(file.palette[3], [0, 0, 0, 0]);
+        assert_eq!(file.nodes.len(), 6);
+        assert_eq!(
+            file.nodes[&1],
+            SceneNode::Group {
+                attributes: Vec::new(),
+                children: vec![2, 4],
+            }
+        );
+        assert_eq!(
+            file.instances().unwrap(),
+            [
+                VoxInstance {
+                    model: 0,
+                    rotation: IDENTITY,
+                    translation: [5, 0, 0],
+                },
+                VoxInstance {
+                    model: 1,
+                    rotation: [[0, 1, 0], [-1, 0, 0], [0, 0, 1]],
+                    translation: [0, 0, 3],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn written_files_parse_back() {
+        for fixture in [SINGLE, SCENE] {
+            let file = VoxFile::parse(fixture).unwrap();
+            assert_eq!(VoxFile::parse(&file.to_bytes()).unwrap(), file);
+        }
+    }
+
+    #[test]
+    fn broken_files_are_rejected() {
+        let message = |bytes: &[u8]| VoxFile::parse(bytes).unwrap_err().to_string();
+        assert_eq!(message(&SINGLE[..SINGLE.len() - 2]), "unexpected end of .vox data");
+        assert_eq!(message(b"RIFF\x96\0\0\0"), "not a .vox file");
+
+        // The last voxel of the single model moved out of its 3x2x1 box.
+        let mut outside = SINGLE.to_vec();
+        let last = outside.len() - 4;
+        outside[last + 2] = 1;
+        assert_eq!(message(&outside), "voxel outside its model");
+    }
+
+    #[test]
+    fn stamping_places_instances_y_up() {
+        let mut registry = BlockRegistry::new();
+        let red = registry.register("red", [0.9, 0.1, 0.1, 1.0]);
+        let green = registry.register("green", [0.1, 0.8, 0.1, 1.0]);
+        let file = VoxFile::parse(SCENE).unwrap();
+        let blocks = map_palette(&file, &registry);
+        assert_eq!((blocks[0], blocks[1], blocks[2]), (BlockId::AIR, red, green));
+
+        let mut world = World::new();
+        world.insert_chunk(ChunkPos::new(0, 0, 0), Chunk::new());
+        let stale = stamp(&mut world, &file, &blocks, [8, 8, 8]).unwrap();
+        assert!(stale.contains(&ChunkPos::new(0, 0, 0)));
+        // MagicaVoxel (5, 0, 0) is block (5, 0, 0) from the origin.
+        assert_eq!(world.block([13, 8, 8]), Some(red));
+        // The rotation turns the model from along x to along y in
+        // MagicaVoxel, along z here, 3 up.
+        assert_eq!(world.block([8, 11, 8]), Some(green));
+        assert_eq!(world.block([8, 11, 9]), Some(green));
+        assert_eq!(world.block([7, 11, 8]), Some(BlockId::AIR));
+    }
+
+    #[test]
+    fn captured_boxes_stamp_back() {
+        let mut registry = BlockRegistry::new();
+        let red = registry.register("red", [1.0, 0.0, 0.0, 1.0]);
+        let blue = registry.register("blue", [0.0, 0.0, 1.0, 1.0]);
+        let mut world = World::new();
+        world.insert_chunk(ChunkPos::new(0, 0, 0), Chunk::new());
+        world.set_block([2, 3, 4], red);
+        world.set_block([3, 3, 4], blue);
+        world.set_block([2, 5, 6], red);
+
+        let file = capture(&world, &registry, [2, 3, 4], [2, 3, 3]).unwrap();
+        let file = VoxFile::parse(&file.to_bytes()).unwrap();
+        assert_eq!(file.models[0].size, [2, 3, 3]);
+        assert_eq!(file.models[0].voxels.len(), 3);
+
+        let mut copy = World::new();
+        copy.insert_chunk(ChunkPos::new(0, 0, 0), Chunk::new());
+        let blocks = map_palette(&file, &registry);
+        // Without a scene graph the minimum corner lands on `origin`.
+        stamp(&mut copy, &file, &blocks, [2, 3, 4]).unwrap();
+        for block in [[2, 3, 4], [3, 3, 4], [2, 5, 6], [2, 4, 4]] {
+            assert_eq!(copy.block(block), world.block(block), "{:?}", block);
+        }
+    }
+}
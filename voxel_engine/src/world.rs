@@ -0,0 +1,118 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::block::{BlockId, DIRT, GRASS, SAND, STONE, WATER};
+use crate::chunk::{local_coordinates, Chunk, ChunkPos, CHUNK_SIZE};
+
+const SEA_LEVEL: i32 = 34;
+
+// All chunks currently resident on the CPU, addressed by chunk position.
+#[derive(Default)]
+pub struct World {
+    chunks: HashMap<ChunkPos, Chunk>,
+    // Chunks changed since they were loaded or last saved. Chunks straight
+    // from the generator are never dirty, they can be generated again.
+    dirty: HashSet<ChunkPos>,
+}
+
+impl World {
+    pub fn new() -> Self {
+        World::default()
+    }
+
+    pub fn chunk(&self, pos: ChunkPos) -> Option<&Chunk> {
+        self.chunks.get(&pos)
+    }
+
+    pub fn insert_chunk(&mut self, pos: ChunkPos, chunk: Chunk) {
+        self.chunks.insert(pos, chunk);
+    }
+
+    pub fn remove_chunk(&mut self, pos: ChunkPos) -> Option<Chunk> {
+        self.dirty.remove(&pos);
+        self.chunks.remove(&pos)
+    }
+
+    pub fn chunks(&self) -> impl Iterator<Item = (ChunkPos, &Chunk)> {
+        self.chunks.iter().map(|(pos, chunk)| (*pos, chunk))
+    }
+
+    pub fn is_dirty(&self, pos: ChunkPos) -> bool {
+        self.dirty.contains(&pos)
+    }
+
+    pub fn dirty_chunks(&self) -> Vec<ChunkPos> {
+        let mut dirty: Vec<ChunkPos> = self.dirty.iter().copied().collect();
+        dirty.sort();
+        dirty
+    }
+
+    pub fn mark_saved(&mut self, pos: ChunkPos) {
+        self.dirty.remove(&pos);
+    }
+
+    // None when the chunk holding the block is not loaded.
+    pub fn block(&self, block: [i32; 3]) -> Option<BlockId> {
+        let [x, y, z] = local_coordinates(block);
+        self.chunk(ChunkPos::from_block(block))
+            .map(|chunk| chunk.get(x, y, z))
+    }
+
+    // Returns false and leaves the world untouched when the chunk holding the
+    // block is not loaded.
+    pub fn set_block(&mut self, block: [i32; 3], id: BlockId) -> bool {
+        let [x, y, z] = local_coordinates(block);
+        let pos = ChunkPos::from_block(block);
+        match self.chunks.get_mut(&pos) {
+            Some(chunk) => {
+                chunk.set(x, y, z, id);
+                self.dirty.insert(pos);
+                true
+            }
+            None => false,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn is_solid(&self, block: [i32; 3]) -> bool {
+        self.block(block).is_some_and(|block| !block.is_air())
+    }
+}
+
+fn terrain_height(x: i32, z: i32) -> i32 {
+    let (x, z) = (x as f32, z as f32);
+    let height = 40.0
+        + 12.0 * (x * 0.05).sin() * (z * 0.04).cos()
+        + 6.0 * ((x + z) * 0.11).sin();
+    height as i32
+}
+
+// Procedural heightmap terrain: grass over a few layers of dirt over stone,
+// sand near the water line and water up to it.
+pub fn generate_chunk(pos: ChunkPos) -> Chunk {
+    let mut chunk = Chunk::new();
+    let [origin_x, origin_y, origin_z] = pos.origin();
+    for z in 0..CHUNK_SIZE {
+        for x in 0..CHUNK_SIZE {
+            let height = terrain_height(origin_x + x as i32, origin_z + z as i32);
+            for y in 0..CHUNK_SIZE {
+                let world_y = origin_y + y as i32;
+                if world_y > height.max(SEA_LEVEL) {
+                    break;
+                }
+                let block = if world_y > height {
+                    WATER
+                } else if world_y < height - 3 {
+                    STONE
+                } else if height <= SEA_LEVEL {
+                    SAND
+                } else if world_y == height {
+                    GRASS
+                } else {
+                    DIRT
+                };
+                chunk.set(x, y, z, block);
+            }
+        }
+    }
+    chunk
+}